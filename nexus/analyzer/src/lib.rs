@@ -3,6 +3,7 @@
 use std::{
     collections::{HashMap, HashSet},
     ops::ControlFlow,
+    time::Duration,
 };
 
 use anyhow::Context;
@@ -31,17 +32,53 @@ pub trait StatementAnalyzer {
 
 /// PeerExistanceAnalyzer is a statement analyzer that checks if the given
 /// statement touches a peer that exists in the system. If there isn't a peer
-/// this points to a catalog query.
+/// this points to the default peer when one is set and the statement reads
+/// tables outside the system schemas, else to a catalog query.
 pub struct PeerExistanceAnalyzer<'a> {
     peers: &'a HashMap<String, Peer>,
+    default_peer: Option<&'a str>,
 }
 
 impl<'a> PeerExistanceAnalyzer<'a> {
     pub fn new(peers: &'a HashMap<String, Peer>) -> Self {
-        Self { peers }
+        Self {
+            peers,
+            default_peer: None,
+        }
+    }
+
+    pub fn with_default_peer(mut self, default_peer: Option<&'a str>) -> Self {
+        self.default_peer = default_peer;
+        self
+    }
+}
+
+// relations of the system catalogs, which clients query to introspect the
+// server and which always stay on the catalog.
+fn is_system_relation(relation: &ast::ObjectName) -> bool {
+    let parts = &relation.0;
+    let is_system_schema = |schema: &ast::Ident| {
+        schema.value.eq_ignore_ascii_case("pg_catalog")
+            || schema.value.eq_ignore_ascii_case("information_schema")
+    };
+    match parts.len() {
+        1 => parts[0].value.to_lowercase().starts_with("pg_"),
+        n => is_system_schema(&parts[n - 2]),
+    }
+}
+
+/// The error of a statement routed to a default peer that doesn't exist.
+#[derive(Debug)]
+pub struct UnknownDefaultPeer(pub String);
+
+impl std::fmt::Display for UnknownDefaultPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "default peer \"{}\" does not exist", self.0)
     }
 }
 
+impl std::error::Error for UnknownDefaultPeer {}
+
 #[derive(Debug, Clone)]
 pub enum QueryAssociation {
     Peer(Box<Peer>),
@@ -68,6 +105,17 @@ impl<'a> StatementAnalyzer for PeerExistanceAnalyzer<'a> {
                         analyze_name(&name.0[0].value);
                     }
                 }
+                // CALL peer.procedure(...) runs the procedure on the peer
+                Statement::Call(function) if function.name.0.len() > 1 => {
+                    analyze_name(&function.name.0[0].value);
+                }
+                // the table of `COPY table TO ...` isn't visited as a relation
+                Statement::Copy {
+                    source: ast::CopySource::Table { table_name, .. },
+                    ..
+                } => {
+                    analyze_name(&table_name.0[0].value);
+                }
                 Statement::Declare { stmts } => {
                     for stmt in stmts {
                         if let Some(ref query) = stmt.for_query {
@@ -94,6 +142,21 @@ impl<'a> StatementAnalyzer for PeerExistanceAnalyzer<'a> {
         } else if let Some(peer_name) = peers_touched.iter().next() {
             let peer = self.peers.get(peer_name).unwrap();
             Ok(QueryAssociation::Peer(Box::new(peer.clone())))
+        } else if let Some(default_peer) = self.default_peer {
+            let mut reads_user_tables = false;
+            visit_relations(statement, |relation| {
+                reads_user_tables |= !is_system_relation(relation);
+                ControlFlow::<()>::Continue(())
+            });
+            if !reads_user_tables {
+                return Ok(QueryAssociation::Catalog);
+            }
+
+            let peer = self
+                .peers
+                .get(&default_peer.to_lowercase())
+                .ok_or_else(|| UnknownDefaultPeer(default_peer.to_owned()))?;
+            Ok(QueryAssociation::Peer(Box::new(peer.clone())))
         } else {
             Ok(QueryAssociation::Catalog)
         }
@@ -111,10 +174,20 @@ pub enum PeerDDL {
     CreatePeer {
         peer: Box<pt::peerdb_peers::Peer>,
         if_not_exists: bool,
+        // connect to the peer before creating it, off with `validate = false`
+        // to provision peers that aren't reachable yet
+        validate: bool,
+    },
+    /// `ALTER PEER name [FROM type] SET CONFIG (...)`, replaces the config
+    /// of an existing peer, e.g. when its credentials rotate.
+    AlterPeer {
+        peer: Box<pt::peerdb_peers::Peer>,
     },
     DropPeer {
         peer_name: String,
         if_exists: bool,
+        // drop the mirrors using the peer too instead of refusing to drop it
+        cascade: bool,
     },
     CreateMirrorForCDC {
         if_not_exists: bool,
@@ -164,10 +237,25 @@ impl StatementAnalyzer for PeerDDLAnalyzer {
                     r#type: db_type as i32,
                     config,
                 };
+                let validate = with_options
+                    .iter()
+                    .find(|option| option.name.value.eq_ignore_ascii_case("validate"));
+                let validate = match validate.map(|option| &option.value) {
+                    None => true,
+                    Some(Expr::Value(ast::Value::Boolean(b))) => *b,
+                    // also support "true" and "false" as strings
+                    Some(Expr::Value(ast::Value::SingleQuotedString(s))) => match s.as_ref() {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(anyhow::anyhow!("validate must be a boolean")),
+                    },
+                    _ => return Err(anyhow::anyhow!("validate must be a boolean")),
+                };
 
                 Ok(Some(PeerDDL::CreatePeer {
                     peer: Box::new(peer),
                     if_not_exists: *if_not_exists,
+                    validate,
                 }))
             }
             Statement::CreateMirror {
@@ -404,6 +492,7 @@ impl StatementAnalyzer for PeerDDLAnalyzer {
             } => Ok(Some(PeerDDL::DropPeer {
                 if_exists: *if_exists,
                 peer_name: peer_name.to_string().to_lowercase(),
+                cascade: false,
             })),
             Statement::ResyncMirror {
                 if_exists,
@@ -447,7 +536,9 @@ impl StatementAnalyzer for PeerDDLAnalyzer {
 
 #[derive(Debug, Clone)]
 pub enum CursorEvent {
-    Fetch(String, usize),
+    Fetch(String, FetchDirection),
+    /// `MOVE`, which takes the same directions as FETCH but returns no rows.
+    Move(String, FetchDirection),
     CloseAll,
     Close(String),
 }
@@ -471,25 +562,10 @@ impl StatementAnalyzer for PeerCursorAnalyzer {
         match statement {
             Statement::Fetch {
                 name, direction, ..
-            } => {
-                let count = match direction {
-                    FetchDirection::ForwardAll | FetchDirection::All => usize::MAX,
-                    FetchDirection::Next | FetchDirection::Forward { limit: None } => 1,
-                    FetchDirection::Count {
-                        limit: ast::Value::Number(n, _),
-                    }
-                    | FetchDirection::Forward {
-                        limit: Some(ast::Value::Number(n, _)),
-                    } => n.parse::<usize>()?,
-                    _ => {
-                        return Err(anyhow::anyhow!(
-                            "invalid fetch direction for cursor: {:?}",
-                            direction
-                        ))
-                    }
-                };
-                Ok(Some(CursorEvent::Fetch(name.value.clone(), count)))
-            }
+            } => Ok(Some(CursorEvent::Fetch(
+                name.value.clone(),
+                direction.clone(),
+            ))),
             Statement::Close { cursor } => match cursor {
                 ast::CloseCursor::All => Ok(Some(CursorEvent::CloseAll)),
                 ast::CloseCursor::Specific { name } => {
@@ -501,6 +577,388 @@ impl StatementAnalyzer for PeerCursorAnalyzer {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Builtin {
+    Sleep(Duration),
+    /// `SELECT * FROM peerdb.peer_stats`, latency and errors of queries per peer.
+    PeerStats,
+    /// `SELECT * FROM peerdb.peer_types`, the peer types and their capabilities.
+    PeerTypes,
+    /// `SELECT peerdb.version()`, the version and build of nexus.
+    Version,
+    /// `SELECT * FROM peerdb.config`, the server settings admins can change.
+    Config,
+    /// `SELECT peerdb.set_config('name', 'value')`, changes a server setting.
+    SetConfig {
+        name: String,
+        value: String,
+    },
+    /// `SELECT peerdb.submit_async('query')`, runs a query in the background,
+    /// returning the id of its job.
+    SubmitAsync {
+        query: String,
+    },
+    /// `SELECT peerdb.async_status('id')`, the status of a submitted job.
+    AsyncStatus {
+        id: String,
+    },
+    /// `SELECT peerdb.async_result('id')`, the rows of a finished job.
+    AsyncResult {
+        id: String,
+    },
+    /// `SELECT * FROM peerdb.rewrite_rules`, the query rewrite rules of peers.
+    RewriteRules,
+    /// `SELECT peerdb.add_rewrite_rule('name', 'peer', 'table', 'action',
+    /// 'argument')`, adds a query rewrite rule.
+    AddRewriteRule {
+        name: String,
+        peer: String,
+        table: String,
+        action: String,
+        argument: String,
+    },
+    /// `SELECT peerdb.drop_rewrite_rule('name')`, drops a query rewrite rule.
+    DropRewriteRule {
+        name: String,
+    },
+    /// `SELECT peerdb.reconnect_all()`, closes the peer connections of every
+    /// client connection so they connect again.
+    ReconnectAll,
+    /// `SELECT peerdb.reconnect_peer('name')`, closes the connections to a
+    /// peer so they connect again.
+    ReconnectPeer {
+        peer: String,
+    },
+    /// `SELECT peerdb.set_password('user', 'password')`, sets the password a
+    /// user logs in to nexus with, adding the user when missing.
+    SetPassword {
+        user: String,
+        password: String,
+    },
+    /// `SELECT peerdb.drop_user('user')`, drops a user added with
+    /// `peerdb.set_password`.
+    DropUser {
+        user: String,
+    },
+}
+
+/// BuiltinAnalyzer is a statement analyzer that checks if the given
+/// statement is a call to a function that nexus evaluates itself rather
+/// than forwarding to a peer or the catalog, for example `SELECT pg_sleep(1)`.
+///
+/// Only bare calls are recognized: a single projection with no FROM clause.
+/// Nexus' virtual tables are read with a plain `SELECT * FROM table`.
+#[derive(Default)]
+pub struct BuiltinAnalyzer;
+
+impl StatementAnalyzer for BuiltinAnalyzer {
+    type Output = Option<Builtin>;
+
+    fn analyze(&self, statement: &Statement) -> anyhow::Result<Self::Output> {
+        if let Some(table) = select_all_from(statement) {
+            let name = table.to_string().to_lowercase();
+            match name.as_str() {
+                "peerdb.peer_stats" => return Ok(Some(Builtin::PeerStats)),
+                "peerdb.peer_types" => return Ok(Some(Builtin::PeerTypes)),
+                "peerdb.config" => return Ok(Some(Builtin::Config)),
+                "peerdb.rewrite_rules" => return Ok(Some(Builtin::RewriteRules)),
+                _ => (),
+            }
+        }
+
+        let Some(function) = bare_function_call(statement) else {
+            return Ok(None);
+        };
+
+        let name = function.name.to_string().to_lowercase();
+        match name.as_str() {
+            "pg_sleep" => {
+                let [arg] = function.args.as_slice() else {
+                    anyhow::bail!("pg_sleep expects exactly one argument");
+                };
+                let ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(arg)) = arg else {
+                    anyhow::bail!("pg_sleep expects a numeric argument");
+                };
+                let seconds = numeric_literal(arg)?;
+                // postgres treats negative durations as no sleep at all
+                let seconds = if seconds.is_nan() {
+                    0.0
+                } else {
+                    seconds.max(0.0)
+                };
+                let duration = Duration::try_from_secs_f64(seconds).map_err(|_| {
+                    anyhow::anyhow!("pg_sleep duration {} is out of range", seconds)
+                })?;
+                Ok(Some(Builtin::Sleep(duration)))
+            }
+            "peerdb.version" => {
+                if !function.args.is_empty() {
+                    anyhow::bail!("peerdb.version expects no arguments");
+                }
+                Ok(Some(Builtin::Version))
+            }
+            "peerdb.set_config" => {
+                let [name, value] = function.args.as_slice() else {
+                    anyhow::bail!("peerdb.set_config expects a setting name and a value");
+                };
+                match (string_arg(name), string_arg(value)) {
+                    (Some(name), Some(value)) => Ok(Some(Builtin::SetConfig { name, value })),
+                    _ => anyhow::bail!("peerdb.set_config expects string literal arguments"),
+                }
+            }
+            "peerdb.add_rewrite_rule" => {
+                let args = function.args.iter().map(string_arg).collect::<Vec<_>>();
+                match args.as_slice() {
+                    [Some(name), Some(peer), Some(table), Some(action), Some(argument)] => {
+                        Ok(Some(Builtin::AddRewriteRule {
+                            name: name.clone(),
+                            peer: peer.clone(),
+                            table: table.clone(),
+                            action: action.clone(),
+                            argument: argument.clone(),
+                        }))
+                    }
+                    _ => anyhow::bail!(
+                        "peerdb.add_rewrite_rule expects a rule name, peer, table, action and \
+                        argument as string literals"
+                    ),
+                }
+            }
+            "peerdb.drop_rewrite_rule" => match function.args.as_slice() {
+                [arg] => match string_arg(arg) {
+                    Some(name) => Ok(Some(Builtin::DropRewriteRule { name })),
+                    None => anyhow::bail!("peerdb.drop_rewrite_rule expects a string literal"),
+                },
+                _ => anyhow::bail!("peerdb.drop_rewrite_rule expects the name of a rule"),
+            },
+            "peerdb.reconnect_all" => {
+                if !function.args.is_empty() {
+                    anyhow::bail!("peerdb.reconnect_all expects no arguments");
+                }
+                Ok(Some(Builtin::ReconnectAll))
+            }
+            "peerdb.reconnect_peer" => match function.args.as_slice() {
+                [arg] => match string_arg(arg) {
+                    Some(peer) => Ok(Some(Builtin::ReconnectPeer { peer })),
+                    None => anyhow::bail!("peerdb.reconnect_peer expects a string literal"),
+                },
+                _ => anyhow::bail!("peerdb.reconnect_peer expects the name of a peer"),
+            },
+            "peerdb.set_password" => {
+                let [user, password] = function.args.as_slice() else {
+                    anyhow::bail!("peerdb.set_password expects a user name and a password");
+                };
+                match (string_arg(user), string_arg(password)) {
+                    (Some(user), Some(password)) if !password.is_empty() => {
+                        Ok(Some(Builtin::SetPassword { user, password }))
+                    }
+                    (Some(_), Some(_)) => anyhow::bail!("password must not be empty"),
+                    _ => anyhow::bail!("peerdb.set_password expects string literal arguments"),
+                }
+            }
+            "peerdb.drop_user" => match function.args.as_slice() {
+                [arg] => match string_arg(arg) {
+                    Some(user) => Ok(Some(Builtin::DropUser { user })),
+                    None => anyhow::bail!("peerdb.drop_user expects a string literal"),
+                },
+                _ => anyhow::bail!("peerdb.drop_user expects the name of a user"),
+            },
+            "peerdb.submit_async" | "peerdb.async_status" | "peerdb.async_result" => {
+                let arg = match function.args.as_slice() {
+                    [arg] => string_arg(arg),
+                    _ => None,
+                };
+                let Some(arg) = arg else {
+                    anyhow::bail!("{} expects a single string literal argument", name);
+                };
+                Ok(Some(match name.as_str() {
+                    "peerdb.submit_async" => Builtin::SubmitAsync { query: arg },
+                    "peerdb.async_status" => Builtin::AsyncStatus { id: arg },
+                    _ => Builtin::AsyncResult { id: arg },
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionVariable {
+    /// `SET name = value`, a value of `None` resets the variable to its default.
+    Set {
+        name: String,
+        value: Option<String>,
+    },
+    Show {
+        name: String,
+    },
+    /// `SET SESSION CHARACTERISTICS AS TRANSACTION ...`, the same as setting
+    /// `default_transaction_isolation` and `default_transaction_read_only`.
+    SetCharacteristics {
+        settings: Vec<(String, String)>,
+    },
+}
+
+/// SessionVariableAnalyzer is a statement analyzer that checks if the given
+/// statement sets or shows a session variable (GUC). These are tracked per
+/// connection by nexus rather than being sent to the catalog.
+#[derive(Default)]
+pub struct SessionVariableAnalyzer;
+
+impl StatementAnalyzer for SessionVariableAnalyzer {
+    type Output = Option<SessionVariable>;
+
+    fn analyze(&self, statement: &Statement) -> anyhow::Result<Self::Output> {
+        match statement {
+            Statement::SetVariable {
+                variable, value, ..
+            } => {
+                let values = value
+                    .iter()
+                    .map(session_variable_value)
+                    .collect::<Option<Vec<_>>>();
+                Ok(Some(SessionVariable::Set {
+                    name: variable.to_string().to_lowercase(),
+                    value: values.map(|v| v.join(", ")),
+                }))
+            }
+            Statement::SetTimeZone { value, .. } => Ok(Some(SessionVariable::Set {
+                name: "timezone".to_owned(),
+                value: session_variable_value(value),
+            })),
+            Statement::SetTransaction {
+                modes,
+                session: true,
+                ..
+            } => Ok(Some(SessionVariable::SetCharacteristics {
+                settings: modes
+                    .iter()
+                    .map(|mode| match mode {
+                        ast::TransactionMode::IsolationLevel(level) => (
+                            "default_transaction_isolation".to_owned(),
+                            level.to_string().to_lowercase(),
+                        ),
+                        ast::TransactionMode::AccessMode(mode) => (
+                            "default_transaction_read_only".to_owned(),
+                            match mode {
+                                ast::TransactionAccessMode::ReadOnly => "on",
+                                ast::TransactionAccessMode::ReadWrite => "off",
+                            }
+                            .to_owned(),
+                        ),
+                    })
+                    .collect(),
+            })),
+            Statement::ShowVariable { variable } => Ok(Some(SessionVariable::Show {
+                name: variable
+                    .iter()
+                    .map(|ident| ident.value.to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join("."),
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+// returns None for DEFAULT
+fn session_variable_value(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(ident) if ident.value.eq_ignore_ascii_case("default") => None,
+        Expr::Identifier(ident) => Some(ident.value.clone()),
+        Expr::Value(ast::Value::SingleQuotedString(s))
+        | Expr::Value(ast::Value::DoubleQuotedString(s))
+        | Expr::Value(ast::Value::Number(s, _)) => Some(s.clone()),
+        Expr::Value(ast::Value::Boolean(b)) => Some(if *b { "on" } else { "off" }.to_owned()),
+        _ => Some(expr.to_string()),
+    }
+}
+
+/// Returns the function called by a statement of the form `SELECT f(...)`.
+fn bare_function_call(statement: &Statement) -> Option<&ast::Function> {
+    let Statement::Query(query) = statement else {
+        return None;
+    };
+    if query.with.is_some() || !query.order_by.is_empty() || query.limit.is_some() {
+        return None;
+    }
+    let ast::SetExpr::Select(select) = query.body.as_ref() else {
+        return None;
+    };
+    if !select.from.is_empty() || select.selection.is_some() {
+        return None;
+    }
+    match select.projection.as_slice() {
+        [ast::SelectItem::UnnamedExpr(Expr::Function(function))]
+        | [ast::SelectItem::ExprWithAlias {
+            expr: Expr::Function(function),
+            ..
+        }] => Some(function),
+        _ => None,
+    }
+}
+
+// the value of a number literal, or of a string literal holding one like
+// postgres coerces it, e.g. a parameter bound as text. Signs and casts are
+// looked through.
+fn numeric_literal(expr: &Expr) -> anyhow::Result<f64> {
+    match expr {
+        Expr::Value(ast::Value::Number(n, _)) => Ok(n.parse::<f64>()?),
+        Expr::Value(ast::Value::SingleQuotedString(s)) => s.trim().parse::<f64>().map_err(|_| {
+            anyhow::anyhow!("invalid input syntax for type double precision: \"{}\"", s)
+        }),
+        // a parameter of a prepared statement, whose statement is analyzed
+        // again with the bound value before it runs
+        Expr::Value(ast::Value::Placeholder(_)) => Ok(0.0),
+        Expr::UnaryOp {
+            op: ast::UnaryOperator::Minus,
+            expr,
+        } => Ok(-numeric_literal(expr)?),
+        Expr::UnaryOp {
+            op: ast::UnaryOperator::Plus,
+            expr,
+        }
+        | Expr::Cast { expr, .. }
+        | Expr::Nested(expr) => numeric_literal(expr),
+        _ => anyhow::bail!("pg_sleep expects a numeric argument"),
+    }
+}
+
+// the value of a string literal argument
+fn string_arg(arg: &ast::FunctionArg) -> Option<String> {
+    match arg {
+        ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(Expr::Value(
+            ast::Value::SingleQuotedString(s),
+        ))) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// the table of a `SELECT * FROM table` without any other clauses.
+fn select_all_from(statement: &Statement) -> Option<&ast::ObjectName> {
+    let Statement::Query(query) = statement else {
+        return None;
+    };
+    if query.with.is_some() || !query.order_by.is_empty() || query.limit.is_some() {
+        return None;
+    }
+    let ast::SetExpr::Select(select) = query.body.as_ref() else {
+        return None;
+    };
+    if select.selection.is_some()
+        || !matches!(select.projection[..], [ast::SelectItem::Wildcard(_)])
+    {
+        return None;
+    }
+    match select.from.as_slice() {
+        [ast::TableWithJoins {
+            relation: ast::TableFactor::Table { name, .. },
+            joins,
+        }] if joins.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
 fn parse_db_options(db_type: DbType, with_options: &[SqlOption]) -> anyhow::Result<Option<Config>> {
     let mut opts: HashMap<&str, &str> = HashMap::with_capacity(with_options.len());
     for opt in with_options {
@@ -519,8 +977,11 @@ fn parse_db_options(db_type: DbType, with_options: &[SqlOption]) -> anyhow::Resu
             let pem_str = opts
                 .get("private_key")
                 .ok_or_else(|| anyhow::anyhow!("missing private_key option for bigquery"))?;
-            pem::parse(pem_str.as_bytes())
-                .map_err(|err| anyhow::anyhow!("unable to parse private_key: {:?}", err))?;
+            // a reference to a secret manager is only resolved by nexus
+            if !pem_str.starts_with("secret://") {
+                pem::parse(pem_str.as_bytes())
+                    .map_err(|err| anyhow::anyhow!("unable to parse private_key: {:?}", err))?;
+            }
             let bq_config = BigqueryConfig {
                 auth_type: opts
                     .get("type")
@@ -567,6 +1028,11 @@ fn parse_db_options(db_type: DbType, with_options: &[SqlOption]) -> anyhow::Resu
                     .get("dataset_id")
                     .ok_or_else(|| anyhow::anyhow!("missing dataset_id in peer options"))?
                     .to_string(),
+                maximum_bytes_billed: opts
+                    .get("maximum_bytes_billed")
+                    .map(|bytes| bytes.parse::<i64>())
+                    .transpose()
+                    .context("unable to parse maximum_bytes_billed as valid int")?,
             };
             Config::BigqueryConfig(bq_config)
         }
@@ -670,6 +1136,32 @@ fn parse_db_options(db_type: DbType, with_options: &[SqlOption]) -> anyhow::Resu
                     .to_string(),
                 metadata_schema: opts.get("metadata_schema").map(|s| s.to_string()),
                 ssh_config: ssh_fields,
+                hosts: opts
+                    .get("hosts")
+                    .map(|hosts| {
+                        hosts
+                            .split(',')
+                            .map(|host| host.trim().to_string())
+                            .filter(|host| !host.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                target_session_attrs: opts.get("target_session_attrs").map(|s| s.to_string()),
+                pool_min_size: opts
+                    .get("pool_min_size")
+                    .map(|size| size.parse::<u32>())
+                    .transpose()
+                    .context("unable to parse pool_min_size as valid int")?,
+                pool_max_size: opts
+                    .get("pool_max_size")
+                    .map(|size| size.parse::<u32>())
+                    .transpose()
+                    .context("unable to parse pool_max_size as valid int")?,
+                pool_idle_timeout: opts
+                    .get("pool_idle_timeout")
+                    .map(|secs| secs.parse::<u32>())
+                    .transpose()
+                    .context("unable to parse pool_idle_timeout as valid int")?,
             };
 
             Config::PostgresConfig(postgres_config)
@@ -753,6 +1245,11 @@ fn parse_db_options(db_type: DbType, with_options: &[SqlOption]) -> anyhow::Resu
                 certificate: opts.get("certificate").map(|s| s.to_string()),
                 private_key: opts.get("private_key").map(|s| s.to_string()),
                 root_ca: opts.get("root_ca").map(|s| s.to_string()),
+                http_port: opts
+                    .get("http_port")
+                    .map(|port| port.parse::<u32>())
+                    .transpose()
+                    .context("unable to parse http_port as valid int")?,
             };
             Config::ClickhouseConfig(clickhouse_config)
         }
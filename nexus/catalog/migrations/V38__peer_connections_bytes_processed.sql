@@ -0,0 +1 @@
+ALTER TABLE peer_connections ADD COLUMN IF NOT EXISTS bytes_processed BIGINT;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS nexus_async_jobs (
+  id TEXT PRIMARY KEY,
+  query TEXT NOT NULL,
+  peer TEXT,
+  submitted_by TEXT NOT NULL,
+  status TEXT NOT NULL,
+  rows BIGINT NOT NULL DEFAULT 0,
+  error TEXT,
+  submitted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS nexus_rewrite_rules (
+  name TEXT PRIMARY KEY,
+  peer_name TEXT NOT NULL,
+  table_name TEXT NOT NULL,
+  action TEXT NOT NULL,
+  argument TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS nexus_users (
+  name TEXT PRIMARY KEY,
+  password_md5 TEXT NOT NULL,
+  scram_salt BYTEA NOT NULL,
+  scram_salted_password BYTEA NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
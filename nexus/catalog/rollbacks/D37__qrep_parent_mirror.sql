@@ -0,0 +1,2 @@
+ALTER TABLE peerdb_stats.qrep_partitions DROP COLUMN IF EXISTS parent_mirror_name;
+ALTER TABLE peerdb_stats.qrep_runs DROP COLUMN IF EXISTS parent_mirror_name;
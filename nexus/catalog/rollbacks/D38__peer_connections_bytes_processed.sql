@@ -0,0 +1 @@
+ALTER TABLE peer_connections DROP COLUMN IF EXISTS bytes_processed;
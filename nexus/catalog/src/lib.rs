@@ -3,10 +3,13 @@ use std::env;
 
 use anyhow::{anyhow, Context};
 use base64::prelude::*;
-use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
-use peer_cursor::{QueryExecutor, QueryOutput, Schema};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, OsRng},
+    KeyInit, XChaCha20Poly1305, XNonce,
+};
+use peer_cursor::{util::InvalidUtf8, BinaryCopy, DryRun, QueryExecutor, QueryOutput, Schema};
 use peer_postgres::{self, ast};
-use pgwire::error::PgWireResult;
+use pgwire::{api::Type, error::PgWireResult};
 use postgres_connection::{connect_postgres, get_pg_connection_string};
 use pt::{
     flow_model::QRepFlowJob,
@@ -15,14 +18,58 @@ use pt::{
     prost::Message,
 };
 use serde_json::{self, Value};
-use sqlparser::ast::Statement;
+use sqlparser::ast::{FetchDirection, Statement};
 use tokio_postgres::{types, Client};
 
+mod peer_cache;
+
+pub use peer_cache::PeerCache;
+
 mod embedded {
     use refinery::embed_migrations;
     embed_migrations!("migrations");
 }
 
+// table refinery records applied migrations in.
+const MIGRATION_HISTORY_TABLE: &str = "refinery_schema_history";
+
+// Scripts undoing a migration, by the version they undo. Rolling back past a
+// version without one is refused.
+const ROLLBACKS: &[(u32, &str)] = &[
+    (37, include_str!("../rollbacks/D37__qrep_parent_mirror.sql")),
+    (
+        38,
+        include_str!("../rollbacks/D38__peer_connections_bytes_processed.sql"),
+    ),
+    (39, include_str!("../rollbacks/D39__nexus_settings.sql")),
+    (40, include_str!("../rollbacks/D40__nexus_async_jobs.sql")),
+    (
+        41,
+        include_str!("../rollbacks/D41__nexus_rewrite_rules.sql"),
+    ),
+    (42, include_str!("../rollbacks/D42__nexus_users.sql")),
+];
+
+/// A query submitted with `peerdb.submit_async`, as kept in the catalog.
+#[derive(Debug, Clone)]
+pub struct AsyncJob {
+    pub status: String,
+    pub peer: Option<String>,
+    pub rows: i64,
+    pub error: Option<String>,
+}
+
+/// The stored password of a user logging in to nexus, as the hashes the
+/// MD5 and SCRAM exchanges check against.
+#[derive(Debug, Clone)]
+pub struct UserCredentials {
+    /// `md5` followed by the hex md5 of the password and user name, like
+    /// postgres stores it.
+    pub password_md5: String,
+    pub scram_salt: Vec<u8>,
+    pub scram_salted_password: Vec<u8>,
+}
+
 pub struct Catalog {
     pg: Client,
 }
@@ -51,6 +98,24 @@ pub struct CatalogConfig<'a> {
     pub database: &'a str,
 }
 
+// the options of a peer as stored in the catalog, before encryption
+fn encode_config(config: &Config) -> Vec<u8> {
+    match config {
+        Config::SnowflakeConfig(config) => config.encode_to_vec(),
+        Config::BigqueryConfig(config) => config.encode_to_vec(),
+        Config::MongoConfig(config) => config.encode_to_vec(),
+        Config::PostgresConfig(config) => config.encode_to_vec(),
+        Config::S3Config(config) => config.encode_to_vec(),
+        Config::SqlserverConfig(config) => config.encode_to_vec(),
+        Config::EventhubGroupConfig(config) => config.encode_to_vec(),
+        Config::ClickhouseConfig(config) => config.encode_to_vec(),
+        Config::KafkaConfig(config) => config.encode_to_vec(),
+        Config::PubsubConfig(config) => config.encode_to_vec(),
+        Config::ElasticsearchConfig(config) => config.encode_to_vec(),
+        Config::MysqlConfig(config) => config.encode_to_vec(),
+    }
+}
+
 impl<'a> CatalogConfig<'a> {
     // convert catalog config to PostgresConfig
     pub fn to_postgres_config(&self) -> pt::peerdb_peers::PostgresConfig {
@@ -62,6 +127,11 @@ impl<'a> CatalogConfig<'a> {
             database: self.database.to_string(),
             metadata_schema: Some("".to_string()),
             ssh_config: None,
+            hosts: vec![],
+            target_session_attrs: None,
+            pool_min_size: None,
+            pool_max_size: None,
+            pool_idle_timeout: None,
         }
     }
 
@@ -80,6 +150,59 @@ impl Catalog {
         run_migrations(&mut self.pg).await
     }
 
+    /// Version of the last migration applied to the catalog, if any.
+    pub async fn migration_version(&mut self) -> anyhow::Result<Option<u32>> {
+        let last = embedded::migrations::runner()
+            .get_last_applied_migration_async(&mut self.pg)
+            .await
+            .context("Failed to read migration history")?;
+        Ok(last.map(|migration| migration.version() as u32))
+    }
+
+    /// Migrates the catalog up or down to the given version. Each migration
+    /// and each rollback runs in its own transaction.
+    pub async fn migrate_to(&mut self, target: u32) -> anyhow::Result<()> {
+        let current = self.migration_version().await?.unwrap_or(0);
+        if target >= current {
+            let migration_report = embedded::migrations::runner()
+                .set_target(refinery::Target::Version(target))
+                .run_async(&mut self.pg)
+                .await
+                .context("Failed to run migrations")?;
+            for migration in migration_report.applied_migrations() {
+                tracing::info!(
+                    "Migration Applied -  Name: {}, Version: {}",
+                    migration.name(),
+                    migration.version()
+                );
+            }
+            return Ok(());
+        }
+
+        for version in (target + 1..=current).rev() {
+            let Some((_, script)) = ROLLBACKS.iter().find(|(v, _)| *v == version) else {
+                return Err(anyhow!(
+                    "migration {} has no rollback script, cannot migrate down to {}",
+                    version,
+                    target
+                ));
+            };
+
+            let txn = self.pg.transaction().await?;
+            txn.batch_execute(script)
+                .await
+                .with_context(|| format!("Failed to roll back migration {}", version))?;
+            txn.execute(
+                &format!("DELETE FROM {} WHERE version = $1", MIGRATION_HISTORY_TABLE),
+                &[&(version as i32)],
+            )
+            .await?;
+            txn.commit().await?;
+            tracing::info!("Migration Rolled Back - Version: {}", version);
+        }
+        Ok(())
+    }
+
     fn env_enc_key(enc_key_id: &str) -> anyhow::Result<Vec<u8>> {
         let enc_keys = env::var("PEERDB_ENC_KEYS")?;
 
@@ -130,6 +253,22 @@ impl Catalog {
             .map_err(|e| anyhow!("Decryption failed: {}", e))
     }
 
+    /// Encrypts the payload like the flow service does, for `decrypt`.
+    pub fn encrypt(payload: &[u8], enc_key_id: &str) -> anyhow::Result<Vec<u8>> {
+        if enc_key_id.is_empty() {
+            return Ok(payload.to_vec());
+        }
+
+        let key = Self::env_enc_key(enc_key_id)?;
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|e| anyhow!("Failed to create ChaCha20Poly1305 cipher: {}", e))?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
     // get peer id as i32
     pub async fn get_peer_id_i32(&self, peer_name: &str) -> anyhow::Result<i32> {
         let stmt = self
@@ -197,6 +336,75 @@ impl Catalog {
         Ok(peers)
     }
 
+    /// The peers as (name, type, created at), ordered by name. With a
+    /// pattern, only the peers whose name is LIKE it.
+    pub async fn list_peers(
+        &self,
+        pattern: Option<&str>,
+    ) -> anyhow::Result<Vec<(String, i32, chrono::NaiveDateTime)>> {
+        let rows = self
+            .pg
+            .query(
+                "SELECT name, type, created_at FROM public.peers
+                WHERE $1::text IS NULL OR name LIKE $1 ORDER BY name",
+                &[&pattern],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect())
+    }
+
+    /// Replaces the config of the peer, encrypted with the key of the config
+    /// it replaces. Returns false when there's no peer of that name and type.
+    pub async fn update_peer_config(&self, peer: &Peer) -> anyhow::Result<bool> {
+        let config = peer
+            .config
+            .as_ref()
+            .with_context(|| format!("peer {} has no config", peer.name))?;
+        let Some(row) = self
+            .pg
+            .query_opt(
+                "SELECT enc_key_id FROM public.peers WHERE name = $1 AND type = $2",
+                &[&peer.name, &peer.r#type],
+            )
+            .await?
+        else {
+            return Ok(false);
+        };
+        let enc_key_id: &str = row.get(0);
+        let options = Self::encrypt(&encode_config(config), enc_key_id)?;
+        let updated = self
+            .pg
+            .execute(
+                "UPDATE public.peers SET options = $3 WHERE name = $1 AND type = $2",
+                &[&peer.name, &peer.r#type, &options],
+            )
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Stores a new peer like the flow service does, encrypted with the
+    /// current key. Returns false when there's a peer of that name already.
+    pub async fn create_peer(&self, peer: &Peer) -> anyhow::Result<bool> {
+        let config = peer
+            .config
+            .as_ref()
+            .with_context(|| format!("peer {} has no config", peer.name))?;
+        let enc_key_id = env::var("PEERDB_CURRENT_ENC_KEY_ID").unwrap_or_default();
+        let options = Self::encrypt(&encode_config(config), &enc_key_id)?;
+        let inserted = self
+            .pg
+            .execute(
+                "INSERT INTO public.peers (name, type, options, enc_key_id) \
+                VALUES ($1, $2, $3, $4) ON CONFLICT (name) DO NOTHING",
+                &[&peer.name, &peer.r#type, &options, &enc_key_id],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
     pub async fn get_peer(&self, peer_name: &str) -> anyhow::Result<Peer> {
         let stmt = self
             .pg
@@ -481,6 +689,221 @@ impl Catalog {
         Ok(())
     }
 
+    /// Names of the mirrors reading from or writing to the peer.
+    pub async fn get_mirrors_for_peer(&self, peer_name: &str) -> anyhow::Result<Vec<String>> {
+        let rows = self
+            .pg
+            .query(
+                "SELECT DISTINCT f.name FROM public.flows f
+                JOIN public.peers p ON p.id IN (f.source_peer, f.destination_peer)
+                WHERE p.name = $1 ORDER BY f.name",
+                &[&peer_name],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Server settings changed at runtime with `peerdb.set_config`, as
+    /// (name, value).
+    pub async fn get_nexus_settings(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let rows = self
+            .pg
+            .query("SELECT name, value FROM public.nexus_settings", &[])
+            .await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    pub async fn set_nexus_setting(&self, name: &str, value: &str) -> anyhow::Result<()> {
+        self.pg
+            .execute(
+                "INSERT INTO public.nexus_settings (name, value) VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value, updated_at = now()",
+                &[&name, &value],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Query rewrite rules of the peers, as (name, peer, table, action,
+    /// argument, created by), in the order they were added.
+    pub async fn get_rewrite_rules(
+        &self,
+    ) -> anyhow::Result<Vec<(String, String, String, String, String, String)>> {
+        let rows = self
+            .pg
+            .query(
+                "SELECT name, peer_name, table_name, action, argument, created_by
+                FROM public.nexus_rewrite_rules ORDER BY created_at, name",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get(0),
+                    row.get(1),
+                    row.get(2),
+                    row.get(3),
+                    row.get(4),
+                    row.get(5),
+                )
+            })
+            .collect())
+    }
+
+    /// Adds a rewrite rule, false if one of the same name exists.
+    pub async fn insert_rewrite_rule(
+        &self,
+        name: &str,
+        peer: &str,
+        table: &str,
+        action: &str,
+        argument: &str,
+        created_by: &str,
+    ) -> anyhow::Result<bool> {
+        let inserted = self
+            .pg
+            .execute(
+                "INSERT INTO public.nexus_rewrite_rules
+                (name, peer_name, table_name, action, argument, created_by)
+                VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (name) DO NOTHING",
+                &[&name, &peer, &table, &action, &argument, &created_by],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    pub async fn delete_rewrite_rule(&self, name: &str) -> anyhow::Result<bool> {
+        let deleted = self
+            .pg
+            .execute(
+                "DELETE FROM public.nexus_rewrite_rules WHERE name = $1",
+                &[&name],
+            )
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    pub async fn get_user_credentials(
+        &self,
+        user: &str,
+    ) -> anyhow::Result<Option<UserCredentials>> {
+        let row = self
+            .pg
+            .query_opt(
+                "SELECT password_md5, scram_salt, scram_salted_password
+                FROM public.nexus_users WHERE name = $1",
+                &[&user],
+            )
+            .await?;
+        Ok(row.map(|row| UserCredentials {
+            password_md5: row.get(0),
+            scram_salt: row.get(1),
+            scram_salted_password: row.get(2),
+        }))
+    }
+
+    /// Sets the password of the user, adding the user when missing.
+    pub async fn set_user_credentials(
+        &self,
+        user: &str,
+        credentials: &UserCredentials,
+    ) -> anyhow::Result<()> {
+        self.pg
+            .execute(
+                "INSERT INTO public.nexus_users
+                (name, password_md5, scram_salt, scram_salted_password)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (name) DO UPDATE SET password_md5 = EXCLUDED.password_md5,
+                scram_salt = EXCLUDED.scram_salt,
+                scram_salted_password = EXCLUDED.scram_salted_password,
+                updated_at = now()",
+                &[
+                    &user,
+                    &credentials.password_md5,
+                    &credentials.scram_salt,
+                    &credentials.scram_salted_password,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, user: &str) -> anyhow::Result<bool> {
+        let deleted = self
+            .pg
+            .execute("DELETE FROM public.nexus_users WHERE name = $1", &[&user])
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    pub async fn insert_async_job(
+        &self,
+        id: &str,
+        query: &str,
+        peer: Option<&str>,
+        submitted_by: &str,
+    ) -> anyhow::Result<()> {
+        self.pg
+            .execute(
+                "INSERT INTO public.nexus_async_jobs (id, query, peer, submitted_by, status)
+                VALUES ($1, $2, $3, $4, 'running')",
+                &[&id, &query, &peer, &submitted_by],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn finish_async_job(
+        &self,
+        id: &str,
+        status: &str,
+        rows: i64,
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.pg
+            .execute(
+                "UPDATE public.nexus_async_jobs
+                SET status = $2, rows = $3, error = $4, finished_at = now() WHERE id = $1",
+                &[&id, &status, &rows, &error],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_async_job(&self, id: &str) -> anyhow::Result<Option<AsyncJob>> {
+        let row = self
+            .pg
+            .query_opt(
+                "SELECT status, peer, rows, error FROM public.nexus_async_jobs WHERE id = $1",
+                &[&id],
+            )
+            .await?;
+        Ok(row.map(|row| AsyncJob {
+            status: row.get(0),
+            peer: row.get(1),
+            rows: row.get(2),
+            error: row.get(3),
+        }))
+    }
+
+    /// Marks the jobs left running by a previous run of nexus as failed,
+    /// returning how many there were.
+    pub async fn fail_unfinished_async_jobs(&self) -> anyhow::Result<u64> {
+        let failed = self
+            .pg
+            .execute(
+                "UPDATE public.nexus_async_jobs
+                SET status = 'failed', error = 'nexus restarted before the job finished',
+                    finished_at = now()
+                WHERE status = 'running'",
+                &[],
+            )
+            .await?;
+        Ok(failed)
+    }
+
     pub async fn check_peer_entry(&self, peer_name: &str) -> anyhow::Result<i64> {
         let peer_check = self
             .pg
@@ -493,6 +916,22 @@ impl Catalog {
         Ok(peer_count)
     }
 
+    /// Removes the peer, and the connection history kept for it, returning
+    /// false when there was no such peer.
+    pub async fn drop_peer(&self, peer_name: &str) -> anyhow::Result<bool> {
+        let deleted = self
+            .pg
+            .execute(
+                "WITH connections AS (
+                    DELETE FROM public.peer_connections WHERE peer_name = $1
+                )
+                DELETE FROM public.peers WHERE name = $1",
+                &[&peer_name],
+            )
+            .await?;
+        Ok(deleted > 0)
+    }
+
     pub async fn get_qrep_config_proto(
         &self,
         flow_job_name: &str,
@@ -518,10 +957,41 @@ impl Catalog {
 impl QueryExecutor for Catalog {
     #[tracing::instrument(skip(self, stmt), fields(stmt = %stmt))]
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
-        peer_postgres::pg_execute(&self.pg, ast::PostgresAst { peername: None }, stmt).await
+        // catalog queries use the lenient default
+        peer_postgres::pg_execute(
+            &self.pg,
+            ast::PostgresAst { peername: None },
+            stmt,
+            InvalidUtf8::default(),
+        )
+        .await
+    }
+
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        peer_postgres::pg_move(&self.pg, name, direction).await
     }
 
     async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
         peer_postgres::pg_describe(&self.pg, stmt).await
     }
+
+    async fn parameter_types(&self, stmt: &Statement) -> PgWireResult<Option<Vec<Type>>> {
+        peer_postgres::pg_parameter_types(&self.pg, ast::PostgresAst { peername: None }, stmt).await
+    }
+
+    async fn describe_table(&self, schema: Option<&str>, table: &str) -> PgWireResult<QueryOutput> {
+        peer_postgres::pg_describe_table(&self.pg, schema, table).await
+    }
+
+    async fn dry_run(&self, stmt: &Statement) -> PgWireResult<DryRun> {
+        peer_postgres::pg_dry_run(&self.pg, ast::PostgresAst { peername: None }, stmt).await
+    }
+
+    async fn copy_out_binary(&self, stmt: &Statement) -> PgWireResult<Option<BinaryCopy>> {
+        peer_postgres::pg_copy_out_binary(&self.pg, ast::PostgresAst { peername: None }, stmt).await
+    }
+
+    async fn cancel(&self) -> PgWireResult<()> {
+        peer_postgres::pg_cancel(&self.pg).await
+    }
 }
@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use pt::peerdb_peers::Peer;
+
+use crate::Catalog;
+
+/// The peers of the catalog, shared by the connections of a nexus so routing
+/// a statement doesn't read the peers table each time. The peers are read
+/// again once they are older than the ttl, and right away after nexus creates
+/// or drops a peer, which invalidates them.
+pub struct PeerCache {
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    peers: Option<(HashMap<String, Peer>, Instant)>,
+    // bumped by every invalidation, so peers read before one aren't cached
+    // after it
+    generation: u64,
+}
+
+impl PeerCache {
+    /// A ttl of zero reads the peers from the catalog every time.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub async fn get_peers(&self, catalog: &Catalog) -> anyhow::Result<HashMap<String, Peer>> {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if let Some((peers, loaded_at)) = &state.peers {
+                if loaded_at.elapsed() < self.ttl {
+                    return Ok(peers.clone());
+                }
+            }
+            state.generation
+        };
+
+        let peers = catalog.get_peers().await?;
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation && !self.ttl.is_zero() {
+            state.peers = Some((peers.clone(), Instant::now()));
+        }
+        Ok(peers)
+    }
+
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.peers = None;
+        state.generation += 1;
+    }
+}
@@ -11,6 +11,11 @@ pub enum PeerCreationResult {
     Failed(String),
 }
 
+pub enum PeerValidationResult {
+    Valid,
+    Invalid(String),
+}
+
 pub struct FlowGrpcClient {
     client: peerdb_route::flow_service_client::FlowServiceClient<tonic::transport::Channel>,
     health_client: health_client::HealthClient<tonic::transport::Channel>,
@@ -303,6 +308,19 @@ impl FlowGrpcClient {
         }
     }
 
+    pub async fn validate_peer(
+        &mut self,
+        validate_request: pt::peerdb_route::ValidatePeerRequest,
+    ) -> anyhow::Result<PeerValidationResult> {
+        let response = self.client.validate_peer(validate_request).await?;
+        let response_body = response.into_inner();
+        if response_body.status == pt::peerdb_route::ValidatePeerStatus::Valid as i32 {
+            Ok(PeerValidationResult::Valid)
+        } else {
+            Ok(PeerValidationResult::Invalid(response_body.message))
+        }
+    }
+
     pub async fn resync_mirror(&mut self, flow_job_name: &str) -> anyhow::Result<()> {
         let resync_mirror_req = pt::peerdb_route::ResyncMirrorRequest {
             flow_job_name: flow_job_name.to_owned(),
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pt::peerdb_peers::Peer;
+use sqlparser::{
+    ast::{Ident, ObjectName},
+    keywords::Keyword,
+    tokenizer::{Token, Tokenizer, Word},
+};
+
+use crate::DIALECT;
+
+/// `IMPORT INTO peer.[schema.]table [(column, ...)] FROM CSV 'data'
+/// [WITH (HEADER [true|false], DELIMITER 'c')]`, loading CSV sent by the
+/// client into a table of any peer.
+#[derive(Debug, Clone)]
+pub struct CsvImport {
+    pub peer: Box<Peer>,
+    /// The table name without the peer.
+    pub table: ObjectName,
+    pub columns: Vec<Ident>,
+    pub header: bool,
+    pub delimiter: u8,
+    pub data: String,
+}
+
+fn syntax_error(message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "42601".to_owned(),
+        message,
+    )))
+}
+
+fn is_word(token: Option<&Token>, word: &str) -> bool {
+    match token {
+        Some(Token::Word(w)) => w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word),
+        _ => false,
+    }
+}
+
+/// Whether the sql is an IMPORT statement, which sqlparser doesn't know.
+pub fn is_import(sql: &str) -> bool {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().unwrap_or_default();
+    let first = tokens
+        .iter()
+        .find(|token| !matches!(token, Token::Whitespace(_)));
+    is_word(first, "import")
+}
+
+struct ImportParser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl ImportParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn found(&self) -> String {
+        self.peek()
+            .map_or("end of statement".to_owned(), |token| token.to_string())
+    }
+
+    fn consume(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_word(&mut self, word: &str) -> bool {
+        if is_word(self.peek(), word) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: &Token) -> PgWireResult<()> {
+        if self.consume(expected) {
+            Ok(())
+        } else {
+            Err(syntax_error(format!(
+                "expected {} in IMPORT, found {}",
+                expected,
+                self.found()
+            )))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> PgWireResult<()> {
+        if self.consume_word(word) {
+            Ok(())
+        } else {
+            Err(syntax_error(format!(
+                "expected {} in IMPORT, found {}",
+                word.to_uppercase(),
+                self.found()
+            )))
+        }
+    }
+
+    fn ident(&mut self) -> PgWireResult<Ident> {
+        match self.peek() {
+            Some(Token::Word(Word {
+                value, quote_style, ..
+            })) => {
+                let ident = Ident {
+                    value: value.clone(),
+                    quote_style: *quote_style,
+                };
+                self.index += 1;
+                Ok(ident)
+            }
+            _ => Err(syntax_error(format!(
+                "expected an identifier in IMPORT, found {}",
+                self.found()
+            ))),
+        }
+    }
+
+    fn string(&mut self) -> PgWireResult<String> {
+        match self.next() {
+            Some(Token::SingleQuotedString(s)) | Some(Token::EscapedStringLiteral(s)) => Ok(s),
+            Some(Token::DollarQuotedString(s)) => Ok(s.value),
+            _ => {
+                self.index -= 1;
+                Err(syntax_error(format!(
+                    "expected a string in IMPORT, found {}",
+                    self.found()
+                )))
+            }
+        }
+    }
+
+    fn boolean(&mut self) -> PgWireResult<bool> {
+        let value = match self.next() {
+            Some(Token::Word(w)) if w.keyword == Keyword::TRUE => true,
+            Some(Token::Word(w)) if w.keyword == Keyword::FALSE => false,
+            Some(Token::Number(n, _)) if n == "1" || n == "0" => n == "1",
+            Some(Token::SingleQuotedString(s)) if s == "true" || s == "false" => s == "true",
+            _ => {
+                self.index -= 1;
+                return Err(syntax_error(format!(
+                    "expected a boolean in IMPORT, found {}",
+                    self.found()
+                )));
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Parses an IMPORT statement, resolving the peer from the first part of the
+/// table name or using the default peer for unqualified tables.
+pub fn parse_import(
+    sql: &str,
+    peers: &HashMap<String, Peer>,
+    default_peer: Option<&str>,
+) -> PgWireResult<CsvImport> {
+    let tokens = Tokenizer::new(&DIALECT, sql)
+        .tokenize()
+        .map_err(|err| syntax_error(err.to_string()))?
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect();
+    let mut parser = ImportParser { tokens, index: 0 };
+
+    parser.expect_word("import")?;
+    parser.expect_word("into")?;
+    let mut name = vec![parser.ident()?];
+    while parser.consume(&Token::Period) {
+        name.push(parser.ident()?);
+    }
+
+    let mut columns = Vec::new();
+    if parser.consume(&Token::LParen) {
+        loop {
+            columns.push(parser.ident()?);
+            if !parser.consume(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect(&Token::RParen)?;
+    }
+
+    parser.expect_word("from")?;
+    parser.expect_word("csv")?;
+    let data = parser.string()?;
+
+    let mut header = false;
+    let mut delimiter = b',';
+    if parser.consume_word("with") {
+        parser.expect(&Token::LParen)?;
+        loop {
+            if parser.consume_word("header") {
+                header = match parser.peek() {
+                    Some(Token::Comma) | Some(Token::RParen) => true,
+                    _ => parser.boolean()?,
+                };
+            } else if parser.consume_word("delimiter") {
+                let value = parser.string()?;
+                delimiter = match value.as_bytes() {
+                    [delimiter] => *delimiter,
+                    _ => {
+                        return Err(syntax_error(
+                            "IMPORT delimiter must be a single one-byte character".to_owned(),
+                        ))
+                    }
+                };
+            } else {
+                return Err(syntax_error(format!(
+                    "unknown IMPORT option {}",
+                    parser.found()
+                )));
+            }
+            if !parser.consume(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect(&Token::RParen)?;
+    }
+    parser.consume(&Token::SemiColon);
+    if parser.peek().is_some() {
+        return Err(syntax_error(format!(
+            "unexpected {} at end of IMPORT",
+            parser.found()
+        )));
+    }
+
+    let qualified = (name.len() > 1)
+        .then(|| peers.get(&name[0].value.to_lowercase()))
+        .flatten();
+    let (peer, table) = match (qualified, default_peer) {
+        (Some(peer), _) => (peer, name[1..].to_vec()),
+        (None, Some(default_peer)) => {
+            let peer = peers.get(default_peer).ok_or_else(|| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42704".to_owned(),
+                    format!("default peer \"{}\" does not exist", default_peer),
+                )))
+            })?;
+            (peer, name)
+        }
+        (None, None) => {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42704".to_owned(),
+                format!(
+                    "IMPORT target {} is not qualified with a peer",
+                    ObjectName(name)
+                ),
+            ))))
+        }
+    };
+
+    Ok(CsvImport {
+        peer: Box::new(peer.clone()),
+        table: ObjectName(table),
+        columns,
+        header,
+        delimiter,
+        data,
+    })
+}
@@ -1,22 +1,48 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
 
 use analyzer::{
-    CursorEvent, PeerCursorAnalyzer, PeerDDL, PeerDDLAnalyzer, PeerExistanceAnalyzer,
-    QueryAssociation, StatementAnalyzer,
+    Builtin, BuiltinAnalyzer, CursorEvent, PeerCursorAnalyzer, PeerDDL, PeerDDLAnalyzer,
+    PeerExistanceAnalyzer, QueryAssociation, SessionVariable, SessionVariableAnalyzer,
+    StatementAnalyzer, UnknownDefaultPeer,
 };
 use async_trait::async_trait;
-use catalog::Catalog;
+use catalog::{Catalog, PeerCache};
 use pgwire::{
     api::{stmt::QueryParser, Type},
     error::{ErrorInfo, PgWireError, PgWireResult},
 };
-use sqlparser::{ast::Statement, dialect::PostgreSqlDialect, parser::Parser};
+use pt::peerdb_peers::DbType;
+use sqlparser::{
+    ast::{Ident, ObjectType, Query, Statement},
+    dialect::PostgreSqlDialect,
+    keywords::Keyword,
+    parser::{IsOptional, Parser, ParserError},
+    tokenizer::{Token, Tokenizer, Whitespace},
+};
+
+mod import;
+mod rewrite_rules;
+mod temp_views;
+
+pub use import::CsvImport;
+pub use rewrite_rules::{RewriteRule, RewriteRules};
+pub use temp_views::TempViews;
 
 const DIALECT: PostgreSqlDialect = PostgreSqlDialect {};
 
 #[derive(Clone)]
 pub struct NexusQueryParser {
     catalog: Arc<Catalog>,
+    peer_cache: Arc<PeerCache>,
+    // peer that queries of unqualified tables are routed to, if any.
+    default_peer: Arc<RwLock<Option<String>>>,
+    // views created with CREATE TEMP VIEW on this connection.
+    temp_views: Arc<RwLock<TempViews>>,
+    // rewrite rules of the peers, shared by all connections.
+    rewrite_rules: Arc<RewriteRules>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,9 +59,73 @@ pub enum NexusStatement {
         stmt: Statement,
         cursor: CursorEvent,
     },
+    Builtin {
+        stmt: Statement,
+        builtin: Builtin,
+    },
+    SetVariable {
+        stmt: Statement,
+        name: String,
+        value: Option<String>,
+    },
+    ShowVariable {
+        stmt: Statement,
+        name: String,
+    },
+    /// `SET SESSION CHARACTERISTICS AS TRANSACTION ...`, the variables it sets
+    /// by name.
+    SetCharacteristics {
+        stmt: Statement,
+        settings: Vec<(String, String)>,
+    },
     Rollback {
         stmt: Statement,
     },
+    Import {
+        import: Box<CsvImport>,
+    },
+    /// `RESET name`, or `RESET ALL` when `name` is None.
+    ResetVariable {
+        name: Option<String>,
+    },
+    /// `TEST PEER name`, connects to the peer and runs a probe query.
+    TestPeer {
+        peer: Box<pt::peerdb_peers::Peer>,
+    },
+    /// `SHOW PEERS [LIKE 'pattern']`, the registered peers, read from the
+    /// catalog.
+    ShowPeers {
+        pattern: Option<String>,
+    },
+    /// `EXPORT SCHEMA FROM PEER name [IN SCHEMA schema]`, every column of
+    /// every table of the peer, or of one of its schemas.
+    ExportSchema {
+        peer: Box<pt::peerdb_peers::Peer>,
+        schema: Option<String>,
+    },
+    /// `COMPARE (query) WITH (query) [USING (column, ...)]`, runs both
+    /// queries and summarizes how their rows differ. Rows are matched on the
+    /// `USING` columns when given, else on all of their columns.
+    Compare {
+        left: Box<ComparedQuery>,
+        right: Box<ComparedQuery>,
+        key: Vec<Ident>,
+    },
+    /// `CREATE TEMP VIEW`, a view of this connection that nexus inlines into
+    /// the statements referring to it.
+    CreateTempView {
+        stmt: Statement,
+        name: String,
+        columns: Vec<Ident>,
+        query: Box<Query>,
+        or_replace: bool,
+    },
+    /// `DROP VIEW` of temporary views only.
+    DropTempViews {
+        stmt: Statement,
+        names: Vec<String>,
+        if_exists: bool,
+    },
     Empty,
 }
 
@@ -43,11 +133,14 @@ impl NexusStatement {
     pub fn new(
         peers: HashMap<String, pt::peerdb_peers::Peer>,
         stmt: &Statement,
+        peer_hint: Option<&str>,
+        default_peer: Option<&str>,
     ) -> PgWireResult<Self> {
+        // malformed peer and mirror options, like a missing required option
         let ddl = PeerDDLAnalyzer.analyze(stmt).map_err(|e| {
             PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "internal_error".to_owned(),
+                "42601".to_owned(),
                 e.to_string(),
             )))
         })?;
@@ -59,6 +152,41 @@ impl NexusStatement {
             });
         }
 
+        let builtin = BuiltinAnalyzer.analyze(stmt).map_err(|e| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "22023".to_owned(),
+                e.to_string(),
+            )))
+        })?;
+
+        if let Some(builtin) = builtin {
+            return Ok(NexusStatement::Builtin {
+                stmt: stmt.clone(),
+                builtin,
+            });
+        }
+
+        if let Ok(Some(variable)) = SessionVariableAnalyzer.analyze(stmt) {
+            return Ok(match variable {
+                SessionVariable::Set { name, value } => NexusStatement::SetVariable {
+                    stmt: stmt.clone(),
+                    name,
+                    value,
+                },
+                SessionVariable::Show { name } => NexusStatement::ShowVariable {
+                    stmt: stmt.clone(),
+                    name,
+                },
+                SessionVariable::SetCharacteristics { settings } => {
+                    NexusStatement::SetCharacteristics {
+                        stmt: stmt.clone(),
+                        settings,
+                    }
+                }
+            });
+        }
+
         if let Ok(Some(cursor)) = PeerCursorAnalyzer.analyze(stmt) {
             return Ok(NexusStatement::PeerCursor {
                 stmt: stmt.clone(),
@@ -66,12 +194,33 @@ impl NexusStatement {
             });
         }
 
+        // a routing hint overrides both the peers named in the query and the
+        // default peer.
+        if let Some(peer_hint) = peer_hint {
+            let peer = peers.get(peer_hint).ok_or_else(|| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42704".to_owned(),
+                    format!("peer \"{}\" in routing hint does not exist", peer_hint),
+                )))
+            })?;
+            return Ok(NexusStatement::PeerQuery {
+                stmt: stmt.clone(),
+                assoc: QueryAssociation::Peer(Box::new(peer.clone())),
+            });
+        }
+
         let assoc = {
-            let pea = PeerExistanceAnalyzer::new(&peers);
+            let pea = PeerExistanceAnalyzer::new(&peers).with_default_peer(default_peer);
             pea.analyze(stmt).map_err(|e| {
+                let code = if e.is::<UnknownDefaultPeer>() {
+                    "42704"
+                } else {
+                    "0A000"
+                };
                 PgWireError::UserError(Box::new(ErrorInfo::new(
                     "ERROR".to_owned(),
-                    "feature_not_supported".to_owned(),
+                    code.to_owned(),
                     e.to_string(),
                 )))
             })
@@ -82,34 +231,847 @@ impl NexusStatement {
             assoc,
         })
     }
+
+    /// The parsed sql statement backing this nexus statement, if any.
+    pub fn ast(&self) -> Option<&Statement> {
+        match self {
+            NexusStatement::PeerDDL { stmt, .. }
+            | NexusStatement::PeerQuery { stmt, .. }
+            | NexusStatement::PeerCursor { stmt, .. }
+            | NexusStatement::Builtin { stmt, .. }
+            | NexusStatement::SetVariable { stmt, .. }
+            | NexusStatement::ShowVariable { stmt, .. }
+            | NexusStatement::SetCharacteristics { stmt, .. }
+            | NexusStatement::Rollback { stmt }
+            | NexusStatement::CreateTempView { stmt, .. }
+            | NexusStatement::DropTempViews { stmt, .. } => Some(stmt),
+            NexusStatement::Import { .. }
+            | NexusStatement::ResetVariable { .. }
+            | NexusStatement::TestPeer { .. }
+            | NexusStatement::ShowPeers { .. }
+            | NexusStatement::ExportSchema { .. }
+            | NexusStatement::Compare { .. }
+            | NexusStatement::Empty => None,
+        }
+    }
+}
+
+// sqlparser doesn't accept CASCADE or RESTRICT after DROP PEER, so they are
+// split off before parsing and applied to the analyzed statement. Returns the
+// sql without the keyword and whether it was CASCADE.
+fn split_drop_peer_behavior(sql: &str) -> Option<(String, bool)> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let significant = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token, Token::Whitespace(_) | Token::SemiColon))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let word = |i: usize| match &tokens[i] {
+        Token::Word(word) => Some(word),
+        _ => None,
+    };
+
+    let (first, second, last) = match significant[..] {
+        [first, second, _, .., last] => (first, second, last),
+        _ => return None,
+    };
+    if !word(first).is_some_and(|w| w.keyword == Keyword::DROP)
+        || !word(second).is_some_and(|w| w.value.eq_ignore_ascii_case("peer"))
+    {
+        return None;
+    }
+    let cascade = match word(last)?.keyword {
+        Keyword::CASCADE => true,
+        Keyword::RESTRICT => false,
+        _ => return None,
+    };
+
+    let sql = tokens
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != last)
+        .map(|(_, token)| token.to_string())
+        .collect();
+    Some((sql, cascade))
+}
+
+// `TEST PEER name` isn't sql either, returns the name of the peer.
+fn test_peer(sql: &str) -> Option<String> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let mut significant = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    if significant.last() == Some(&&Token::SemiColon) {
+        significant.pop();
+    }
+
+    match significant.as_slice() {
+        [Token::Word(test), Token::Word(peer), Token::Word(name)]
+            if test.value.eq_ignore_ascii_case("test")
+                && peer.value.eq_ignore_ascii_case("peer") =>
+        {
+            Some(match name.quote_style {
+                Some(_) => name.value.clone(),
+                None => name.value.to_lowercase(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// sqlparser doesn't know ALTER PEER either, `ALTER PEER name [FROM type] SET
+// CONFIG (option = value, ...)` is parsed as the CREATE PEER of the same
+// options. Returns the peer, the type it was given if any and the sql of the
+// options.
+fn alter_peer(sql: &str) -> Option<(String, Option<String>, String)> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let significant = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+
+    let is_keyword = |token: &Token, keyword: &str| match token {
+        Token::Word(word) => word.quote_style.is_none() && word.value.eq_ignore_ascii_case(keyword),
+        _ => false,
+    };
+    let (statement, rest) = significant.split_at(significant.len().min(3));
+    let [(_, alter), (_, peer), (_, Token::Word(name))] = statement else {
+        return None;
+    };
+    if !is_keyword(alter, "alter") || !is_keyword(peer, "peer") {
+        return None;
+    }
+    let (peer_type, rest) = match rest {
+        [(_, from), (_, Token::Word(peer_type)), rest @ ..] if is_keyword(from, "from") => {
+            (Some(peer_type.value.clone()), rest)
+        }
+        rest => (None, rest),
+    };
+    let [(_, set), (_, config), (options, _), ..] = rest else {
+        return None;
+    };
+    if !is_keyword(set, "set") || !is_keyword(config, "config") {
+        return None;
+    }
+    let name = match name.quote_style {
+        Some(_) => name.value.clone(),
+        None => name.value.to_lowercase(),
+    };
+    let options = tokens[*options..].iter().map(ToString::to_string).collect();
+    Some((name, peer_type, options))
+}
+
+// `SHOW PEERS [LIKE 'pattern']` isn't sql either, sqlparser takes it for a
+// variable. Returns the pattern.
+fn show_peers(sql: &str) -> Option<Option<String>> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let mut significant = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    if significant.last() == Some(&&Token::SemiColon) {
+        significant.pop();
+    }
+
+    let is_keyword = |token: &Token, keyword: &str| match token {
+        Token::Word(word) => word.quote_style.is_none() && word.value.eq_ignore_ascii_case(keyword),
+        _ => false,
+    };
+    match significant.as_slice() {
+        [show, peers] if is_keyword(show, "show") && is_keyword(peers, "peers") => Some(None),
+        [show, peers, like, Token::SingleQuotedString(pattern)]
+            if is_keyword(show, "show")
+                && is_keyword(peers, "peers")
+                && is_keyword(like, "like") =>
+        {
+            Some(Some(pattern.clone()))
+        }
+        _ => None,
+    }
+}
+
+// `EXPORT SCHEMA FROM PEER name [IN SCHEMA schema]`, returns the peer and
+// the schema to export.
+fn export_schema(sql: &str) -> Option<(String, Option<String>)> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let mut significant = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    if significant.last() == Some(&&Token::SemiColon) {
+        significant.pop();
+    }
+
+    let is_keyword = |token: &Token, keyword: &str| match token {
+        Token::Word(word) => word.quote_style.is_none() && word.value.eq_ignore_ascii_case(keyword),
+        _ => false,
+    };
+    let name = |token: &Token| match token {
+        Token::Word(word) if word.quote_style.is_some() => Some(word.value.clone()),
+        Token::Word(word) => Some(word.value.to_lowercase()),
+        _ => None,
+    };
+    let (statement, filter) = significant.split_at(significant.len().min(5));
+    let keywords = ["export", "schema", "from", "peer"];
+    if statement.len() != 5
+        || !keywords
+            .iter()
+            .zip(statement)
+            .all(|(keyword, token)| is_keyword(token, keyword))
+    {
+        return None;
+    }
+    let peer = name(statement[4])?;
+    match filter {
+        [] => Some((peer, None)),
+        [in_, schema, schema_name] if is_keyword(in_, "in") && is_keyword(schema, "schema") => {
+            Some((peer, Some(name(schema_name)?)))
+        }
+        _ => None,
+    }
+}
+
+// `COMPARE (query) WITH (query) [USING (column, ...)]`, None for other
+// statements.
+fn compare(sql: &str) -> Option<PgWireResult<(Statement, Statement, Vec<Ident>)>> {
+    let mut parser = Parser::new(&DIALECT).try_with_sql(sql).ok()?;
+    match parser.peek_token().token {
+        Token::Word(word)
+            if word.quote_style.is_none() && word.value.eq_ignore_ascii_case("compare") =>
+        {
+            parser.next_token();
+        }
+        _ => return None,
+    }
+    Some(parse_compare(&mut parser).map_err(|err| syntax_error(sql, err)))
+}
+
+fn parse_compare(parser: &mut Parser) -> Result<(Statement, Statement, Vec<Ident>), ParserError> {
+    let left = parse_compared_query(parser)?;
+    parser.expect_keyword(Keyword::WITH)?;
+    let right = parse_compared_query(parser)?;
+    let key = if parser.parse_keyword(Keyword::USING) {
+        parser.parse_parenthesized_column_list(IsOptional::Mandatory, false)?
+    } else {
+        Vec::new()
+    };
+    while parser.consume_token(&Token::SemiColon) {}
+    if parser.peek_token().token != Token::EOF {
+        return parser.expected("end of statement", parser.peek_token());
+    }
+    Ok((left, right, key))
+}
+
+fn parse_compared_query(parser: &mut Parser) -> Result<Statement, ParserError> {
+    parser.expect_token(&Token::LParen)?;
+    let stmt = parser.parse_statement()?;
+    if !matches!(stmt, Statement::Query(_)) {
+        return Err(ParserError::ParserError(
+            "COMPARE takes two queries".to_owned(),
+        ));
+    }
+    parser.expect_token(&Token::RParen)?;
+    Ok(stmt)
+}
+
+// sqlparser doesn't know MOVE, which reads like FETCH, so it is parsed as
+// the FETCH with the same direction. Returns the sql of that FETCH.
+fn move_as_fetch(sql: &str) -> Option<String> {
+    let mut tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let first = tokens
+        .iter_mut()
+        .find(|token| !matches!(token, Token::Whitespace(_)))?;
+    match first {
+        Token::Word(word)
+            if word.value.eq_ignore_ascii_case("move") && word.quote_style.is_none() =>
+        {
+            *first = Token::make_keyword("FETCH");
+        }
+        _ => return None,
+    }
+    Some(tokens.iter().map(Token::to_string).collect())
+}
+
+// sqlparser doesn't know RESET, so `RESET name` and `RESET ALL` are read from
+// the tokens. Returns the variable, None for ALL.
+fn reset_variable(sql: &str) -> Option<Option<String>> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().ok()?;
+    let mut significant = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    if significant.last() == Some(&&Token::SemiColon) {
+        significant.pop();
+    }
+
+    let (first, name) = significant.split_first()?;
+    if !matches!(first, Token::Word(word) if word.keyword == Keyword::RESET) {
+        return None;
+    }
+    match name {
+        [Token::Word(word)] if word.keyword == Keyword::ALL && word.quote_style.is_none() => {
+            Some(None)
+        }
+        // name, or a dotted name like peerdb.dry_run
+        [Token::Word(_), ..] if name.len() % 2 == 1 => {
+            let mut parts = Vec::with_capacity(name.len() / 2 + 1);
+            for (i, token) in name.iter().enumerate() {
+                match token {
+                    Token::Word(word) if i % 2 == 0 => parts.push(word.value.to_lowercase()),
+                    Token::Period if i % 2 == 1 => {}
+                    _ => return None,
+                }
+            }
+            Some(Some(parts.join(".")))
+        }
+        _ => None,
+    }
+}
+
+// hints in a comment ahead of the statement
+#[derive(Default)]
+struct QueryHints {
+    peer: Option<String>,
+    timeout: Option<String>,
+}
+
+/// Reads the hints of a query from a comment ahead of the statement, the
+/// routing hint `/*+ peer(name) */` and the statement timeout hint
+/// `/*+ timeout(5s) */`. Other hints in the comment are ignored, peers are
+/// sent the statement without its comments.
+fn query_hints(sql: &str) -> PgWireResult<QueryHints> {
+    let tokens = Tokenizer::new(&DIALECT, sql).tokenize().unwrap_or_default();
+    let comments = tokens.iter().map_while(|token| match token {
+        Token::Whitespace(whitespace) => Some(whitespace),
+        _ => None,
+    });
+    let mut query_hints = QueryHints::default();
+    for comment in comments {
+        let Whitespace::MultiLineComment(comment) = comment else {
+            continue;
+        };
+        let Some(hints) = comment.strip_prefix('+') else {
+            continue;
+        };
+        for hint in hints.split_inclusive(')') {
+            let Some((name, args)) = hint.split_once('(') else {
+                continue;
+            };
+            let name = name.trim();
+            let arg = args.trim_end_matches(')').trim();
+            let invalid = |kind: &str| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42601".to_owned(),
+                    format!("invalid {} hint: {}", kind, hint.trim()),
+                )))
+            };
+            if name.eq_ignore_ascii_case("peer") && query_hints.peer.is_none() {
+                let peer = match arg.strip_prefix('"').and_then(|p| p.strip_suffix('"')) {
+                    Some(quoted) => quoted.to_owned(),
+                    None => arg.to_lowercase(),
+                };
+                if peer.is_empty() || !hint.ends_with(')') {
+                    return Err(invalid("routing"));
+                }
+                query_hints.peer = Some(peer);
+            } else if name.eq_ignore_ascii_case("timeout") && query_hints.timeout.is_none() {
+                if arg.is_empty() || !hint.ends_with(')') {
+                    return Err(invalid("timeout"));
+                }
+                query_hints.timeout = Some(arg.to_owned());
+            }
+        }
+    }
+    Ok(query_hints)
+}
+
+fn parse_statements(sql: &str) -> PgWireResult<(Vec<Statement>, Option<bool>)> {
+    let (stmts, cascade) = match split_drop_peer_behavior(sql) {
+        Some((sql, cascade)) => (Parser::parse_sql(&DIALECT, &sql), Some(cascade)),
+        None => (Parser::parse_sql(&DIALECT, sql), None),
+    };
+    let stmts = stmts.map_err(|err| syntax_error(sql, err))?;
+    Ok((stmts, cascade))
+}
+
+// a parse error as postgres reports it, with the position of the offending
+// token for clients to point at.
+fn syntax_error(sql: &str, err: ParserError) -> PgWireError {
+    let (code, message) = match err {
+        ParserError::ParserError(message) | ParserError::TokenizerError(message) => {
+            ("42601", message)
+        }
+        ParserError::RecursionLimitExceeded => {
+            ("54001", "statement is too deeply nested".to_owned())
+        }
+    };
+    let (message, position) = match message.rfind(" at Line: ") {
+        Some(at) => (
+            message[..at].to_owned(),
+            error_position(sql, &message[at + " at Line: ".len()..]),
+        ),
+        None => (message, None),
+    };
+    let mut info = ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        format!("syntax error: {}", message),
+    );
+    info.set_position(position.map(|position| position.to_string()));
+    PgWireError::UserError(Box::new(info))
+}
+
+// the 1-based character offset into the query of a `<line>, Column[:] <col>`
+// location, None when it doesn't point into the query.
+fn error_position(sql: &str, location: &str) -> Option<usize> {
+    let (line, column) = location.split_once(',')?;
+    let line = line.trim().parse::<usize>().ok()?;
+    let column = column
+        .trim_start()
+        .strip_prefix("Column")?
+        .trim_start_matches(|c: char| c == ':' || c.is_whitespace())
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse::<usize>()
+        .ok()?;
+    if line == 0 || column == 0 {
+        return None;
+    }
+
+    let preceding = sql
+        .split('\n')
+        .take(line - 1)
+        .map(|line| line.chars().count() + 1)
+        .sum::<usize>();
+    let position = preceding.checked_add(column)?;
+    // errors at the end of the query point just past it
+    (position <= sql.chars().count() + 1).then_some(position)
+}
+
+/// A query of `COMPARE`, with the peer it runs on.
+#[derive(Debug, Clone)]
+pub struct ComparedQuery {
+    pub stmt: Statement,
+    pub assoc: QueryAssociation,
 }
 
 #[derive(Debug, Clone)]
 pub struct NexusParsedStatement {
     pub statement: NexusStatement,
     pub query: String,
+    /// The argument of a `/*+ timeout(5s) */` hint, the statement timeout of
+    /// just this statement.
+    pub timeout_hint: Option<String>,
+    /// Types of the parameters, set the first time the statement is described
+    /// or executed so the peer is asked to infer them once.
+    pub parameter_types: OnceLock<Vec<Type>>,
 }
 
 impl NexusQueryParser {
-    pub fn new(catalog: Arc<Catalog>) -> Self {
-        Self { catalog }
+    pub fn new(
+        catalog: Arc<Catalog>,
+        peer_cache: Arc<PeerCache>,
+        default_peer: Option<String>,
+        rewrite_rules: Arc<RewriteRules>,
+    ) -> Self {
+        Self {
+            catalog,
+            peer_cache,
+            default_peer: Arc::new(RwLock::new(default_peer)),
+            temp_views: Arc::new(RwLock::new(TempViews::default())),
+            rewrite_rules,
+        }
+    }
+
+    pub fn set_default_peer(&self, default_peer: Option<String>) {
+        *self.default_peer.write().unwrap() = default_peer;
+    }
+
+    pub fn create_temp_view(
+        &self,
+        name: &str,
+        columns: Vec<Ident>,
+        query: Query,
+        or_replace: bool,
+    ) -> PgWireResult<()> {
+        let mut temp_views = self.temp_views.write().unwrap();
+        temp_views.create(name, columns, query, or_replace)
+    }
+
+    pub fn drop_temp_views(&self, names: &[String], if_exists: bool) -> PgWireResult<()> {
+        let mut temp_views = self.temp_views.write().unwrap();
+        if !if_exists {
+            if let Some(name) = names.iter().find(|name| !temp_views.contains(name)) {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42P01".to_owned(),
+                    format!("view \"{}\" does not exist", name),
+                ))));
+            }
+        }
+        for name in names {
+            temp_views.drop(name);
+        }
+        Ok(())
+    }
+
+    fn new_statement(
+        &self,
+        peers: HashMap<String, pt::peerdb_peers::Peer>,
+        stmt: &Statement,
+        peer_hint: Option<&str>,
+        drop_cascade: Option<bool>,
+    ) -> PgWireResult<NexusStatement> {
+        let default_peer = self.default_peer.read().unwrap().clone();
+        if let Some(temp_view_stmt) =
+            self.temp_view_statement(&peers, stmt, default_peer.as_deref())?
+        {
+            return Ok(temp_view_stmt);
+        }
+
+        let mut stmt = stmt.clone();
+        self.temp_views.read().unwrap().expand(&mut stmt)?;
+        let mut nexus_stmt = NexusStatement::new(peers, &stmt, peer_hint, default_peer.as_deref())?;
+        if let NexusStatement::PeerQuery {
+            stmt,
+            assoc: QueryAssociation::Peer(peer),
+        } = &mut nexus_stmt
+        {
+            self.rewrite_rules.apply(&peer.name, stmt)?;
+        }
+        if let (NexusStatement::PeerDDL { ddl, .. }, Some(drop_cascade)) =
+            (&mut nexus_stmt, drop_cascade)
+        {
+            if let PeerDDL::DropPeer { cascade, .. } = ddl.as_mut() {
+                *cascade = drop_cascade;
+            }
+        }
+        Ok(nexus_stmt)
+    }
+
+    // CREATE TEMP VIEW, and DROP VIEW when all the views dropped are
+    // temporary, are handled by nexus instead of a peer.
+    fn temp_view_statement(
+        &self,
+        peers: &HashMap<String, pt::peerdb_peers::Peer>,
+        stmt: &Statement,
+        default_peer: Option<&str>,
+    ) -> PgWireResult<Option<NexusStatement>> {
+        match stmt {
+            Statement::CreateView {
+                or_replace,
+                materialized: false,
+                name,
+                columns,
+                query,
+                temporary: true,
+                ..
+            } => {
+                let view_name = TempViews::view_name(name).ok_or_else(|| {
+                    PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "42P16".to_owned(),
+                        format!(
+                            "temporary view \"{}\" can't be created in a peer or schema",
+                            name
+                        ),
+                    )))
+                })?;
+                let columns: Vec<Ident> =
+                    columns.iter().map(|column| column.name.clone()).collect();
+
+                // reject definitions that refer to themselves or to peers that
+                // don't exist before the view is created.
+                let mut temp_views = self.temp_views.read().unwrap().clone();
+                temp_views.create(&view_name, columns.clone(), *query.clone(), *or_replace)?;
+                let mut definition = Statement::Query(query.clone());
+                temp_views.expand(&mut definition)?;
+                NexusStatement::new(peers.clone(), &definition, None, default_peer)?;
+
+                Ok(Some(NexusStatement::CreateTempView {
+                    stmt: stmt.clone(),
+                    name: view_name,
+                    columns,
+                    query: query.clone(),
+                    or_replace: *or_replace,
+                }))
+            }
+            Statement::Drop {
+                object_type: ObjectType::View,
+                if_exists,
+                names,
+                ..
+            } => {
+                let temp_views = self.temp_views.read().unwrap();
+                let names: Option<Vec<String>> = names
+                    .iter()
+                    .map(|name| TempViews::view_name(name).filter(|name| temp_views.contains(name)))
+                    .collect();
+                Ok(names.map(|names| NexusStatement::DropTempViews {
+                    stmt: stmt.clone(),
+                    names,
+                    if_exists: *if_exists,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // IMPORT isn't sql sqlparser knows, so it's parsed on its own.
+    async fn parse_import(&self, sql: &str) -> PgWireResult<NexusParsedStatement> {
+        let peers = self.get_peers_bridge().await?;
+        let default_peer = self.default_peer.read().unwrap().clone();
+        let import = import::parse_import(sql, &peers, default_peer.as_deref())?;
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::Import {
+                import: Box::new(import),
+            },
+            query: sql.to_owned(),
+            timeout_hint: None,
+            parameter_types: OnceLock::new(),
+        })
+    }
+
+    async fn parse_test_peer(
+        &self,
+        sql: &str,
+        peer_name: &str,
+    ) -> PgWireResult<NexusParsedStatement> {
+        let mut peers = self.get_peers_bridge().await?;
+        let peer = peers.remove(peer_name).ok_or_else(|| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42704".to_owned(),
+                format!("peer \"{}\" does not exist", peer_name),
+            )))
+        })?;
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::TestPeer {
+                peer: Box::new(peer),
+            },
+            query: sql.to_owned(),
+            timeout_hint: None,
+            parameter_types: OnceLock::new(),
+        })
+    }
+
+    // the config is parsed for the type of the existing peer, which a type
+    // given with FROM has to match
+    async fn parse_alter_peer(
+        &self,
+        sql: &str,
+        peer_name: &str,
+        peer_type: Option<&str>,
+        options: &str,
+    ) -> PgWireResult<NexusParsedStatement> {
+        let peers = self.get_peers_bridge().await?;
+        let peer = peers.get(peer_name).ok_or_else(|| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42704".to_owned(),
+                format!("peer \"{}\" does not exist", peer_name),
+            )))
+        })?;
+        let existing_type = DbType::try_from(peer.r#type).map_err(|_| {
+            PgWireError::ApiError(format!("peer \"{}\" has an unknown type", peer_name).into())
+        })?;
+        if let Some(peer_type) = peer_type {
+            if DbType::from_str_name(&peer_type.to_uppercase()) != Some(existing_type) {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42809".to_owned(),
+                    format!(
+                        "peer \"{}\" is a {} peer, not {}",
+                        peer_name,
+                        existing_type.as_str_name(),
+                        peer_type.to_uppercase()
+                    ),
+                ))));
+            }
+        }
+
+        let create_sql = format!(
+            "CREATE PEER {} FROM {} WITH {}",
+            peer_name,
+            existing_type.as_str_name(),
+            options
+        );
+        let (mut stmts, _) = parse_statements(&create_sql)?;
+        let stmt = match stmts.len() {
+            1 => stmts.remove(0),
+            _ => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42601".to_owned(),
+                    format!("syntax error: unsupported ALTER PEER: {}", sql),
+                ))))
+            }
+        };
+        let ddl = PeerDDLAnalyzer.analyze(&stmt).map_err(|e| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42601".to_owned(),
+                e.to_string(),
+            )))
+        })?;
+        let Some(PeerDDL::CreatePeer { peer, .. }) = ddl else {
+            return Err(PgWireError::ApiError(
+                "ALTER PEER was not parsed as a peer config".into(),
+            ));
+        };
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::PeerDDL {
+                stmt,
+                ddl: Box::new(PeerDDL::AlterPeer { peer }),
+            },
+            query: sql.to_owned(),
+            timeout_hint: None,
+            parameter_types: OnceLock::new(),
+        })
+    }
+
+    async fn parse_export_schema(
+        &self,
+        sql: &str,
+        peer_name: &str,
+        schema: Option<String>,
+    ) -> PgWireResult<NexusParsedStatement> {
+        let mut peers = self.get_peers_bridge().await?;
+        let peer = peers.remove(peer_name).ok_or_else(|| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42704".to_owned(),
+                format!("peer \"{}\" does not exist", peer_name),
+            )))
+        })?;
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::ExportSchema {
+                peer: Box::new(peer),
+                schema,
+            },
+            query: sql.to_owned(),
+            timeout_hint: None,
+            parameter_types: OnceLock::new(),
+        })
+    }
+
+    async fn parse_compare(
+        &self,
+        sql: &str,
+        left: Statement,
+        right: Statement,
+        key: Vec<Ident>,
+    ) -> PgWireResult<NexusParsedStatement> {
+        let peers = self.get_peers_bridge().await?;
+        let compared = |stmt: &Statement| -> PgWireResult<Box<ComparedQuery>> {
+            match self.new_statement(peers.clone(), stmt, None, None)? {
+                NexusStatement::PeerQuery { stmt, assoc } => {
+                    Ok(Box::new(ComparedQuery { stmt, assoc }))
+                }
+                _ => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42601".to_owned(),
+                    "COMPARE takes two queries".to_owned(),
+                )))),
+            }
+        };
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::Compare {
+                left: compared(&left)?,
+                right: compared(&right)?,
+                key,
+            },
+            query: sql.to_owned(),
+            timeout_hint: query_hints(sql)?.timeout,
+            parameter_types: OnceLock::new(),
+        })
+    }
+
+    fn parse_move(&self, sql: &str, fetch_sql: &str) -> PgWireResult<NexusParsedStatement> {
+        let unsupported = || {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42601".to_owned(),
+                format!("syntax error: unsupported MOVE: {}", sql),
+            )))
+        };
+        let mut stmts =
+            Parser::parse_sql(&DIALECT, fetch_sql).map_err(|err| syntax_error(sql, err))?;
+        if stmts.len() != 1 {
+            return Err(unsupported());
+        }
+        let stmt = stmts.remove(0);
+        let Statement::Fetch {
+            name, direction, ..
+        } = &stmt
+        else {
+            return Err(unsupported());
+        };
+        let cursor = CursorEvent::Move(name.value.clone(), direction.clone());
+        Ok(NexusParsedStatement {
+            statement: NexusStatement::PeerCursor { stmt, cursor },
+            query: sql.to_owned(),
+            timeout_hint: None,
+            parameter_types: OnceLock::new(),
+        })
     }
 
     pub async fn get_peers_bridge(&self) -> PgWireResult<HashMap<String, pt::peerdb_peers::Peer>> {
-        let peers = self.catalog.get_peers().await;
+        let peers = self.peer_cache.get_peers(&self.catalog).await;
 
         peers.map_err(|e| {
             PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "internal_error".to_owned(),
+                "XX000".to_owned(),
                 e.to_string(),
             )))
         })
     }
 
     pub async fn parse_simple_sql(&self, sql: &str) -> PgWireResult<NexusParsedStatement> {
-        let mut stmts =
-            Parser::parse_sql(&DIALECT, sql).map_err(|e| PgWireError::ApiError(Box::new(e)))?;
+        if import::is_import(sql) {
+            return self.parse_import(sql).await;
+        }
+        if let Some(name) = reset_variable(sql) {
+            return Ok(NexusParsedStatement {
+                statement: NexusStatement::ResetVariable { name },
+                query: sql.to_owned(),
+                timeout_hint: None,
+                parameter_types: OnceLock::new(),
+            });
+        }
+        if let Some(peer_name) = test_peer(sql) {
+            return self.parse_test_peer(sql, &peer_name).await;
+        }
+        if let Some((peer_name, schema)) = export_schema(sql) {
+            return self.parse_export_schema(sql, &peer_name, schema).await;
+        }
+        if let Some((peer_name, peer_type, options)) = alter_peer(sql) {
+            return self
+                .parse_alter_peer(sql, &peer_name, peer_type.as_deref(), &options)
+                .await;
+        }
+        if let Some(pattern) = show_peers(sql) {
+            return Ok(NexusParsedStatement {
+                statement: NexusStatement::ShowPeers { pattern },
+                query: sql.to_owned(),
+                timeout_hint: None,
+                parameter_types: OnceLock::new(),
+            });
+        }
+        if let Some(compare) = compare(sql) {
+            let (left, right, key) = compare?;
+            return self.parse_compare(sql, left, right, key).await;
+        }
+        if let Some(fetch_sql) = move_as_fetch(sql) {
+            return self.parse_move(sql, &fetch_sql);
+        }
+        let (mut stmts, drop_cascade) = parse_statements(sql)?;
+        let hints = query_hints(sql)?;
         if stmts.len() > 1 {
             let err_msg = format!("unsupported sql: {}, statements: {:?}", sql, stmts);
             // TODO (kaushik): Better error message for this. When do we start seeing multiple statements?
@@ -122,20 +1084,28 @@ impl NexusQueryParser {
             Ok(NexusParsedStatement {
                 statement: NexusStatement::Empty,
                 query: sql.to_owned(),
+                timeout_hint: hints.timeout,
+                parameter_types: OnceLock::new(),
             })
         } else {
             let stmt = stmts.remove(0);
-            if matches!(stmt, Statement::Rollback { .. }) {
+            // a ROLLBACK routed with a hint ends the transaction on that peer
+            if matches!(stmt, Statement::Rollback { .. }) && hints.peer.is_none() {
                 Ok(NexusParsedStatement {
                     statement: NexusStatement::Rollback { stmt },
                     query: sql.to_owned(),
+                    timeout_hint: hints.timeout,
+                    parameter_types: OnceLock::new(),
                 })
             } else {
                 let peers = self.get_peers_bridge().await?;
-                let nexus_stmt = NexusStatement::new(peers, &stmt)?;
+                let nexus_stmt =
+                    self.new_statement(peers, &stmt, hints.peer.as_deref(), drop_cascade)?;
                 Ok(NexusParsedStatement {
                     statement: nexus_stmt,
                     query: sql.to_owned(),
+                    timeout_hint: hints.timeout,
+                    parameter_types: OnceLock::new(),
                 })
             }
         }
@@ -147,27 +1117,69 @@ impl QueryParser for NexusQueryParser {
     type Statement = NexusParsedStatement;
 
     async fn parse_sql(&self, sql: &str, _types: &[Type]) -> PgWireResult<Self::Statement> {
-        let mut stmts =
-            Parser::parse_sql(&DIALECT, sql).map_err(|e| PgWireError::ApiError(Box::new(e)))?;
+        if import::is_import(sql) {
+            return self.parse_import(sql).await;
+        }
+        if let Some(name) = reset_variable(sql) {
+            return Ok(NexusParsedStatement {
+                statement: NexusStatement::ResetVariable { name },
+                query: sql.to_owned(),
+                timeout_hint: None,
+                parameter_types: OnceLock::new(),
+            });
+        }
+        if let Some(peer_name) = test_peer(sql) {
+            return self.parse_test_peer(sql, &peer_name).await;
+        }
+        if let Some((peer_name, schema)) = export_schema(sql) {
+            return self.parse_export_schema(sql, &peer_name, schema).await;
+        }
+        if let Some((peer_name, peer_type, options)) = alter_peer(sql) {
+            return self
+                .parse_alter_peer(sql, &peer_name, peer_type.as_deref(), &options)
+                .await;
+        }
+        if let Some(pattern) = show_peers(sql) {
+            return Ok(NexusParsedStatement {
+                statement: NexusStatement::ShowPeers { pattern },
+                query: sql.to_owned(),
+                timeout_hint: None,
+                parameter_types: OnceLock::new(),
+            });
+        }
+        if let Some(compare) = compare(sql) {
+            let (left, right, key) = compare?;
+            return self.parse_compare(sql, left, right, key).await;
+        }
+        if let Some(fetch_sql) = move_as_fetch(sql) {
+            return self.parse_move(sql, &fetch_sql);
+        }
+        let (mut stmts, drop_cascade) = parse_statements(sql)?;
+        let hints = query_hints(sql)?;
         if stmts.len() > 1 {
-            let err_msg = format!("unsupported sql: {}, statements: {:?}", sql, stmts);
+            // an Execute has room for the results of a single statement
             Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "42P14".to_owned(),
-                err_msg,
+                "42601".to_owned(),
+                "cannot insert multiple commands into a prepared statement".to_owned(),
             ))))
         } else if stmts.is_empty() {
             Ok(NexusParsedStatement {
                 statement: NexusStatement::Empty,
                 query: sql.to_owned(),
+                timeout_hint: hints.timeout,
+                parameter_types: OnceLock::new(),
             })
         } else {
             let stmt = stmts.remove(0);
             let peers = self.get_peers_bridge().await?;
-            let nexus_stmt = NexusStatement::new(peers, &stmt)?;
+            let nexus_stmt =
+                self.new_statement(peers, &stmt, hints.peer.as_deref(), drop_cascade)?;
             Ok(NexusParsedStatement {
                 statement: nexus_stmt,
                 query: sql.to_owned(),
+                timeout_hint: hints.timeout,
+                parameter_types: OnceLock::new(),
             })
         }
     }
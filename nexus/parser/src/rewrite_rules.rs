@@ -0,0 +1,401 @@
+//! Rules admins define per peer, rewriting the statements routed to the peer
+//! before they are sent to it:
+//!
+//! - `rename` refers to a table by another name, e.g. to move clients off a
+//!   deprecated table without changing them.
+//! - `filter` lets queries only see the rows of a table matching a predicate,
+//!   e.g. `tenant_id = 42`, by reading the table through a subquery. Filters
+//!   name tables as renamed. Statements other than queries can't refer to a
+//!   filtered table, as they could change rows outside of the filter.
+//!
+//! Rules are added with `peerdb.add_rewrite_rule` and dropped with
+//! `peerdb.drop_rewrite_rule`, listed in `peerdb.rewrite_rules` and kept in
+//! the catalog's `nexus_rewrite_rules` table along with who added them.
+//! Every statement a rule rewrites is logged with the rules applied. Rules
+//! changed by another nexus sharing the catalog apply here after a restart.
+
+use std::{ops::ControlFlow, sync::RwLock};
+
+use catalog::Catalog;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use sqlparser::{
+    ast::{
+        visit_expressions, Expr, ObjectName, Query, Statement, TableAlias, TableFactor, VisitMut,
+        VisitorMut,
+    },
+    parser::{Parser, ParserError},
+    tokenizer::Token,
+};
+
+use crate::{temp_views::ident_key, DIALECT};
+
+#[derive(Debug, Clone)]
+pub enum RewriteAction {
+    Rename(ObjectName),
+    Filter(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub name: String,
+    pub peer: String,
+    pub table: ObjectName,
+    pub action: RewriteAction,
+    pub created_by: String,
+}
+
+impl RewriteRule {
+    /// Parses a rule, checking that the table and a renamed table are plain
+    /// names and a filter is an expression without subqueries.
+    pub fn parse(
+        name: &str,
+        peer: &str,
+        table: &str,
+        action: &str,
+        argument: &str,
+        created_by: &str,
+    ) -> PgWireResult<Self> {
+        let invalid = |message: String| rule_error("22023", message);
+        let table = parse_table_name(table).ok_or_else(|| {
+            invalid(format!(
+                "rewrite rule \"{}\" names an invalid table: {}",
+                name, table
+            ))
+        })?;
+        let action = match action.to_lowercase().as_str() {
+            "rename" => RewriteAction::Rename(parse_table_name(argument).ok_or_else(|| {
+                invalid(format!(
+                    "rewrite rule \"{}\" renames to an invalid table: {}",
+                    name, argument
+                ))
+            })?),
+            "filter" => {
+                let predicate = parse_filter(argument).map_err(|err| {
+                    invalid(format!(
+                        "rewrite rule \"{}\" has an invalid filter: {}",
+                        name, err
+                    ))
+                })?;
+                if has_subquery(&predicate) {
+                    return Err(invalid(format!(
+                        "the filter of rewrite rule \"{}\" can't contain subqueries",
+                        name
+                    )));
+                }
+                RewriteAction::Filter(predicate)
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unknown rewrite action \"{}\", expected rename or filter",
+                    action
+                )))
+            }
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            peer: peer.to_owned(),
+            table,
+            action,
+            created_by: created_by.to_owned(),
+        })
+    }
+
+    pub fn action_name(&self) -> &'static str {
+        match self.action {
+            RewriteAction::Rename(_) => "rename",
+            RewriteAction::Filter(_) => "filter",
+        }
+    }
+
+    pub fn argument(&self) -> String {
+        match &self.action {
+            RewriteAction::Rename(table) => table.to_string(),
+            RewriteAction::Filter(predicate) => predicate.to_string(),
+        }
+    }
+
+    // the number of leading parts of `name` naming the peer, if `name` is
+    // the table of this rule.
+    fn matches(&self, name: &ObjectName) -> Option<usize> {
+        let prefix = name.0.len().checked_sub(self.table.0.len())?;
+        let peer_prefix = match &name.0[..prefix] {
+            [] => true,
+            [peer] => ident_key(peer) == self.peer,
+            _ => false,
+        };
+        let same_table = name.0[prefix..]
+            .iter()
+            .zip(&self.table.0)
+            .all(|(part, rule_part)| ident_key(part) == ident_key(rule_part));
+        (peer_prefix && same_table).then_some(prefix)
+    }
+}
+
+/// The rewrite rules of all peers.
+#[derive(Default)]
+pub struct RewriteRules {
+    rules: RwLock<Vec<RewriteRule>>,
+}
+
+impl RewriteRules {
+    /// The rules stored in the catalog. A rule no longer valid fails loading
+    /// rather than being skipped, as a skipped filter would expose rows.
+    pub async fn load(catalog: &Catalog) -> anyhow::Result<Self> {
+        let mut rules = Vec::new();
+        for (name, peer, table, action, argument, created_by) in catalog.get_rewrite_rules().await?
+        {
+            let rule = RewriteRule::parse(&name, &peer, &table, &action, &argument, &created_by)
+                .map_err(|err| anyhow::anyhow!("invalid rewrite rule {}: {}", name, err))?;
+            tracing::info!(
+                "rewrite rule {} on peer {}: {} {} {}",
+                rule.name,
+                rule.peer,
+                rule.action_name(),
+                rule.table,
+                rule.argument()
+            );
+            rules.push(rule);
+        }
+        Ok(Self {
+            rules: RwLock::new(rules),
+        })
+    }
+
+    pub async fn add(&self, catalog: &Catalog, rule: RewriteRule) -> PgWireResult<()> {
+        let added = catalog
+            .insert_rewrite_rule(
+                &rule.name,
+                &rule.peer,
+                &rule.table.to_string(),
+                rule.action_name(),
+                &rule.argument(),
+                &rule.created_by,
+            )
+            .await
+            .map_err(store_error)?;
+        if !added {
+            return Err(rule_error(
+                "42710",
+                format!("rewrite rule \"{}\" already exists", rule.name),
+            ));
+        }
+        self.rules.write().unwrap().push(rule);
+        Ok(())
+    }
+
+    pub async fn drop(&self, catalog: &Catalog, name: &str) -> PgWireResult<()> {
+        let dropped = catalog
+            .delete_rewrite_rule(name)
+            .await
+            .map_err(store_error)?;
+        if !dropped {
+            return Err(rule_error(
+                "42704",
+                format!("rewrite rule \"{}\" does not exist", name),
+            ));
+        }
+        self.rules.write().unwrap().retain(|rule| rule.name != name);
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<RewriteRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Rewrites a statement routed to the peer, logging the rules applied.
+    pub fn apply(&self, peer: &str, stmt: &mut Statement) -> PgWireResult<()> {
+        let rules = self.rules.read().unwrap();
+        let rules = rules
+            .iter()
+            .filter(|rule| rule.peer == peer)
+            .collect::<Vec<_>>();
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let original = stmt.to_string();
+        let mut rewriter = Rewriter {
+            rules,
+            is_query: matches!(stmt, Statement::Query(_)),
+            applied: Vec::new(),
+        };
+        if let ControlFlow::Break(err) = stmt.visit(&mut rewriter) {
+            return Err(err);
+        }
+        if !rewriter.applied.is_empty() {
+            tracing::info!(
+                "rewrite rules {} applied to query on peer {}: {} became {}",
+                rewriter.applied.join(", "),
+                peer,
+                original,
+                stmt
+            );
+        }
+        Ok(())
+    }
+}
+
+struct Rewriter<'a> {
+    rules: Vec<&'a RewriteRule>,
+    is_query: bool,
+    // names of the rules applied, in order
+    applied: Vec<String>,
+}
+
+impl<'a> Rewriter<'a> {
+    // the first rule filtering the table, with its predicate
+    fn filter(&self, name: &ObjectName) -> Option<(&'a RewriteRule, &'a Expr)> {
+        self.rules
+            .iter()
+            .copied()
+            .find_map(|rule| match &rule.action {
+                RewriteAction::Filter(predicate) if rule.matches(name).is_some() => {
+                    Some((rule, predicate))
+                }
+                _ => None,
+            })
+    }
+
+    fn applied(&mut self, rule: &RewriteRule) {
+        if !self.applied.contains(&rule.name) {
+            self.applied.push(rule.name.clone());
+        }
+    }
+}
+
+impl VisitorMut for Rewriter<'_> {
+    type Break = PgWireError;
+
+    // a renamed table keeps the name it was referred to by as its alias, so
+    // that qualified columns keep referring to it.
+    fn pre_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+        if let TableFactor::Table { name, alias, .. } = factor {
+            let renamed = self.rules.iter().any(|rule| {
+                matches!(rule.action, RewriteAction::Rename(_)) && rule.matches(name).is_some()
+            });
+            if renamed && alias.is_none() {
+                *alias = Some(TableAlias {
+                    name: name.0[name.0.len() - 1].clone(),
+                    columns: Vec::new(),
+                });
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    // a table is renamed once, by the first rule renaming it, so renames
+    // don't chain.
+    fn pre_visit_relation(&mut self, name: &mut ObjectName) -> ControlFlow<Self::Break> {
+        let rename = self
+            .rules
+            .iter()
+            .copied()
+            .find_map(|rule| match &rule.action {
+                RewriteAction::Rename(to) => rule.matches(name).map(|prefix| (rule, prefix, to)),
+                RewriteAction::Filter(_) => None,
+            });
+        if let Some((rule, prefix, to)) = rename {
+            name.0.truncate(prefix);
+            name.0.extend(to.0.iter().cloned());
+            self.applied(rule);
+        }
+
+        if self.is_query {
+            return ControlFlow::Continue(());
+        }
+        match self.filter(name) {
+            Some((rule, _)) => ControlFlow::Break(rule_error(
+                "42501",
+                format!(
+                    "table {} of peer {} is filtered by rewrite rule \"{}\", \
+                    only queries can refer to it",
+                    rule.table, rule.peer, rule.name
+                ),
+            )),
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    // filters wrap the table after its name is visited, so the subquery
+    // reading it isn't filtered again.
+    fn post_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+        let TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } = factor
+        else {
+            return ControlFlow::Continue(());
+        };
+        let Some((rule, predicate)) = self.filter(name) else {
+            return ControlFlow::Continue(());
+        };
+        let subquery = match filtered_table(name, predicate) {
+            Ok(subquery) => subquery,
+            Err(err) => return ControlFlow::Break(err),
+        };
+        // the subquery goes by the table's name, so that qualified columns
+        // keep referring to it.
+        let alias = alias.clone().unwrap_or_else(|| TableAlias {
+            name: name.0[name.0.len() - 1].clone(),
+            columns: Vec::new(),
+        });
+        *factor = TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(subquery),
+            alias: Some(alias),
+        };
+        self.applied(rule);
+        ControlFlow::Continue(())
+    }
+}
+
+// `SELECT * FROM table WHERE predicate`
+fn filtered_table(name: &ObjectName, predicate: &Expr) -> PgWireResult<Query> {
+    let sql = format!("SELECT * FROM {} WHERE {}", name, predicate);
+    match Parser::parse_sql(&DIALECT, &sql) {
+        Ok(mut stmts) => match stmts.pop() {
+            Some(Statement::Query(query)) => Ok(*query),
+            _ => Err(rule_error("XX000", format!("unable to filter {}", name))),
+        },
+        Err(err) => Err(rule_error(
+            "XX000",
+            format!("unable to filter {}: {}", name, err),
+        )),
+    }
+}
+
+fn parse_table_name(name: &str) -> Option<ObjectName> {
+    let mut parser = Parser::new(&DIALECT).try_with_sql(name).ok()?;
+    let table = parser.parse_object_name(false).ok()?;
+    parser.expect_token(&Token::EOF).ok()?;
+    Some(table)
+}
+
+fn parse_filter(sql: &str) -> Result<Expr, ParserError> {
+    let mut parser = Parser::new(&DIALECT).try_with_sql(sql)?;
+    let predicate = parser.parse_expr()?;
+    parser.expect_token(&Token::EOF)?;
+    Ok(predicate)
+}
+
+fn has_subquery(predicate: &Expr) -> bool {
+    visit_expressions(predicate, |expr| match expr {
+        Expr::Subquery(_) | Expr::Exists { .. } | Expr::InSubquery { .. } => ControlFlow::Break(()),
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break()
+}
+
+fn store_error(err: anyhow::Error) -> PgWireError {
+    PgWireError::ApiError(format!("unable to store rewrite rule: {:?}", err).into())
+}
+
+fn rule_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
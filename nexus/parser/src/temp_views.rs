@@ -0,0 +1,171 @@
+//! `CREATE TEMP VIEW v AS SELECT ...` defines a view for the rest of the
+//! connection. Nexus keeps the definition and inlines it as a subquery
+//! wherever a later statement refers to `v`, before the statement is routed,
+//! so a view over one peer can be used in queries of another.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::ControlFlow,
+};
+
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use sqlparser::ast::{Ident, ObjectName, Query, TableAlias, TableFactor, VisitMut, VisitorMut};
+
+#[derive(Debug, Clone)]
+struct TempView {
+    columns: Vec<Ident>,
+    query: Query,
+}
+
+/// The temporary views of a connection, by name.
+#[derive(Debug, Clone, Default)]
+pub struct TempViews {
+    views: HashMap<String, TempView>,
+}
+
+impl TempViews {
+    /// The name a view is stored under, None for names qualified with a
+    /// schema or peer, which never refer to temporary views.
+    pub fn view_name(name: &ObjectName) -> Option<String> {
+        match name.0.as_slice() {
+            [ident] => Some(ident_key(ident)),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.views.contains_key(name)
+    }
+
+    pub fn create(
+        &mut self,
+        name: &str,
+        columns: Vec<Ident>,
+        query: Query,
+        or_replace: bool,
+    ) -> PgWireResult<()> {
+        if !or_replace && self.views.contains_key(name) {
+            return Err(view_error(
+                "42P07",
+                format!("relation \"{}\" already exists", name),
+            ));
+        }
+
+        // a view referring to itself, directly or through other views, would
+        // expand forever.
+        let mut views = self.clone();
+        views
+            .views
+            .insert(name.to_owned(), TempView { columns, query });
+        let mut query = views.views[name].query.clone();
+        views.expand_query(&mut query, vec![name.to_owned()])?;
+
+        *self = views;
+        Ok(())
+    }
+
+    pub fn drop(&mut self, name: &str) -> bool {
+        self.views.remove(name).is_some()
+    }
+
+    /// Inlines the temporary views the statement refers to as subqueries.
+    pub fn expand<V: VisitMut>(&self, node: &mut V) -> PgWireResult<()> {
+        if self.views.is_empty() {
+            return Ok(());
+        }
+        self.expand_query(node, Vec::new())
+    }
+
+    fn expand_query<V: VisitMut>(&self, node: &mut V, expanding: Vec<String>) -> PgWireResult<()> {
+        let mut expander = Expander {
+            views: self,
+            expanding,
+            ctes: HashSet::new(),
+        };
+        match node.visit(&mut expander) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(err) => Err(err),
+        }
+    }
+}
+
+struct Expander<'a> {
+    views: &'a TempViews,
+    // the views being expanded, outermost first
+    expanding: Vec<String>,
+    // names of common table expressions, which hide views of the same name
+    ctes: HashSet<String>,
+}
+
+impl VisitorMut for Expander<'_> {
+    type Break = PgWireError;
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        if let Some(with) = &query.with {
+            self.ctes
+                .extend(with.cte_tables.iter().map(|cte| ident_key(&cte.alias.name)));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+        let TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } = factor
+        else {
+            return ControlFlow::Continue(());
+        };
+        let Some(key) = TempViews::view_name(name).filter(|key| !self.ctes.contains(key)) else {
+            return ControlFlow::Continue(());
+        };
+        let Some(view) = self.views.views.get(&key) else {
+            return ControlFlow::Continue(());
+        };
+        if self.expanding.contains(&key) {
+            return ControlFlow::Break(view_error(
+                "42P17",
+                format!("infinite recursion detected in temporary view \"{}\"", key),
+            ));
+        }
+
+        let mut subquery = view.query.clone();
+        let mut expanding = self.expanding.clone();
+        expanding.push(key);
+        if let Err(err) = self.views.expand_query(&mut subquery, expanding) {
+            return ControlFlow::Break(err);
+        }
+
+        let mut alias = alias.clone().unwrap_or_else(|| TableAlias {
+            name: name.0[0].clone(),
+            columns: Vec::new(),
+        });
+        if alias.columns.is_empty() {
+            alias.columns = view.columns.clone();
+        }
+        *factor = TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(subquery),
+            alias: Some(alias),
+        };
+        ControlFlow::Continue(())
+    }
+}
+
+// unquoted names are case insensitive, like in postgres
+pub(crate) fn ident_key(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+fn view_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
@@ -69,9 +69,14 @@ impl BigqueryAst {
     }
 
     pub fn rewrite(&self, dataset: &str, query: &mut Query) -> anyhow::Result<()> {
-        // replace peername with the connected dataset.
+        // replace peername with the connected dataset, tables of a default
+        // peer come without one.
         visit_relations_mut(query, |table| {
-            table.0[0] = dataset.into();
+            if table.0.len() == 1 {
+                table.0.insert(0, dataset.into());
+            } else {
+                table.0[0] = dataset.into();
+            }
             ControlFlow::<()>::Continue(())
         });
 
@@ -96,6 +101,39 @@ impl BigqueryAst {
         });
 
         visit_expressions_mut(query, |node| {
+            // '\x..'::bytea, as bound parameters are given, to FROM_HEX('..')
+            let hex = match node {
+                Expr::Cast {
+                    expr,
+                    data_type: DataType::Bytea,
+                    ..
+                } => match expr.as_ref() {
+                    Expr::Value(
+                        sqlparser::ast::Value::SingleQuotedString(s)
+                        | sqlparser::ast::Value::EscapedStringLiteral(s),
+                    ) => s
+                        .trim_start_matches('\\')
+                        .strip_prefix('x')
+                        .map(str::to_owned),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(hex) = hex {
+                *node = Expr::Function(Function {
+                    name: ObjectName(vec![Ident::new("FROM_HEX".to_string())]),
+                    args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                        sqlparser::ast::Value::SingleQuotedString(hex),
+                    )))],
+                    null_treatment: None,
+                    filter: None,
+                    over: None,
+                    distinct: false,
+                    special: false,
+                    order_by: vec![],
+                });
+            }
+
             // CAST AS Text to CAST AS String
             if let Expr::Cast { data_type: dt, .. } = node {
                 if let DataType::Text = dt {
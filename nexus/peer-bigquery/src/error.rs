@@ -0,0 +1,66 @@
+use gcp_bigquery_client::error::BQError;
+use peer_cursor::error::{sqlstate, PeerError};
+use pgwire::error::PgWireError;
+
+// the SQLSTATE of an error bigquery reported, from the reason of its first
+// error and else its HTTP status,
+// https://cloud.google.com/bigquery/docs/error-messages
+fn response_sqlstate(reason: Option<&str>, status: i64) -> &'static str {
+    match reason {
+        Some("invalidQuery") => sqlstate::SYNTAX_ERROR,
+        Some("notFound") => sqlstate::UNDEFINED_TABLE,
+        Some("duplicate") => sqlstate::DUPLICATE_TABLE,
+        Some("accessDenied") => sqlstate::INSUFFICIENT_PRIVILEGE,
+        Some("quotaExceeded" | "rateLimitExceeded") => sqlstate::CONFIGURATION_LIMIT_EXCEEDED,
+        Some(
+            "bytesBilledLimitExceeded"
+            | "billingTierLimitExceeded"
+            | "resourcesExceeded"
+            | "responseTooLarge",
+        ) => sqlstate::PROGRAM_LIMIT_EXCEEDED,
+        Some("stopped" | "timeout") => sqlstate::QUERY_CANCELED,
+        Some("backendError") => sqlstate::SYSTEM_ERROR,
+        _ => match status {
+            400 => sqlstate::SYNTAX_ERROR,
+            401 => sqlstate::INVALID_AUTHORIZATION_SPECIFICATION,
+            403 => sqlstate::INSUFFICIENT_PRIVILEGE,
+            404 => sqlstate::UNDEFINED_TABLE,
+            409 => sqlstate::DUPLICATE_TABLE,
+            429 => sqlstate::CONFIGURATION_LIMIT_EXCEEDED,
+            _ => sqlstate::INTERNAL_ERROR,
+        },
+    }
+}
+
+/// The error of a bigquery request with the SQLSTATE of its class: errors of
+/// the query with the one postgres would report, failed requests as
+/// connection failures and credentials that aren't accepted as such.
+pub fn bq_error(context: &str, err: BQError) -> PgWireError {
+    tracing::error!("{}: {}", context, err);
+    let err = match err {
+        BQError::ResponseError { error } => {
+            let reason = error
+                .error
+                .errors
+                .first()
+                .and_then(|error| error.get("reason"))
+                .map(String::as_str);
+            PeerError::new(
+                response_sqlstate(reason, error.error.code),
+                error.error.message,
+            )
+        }
+        BQError::RequestError(err) => PeerError::new(
+            sqlstate::CONNECTION_FAILURE,
+            format!("{}: {}", context, err),
+        ),
+        err @ (BQError::AuthError(_) | BQError::YupAuthError(_) | BQError::NoToken) => {
+            PeerError::new(
+                sqlstate::INVALID_AUTHORIZATION_SPECIFICATION,
+                format!("{}: {}", context, err),
+            )
+        }
+        err => PeerError::new(sqlstate::INTERNAL_ERROR, format!("{}: {}", context, err)),
+    };
+    err.into()
+}
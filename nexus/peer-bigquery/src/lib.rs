@@ -1,20 +1,40 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
 
 use anyhow::Context;
+use error::bq_error;
+use futures::TryStreamExt;
 use gcp_bigquery_client::{
-    model::{query_request::QueryRequest, query_response::ResultSet},
+    error::BQError,
+    model::{
+        query_request::QueryRequest, query_response::ResultSet,
+        table_data_insert_all_request::TableDataInsertAllRequest,
+    },
     yup_oauth2, Client,
 };
+use load::JsonRow;
 use peer_connections::PeerConnectionTracker;
-use peer_cursor::{CursorManager, CursorModification, QueryExecutor, QueryOutput, Schema};
+use peer_cursor::{
+    error::client_error,
+    labels::{QueryLabels, QUERY_LABELS},
+    util::{describe_table_schema, explain_records, explain_schema, export_schema_schema},
+    BulkLoadFormat, ByteStream, CursorManager, CursorModification, DryRun, PeerCapabilities,
+    QueryExecutor, QueryOutput, Record, Records, Schema,
+};
 use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
 use pt::peerdb_peers::BigqueryConfig;
-use sqlparser::ast::{CloseCursor, Declare, Expr, FetchDirection, Statement, Value};
+use sqlparser::ast::{
+    CloseCursor, Declare, Expr, FetchDirection, Ident, ObjectName, Statement, Value,
+};
 use stream::{BqRecordStream, BqSchema};
 
 mod ast;
+mod error;
+mod load;
 mod stream;
 
+// rows per insertAll request, as recommended by bigquery.
+const INSERT_BATCH_SIZE: usize = 500;
+
 pub struct BigQueryQueryExecutor {
     peer_name: String,
     project_id: String,
@@ -22,6 +42,9 @@ pub struct BigQueryQueryExecutor {
     peer_connections: PeerConnectionTracker,
     client: Box<Client>,
     cursor_manager: CursorManager,
+    maximum_bytes_billed: Option<i64>,
+    // sent as the labels of the query jobs
+    query_labels: Mutex<QueryLabels>,
 }
 
 pub async fn bq_client_from_config(config: &BigqueryConfig) -> anyhow::Result<Client> {
@@ -45,6 +68,14 @@ pub async fn bq_client_from_config(config: &BigqueryConfig) -> anyhow::Result<Cl
 }
 
 impl BigQueryQueryExecutor {
+    pub const CAPABILITIES: PeerCapabilities = PeerCapabilities {
+        queryable: true,
+        writable: false,
+        supports_cursors: true,
+        supports_transactions: false,
+        supports_copy: true,
+    };
+
     pub async fn new(
         peer_name: String,
         config: &BigqueryConfig,
@@ -59,14 +90,26 @@ impl BigQueryQueryExecutor {
             peer_connections,
             client: Box::new(client),
             cursor_manager: Default::default(),
+            maximum_bytes_billed: config.maximum_bytes_billed,
+            query_labels: Mutex::default(),
         })
     }
 
     async fn run_tracked(&self, query: &str) -> PgWireResult<ResultSet> {
         let mut query_req = QueryRequest::new(query);
         query_req.timeout_ms = Some(Duration::from_secs(120).as_millis() as i32);
+        query_req.maximum_bytes_billed = self.maximum_bytes_billed.map(|bytes| bytes.to_string());
+        let labels = self.query_labels.lock().unwrap().clone();
+        if !labels.is_empty() {
+            query_req.labels = Some(
+                labels
+                    .iter()
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
 
-        let token = self
+        let mut token = self
             .peer_connections
             .track_query(&self.peer_name, query)
             .await
@@ -77,20 +120,107 @@ impl BigQueryQueryExecutor {
 
         let result_set = self.client.job().query(&self.project_id, query_req).await;
 
+        let bytes_processed = result_set.as_ref().ok().and_then(|result_set| {
+            result_set
+                .query_response()
+                .total_bytes_processed
+                .as_deref()
+                .and_then(|bytes| bytes.parse::<i64>().ok())
+        });
+        if let Some(bytes) = bytes_processed {
+            tracing::info!("Query processed {}", format_bytes(bytes));
+            token.set_bytes_processed(bytes);
+        }
+
         token.end().await.map_err(|err| {
             tracing::error!("error closing tracking token: {}", err);
             PgWireError::ApiError(err.into())
         })?;
 
-        result_set.map_err(|err| {
-            tracing::error!("error running query: {}", err);
-            PgWireError::ApiError(err.into())
-        })
+        result_set.map_err(|err| bq_error("error running query", err))
+    }
+
+    async fn insert_rows(&self, dataset: &str, table: &str, rows: &[JsonRow]) -> PgWireResult<()> {
+        let bq_err = |err: BQError| bq_error("error loading rows", err);
+        let mut request = TableDataInsertAllRequest::new();
+        for row in rows {
+            request.add_row(None, row).map_err(bq_err)?;
+        }
+        let response = self
+            .client
+            .tabledata()
+            .insert_all(&self.project_id, dataset, table, request)
+            .await
+            .map_err(bq_err)?;
+        if let Some(errors) = response.insert_errors.filter(|errors| !errors.is_empty()) {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "22000".to_owned(),
+                format!(
+                    "unable to insert rows into {}.{}: {:?}",
+                    dataset, table, errors
+                ),
+            ))));
+        }
+        Ok(())
+    }
+}
+
+// format a byte count the way the BigQuery console does, e.g. 1.2 GB
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// the error of a bulk load that stopped part way, with the rows it inserted
+// before.
+fn partial_load_error(err: PgWireError, loaded: usize, dataset: &str, table: &str) -> PgWireError {
+    match client_error(err) {
+        PgWireError::UserError(info) if loaded > 0 => {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                info.code().clone(),
+                format!(
+                    "{}, the {} rows loaded before remain in {}.{}",
+                    info.message(),
+                    loaded,
+                    dataset,
+                    table
+                ),
+            )))
+        }
+        err => err,
     }
 }
 
 #[async_trait::async_trait]
 impl QueryExecutor for BigQueryQueryExecutor {
+    fn capabilities(&self) -> PeerCapabilities {
+        Self::CAPABILITIES
+    }
+
+    // a dry run is validated like a query job, and free
+    async fn test_connection(&self) -> PgWireResult<()> {
+        let mut query_req = QueryRequest::new("SELECT 1");
+        query_req.dry_run = Some(true);
+        self.client
+            .job()
+            .query(&self.project_id, query_req)
+            .await
+            .map(|_| ())
+            .map_err(|err| bq_error("error testing connection", err))
+    }
+
     #[tracing::instrument(skip(self, stmt), fields(stmt = %stmt))]
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
         // only support SELECT statements
@@ -115,6 +245,45 @@ impl QueryExecutor for BigQueryQueryExecutor {
                 );
                 Ok(QueryOutput::Stream(Box::pin(cursor)))
             }
+            // bigquery has no EXPLAIN, the plan is the dry run of the query
+            // with the bytes it would scan
+            Statement::Explain {
+                statement,
+                analyze: false,
+                ..
+            } => {
+                let dry_run = self.dry_run(statement).await?;
+                let mut lines = dry_run.plan.lines().map(str::to_owned).collect::<Vec<_>>();
+                if let Some(bytes) = dry_run.estimated_bytes_processed {
+                    lines.push(format!(
+                        "Estimated Bytes Processed: {} ({})",
+                        bytes,
+                        format_bytes(bytes)
+                    ));
+                }
+                Ok(QueryOutput::Records(explain_records(lines)))
+            }
+            Statement::Call(function) => {
+                // the peer name is replaced by the connected dataset, the call
+                // runs as a script whose results are those of the procedure.
+                let mut function = function.clone();
+                function.name.0[0] = self.dataset_id.as_str().into();
+                let query = Statement::Call(function).to_string();
+                tracing::info!("bq rewritten call: {}", query);
+
+                let result_set = self.run_tracked(&query).await?;
+                let has_schema = result_set
+                    .query_response()
+                    .schema
+                    .as_ref()
+                    .is_some_and(|schema| schema.fields.is_some());
+                if !has_schema {
+                    return Ok(QueryOutput::AffectedRows(0));
+                }
+                Ok(QueryOutput::Stream(Box::pin(BqRecordStream::new(
+                    result_set,
+                ))))
+            }
             Statement::Declare { stmts } => {
                 if stmts.len() != 1 {
                     Err(PgWireError::ApiError(
@@ -146,31 +315,8 @@ impl QueryExecutor for BigQueryQueryExecutor {
             } => {
                 tracing::info!("fetching cursor for bigquery: {}", name.value);
 
-                // Attempt to extract the count from the direction
-                let count = match direction {
-                    FetchDirection::ForwardAll | FetchDirection::All => usize::MAX,
-                    FetchDirection::Next | FetchDirection::Forward { limit: None } => 1,
-                    FetchDirection::Count {
-                        limit: sqlparser::ast::Value::Number(n, _),
-                    }
-                    | FetchDirection::Forward {
-                        limit: Some(sqlparser::ast::Value::Number(n, _)),
-                    } => n
-                        .parse::<usize>()
-                        .map_err(|err| PgWireError::ApiError(err.into()))?,
-                    _ => {
-                        return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                            "ERROR".to_owned(),
-                            "fdw_error".to_owned(),
-                            "only FORWARD count and COUNT count are supported in FETCH".to_owned(),
-                        ))))
-                    }
-                };
-
-                tracing::info!("fetching {} rows", count);
-
                 // Fetch rows from the cursor manager
-                let records = self.cursor_manager.fetch(&name.value, count).await?;
+                let records = self.cursor_manager.fetch(&name.value, direction).await?;
 
                 // Return the fetched records as the query output
                 Ok(QueryOutput::Records(records))
@@ -194,19 +340,222 @@ impl QueryExecutor for BigQueryQueryExecutor {
                 );
                 PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                     "ERROR".to_owned(),
-                    "fdw_error".to_owned(),
+                    "0A000".to_owned(),
                     error,
                 ))))
             }
         }
     }
 
+    async fn describe_table(&self, schema: Option<&str>, table: &str) -> PgWireResult<QueryOutput> {
+        let dataset = schema.unwrap_or(&self.dataset_id);
+        if dataset.contains('`') {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42602".to_owned(),
+                format!("invalid dataset name: {}", dataset),
+            ))));
+        }
+        let query = format!(
+            "SELECT column_name, data_type, is_nullable = 'YES', column_default \
+            FROM `{}.{}`.INFORMATION_SCHEMA.COLUMNS \
+            WHERE table_name = '{}' ORDER BY ordinal_position",
+            self.project_id,
+            dataset,
+            table.replace('\\', "\\\\").replace('\'', "\\'")
+        );
+        let mut result_set = self.run_tracked(&query).await?;
+
+        let bq_err = |err: BQError| bq_error("error describing table", err);
+        let schema = describe_table_schema();
+        let mut records = Vec::new();
+        while result_set.next_row() {
+            let text = |value: Option<String>| value.map_or(value::Value::Null, value::Value::Text);
+            // bigquery reports columns without a default as the string NULL
+            let default = result_set
+                .get_string(3)
+                .map_err(bq_err)?
+                .filter(|d| d != "NULL");
+            records.push(Record {
+                values: vec![
+                    text(result_set.get_string(0).map_err(bq_err)?),
+                    text(result_set.get_string(1).map_err(bq_err)?),
+                    result_set
+                        .get_bool(2)
+                        .map_err(bq_err)?
+                        .map_or(value::Value::Null, value::Value::Bool),
+                    text(default),
+                ],
+                schema: schema.clone(),
+            });
+        }
+
+        if records.is_empty() {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42P01".to_owned(),
+                format!("relation \"{}.{}\" does not exist", dataset, table),
+            ))));
+        }
+        Ok(QueryOutput::Records(Records { records, schema }))
+    }
+
+    // a dataset is the closest thing to a schema, so this lists the peer's
+    // dataset unless another one is asked for.
+    async fn export_schema(&self, schema: Option<&str>) -> PgWireResult<QueryOutput> {
+        let dataset = schema.unwrap_or(&self.dataset_id);
+        if dataset.contains('`') {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42602".to_owned(),
+                format!("invalid dataset name: {}", dataset),
+            ))));
+        }
+        let query = format!(
+            "SELECT table_schema, table_name, column_name, data_type, is_nullable = 'YES', \
+            ordinal_position FROM `{}.{}`.INFORMATION_SCHEMA.COLUMNS \
+            ORDER BY table_name, ordinal_position",
+            self.project_id, dataset
+        );
+        let mut result_set = self.run_tracked(&query).await?;
+
+        let bq_err = |err: BQError| bq_error("error exporting schema", err);
+        let schema = export_schema_schema();
+        let mut records = Vec::new();
+        while result_set.next_row() {
+            let text = |value: Option<String>| value.map_or(value::Value::Null, value::Value::Text);
+            records.push(Record {
+                values: vec![
+                    text(result_set.get_string(0).map_err(bq_err)?),
+                    text(result_set.get_string(1).map_err(bq_err)?),
+                    text(result_set.get_string(2).map_err(bq_err)?),
+                    text(result_set.get_string(3).map_err(bq_err)?),
+                    result_set
+                        .get_bool(4)
+                        .map_err(bq_err)?
+                        .map_or(value::Value::Null, value::Value::Bool),
+                    result_set
+                        .get_i64(5)
+                        .map_err(bq_err)?
+                        .map_or(value::Value::Null, value::Value::BigInt),
+                ],
+                schema: schema.clone(),
+            });
+        }
+        Ok(QueryOutput::Records(Records { records, schema }))
+    }
+
+    // the client can't upload data for a load job, so the rows are parsed
+    // here as the data arrives and streamed into the table with insertAll a
+    // batch at a time. insertAll can't be rolled back, when a row is invalid
+    // or a batch fails the rows inserted before it stay in the table, which
+    // the error reports.
+    async fn bulk_load(
+        &self,
+        table: &ObjectName,
+        columns: &[Ident],
+        format: BulkLoadFormat,
+        mut data: ByteStream,
+    ) -> PgWireResult<QueryOutput> {
+        let (dataset, table) = match &table.0[..] {
+            [table] => (self.dataset_id.as_str(), table.value.as_str()),
+            [dataset, table] => (dataset.value.as_str(), table.value.as_str()),
+            _ => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42601".to_owned(),
+                    format!("improper qualified name: {}", table),
+                ))))
+            }
+        };
+
+        let bq_table = self
+            .client
+            .table()
+            .get(&self.project_id, dataset, table, None)
+            .await
+            .map_err(|err| bq_error("error loading rows", err))?;
+        let fields = bq_table.schema.fields.unwrap_or_default();
+        let mut rows = load::CsvRows::new(format, &fields, columns)?;
+
+        let mut loaded = 0;
+        let load = async {
+            let mut batch = Vec::new();
+            while let Some(chunk) = data.try_next().await? {
+                batch.extend(rows.push(&chunk)?);
+                while batch.len() >= INSERT_BATCH_SIZE {
+                    let rest = batch.split_off(INSERT_BATCH_SIZE);
+                    self.insert_rows(dataset, table, &batch).await?;
+                    loaded += batch.len();
+                    batch = rest;
+                }
+            }
+            batch.extend(rows.finish()?);
+            for batch in batch.chunks(INSERT_BATCH_SIZE) {
+                self.insert_rows(dataset, table, batch).await?;
+                loaded += batch.len();
+            }
+            Ok::<_, PgWireError>(())
+        };
+        let res = load.await;
+        if let Err(err) = res {
+            return Err(partial_load_error(err, loaded, dataset, table));
+        }
+        tracing::info!("bq bulk load of {} rows into {}.{}", loaded, dataset, table);
+        Ok(QueryOutput::AffectedRows(loaded))
+    }
+
+    // bigquery validates the query and estimates the bytes it would scan
+    // without running or billing it.
+    async fn dry_run(&self, stmt: &Statement) -> PgWireResult<DryRun> {
+        let Statement::Query(query) = stmt else {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                "only SELECT statements are supported in bigquery".to_owned(),
+            ))));
+        };
+        let mut query = query.clone();
+        ast::BigqueryAst
+            .rewrite(&self.dataset_id, &mut query)
+            .context("unable to rewrite query")
+            .map_err(|err| PgWireError::ApiError(err.into()))?;
+        let query = query.to_string();
+
+        let mut query_req = QueryRequest::new(&query);
+        query_req.dry_run = Some(true);
+        let result_set = self
+            .client
+            .job()
+            .query(&self.project_id, query_req)
+            .await
+            .map_err(|err| bq_error("error running dry run", err))?;
+
+        let estimated_bytes_processed = result_set
+            .query_response()
+            .total_bytes_processed
+            .as_deref()
+            .and_then(|bytes| bytes.parse::<i64>().ok());
+        if let Some(bytes) = estimated_bytes_processed {
+            tracing::info!("Query would process {}", format_bytes(bytes));
+        }
+        Ok(DryRun {
+            plan: query,
+            estimated_bytes_processed,
+        })
+    }
+
     // describe the output of the query
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        self.cursor_manager.move_cursor(name, direction).await
+    }
+
     async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
         // print the statement
         tracing::info!("[bigquery] describe: {}", stmt);
         // only support SELECT statements
         match stmt {
+            Statement::Explain { analyze: false, .. } => Ok(Some(explain_schema())),
             Statement::Query(query) => {
                 let mut query = query.clone();
                 ast::BigqueryAst
@@ -248,9 +597,17 @@ impl QueryExecutor for BigQueryQueryExecutor {
             }
             _ => PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "fdw_error".to_owned(),
+                "0A000".to_owned(),
                 "only SELECT statements are supported in bigquery".to_owned(),
             )))),
         }
     }
+
+    async fn set_session_parameter(&self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == QUERY_LABELS {
+            *self.query_labels.lock().unwrap() =
+                QueryLabels::parse(value).map_err(|err| PgWireError::ApiError(err.into()))?;
+        }
+        Ok(())
+    }
 }
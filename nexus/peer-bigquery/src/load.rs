@@ -0,0 +1,179 @@
+use gcp_bigquery_client::model::{field_type::FieldType, table_field_schema::TableFieldSchema};
+use peer_cursor::BulkLoadFormat;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use serde_json::{Map, Value as JsonValue};
+use sqlparser::ast::Ident;
+
+pub type JsonRow = Map<String, JsonValue>;
+
+fn user_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
+
+/// Parses CSV data as it arrives into rows keyed by column name, with each
+/// field coerced to the type of its column in the table so bad values are
+/// reported with their line instead of as a failed insert. Only the data
+/// after the last complete record is kept between chunks.
+pub struct CsvRows<'a> {
+    targets: Vec<&'a TableFieldSchema>,
+    delimiter: u8,
+    // whether the next part starts with the header
+    header: bool,
+    pending: Vec<u8>,
+    // lines before `pending`, to number the lines of errors
+    lines: u64,
+}
+
+impl<'a> CsvRows<'a> {
+    pub fn new(
+        format: BulkLoadFormat,
+        fields: &'a [TableFieldSchema],
+        columns: &[Ident],
+    ) -> PgWireResult<Self> {
+        let BulkLoadFormat::Csv { header, delimiter } = format else {
+            return Err(user_error(
+                "0A000",
+                "binary COPY data can only be loaded into postgres peers".to_owned(),
+            ));
+        };
+        let targets = if columns.is_empty() {
+            fields.iter().collect::<Vec<_>>()
+        } else {
+            columns
+                .iter()
+                .map(|column| {
+                    fields
+                        .iter()
+                        .find(|field| field.name.eq_ignore_ascii_case(&column.value))
+                        .ok_or_else(|| {
+                            user_error(
+                                "42703",
+                                format!("column \"{}\" does not exist", column.value),
+                            )
+                        })
+                })
+                .collect::<PgWireResult<Vec<_>>>()?
+        };
+        Ok(Self {
+            targets,
+            delimiter,
+            header,
+            pending: Vec::new(),
+            lines: 0,
+        })
+    }
+
+    /// Adds a chunk of the data and returns the rows of the records it
+    /// completed.
+    pub fn push(&mut self, chunk: &[u8]) -> PgWireResult<Vec<JsonRow>> {
+        self.pending.extend_from_slice(chunk);
+        let Some(end) = last_record_end(&self.pending) else {
+            return Ok(Vec::new());
+        };
+        let rest = self.pending.split_off(end);
+        let part = std::mem::replace(&mut self.pending, rest);
+        self.parse(&part)
+    }
+
+    /// The rows of the data after the last newline.
+    pub fn finish(mut self) -> PgWireResult<Vec<JsonRow>> {
+        let part = std::mem::take(&mut self.pending);
+        self.parse(&part)
+    }
+
+    fn parse(&mut self, data: &[u8]) -> PgWireResult<Vec<JsonRow>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(self.header)
+            .delimiter(self.delimiter)
+            .from_reader(data);
+        self.header = false;
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record =
+                record.map_err(|err| user_error("22P04", format!("bad CSV data: {}", err)))?;
+            let line = self.lines + record.position().map_or(0, |position| position.line());
+            if record.len() != self.targets.len() {
+                return Err(user_error(
+                    "22P04",
+                    format!(
+                        "line {}: expected {} fields, got {}",
+                        line,
+                        self.targets.len(),
+                        record.len()
+                    ),
+                ));
+            }
+
+            let mut row = JsonRow::with_capacity(self.targets.len());
+            for (field, value) in self.targets.iter().zip(record.iter()) {
+                let value = coerce(&field.r#type, value).ok_or_else(|| {
+                    user_error(
+                        "22P02",
+                        format!(
+                            "line {}: invalid input for column \"{}\" of type {:?}: \"{}\"",
+                            line, field.name, field.r#type, value
+                        ),
+                    )
+                })?;
+                row.insert(field.name.clone(), value);
+            }
+            rows.push(row);
+        }
+        self.lines += data.iter().filter(|b| **b == b'\n').count() as u64;
+        Ok(rows)
+    }
+}
+
+// the end of the last complete record, past its newline. Quotes in quoted
+// fields are doubled, so a newline after an odd number of quotes is within a
+// field.
+fn last_record_end(data: &[u8]) -> Option<usize> {
+    let mut quoted = false;
+    let mut end = None;
+    for (i, b) in data.iter().enumerate() {
+        match b {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => end = Some(i + 1),
+            _ => (),
+        }
+    }
+    end
+}
+
+// an empty field is NULL, as with COPY's CSV format.
+fn coerce(field_type: &FieldType, value: &str) -> Option<JsonValue> {
+    if value.is_empty() {
+        return Some(JsonValue::Null);
+    }
+    Some(match field_type {
+        FieldType::Int64 | FieldType::Integer => JsonValue::from(value.parse::<i64>().ok()?),
+        FieldType::Float | FieldType::Float64 => {
+            let float = value.parse::<f64>().ok()?;
+            // bigquery takes NaN and infinities as strings
+            serde_json::Number::from_f64(float)
+                .map_or_else(|| JsonValue::from(value), JsonValue::Number)
+        }
+        FieldType::Boolean | FieldType::Bool => match value.to_ascii_lowercase().as_str() {
+            "t" | "true" | "y" | "yes" | "on" | "1" => JsonValue::Bool(true),
+            "f" | "false" | "n" | "no" | "off" | "0" => JsonValue::Bool(false),
+            _ => return None,
+        },
+        FieldType::Numeric | FieldType::Bignumeric => {
+            value.parse::<rust_decimal::Decimal>().ok()?;
+            JsonValue::from(value)
+        }
+        FieldType::Record | FieldType::Struct => {
+            let object = serde_json::from_str::<JsonValue>(value).ok()?;
+            if !object.is_object() {
+                return None;
+            }
+            object
+        }
+        // bigquery parses dates, times, bytes (base64) and the rest itself
+        _ => JsonValue::from(value),
+    })
+}
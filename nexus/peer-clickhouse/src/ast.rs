@@ -0,0 +1,32 @@
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{visit_relations_mut, Expr, Offset, Query};
+
+pub fn rewrite_query(peername: &str, query: &mut Query) {
+    // tables are named by their clickhouse database, the peer name or a
+    // `public.` added by postgres clients is dropped
+    visit_relations_mut(query, |table| {
+        if table.0.len() > 1
+            && (peername.eq_ignore_ascii_case(&table.0[0].value) || table.0[0].value == "public")
+        {
+            table.0.remove(0);
+        }
+        ControlFlow::<()>::Continue(())
+    });
+
+    // postgres_fdw sends `limit 1` as `limit 1::bigint`, clickhouse wants a
+    // literal
+    if let Some(Expr::Cast { expr, .. }) = &query.limit {
+        query.limit = Some((**expr).clone());
+    }
+    if let Some(Offset {
+        value: Expr::Cast { expr, .. },
+        rows,
+    }) = &query.offset
+    {
+        query.offset = Some(Offset {
+            value: (**expr).clone(),
+            rows: *rows,
+        });
+    }
+}
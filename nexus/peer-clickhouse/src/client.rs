@@ -0,0 +1,121 @@
+use futures::StreamExt;
+use pgwire::error::{PgWireError, PgWireResult};
+use pt::peerdb_peers::ClickhouseConfig;
+use reqwest::{Certificate, Identity};
+use tokio::sync::mpsc;
+
+// lines read ahead of the client, the query waits on clickhouse beyond them
+const LINE_BUFFER: usize = 1024;
+
+/// Queries clickhouse over its HTTP interface, each response streamed back
+/// line by line.
+pub struct ChClient {
+    http: reqwest::Client,
+    url: String,
+    user: String,
+    password: String,
+    settings: Vec<(&'static str, String)>,
+}
+
+impl ChClient {
+    pub fn new(config: &ClickhouseConfig) -> anyhow::Result<Self> {
+        let mut http = reqwest::Client::builder();
+        if let Some(root_ca) = &config.root_ca {
+            http = http.add_root_certificate(Certificate::from_pem(root_ca.as_bytes())?);
+        }
+        if let (Some(certificate), Some(private_key)) = (&config.certificate, &config.private_key) {
+            let pem = format!("{}\n{}", private_key, certificate);
+            http = http.identity(Identity::from_pem(pem.as_bytes())?);
+        }
+        let (scheme, default_port) = if config.disable_tls {
+            ("http", 8123)
+        } else {
+            ("https", 8443)
+        };
+        Ok(Self {
+            http: http.build()?,
+            url: format!(
+                "{}://{}:{}/",
+                scheme,
+                config.host,
+                config.http_port.unwrap_or(default_port)
+            ),
+            user: config.user.clone(),
+            password: config.password.clone(),
+            settings: vec![
+                ("database", config.database.clone()),
+                (
+                    "default_format",
+                    "JSONCompactEachRowWithNamesAndTypes".to_owned(),
+                ),
+                // timestamps in UTC with their zone, decimals as strings so
+                // they keep their digits
+                ("date_time_output_format", "iso".to_owned()),
+                ("output_format_json_quote_decimals", "1".to_owned()),
+                // a stream dropped by nexus cancels its query
+                (
+                    "cancel_http_readonly_queries_on_client_close",
+                    "1".to_owned(),
+                ),
+            ],
+        })
+    }
+
+    /// Runs the query, returning the lines of its output. An error of
+    /// clickhouse past the first rows arrives as the last line.
+    pub async fn query(
+        &self,
+        query: String,
+    ) -> PgWireResult<mpsc::Receiver<PgWireResult<Vec<u8>>>> {
+        let response = self
+            .http
+            .post(&self.url)
+            .query(&self.settings)
+            .header("X-ClickHouse-User", &self.user)
+            .header("X-ClickHouse-Key", &self.password)
+            .body(query)
+            .send()
+            .await
+            .map_err(|err| PgWireError::ApiError(err.into()))?;
+        if !response.status().is_success() {
+            let message = response
+                .text()
+                .await
+                .map_err(|err| PgWireError::ApiError(err.into()))?;
+            return Err(PgWireError::ApiError(message.trim().to_owned().into()));
+        }
+
+        let (send, recv) = mpsc::channel(LINE_BUFFER);
+        tokio::spawn(async move {
+            let mut body = response.bytes_stream();
+            let mut pending = Vec::new();
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        send.send(Err(PgWireError::ApiError(err.into()))).await.ok();
+                        return;
+                    }
+                };
+                // only the new chunk can end the pending line
+                let mut searched = pending.len();
+                pending.extend_from_slice(&chunk);
+                while let Some(end) = pending[searched..].iter().position(|b| *b == b'\n') {
+                    let mut line = pending.split_off(searched + end + 1);
+                    std::mem::swap(&mut line, &mut pending);
+                    line.pop();
+                    searched = 0;
+                    // the rows were dropped, so is the response, which
+                    // cancels the query
+                    if send.send(Ok(line)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            if !pending.is_empty() {
+                send.send(Ok(pending)).await.ok();
+            }
+        });
+        Ok(recv)
+    }
+}
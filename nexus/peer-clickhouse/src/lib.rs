@@ -0,0 +1,197 @@
+mod ast;
+mod client;
+mod stream;
+
+use std::sync::Mutex;
+
+use peer_cursor::{
+    labels::{QueryLabels, QUERY_LABELS},
+    util::select_one,
+    CursorManager, CursorModification, PeerCapabilities, QueryExecutor, QueryOutput, RecordStream,
+    Schema,
+};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pt::peerdb_peers::ClickhouseConfig;
+use sqlparser::ast::{CloseCursor, Declare, Expr, FetchDirection, Statement, Value};
+use stream::ChRecordStream;
+
+pub struct ClickhouseQueryExecutor {
+    peer_name: String,
+    client: client::ChClient,
+    cursor_manager: CursorManager,
+    query_labels: Mutex<QueryLabels>,
+}
+
+impl ClickhouseQueryExecutor {
+    pub const CAPABILITIES: PeerCapabilities = PeerCapabilities {
+        queryable: true,
+        writable: false,
+        supports_cursors: true,
+        supports_transactions: false,
+        supports_copy: false,
+    };
+
+    pub async fn new(peer_name: String, config: &ClickhouseConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            peer_name,
+            client: client::ChClient::new(config)?,
+            cursor_manager: Default::default(),
+            query_labels: Mutex::default(),
+        })
+    }
+
+    async fn query(&self, query: String) -> PgWireResult<ChRecordStream> {
+        let query = self.query_labels.lock().unwrap().annotate(&query);
+        ChRecordStream::new(self.client.query(query).await?).await
+    }
+}
+
+#[async_trait::async_trait]
+impl QueryExecutor for ClickhouseQueryExecutor {
+    fn capabilities(&self) -> PeerCapabilities {
+        Self::CAPABILITIES
+    }
+
+    async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
+        // only support SELECT statements
+        match stmt {
+            Statement::Explain { statement, .. } => {
+                if let Statement::Query(ref query) = **statement {
+                    let mut query = query.clone();
+                    ast::rewrite_query(&self.peer_name, &mut query);
+                    tracing::info!("clickhouse rewritten query: {}", query);
+
+                    let cursor = self.query(format!("EXPLAIN {}", query)).await?;
+                    Ok(QueryOutput::Stream(Box::pin(cursor)))
+                } else {
+                    let error = format!(
+                        "only EXPLAIN SELECT statements are supported in clickhouse. got: {}",
+                        statement
+                    );
+                    Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "0A000".to_owned(),
+                        error,
+                    ))))
+                }
+            }
+            Statement::Query(query) => {
+                let mut query = query.clone();
+                ast::rewrite_query(&self.peer_name, &mut query);
+                let query = query.to_string();
+                tracing::info!("clickhouse rewritten query: {}", query);
+
+                let cursor = self.query(query).await?;
+                Ok(QueryOutput::Stream(Box::pin(cursor)))
+            }
+            Statement::Declare { stmts } => {
+                if stmts.len() != 1 {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports singular declare statements".into(),
+                    ))
+                } else if let Declare {
+                    ref names,
+                    for_query: Some(ref query),
+                    ..
+                } = stmts[0]
+                {
+                    let name = &names[0];
+                    let mut query = query.clone();
+                    ast::rewrite_query(&self.peer_name, &mut query);
+                    let query_stmt = Statement::Query(query);
+                    self.cursor_manager
+                        .create_cursor(&name.value, &query_stmt, self)
+                        .await?;
+
+                    Ok(QueryOutput::Cursor(CursorModification::Created(
+                        name.value.clone(),
+                    )))
+                } else {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports declare for query statements".into(),
+                    ))
+                }
+            }
+            Statement::Fetch {
+                name, direction, ..
+            } => {
+                let records = self.cursor_manager.fetch(&name.value, direction).await?;
+                Ok(QueryOutput::Records(records))
+            }
+            Statement::Close { cursor } => {
+                let closed_cursors = match cursor {
+                    CloseCursor::All => self.cursor_manager.close_all_cursors().await?,
+                    CloseCursor::Specific { name } => {
+                        self.cursor_manager.close(&name.value).await?;
+                        vec![name.value.clone()]
+                    }
+                };
+                Ok(QueryOutput::Cursor(CursorModification::Closed(
+                    closed_cursors,
+                )))
+            }
+            _ => {
+                let error = format!(
+                    "only SELECT statements are supported in clickhouse. got: {}",
+                    stmt
+                );
+                Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "0A000".to_owned(),
+                    error,
+                ))))
+            }
+        }
+    }
+
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        self.cursor_manager.move_cursor(name, direction).await
+    }
+
+    // the output of the query without its rows
+    async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
+        match stmt {
+            Statement::Query(query) => {
+                let mut query = query.clone();
+                ast::rewrite_query(&self.peer_name, &mut query);
+                query.limit = Some(Expr::Value(Value::Number(String::from("0"), false)));
+                Ok(Some(self.query(query.to_string()).await?.schema()))
+            }
+            Statement::Declare { stmts } => {
+                if stmts.len() != 1 {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports singular declare statements".into(),
+                    ))
+                } else if let Declare {
+                    for_query: Some(ref query),
+                    ..
+                } = stmts[0]
+                {
+                    let query_stmt = Statement::Query(query.clone());
+                    self.describe(&query_stmt).await
+                } else {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports declare for query statements".into(),
+                    ))
+                }
+            }
+            _ => PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                "only SELECT statements are supported in clickhouse".to_owned(),
+            )))),
+        }
+    }
+
+    async fn set_session_parameter(&self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == QUERY_LABELS {
+            *self.query_labels.lock().unwrap() =
+                QueryLabels::parse(value).map_err(|err| PgWireError::ApiError(err.into()))?;
+        }
+        Ok(())
+    }
+
+    async fn test_connection(&self) -> PgWireResult<()> {
+        select_one(self).await
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::Stream;
+use peer_cursor::{Record, RecordStream, Schema};
+use pgwire::{
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::{PgWireError, PgWireResult},
+};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use value::Value;
+
+// the most digits a Decimal holds, wider clickhouse numbers are passed on as
+// text
+const DECIMAL_PRECISION: u32 = 28;
+
+/// How the values of a clickhouse column are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChType {
+    Null,
+    Bool,
+    SmallInt,
+    Integer,
+    BigInt,
+    Numeric,
+    Float,
+    Double,
+    Text,
+    Uuid,
+    Date,
+    DateTime,
+    Json,
+}
+
+impl ChType {
+    fn parse(name: &str) -> Self {
+        let name = name.trim();
+        // wrappers that don't change how values are read, NULL is read as
+        // NULL whatever the type
+        for wrapper in ["Nullable(", "LowCardinality("] {
+            if let Some(inner) = name
+                .strip_prefix(wrapper)
+                .and_then(|inner| inner.strip_suffix(')'))
+            {
+                return Self::parse(inner);
+            }
+        }
+        let (base, args) = match name.split_once('(') {
+            Some((base, args)) => (base, args.trim_end_matches(')')),
+            None => (name, ""),
+        };
+        match base {
+            "Nothing" => ChType::Null,
+            "Bool" => ChType::Bool,
+            "Int8" | "UInt8" | "Int16" => ChType::SmallInt,
+            "UInt16" | "Int32" => ChType::Integer,
+            "UInt32" | "Int64" => ChType::BigInt,
+            "UInt64" | "Decimal32" | "Decimal64" => ChType::Numeric,
+            "Decimal" => {
+                let precision = args.split(',').next().unwrap_or_default().trim();
+                match precision.parse::<u32>() {
+                    Ok(precision) if precision <= DECIMAL_PRECISION => ChType::Numeric,
+                    _ => ChType::Text,
+                }
+            }
+            "Float32" => ChType::Float,
+            "Float64" => ChType::Double,
+            "UUID" => ChType::Uuid,
+            "Date" | "Date32" => ChType::Date,
+            "DateTime" | "DateTime64" => ChType::DateTime,
+            "Array" | "Map" | "Tuple" | "Nested" | "Object" | "JSON" => ChType::Json,
+            // strings, enums, IP addresses, 128 and 256 bit numbers
+            _ => ChType::Text,
+        }
+    }
+
+    fn pg_type(self) -> Type {
+        match self {
+            ChType::Null => Type::VOID,
+            ChType::Bool => Type::BOOL,
+            ChType::SmallInt => Type::INT2,
+            ChType::Integer => Type::INT4,
+            ChType::BigInt => Type::INT8,
+            ChType::Numeric => Type::NUMERIC,
+            ChType::Float => Type::FLOAT4,
+            ChType::Double => Type::FLOAT8,
+            ChType::Text => Type::TEXT,
+            ChType::Uuid => Type::UUID,
+            ChType::Date => Type::DATE,
+            ChType::DateTime => Type::TIMESTAMPTZ,
+            ChType::Json => Type::JSONB,
+        }
+    }
+
+    fn value(self, json: JsonValue) -> PgWireResult<Value> {
+        if json.is_null() {
+            return Ok(Value::Null);
+        }
+        let invalid = |json: &JsonValue| {
+            PgWireError::ApiError(
+                format!("unexpected {:?} value from clickhouse: {}", self, json).into(),
+            )
+        };
+        // 64 bit integers and decimals come quoted
+        let text = |json: &JsonValue| match json {
+            JsonValue::String(s) => s.clone(),
+            json => json.to_string(),
+        };
+        Ok(match self {
+            ChType::Null => Value::Null,
+            ChType::Bool => Value::Bool(json.as_bool().ok_or_else(|| invalid(&json))?),
+            ChType::SmallInt => Value::SmallInt(text(&json).parse().map_err(|_| invalid(&json))?),
+            ChType::Integer => Value::Integer(text(&json).parse().map_err(|_| invalid(&json))?),
+            ChType::BigInt => Value::BigInt(text(&json).parse().map_err(|_| invalid(&json))?),
+            ChType::Numeric => {
+                Value::Numeric(Decimal::from_str(&text(&json)).map_err(|_| invalid(&json))?)
+            }
+            ChType::Float => Value::Float(json.as_f64().ok_or_else(|| invalid(&json))? as f32),
+            ChType::Double => Value::Double(json.as_f64().ok_or_else(|| invalid(&json))?),
+            ChType::Text => Value::Text(text(&json)),
+            ChType::Uuid => {
+                Value::Uuid(uuid::Uuid::parse_str(&text(&json)).map_err(|_| invalid(&json))?)
+            }
+            ChType::Date => Value::Date(
+                NaiveDate::parse_from_str(&text(&json), "%Y-%m-%d").map_err(|_| invalid(&json))?,
+            ),
+            ChType::DateTime => Value::TimestampWithTimeZone(
+                DateTime::parse_from_rfc3339(&text(&json))
+                    .map_err(|_| invalid(&json))?
+                    .with_timezone(&Utc),
+            ),
+            ChType::Json => Value::JsonB(json),
+        })
+    }
+}
+
+/// The rows of a query in clickhouse's JSONCompactEachRowWithNamesAndTypes
+/// format: a line with the column names, one with their types, then a JSON
+/// array per row.
+pub struct ChRecordStream {
+    schema: Schema,
+    types: Arc<[ChType]>,
+    lines: ReceiverStream<PgWireResult<Vec<u8>>>,
+}
+
+// a line that isn't the JSON expected is the error clickhouse ran into
+fn parse_line<T: DeserializeOwned>(line: PgWireResult<Vec<u8>>) -> PgWireResult<T> {
+    let line = line?;
+    serde_json::from_slice(&line)
+        .map_err(|_| PgWireError::ApiError(String::from_utf8_lossy(&line).trim().to_owned().into()))
+}
+
+async fn header(lines: &mut mpsc::Receiver<PgWireResult<Vec<u8>>>) -> PgWireResult<Vec<String>> {
+    let line = lines
+        .recv()
+        .await
+        .ok_or_else(|| PgWireError::ApiError("clickhouse returned no columns".into()))?;
+    parse_line(line)
+}
+
+impl ChRecordStream {
+    pub async fn new(mut lines: mpsc::Receiver<PgWireResult<Vec<u8>>>) -> PgWireResult<Self> {
+        let names = header(&mut lines).await?;
+        let types = header(&mut lines)
+            .await?
+            .iter()
+            .map(|name| ChType::parse(name))
+            .collect::<Arc<[_]>>();
+        let schema = Arc::new(
+            names
+                .into_iter()
+                .zip(types.iter())
+                .map(|(name, ch_type)| {
+                    FieldInfo::new(name, None, None, ch_type.pg_type(), FieldFormat::Text)
+                })
+                .collect(),
+        );
+        Ok(Self {
+            schema,
+            types,
+            lines: ReceiverStream::new(lines),
+        })
+    }
+
+    fn record(&self, line: PgWireResult<Vec<u8>>) -> PgWireResult<Record> {
+        let row = parse_line::<Vec<JsonValue>>(line)?;
+        if row.len() != self.types.len() {
+            return Err(PgWireError::ApiError(
+                format!(
+                    "clickhouse returned a row of {} values for {} columns",
+                    row.len(),
+                    self.types.len()
+                )
+                .into(),
+            ));
+        }
+        let values = row
+            .into_iter()
+            .zip(self.types.iter())
+            .map(|(json, ch_type)| ch_type.value(json))
+            .collect::<PgWireResult<Vec<_>>>()?;
+        Ok(Record {
+            schema: self.schema.clone(),
+            values,
+        })
+    }
+}
+
+impl Stream for ChRecordStream {
+    type Item = PgWireResult<Record>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.lines).poll_next(cx) {
+            Poll::Ready(Some(line)) => Poll::Ready(Some(self.record(line))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl RecordStream for ChRecordStream {
+    fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+}
@@ -72,8 +72,8 @@ impl PeerConnectionTracker {
             .await
             .context("Failed to get connection from pool")?;
         conn.execute(
-            "UPDATE peer_connections SET closed_at = NOW() WHERE id = $1",
-            &[&token.trace_id.unwrap()],
+            "UPDATE peer_connections SET closed_at = NOW(), bytes_processed = $2 WHERE id = $1",
+            &[&token.trace_id.unwrap(), &token.bytes_processed],
         )
         .await
         .context("Failed to update peer_connections")?;
@@ -86,6 +86,7 @@ pub struct TrackingToken<'a> {
     peer_name: &'a str,
     query: &'a str,
     trace_id: Option<i32>,
+    bytes_processed: Option<i64>,
 }
 
 impl<'a> TrackingToken<'a> {
@@ -99,11 +100,18 @@ impl<'a> TrackingToken<'a> {
             peer_name,
             query,
             trace_id: None,
+            bytes_processed: None,
         };
         tracker.record_start(&mut token).await?;
         Ok(token)
     }
 
+    /// Records the bytes the peer reported processing for the query, for
+    /// peers that bill by bytes scanned.
+    pub fn set_bytes_processed(&mut self, bytes: i64) {
+        self.bytes_processed = Some(bytes);
+    }
+
     pub async fn end(self) -> anyhow::Result<()> {
         self.tracker.record_end(&self).await?;
         Ok(())
@@ -0,0 +1,130 @@
+//! How much encoding wide rows holds up other tasks on the runtime, with the
+//! rows encoded on the runtime and on an `EncodePool`.
+//!
+//! A task ticking every millisecond stands in for the I/O of other
+//! connections, its worst delay is reported next to the encoding throughput.
+//!
+//! cargo bench -p peer-cursor --bench encode_offload
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use peer_cursor::{
+    util::{encode_record, EncodePool},
+    Record, Schema,
+};
+use pgwire::api::{
+    results::{FieldFormat, FieldInfo},
+    Type,
+};
+use tokio::sync::watch;
+use value::Value;
+
+const STREAMS: usize = 8;
+const BATCHES: usize = 200;
+const BATCH_ROWS: usize = 256;
+const COLUMNS: usize = 40;
+
+fn schema() -> Schema {
+    Arc::new(
+        (0..COLUMNS)
+            .map(|i| {
+                let datatype = if i % 2 == 0 { Type::TEXT } else { Type::FLOAT8 };
+                FieldInfo::new(format!("c{}", i), None, None, datatype, FieldFormat::Text)
+            })
+            .collect(),
+    )
+}
+
+fn batch(schema: &Schema) -> Vec<Record> {
+    (0..BATCH_ROWS)
+        .map(|row| Record {
+            values: (0..COLUMNS)
+                .map(|i| {
+                    if i % 2 == 0 {
+                        Value::Text(format!("row {} column {} of a wide table", row, i))
+                    } else {
+                        Value::Double(row as f64 * 1.5 + i as f64)
+                    }
+                })
+                .collect(),
+            schema: schema.clone(),
+        })
+        .collect()
+}
+
+// the worst delay of a 1ms tick until `stop` changes
+async fn tick_lag(mut stop: watch::Receiver<bool>) -> Duration {
+    let mut worst = Duration::ZERO;
+    loop {
+        let start = Instant::now();
+        tokio::select! {
+            _ = stop.changed() => return worst,
+            _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+        }
+        worst = worst.max(start.elapsed().saturating_sub(Duration::from_millis(1)));
+    }
+}
+
+async fn run(pool: Option<Arc<EncodePool>>) -> (Duration, Duration) {
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let ticker = tokio::spawn(tick_lag(stop_rx));
+    let start = Instant::now();
+
+    let streams: Vec<_> = (0..STREAMS)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let schema = schema();
+                for _ in 0..BATCHES {
+                    let records = batch(&schema);
+                    match &pool {
+                        Some(pool) => {
+                            let records = records.into_iter().map(Ok).collect();
+                            for row in pool.encode(schema.clone(), records).await {
+                                row.unwrap();
+                            }
+                        }
+                        None => {
+                            for record in &records {
+                                encode_record(&schema, record).unwrap();
+                            }
+                        }
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+    for stream in streams {
+        stream.await.unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    stop_tx.send(true).unwrap();
+    (elapsed, ticker.await.unwrap())
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+    let rows = STREAMS * BATCHES * BATCH_ROWS;
+
+    for (name, pool) in [
+        ("runtime", None),
+        ("pool(4)", Some(Arc::new(EncodePool::new(4).unwrap()))),
+    ] {
+        let (elapsed, lag) = runtime.block_on(run(pool));
+        println!(
+            "{:<8} {:>9.0} rows/s, worst tick delay {:>8.3} ms",
+            name,
+            rows as f64 / elapsed.as_secs_f64(),
+            lag.as_secs_f64() * 1000.0
+        );
+    }
+}
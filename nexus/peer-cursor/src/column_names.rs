@@ -0,0 +1,202 @@
+//! Names of result columns the way postgres names them, so a query returns
+//! the same column names whatever peer it runs on.
+//!
+//! Peers name unaliased expressions their own way, e.g. BigQuery's `f0_` for
+//! `SELECT a + b`. Postgres names an expression after the column, function or
+//! type it ends in, and `?column?` when there is none. Aliases are kept, and
+//! folded to lower case like postgres does unless they are quoted. Plain
+//! column references keep the name the peer reports.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{Stream, StreamExt};
+use pgwire::{api::results::FieldInfo, error::PgWireResult};
+use sqlparser::ast::{
+    DataType, Expr, Ident, Query, SelectItem, SetExpr, Statement, TimezoneInfo, Value,
+};
+
+use crate::{QueryOutput, Record, RecordStream, Schema, SendableStream};
+
+const UNNAMED_COLUMN: &str = "?column?";
+
+/// The peer's schema of the statement's result with postgres' names for the
+/// aliased and expression columns. The schema is returned as is when its
+/// columns can't be matched with the statement's projection, e.g. for
+/// `SELECT *`.
+pub fn postgres_column_names(stmt: &Statement, schema: &Schema) -> Schema {
+    let Statement::Query(query) = stmt else {
+        return schema.clone();
+    };
+    let Some(projection) = projection(query) else {
+        return schema.clone();
+    };
+    if projection.len() != schema.len() {
+        return schema.clone();
+    }
+
+    Arc::new(
+        projection
+            .iter()
+            .zip(schema.iter())
+            .map(|(item, field)| {
+                let name = match item {
+                    SelectItem::ExprWithAlias { alias, .. } => Some(ident_name(alias)),
+                    SelectItem::UnnamedExpr(Expr::Identifier(_) | Expr::CompoundIdentifier(_)) => {
+                        None
+                    }
+                    SelectItem::UnnamedExpr(expr) => {
+                        Some(expr_name(expr).unwrap_or_else(|| UNNAMED_COLUMN.to_owned()))
+                    }
+                    _ => None,
+                };
+                match name {
+                    Some(name) if name != field.name() => FieldInfo::new(
+                        name,
+                        field.table_id(),
+                        field.column_id(),
+                        field.datatype().clone(),
+                        field.format(),
+                    ),
+                    _ => field.clone(),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// The output with `postgres_column_names` applied to the schema of its rows.
+pub fn with_postgres_column_names(stmt: &Statement, output: QueryOutput) -> QueryOutput {
+    match output {
+        QueryOutput::Stream(stream) => {
+            let schema = postgres_column_names(stmt, &stream.schema());
+            QueryOutput::Stream(Box::pin(RenamedStream { stream, schema }))
+        }
+        QueryOutput::Records(mut records) => {
+            let schema = postgres_column_names(stmt, &records.schema);
+            for record in records.records.iter_mut() {
+                record.schema = schema.clone();
+            }
+            records.schema = schema;
+            QueryOutput::Records(records)
+        }
+        output => output,
+    }
+}
+
+// the select list naming the columns, that of the first query of a UNION and
+// the like. Lists with wildcards don't tell how many columns there are.
+fn projection(query: &Query) -> Option<&[SelectItem]> {
+    let mut body = query.body.as_ref();
+    let select = loop {
+        body = match body {
+            SetExpr::Select(select) => break select,
+            SetExpr::Query(query) => query.body.as_ref(),
+            SetExpr::SetOperation { left, .. } => left.as_ref(),
+            _ => return None,
+        };
+    };
+    let has_wildcard = select.projection.iter().any(|item| {
+        matches!(
+            item,
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..)
+        )
+    });
+    (!has_wildcard).then_some(select.projection.as_slice())
+}
+
+// postgres' name of an expression, None for `?column?`
+fn expr_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(ident) => Some(ident_name(ident)),
+        Expr::CompoundIdentifier(idents) => idents.last().map(ident_name),
+        Expr::Nested(expr) => expr_name(expr),
+        Expr::Cast {
+            expr, data_type, ..
+        } => expr_name(expr).or_else(|| Some(type_name(data_type))),
+        Expr::Function(function) => function.name.0.last().map(ident_name),
+        Expr::Case { .. } => Some("case".to_owned()),
+        Expr::Exists { negated: false, .. } => Some("exists".to_owned()),
+        Expr::Array(_) | Expr::ArraySubquery(_) => Some("array".to_owned()),
+        Expr::Tuple(_) => Some("row".to_owned()),
+        Expr::Extract { .. } => Some("extract".to_owned()),
+        Expr::Position { .. } => Some("position".to_owned()),
+        Expr::Substring { .. } => Some("substring".to_owned()),
+        Expr::Trim { .. } => Some("btrim".to_owned()),
+        Expr::Interval(_) => Some("interval".to_owned()),
+        Expr::TypedString { data_type, .. } => Some(type_name(data_type)),
+        Expr::Value(Value::Boolean(_)) => Some("bool".to_owned()),
+        Expr::Subquery(query) => {
+            projection(query)
+                .and_then(|items| items.first())
+                .and_then(|item| match item {
+                    SelectItem::ExprWithAlias { alias, .. } => Some(ident_name(alias)),
+                    SelectItem::UnnamedExpr(expr) => expr_name(expr),
+                    _ => None,
+                })
+        }
+        _ => None,
+    }
+}
+
+// the name postgres gives the type, e.g. `int4` for INTEGER
+fn type_name(data_type: &DataType) -> String {
+    let name = match data_type {
+        DataType::SmallInt(_) => "int2",
+        DataType::Int(_) | DataType::Integer(_) => "int4",
+        DataType::BigInt(_) => "int8",
+        DataType::Real | DataType::Float4 => "float4",
+        DataType::Double | DataType::DoublePrecision | DataType::Float8 => "float8",
+        DataType::Numeric(_) | DataType::Decimal(_) => "numeric",
+        DataType::Bool | DataType::Boolean => "bool",
+        DataType::Char(_) | DataType::Character(_) => "bpchar",
+        DataType::Varchar(_) | DataType::CharacterVarying(_) => "varchar",
+        DataType::Text => "text",
+        DataType::Bytea => "bytea",
+        DataType::Uuid => "uuid",
+        DataType::JSONB => "jsonb",
+        DataType::Date => "date",
+        DataType::Timestamp(_, TimezoneInfo::WithTimeZone | TimezoneInfo::Tz) => "timestamptz",
+        DataType::Timestamp(..) => "timestamp",
+        data_type => return data_type.to_string().to_lowercase(),
+    };
+    name.to_owned()
+}
+
+// identifiers are folded to lower case unless quoted
+fn ident_name(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+struct RenamedStream {
+    stream: SendableStream,
+    schema: Schema,
+}
+
+impl Stream for RenamedStream {
+    type Item = PgWireResult<Record>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let schema = self.schema.clone();
+        self.stream.poll_next_unpin(cx).map(|record| {
+            record.map(|record| {
+                record.map(|record| Record {
+                    values: record.values,
+                    schema,
+                })
+            })
+        })
+    }
+}
+
+impl RecordStream for RenamedStream {
+    fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+}
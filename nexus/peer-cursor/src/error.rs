@@ -0,0 +1,77 @@
+//! Errors of peers with the SQLSTATE they are reported to the client with.
+//!
+//! Executors return a [`PeerError`] as a `PgWireError::ApiError`, the code
+//! stays with the error through the layers that only pass it on, and nexus
+//! turns it into the error response with [`client_error`]. Other API errors
+//! are internal errors.
+
+use std::fmt;
+
+use pgwire::error::{ErrorInfo, PgWireError};
+
+/// The SQLSTATEs peer errors are mapped to, named like postgres does.
+pub mod sqlstate {
+    pub const CONNECTION_FAILURE: &str = "08006";
+    pub const FEATURE_NOT_SUPPORTED: &str = "0A000";
+    pub const INVALID_AUTHORIZATION_SPECIFICATION: &str = "28000";
+    pub const INVALID_CURSOR_NAME: &str = "34000";
+    pub const SYNTAX_ERROR: &str = "42601";
+    pub const INSUFFICIENT_PRIVILEGE: &str = "42501";
+    pub const UNDEFINED_TABLE: &str = "42P01";
+    pub const DUPLICATE_TABLE: &str = "42P07";
+    pub const CONFIGURATION_LIMIT_EXCEEDED: &str = "53400";
+    pub const PROGRAM_LIMIT_EXCEEDED: &str = "54000";
+    pub const QUERY_CANCELED: &str = "57014";
+    pub const SYSTEM_ERROR: &str = "58000";
+    pub const INTERNAL_ERROR: &str = "XX000";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerError {
+    sqlstate: String,
+    message: String,
+}
+
+impl PeerError {
+    pub fn new(sqlstate: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            sqlstate: sqlstate.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn sqlstate(&self) -> &str {
+        &self.sqlstate
+    }
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PeerError {}
+
+impl From<PeerError> for PgWireError {
+    fn from(err: PeerError) -> Self {
+        PgWireError::ApiError(Box::new(err))
+    }
+}
+
+/// The error as sent to the client: peer errors with their SQLSTATE, other
+/// API errors as internal errors. Errors already carrying a code are kept.
+pub fn client_error(err: PgWireError) -> PgWireError {
+    let err = match err {
+        PgWireError::ApiError(err) => match err.downcast::<PeerError>() {
+            Ok(err) => *err,
+            Err(err) => PeerError::new(sqlstate::INTERNAL_ERROR, err.to_string()),
+        },
+        err => return err,
+    };
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        err.sqlstate,
+        err.message,
+    )))
+}
@@ -0,0 +1,98 @@
+//! Labels attached to the queries of a session with
+//! `SET peerdb.query_labels = 'team=data,job=nightly'`, sent along to the
+//! peers so their usage can be attributed: as job labels on BigQuery, in the
+//! `application_name` on postgres and as a comment before the query text on
+//! the other peers.
+
+use std::fmt;
+
+/// The session parameter the labels are set with, and forwarded to the
+/// executors under.
+pub const QUERY_LABELS: &str = "peerdb.query_labels";
+
+// the limits of BigQuery labels, which hold for the other peers as well
+const MAX_LABELS: usize = 64;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryLabels(Vec<(String, String)>);
+
+impl QueryLabels {
+    /// Parses comma separated `key=value` pairs. Keys start with a lower case
+    /// letter, keys and values consist of lower case letters, digits, `_` and
+    /// `-`, so they can't break out of a comment or a quoted string.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut labels: Vec<(String, String)> = Vec::new();
+        for pair in value
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("expected key=value, got \"{}\"", pair));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if !key.starts_with(|c: char| c.is_ascii_lowercase()) {
+                return Err(format!(
+                    "label key \"{}\" must start with a lower case letter",
+                    key
+                ));
+            }
+            for part in [key, value] {
+                if part.len() > MAX_LABEL_LEN {
+                    return Err(format!(
+                        "\"{}\" is longer than {} characters",
+                        part, MAX_LABEL_LEN
+                    ));
+                }
+                if !part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+                {
+                    return Err(format!(
+                        "\"{}\" may only contain lower case letters, digits, _ and -",
+                        part
+                    ));
+                }
+            }
+            match labels.iter_mut().find(|(k, _)| k == key) {
+                Some(label) => label.1 = value.to_owned(),
+                None => labels.push((key.to_owned(), value.to_owned())),
+            }
+        }
+        if labels.len() > MAX_LABELS {
+            return Err(format!("at most {} labels are allowed", MAX_LABELS));
+        }
+        Ok(Self(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The query text with the labels in a comment before it, for peers
+    /// without a better place for them.
+    pub fn annotate(&self, query: &str) -> String {
+        if self.is_empty() {
+            query.to_owned()
+        } else {
+            format!("/* {} */ {}", self, query)
+        }
+    }
+}
+
+impl fmt::Display for QueryLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
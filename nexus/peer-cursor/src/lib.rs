@@ -1,10 +1,17 @@
 use std::{pin::Pin, sync::Arc};
 
+use bytes::Bytes;
 use futures::Stream;
-use pgwire::{api::results::FieldInfo, error::PgWireResult};
-use sqlparser::ast::Statement;
+use pgwire::{
+    api::{results::FieldInfo, Type},
+    error::{ErrorInfo, PgWireError, PgWireResult},
+};
+use sqlparser::ast::{FetchDirection, Ident, ObjectName, Statement};
 use value::Value;
 
+pub mod column_names;
+pub mod error;
+pub mod labels;
 mod manager;
 pub mod util;
 
@@ -12,6 +19,7 @@ pub use manager::CursorManager;
 
 pub type Schema = Arc<Vec<FieldInfo>>;
 
+#[derive(Clone)]
 pub struct Record {
     pub values: Vec<Value>,
     pub schema: Schema,
@@ -44,14 +52,201 @@ pub enum QueryOutput {
     Cursor(CursorModification),
 }
 
+/// What nexus can do with the peers of a type, listed by `peerdb.peer_types`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    /// SELECT queries run on the peer.
+    pub queryable: bool,
+    /// Statements other than queries, like INSERT or DDL, run on the peer.
+    pub writable: bool,
+    /// Cursors can be declared on the peer's queries and fetched from.
+    pub supports_cursors: bool,
+    /// BEGIN, COMMIT and ROLLBACK run on the peer.
+    pub supports_transactions: bool,
+    /// Rows can be copied into the peer's tables with `IMPORT INTO`.
+    pub supports_copy: bool,
+}
+
+impl PeerCapabilities {
+    /// Peers nexus can create but not run statements on.
+    pub const NONE: Self = Self {
+        queryable: false,
+        writable: false,
+        supports_cursors: false,
+        supports_transactions: false,
+        supports_copy: false,
+    };
+}
+
+/// The outcome of planning a statement without running it.
+pub struct DryRun {
+    /// The plan of the statement as reported by the peer, or the statement
+    /// sent to the peer when the peer doesn't report plans.
+    pub plan: String,
+    /// Bytes the statement is estimated to scan, for peers billing by bytes.
+    pub estimated_bytes_processed: Option<i64>,
+}
+
+/// Format of the data handed to `QueryExecutor::bulk_load`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkLoadFormat {
+    /// Comma separated values as written by postgres' `COPY ... (FORMAT csv)`,
+    /// optionally starting with a header line that is skipped.
+    Csv { header: bool, delimiter: u8 },
+    /// Postgres' binary COPY format, signature and trailer included, as
+    /// `QueryExecutor::copy_out_binary` of a postgres peer produces it. The
+    /// fields have to be of the types of the columns they are loaded into.
+    Binary,
+}
+
+/// Rows encoded in postgres' binary COPY format by the peer itself.
+pub struct BinaryCopy {
+    /// The number of columns of each row.
+    pub columns: usize,
+    pub data: ByteStream,
+}
+
+/// Chunks of the data to load, split at arbitrary points.
+pub type ByteStream = Pin<Box<dyn Stream<Item = PgWireResult<Bytes>> + Send>>;
+
 #[async_trait::async_trait]
 pub trait QueryExecutor: Send + Sync {
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput>;
     async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>>;
+
+    /// Infers the types of the statement's `$n` parameters, for clients that
+    /// prepare it without declaring them. None when the peer can't tell.
+    async fn parameter_types(&self, _stmt: &Statement) -> PgWireResult<Option<Vec<Type>>> {
+        Ok(None)
+    }
+
+    /// Lists the columns of a table on the peer as records following
+    /// `util::describe_table_schema`. `schema` is None when the table name
+    /// wasn't qualified.
+    async fn describe_table(
+        &self,
+        _schema: Option<&str>,
+        _table: &str,
+    ) -> PgWireResult<QueryOutput> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "DESCRIBE is not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// Lists the columns of all tables of the peer, or of the tables in
+    /// `schema`, as records following `util::export_schema_schema`.
+    async fn export_schema(&self, _schema: Option<&str>) -> PgWireResult<QueryOutput> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "EXPORT SCHEMA is not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// Repositions a cursor declared on the peer for `MOVE`, without
+    /// returning rows. Returns the number of rows moved over.
+    async fn move_cursor(&self, _name: &str, _direction: &FetchDirection) -> PgWireResult<usize> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "MOVE is not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// Plans the statement without running it, for `SET peerdb.dry_run = on`.
+    async fn dry_run(&self, _stmt: &Statement) -> PgWireResult<DryRun> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "dry run is not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// Loads rows into an existing table of the peer, for `IMPORT INTO`.
+    /// `table` is the name without the peer and `columns` lists the table's
+    /// columns in the order of the fields, all columns when empty. Returns
+    /// the number of rows loaded as `QueryOutput::AffectedRows`.
+    ///
+    /// Empty CSV fields are loaded as NULL. Peers load all rows or none where
+    /// they can, bigquery's insertAll keeps the rows inserted before a
+    /// failure.
+    async fn bulk_load(
+        &self,
+        _table: &ObjectName,
+        _columns: &[Ident],
+        _format: BulkLoadFormat,
+        _data: ByteStream,
+    ) -> PgWireResult<QueryOutput> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "IMPORT is not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// The rows of a query in postgres' binary COPY format, for
+    /// `COPY ... TO STDOUT (FORMAT binary)` to pass them through without
+    /// decoding and encoding them again. None when the peer can't produce
+    /// them, nexus encodes the rows of the query instead.
+    async fn copy_out_binary(&self, _stmt: &Statement) -> PgWireResult<Option<BinaryCopy>> {
+        Ok(None)
+    }
+
+    /// What the peer supports, queries only unless the executor says more.
+    fn capabilities(&self) -> PeerCapabilities {
+        PeerCapabilities {
+            queryable: true,
+            ..PeerCapabilities::NONE
+        }
+    }
+
+    /// Applies a session parameter set by the client (e.g. `statement_timeout`)
+    /// on the upstream connection. Executors that can't enforce it ignore it.
+    async fn set_session_parameter(&self, _name: &str, _value: &str) -> PgWireResult<()> {
+        Ok(())
+    }
+
+    /// Asks the peer to cancel the query running on the connection, for a
+    /// client's CancelRequest. Executors that can't leave the query running,
+    /// nexus stops waiting for it all the same.
+    async fn cancel(&self) -> PgWireResult<()> {
+        Ok(())
+    }
+
+    /// Checks the peer answers queries with the credentials of its config,
+    /// with the cheapest round trip the peer has, for `TEST PEER` and
+    /// `CREATE PEER`. `util::select_one` does for peers without a cheaper one.
+    async fn test_connection(&self) -> PgWireResult<()> {
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            "connection tests are not supported for this peer".to_owned(),
+        ))))
+    }
+
+    /// Whether the connection to the peer was lost, e.g. closed by the peer
+    /// while idle. Executors without a long-lived connection never are.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Whether a transaction block is open on the peer connection, which
+    /// can't be recovered if the connection is lost.
+    fn in_transaction(&self) -> bool {
+        false
+    }
 }
 
 pub struct Cursor {
+    // 0 before the first row, n on the nth row, one past the last row once
+    // read past it
     position: usize,
+    // the row the cursor is on
+    current: Option<Record>,
+    // whether the stream ended, it isn't polled again
+    exhausted: bool,
     stream: SendableStream,
     schema: Schema,
 }
@@ -1,10 +1,10 @@
-use dashmap::DashMap;
+use dashmap::{mapref::one::RefMut, DashMap};
 
 use futures::StreamExt;
 use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
-use sqlparser::ast::Statement;
+use sqlparser::ast::{FetchDirection, Statement, Value};
 
-use crate::{Cursor, QueryExecutor, QueryOutput, Records};
+use crate::{Cursor, QueryExecutor, QueryOutput, Record, Records};
 
 #[derive(Default)]
 pub struct CursorManager {
@@ -26,6 +26,8 @@ impl CursorManager {
 
                 let cursor = Cursor {
                     position: 0,
+                    current: None,
+                    exhausted: false,
                     stream,
                     schema,
                 };
@@ -38,41 +40,75 @@ impl CursorManager {
             }
             _ => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "fdw_error".to_owned(),
+                "0A000".to_owned(),
                 "Only SELECT queries can be used with cursors".to_owned(),
             )))),
         }
     }
 
-    pub async fn fetch(&self, name: &str, count: usize) -> PgWireResult<Records> {
-        let mut cursor = self.cursors.get_mut(name).ok_or_else(|| {
-            PgWireError::UserError(Box::new(ErrorInfo::new(
-                "ERROR".to_owned(),
-                "fdw_error".to_owned(),
-                format!("Cursor {} does not exist", name),
-            )))
-        })?;
+    /// Reads the rows of a `FETCH` in its direction. Cursors of peers only go
+    /// forward: directions that go back to rows already read fail, `LAST`
+    /// reads up to the last row.
+    pub async fn fetch(&self, name: &str, direction: &FetchDirection) -> PgWireResult<Records> {
+        let mut cursor = self.cursor(name)?;
+        let step = Step::of(name, &cursor, direction)?;
 
         let mut records = Vec::new();
-        while records.len() < count {
-            match cursor.stream.next().await {
-                Some(Ok(record)) => {
-                    records.push(record);
+        match step {
+            Step::Forward(count) => {
+                while records.len() < count {
+                    match cursor.next().await? {
+                        Some(record) => records.push(record),
+                        None => break,
+                    }
+                }
+            }
+            Step::Skip(skip) => {
+                if cursor.skip(skip).await? == skip {
+                    records.extend(cursor.next().await?);
                 }
-                Some(Err(err)) => return Err(err),
-                None => break,
             }
+            Step::Current => records.extend(cursor.current.clone()),
+            Step::Last => records.extend(cursor.last().await?),
         }
 
         tracing::info!("Cursor {} fetched {} records", name, records.len());
-        cursor.position += records.len();
-
         Ok(Records {
             records,
             schema: cursor.schema.clone(),
         })
     }
 
+    /// Repositions the cursor for `MOVE`, skipping rows without returning
+    /// them, in the directions `fetch` takes. Returns the number of rows
+    /// skipped.
+    pub async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        let mut cursor = self.cursor(name)?;
+        let moved = match Step::of(name, &cursor, direction)? {
+            Step::Forward(count) => cursor.skip(count).await?,
+            Step::Skip(skip) => cursor.skip(skip.saturating_add(1)).await?,
+            Step::Current => 0,
+            Step::Last => {
+                let position = cursor.position;
+                cursor.last().await?;
+                cursor.position.saturating_sub(position)
+            }
+        };
+
+        tracing::info!("Cursor {} moved over {} records", name, moved);
+        Ok(moved)
+    }
+
+    fn cursor(&self, name: &str) -> PgWireResult<RefMut<'_, String, Cursor>> {
+        self.cursors.get_mut(name).ok_or_else(|| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "34000".to_owned(),
+                format!("Cursor {} does not exist", name),
+            )))
+        })
+    }
+
     pub async fn close(&self, name: &str) -> PgWireResult<()> {
         tracing::info!("Removing cursor {}", name);
 
@@ -81,7 +117,7 @@ impl CursorManager {
             .ok_or_else(|| {
                 PgWireError::UserError(Box::new(ErrorInfo::new(
                     "ERROR".to_owned(),
-                    "fdw_error".to_owned(),
+                    "34000".to_owned(),
                     format!("Cursor {} does not exist", name),
                 )))
             })
@@ -100,3 +136,124 @@ impl CursorManager {
         Ok(keys)
     }
 }
+
+// where a FETCH or MOVE takes a cursor
+enum Step {
+    // the next rows, up to the count
+    Forward(usize),
+    // skips rows and takes the one after them
+    Skip(usize),
+    // the row the cursor is on
+    Current,
+    // the last row, reading all rows up to it
+    Last,
+}
+
+impl Step {
+    fn of(name: &str, cursor: &Cursor, direction: &FetchDirection) -> PgWireResult<Self> {
+        let backward = || {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "55000".to_owned(),
+                format!(
+                    "cursor {} can only scan forward, it is at row {}",
+                    name, cursor.position
+                ),
+            )))
+        };
+        let count = |limit: &Value| match limit {
+            Value::Number(n, _) => n
+                .parse::<i64>()
+                .map_err(|err| PgWireError::ApiError(err.into())),
+            limit => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "22023".to_owned(),
+                format!("invalid row count {} for cursor {}", limit, name),
+            )))),
+        };
+        // the position of a row ahead, as rows to skip before it
+        let to_row = |row: i64| -> PgWireResult<Self> {
+            match usize::try_from(row) {
+                Ok(row) if row == cursor.position => Ok(Step::Current),
+                Ok(row) if row > cursor.position => Ok(Step::Skip(row - cursor.position - 1)),
+                _ => Err(backward()),
+            }
+        };
+
+        match direction {
+            FetchDirection::Next | FetchDirection::Forward { limit: None } => Ok(Step::Forward(1)),
+            FetchDirection::All | FetchDirection::ForwardAll => Ok(Step::Forward(usize::MAX)),
+            FetchDirection::Count { limit } | FetchDirection::Forward { limit: Some(limit) } => {
+                match count(limit)? {
+                    0 => Ok(Step::Current),
+                    n if n > 0 => Ok(Step::Forward(n as usize)),
+                    _ => Err(backward()),
+                }
+            }
+            FetchDirection::First => to_row(1),
+            // past the last row, it was left behind
+            FetchDirection::Last if cursor.past_end() && cursor.position > 1 => Err(backward()),
+            FetchDirection::Last => Ok(Step::Last),
+            FetchDirection::Absolute { limit } => match count(limit)? {
+                -1 if cursor.past_end() && cursor.position > 1 => Err(backward()),
+                -1 => Ok(Step::Last),
+                n => to_row(n),
+            },
+            FetchDirection::Relative { limit } => match count(limit)? {
+                n if n >= 0 => to_row((cursor.position as i64).saturating_add(n)),
+                _ => Err(backward()),
+            },
+            FetchDirection::Prior
+            | FetchDirection::Backward { .. }
+            | FetchDirection::BackwardAll => Err(backward()),
+        }
+    }
+}
+
+impl Cursor {
+    fn past_end(&self) -> bool {
+        self.exhausted && self.current.is_none() && self.position > 0
+    }
+
+    // the next row, which the cursor is on after it
+    async fn next(&mut self) -> PgWireResult<Option<Record>> {
+        if !self.exhausted {
+            match self.stream.next().await {
+                Some(Ok(record)) => {
+                    self.position += 1;
+                    self.current = Some(record.clone());
+                    return Ok(Some(record));
+                }
+                Some(Err(err)) => return Err(err),
+                None => self.exhausted = true,
+            }
+        }
+        // past the last row, counted as a position like postgres does
+        if self.current.take().is_some() || self.position == 0 {
+            self.position += 1;
+        }
+        Ok(None)
+    }
+
+    // skips up to `count` rows, returning how many there were
+    async fn skip(&mut self, count: usize) -> PgWireResult<usize> {
+        let mut skipped = 0;
+        while skipped < count && self.next().await?.is_some() {
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+
+    // reads up to the last row and stays on it
+    async fn last(&mut self) -> PgWireResult<Option<Record>> {
+        let mut last = self.current.clone();
+        while let Some(record) = self.next().await? {
+            last = Some(record);
+        }
+        if let Some(record) = &last {
+            self.position -= 1;
+            self.current = Some(record.clone());
+        }
+        Ok(last)
+    }
+}
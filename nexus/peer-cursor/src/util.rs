@@ -1,11 +1,24 @@
+use std::{str::FromStr, sync::Arc};
+
+use bytes::BytesMut;
 use futures::{stream, StreamExt};
 use pgwire::{
-    api::results::{DataRowEncoder, QueryResponse, Response},
-    error::{PgWireError, PgWireResult},
+    api::{
+        results::{DataRowEncoder, FieldFormat, FieldInfo, QueryResponse, Response},
+        Type,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
+    messages::data::DataRow,
+    types::ToSqlText,
 };
+use postgres_types::ToSql;
+use sqlparser::{ast::Statement, dialect::PostgreSqlDialect, parser::Parser};
+use tokio::sync::oneshot;
 use value::Value;
 
-use crate::{Records, Schema, SendableStream};
+use crate::{
+    error::client_error, QueryExecutor, QueryOutput, Record, Records, Schema, SendableStream,
+};
 
 fn encode_value(value: &Value, builder: &mut DataRowEncoder) -> PgWireResult<()> {
     match value {
@@ -55,23 +68,156 @@ fn encode_value(value: &Value, builder: &mut DataRowEncoder) -> PgWireResult<()>
     }
 }
 
+fn write_field<T: ToSql + ToSqlText>(
+    value: &T,
+    datatype: &Type,
+    format: FieldFormat,
+    out: &mut BytesMut,
+) -> PgWireResult<()> {
+    match format {
+        FieldFormat::Text => value.to_sql_text(datatype, out),
+        FieldFormat::Binary => value.to_sql(datatype, out),
+    }
+    .map(|_| ())
+    .map_err(PgWireError::ApiError)
+}
+
+/// Writes a value the way it's encoded in a data row of the given format, for
+/// output that isn't made of data rows like COPY. Returns false for NULL, for
+/// which nothing is written.
+pub fn write_value(
+    value: &Value,
+    datatype: &Type,
+    format: FieldFormat,
+    out: &mut BytesMut,
+) -> PgWireResult<bool> {
+    match value {
+        Value::Null => return Ok(false),
+        Value::Bool(v) => write_field(v, datatype, format, out)?,
+        Value::Oid(o) => write_field(o, datatype, format, out)?,
+        Value::TinyInt(v) => write_field(v, datatype, format, out)?,
+        Value::SmallInt(v) => write_field(v, datatype, format, out)?,
+        Value::Integer(v) => write_field(v, datatype, format, out)?,
+        Value::BigInt(v) => write_field(v, datatype, format, out)?,
+        Value::Float(v) => write_field(v, datatype, format, out)?,
+        Value::Double(v) => write_field(v, datatype, format, out)?,
+        Value::Numeric(v) => write_field(&v.to_string(), datatype, format, out)?,
+        Value::Char(v) => write_field(&v.to_string(), datatype, format, out)?,
+        Value::VarChar(v) | Value::Text(v) => write_field(v, datatype, format, out)?,
+        Value::Binary(b) | Value::VarBinary(b) => {
+            let bytes: &[u8] = b.as_ref();
+            write_field(&bytes, datatype, format, out)?
+        }
+        Value::Date(d) => write_field(d, datatype, format, out)?,
+        Value::Time(t) | Value::TimeWithTimeZone(t) => write_field(t, datatype, format, out)?,
+        Value::Timestamp(ts) | Value::TimestampWithTimeZone(ts) => {
+            write_field(ts, datatype, format, out)?
+        }
+        Value::PostgresTimestamp(pgts) => write_field(pgts, datatype, format, out)?,
+        Value::IpAddr(ip) => write_field(&ip.to_string(), datatype, format, out)?,
+        Value::Interval(i) => write_field(i, datatype, format, out)?,
+        Value::Array(a) => write_field(a, datatype, format, out)?,
+        Value::Json(j) | Value::JsonB(j) => write_field(&j.to_string(), datatype, format, out)?,
+        Value::Uuid(u) => write_field(&u.to_string(), datatype, format, out)?,
+        Value::Enum(_) | Value::Hstore(_) => {
+            return Err(PgWireError::ApiError(
+                format!(
+                    "cannot write value {:?} in postgres protocol: unimplemented",
+                    &value
+                )
+                .into(),
+            ))
+        }
+    }
+    Ok(true)
+}
+
+/// Encodes a row of query results or of a cursor fetch, by the schema's type
+/// and format for each column, with NULL for any type.
+pub fn encode_record(schema: &Schema, record: &Record) -> PgWireResult<DataRow> {
+    let mut encoder = DataRowEncoder::new(schema.clone());
+    for value in record.values.iter() {
+        encode_value(value, &mut encoder)?;
+    }
+    encoder.finish()
+}
+
+/// Records encoded together on the encode pool, at most. Only the records a
+/// peer stream already has ready are batched, a slow stream isn't waited on.
+const ENCODE_BATCH_SIZE: usize = 256;
+
+/// Threads encoding the rows of peer streams for the client. Encoding wide
+/// rows is CPU bound, on the async runtime it holds up the I/O of the other
+/// connections scheduled on the same worker.
+pub struct EncodePool {
+    pool: rayon::ThreadPool,
+}
+
+impl EncodePool {
+    pub fn new(threads: usize) -> anyhow::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("nexus-encode-{}", i))
+            .build()?;
+        Ok(Self { pool })
+    }
+
+    /// Encodes the records on one of the pool's threads, in order.
+    pub async fn encode(
+        &self,
+        schema: Schema,
+        records: Vec<PgWireResult<Record>>,
+    ) -> Vec<PgWireResult<DataRow>> {
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let rows = records
+                .into_iter()
+                .map(|record| record.and_then(|record| encode_record(&schema, &record)))
+                .collect();
+            // the response may have been dropped meanwhile
+            let _ = tx.send(rows);
+        });
+        rx.await.unwrap_or_else(|_| {
+            vec![Err(PgWireError::ApiError(
+                "row encoding thread stopped".into(),
+            ))]
+        })
+    }
+}
+
+/// The response streaming the rows of a peer. They are encoded on the
+/// runtime, or on `encode_pool` when there is one.
+///
+/// Rows are pulled from the peer stream as the client is sent them, so a
+/// client reading slowly holds the peer back instead of making nexus buffer
+/// its rows, and a dropped response stops reading from the peer.
 pub fn sendable_stream_to_query_response<'a>(
     schema: Schema,
     record_stream: SendableStream,
+    encode_pool: Option<Arc<EncodePool>>,
 ) -> PgWireResult<Response<'a>> {
     let schema_copy = schema.clone();
 
-    let data_row_stream = record_stream
-        .map(move |record_result| {
-            record_result.and_then(|record| {
-                let mut encoder = DataRowEncoder::new(schema_copy.clone());
-                for value in record.values.iter() {
-                    encode_value(value, &mut encoder)?;
-                }
-                encoder.finish()
+    let rows = match encode_pool {
+        None => record_stream
+            .map(move |record| record.and_then(|record| encode_record(&schema_copy, &record)))
+            .boxed(),
+        // the next batch is read from the peer while the pool encodes one
+        Some(pool) => record_stream
+            .ready_chunks(ENCODE_BATCH_SIZE)
+            .map(move |records| {
+                let pool = pool.clone();
+                let schema = schema_copy.clone();
+                async move { pool.encode(schema, records).await }
             })
-        })
-        .boxed();
+            .buffered(2)
+            .flat_map(stream::iter)
+            .boxed(),
+    };
+
+    // the rows are sent after do_query returned, a failed one is reported
+    // here with the SQLSTATE of the peer's error
+    let data_row_stream = rows.map(|row| row.map_err(client_error)).boxed();
 
     Ok(Response::Query(QueryResponse::new(schema, data_row_stream)))
 }
@@ -80,13 +226,7 @@ pub fn records_to_query_response<'a>(records: Records) -> PgWireResult<Response<
     let schema_copy = records.schema.clone();
 
     let data_row_stream = stream::iter(records.records)
-        .map(move |record| {
-            let mut encoder = DataRowEncoder::new(schema_copy.clone());
-            for value in record.values.iter() {
-                encode_value(value, &mut encoder)?;
-            }
-            encoder.finish()
-        })
+        .map(move |record| encode_record(&schema_copy, &record))
         .boxed();
 
     Ok(Response::Query(QueryResponse::new(
@@ -94,3 +234,156 @@ pub fn records_to_query_response<'a>(records: Records) -> PgWireResult<Response<
         data_row_stream,
     )))
 }
+
+/// Runs `SELECT 1` on the executor and reads its rows, the connection test
+/// of executors without a cheaper one.
+pub async fn select_one<E: QueryExecutor + ?Sized>(executor: &E) -> PgWireResult<()> {
+    let stmt = Parser::parse_sql(&PostgreSqlDialect {}, "SELECT 1")
+        .map_err(|err| PgWireError::ApiError(err.into()))?
+        .remove(0);
+    if let QueryOutput::Stream(mut rows) = executor.execute(&stmt).await? {
+        while let Some(row) = rows.next().await {
+            row?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the statement is an INSERT, UPDATE or DELETE with a RETURNING
+/// clause, which returns rows like a query.
+pub fn has_returning(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::Insert {
+            returning: Some(_),
+            ..
+        } | Statement::Update {
+            returning: Some(_),
+            ..
+        } | Statement::Delete {
+            returning: Some(_),
+            ..
+        }
+    )
+}
+
+/// Schema of the rows returned for `DESCRIBE peer.schema.table`, the same for
+/// every peer type: one row per column of the table, in column order.
+pub fn describe_table_schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("column_name", Type::TEXT),
+        field("data_type", Type::TEXT),
+        field("is_nullable", Type::BOOL),
+        field("column_default", Type::TEXT),
+    ])
+}
+
+/// Schema of the rows returned for `EXPORT SCHEMA FROM PEER`, the same for
+/// every peer type: one row per column of every table, ordered by schema,
+/// table and column position.
+pub fn export_schema_schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("schema_name", Type::TEXT),
+        field("table_name", Type::TEXT),
+        field("column_name", Type::TEXT),
+        field("data_type", Type::TEXT),
+        field("is_nullable", Type::BOOL),
+        field("ordinal_position", Type::INT8),
+    ])
+}
+
+/// Schema of the row returned in place of results while `peerdb.dry_run` is
+/// on: the peer the statement was routed to, its plan and estimated cost.
+pub fn dry_run_schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("peer", Type::TEXT),
+        field("plan", Type::TEXT),
+        field("estimated_bytes_processed", Type::INT8),
+    ])
+}
+
+/// Schema of the plan returned for `EXPLAIN`, a row per line like postgres.
+pub fn explain_schema() -> Schema {
+    Arc::new(vec![FieldInfo::new(
+        "QUERY PLAN".to_owned(),
+        None,
+        None,
+        Type::TEXT,
+        FieldFormat::Text,
+    )])
+}
+
+/// The lines of a plan as the rows of `EXPLAIN`.
+pub fn explain_records(lines: impl IntoIterator<Item = String>) -> Records {
+    let schema = explain_schema();
+    Records {
+        records: lines
+            .into_iter()
+            .map(|line| Record {
+                values: vec![Value::Text(line)],
+                schema: schema.clone(),
+            })
+            .collect(),
+        schema,
+    }
+}
+
+/// What to do with text from a peer that isn't valid UTF-8, the only encoding
+/// nexus sends to clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Replace invalid sequences with U+FFFD.
+    #[default]
+    Replace,
+    /// Fail the query like postgres does when it can't convert a value.
+    Error,
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(InvalidUtf8::Replace),
+            "error" => Ok(InvalidUtf8::Error),
+            _ => Err(anyhow::anyhow!("expected replace or error, got {}", s)),
+        }
+    }
+}
+
+impl InvalidUtf8 {
+    pub fn decode(self, bytes: Vec<u8>) -> PgWireResult<String> {
+        let err = match String::from_utf8(bytes) {
+            Ok(text) => return Ok(text),
+            Err(err) => err,
+        };
+        match self {
+            InvalidUtf8::Replace => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+            InvalidUtf8::Error => {
+                let start = err.utf8_error().valid_up_to();
+                let len = err.utf8_error().error_len().unwrap_or(1);
+                let sequence = err.as_bytes()[start..start + len]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "22021".to_owned(),
+                    format!(
+                        "invalid byte sequence for encoding \"UTF8\": 0x{}",
+                        sequence
+                    ),
+                ))))
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use bytes::Bytes;
 use futures::StreamExt;
 use mysql_async::{self, prelude::Queryable};
 use tokio::{spawn, sync::mpsc};
@@ -8,11 +9,16 @@ pub enum Response {
     Row(mysql_async::Row),
     Schema(Arc<[mysql_async::Column]>),
     Err(mysql_async::Error),
+    AffectedRows(u64),
+    /// The first warning of a LOAD DATA, which was rolled back for it.
+    LoadWarning(String),
 }
 
 pub struct Message {
     pub query: String,
     pub response: mpsc::Sender<Response>,
+    // served to LOAD DATA LOCAL INFILE, which gets AffectedRows in response.
+    pub infile: Option<Vec<Bytes>>,
 }
 
 #[derive(Clone)]
@@ -25,7 +31,25 @@ impl MyClient {
         let mut conn = mysql_async::Conn::new(opts).await?;
         let (send, mut recv) = mpsc::channel(1);
         spawn(async move {
-            while let Some(Message { query, response }) = recv.recv().await {
+            while let Some(Message {
+                query,
+                response,
+                infile,
+            }) = recv.recv().await
+            {
+                if let Some(chunks) = infile {
+                    conn.set_infile_handler(async move {
+                        Ok::<_, mysql_async::LocalInfileError>(
+                            futures::stream::iter(chunks.into_iter().map(Ok)).boxed(),
+                        )
+                    });
+                    let res = load_data(&mut conn, query)
+                        .await
+                        .unwrap_or_else(Response::Err);
+                    response.send(res).await.ok();
+                    continue;
+                }
+
                 match conn.query_stream(query).await {
                     Ok(stream) => {
                         response.send(Response::Schema(stream.columns())).await.ok();
@@ -46,8 +70,35 @@ impl MyClient {
                     }
                 }
             }
+            // the executor was dropped, close the connection cleanly
+            if let Err(err) = conn.disconnect().await {
+                tracing::info!("unable to disconnect from mysql: {}", err);
+            }
         });
 
         Ok(MyClient { chan: send })
     }
 }
+
+// LOAD DATA LOCAL only warns about values that don't fit their column and
+// loads them truncated or as zero, so the load runs in a transaction that is
+// rolled back when there was a warning.
+async fn load_data(conn: &mut mysql_async::Conn, query: String) -> mysql_async::Result<Response> {
+    conn.query_drop("START TRANSACTION").await?;
+    if let Err(err) = conn.query_drop(query).await {
+        conn.query_drop("ROLLBACK").await?;
+        return Err(err);
+    }
+    let rows = conn.affected_rows();
+    if conn.get_warnings() > 0 {
+        let warning: Option<(String, u32, String)> =
+            conn.query_first("SHOW WARNINGS LIMIT 1").await?;
+        conn.query_drop("ROLLBACK").await?;
+        return Ok(Response::LoadWarning(warning.map_or_else(
+            || "LOAD DATA reported a warning".to_owned(),
+            |(_, _, message)| message,
+        )));
+    }
+    conn.query_drop("COMMIT").await?;
+    Ok(Response::AffectedRows(rows))
+}
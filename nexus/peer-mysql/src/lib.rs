@@ -2,24 +2,45 @@ mod ast;
 mod client;
 mod stream;
 
-use std::fmt::Write;
+use std::{fmt::Write, sync::Mutex};
 
+use futures::TryStreamExt;
 use peer_cursor::{
-    CursorManager, CursorModification, QueryExecutor, QueryOutput, RecordStream, Schema,
+    labels::{QueryLabels, QUERY_LABELS},
+    util::{select_one, InvalidUtf8},
+    BulkLoadFormat, ByteStream, CursorManager, CursorModification, PeerCapabilities, QueryExecutor,
+    QueryOutput, RecordStream, Schema,
 };
 use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
 use pt::peerdb_peers::MySqlConfig;
-use sqlparser::ast::{CloseCursor, Declare, Expr, FetchDirection, Statement, Value};
+use sqlparser::ast::{
+    CloseCursor, Declare, Expr, FetchDirection, Ident, ObjectName, Statement, Value,
+};
 use stream::MyRecordStream;
+use tokio::sync::mpsc;
 
 pub struct MySqlQueryExecutor {
     peer_name: String,
     client: client::MyClient,
     cursor_manager: CursorManager,
+    invalid_utf8: InvalidUtf8,
+    query_labels: Mutex<QueryLabels>,
 }
 
 impl MySqlQueryExecutor {
-    pub async fn new(peer_name: String, config: &MySqlConfig) -> anyhow::Result<Self> {
+    pub const CAPABILITIES: PeerCapabilities = PeerCapabilities {
+        queryable: true,
+        writable: false,
+        supports_cursors: true,
+        supports_transactions: false,
+        supports_copy: true,
+    };
+
+    pub async fn new(
+        peer_name: String,
+        config: &MySqlConfig,
+        invalid_utf8: InvalidUtf8,
+    ) -> anyhow::Result<Self> {
         let mut opts = mysql_async::OptsBuilder::default().prefer_socket(Some(false)); // prefer_socket breaks connecting to StarRocks
         if !config.user.is_empty() {
             opts = opts.user(Some(config.user.clone()))
@@ -33,8 +54,12 @@ impl MySqlQueryExecutor {
         if !config.disable_tls {
             opts = opts.ssl_opts(mysql_async::SslOpts::default())
         }
+        // have the server convert text from columns in other character sets
+        // to UTF-8 before any setup statements of the peer.
+        let mut setup = vec!["SET NAMES utf8mb4".to_owned()];
+        setup.extend(config.setup.iter().cloned());
         opts = opts
-            .setup(config.setup.clone())
+            .setup(setup)
             .compression(mysql_async::Compression::new(config.compression))
             .ip_or_hostname(config.host.clone())
             .tcp_port(config.port as u16);
@@ -44,21 +69,58 @@ impl MySqlQueryExecutor {
             peer_name,
             client,
             cursor_manager: Default::default(),
+            invalid_utf8,
+            query_labels: Mutex::default(),
         })
     }
 
     async fn query(&self, query: String) -> PgWireResult<MyRecordStream> {
-        MyRecordStream::query(self.client.clone(), query).await
+        let query = self.query_labels.lock().unwrap().annotate(&query);
+        MyRecordStream::query(self.client.clone(), query, self.invalid_utf8).await
+    }
+
+    // LOAD DATA reads the file from the client, which serves it the data.
+    async fn load_data(&self, query: String, data: ByteStream) -> PgWireResult<u64> {
+        let chunks = data.try_collect::<Vec<_>>().await?;
+        let (send, mut recv) = mpsc::channel(1);
+        self.client
+            .chan
+            .send(client::Message {
+                query,
+                response: send,
+                infile: Some(chunks),
+            })
+            .await
+            .map_err(|_| PgWireError::ApiError("mysql connection closed".into()))?;
+
+        match recv.recv().await {
+            Some(client::Response::AffectedRows(rows)) => Ok(rows),
+            Some(client::Response::Err(err)) => Err(PgWireError::ApiError(err.into())),
+            Some(client::Response::LoadWarning(warning)) => {
+                Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "22P02".to_owned(),
+                    format!("invalid input, nothing was loaded: {}", warning),
+                ))))
+            }
+            _ => Err(PgWireError::ApiError(
+                "unexpected response to LOAD DATA".into(),
+            )),
+        }
     }
 
     async fn query_schema(&self, query: String) -> PgWireResult<Schema> {
-        let stream = MyRecordStream::query(self.client.clone(), query).await?;
+        let stream = MyRecordStream::query(self.client.clone(), query, self.invalid_utf8).await?;
         Ok(stream.schema())
     }
 }
 
 #[async_trait::async_trait]
 impl QueryExecutor for MySqlQueryExecutor {
+    fn capabilities(&self) -> PeerCapabilities {
+        Self::CAPABILITIES
+    }
+
     // #[tracing::instrument(skip(self, stmt), fields(stmt = %stmt))]
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
         // only support SELECT statements
@@ -91,7 +153,7 @@ impl QueryExecutor for MySqlQueryExecutor {
                     );
                     Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                         "ERROR".to_owned(),
-                        "fdw_error".to_owned(),
+                        "0A000".to_owned(),
                         error,
                     ))))
                 }
@@ -138,31 +200,8 @@ impl QueryExecutor for MySqlQueryExecutor {
             } => {
                 tracing::info!("fetching cursor for mysql: {}", name.value);
 
-                // Attempt to extract the count from the direction
-                let count = match direction {
-                    FetchDirection::ForwardAll | FetchDirection::All => usize::MAX,
-                    FetchDirection::Next | FetchDirection::Forward { limit: None } => 1,
-                    FetchDirection::Count {
-                        limit: sqlparser::ast::Value::Number(n, _),
-                    }
-                    | FetchDirection::Forward {
-                        limit: Some(sqlparser::ast::Value::Number(n, _)),
-                    } => n
-                        .parse::<usize>()
-                        .map_err(|err| PgWireError::ApiError(err.into()))?,
-                    _ => {
-                        return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                            "ERROR".to_owned(),
-                            "fdw_error".to_owned(),
-                            "only FORWARD count and COUNT count are supported in FETCH".to_owned(),
-                        ))))
-                    }
-                };
-
-                tracing::info!("fetching {} rows", count);
-
                 // Fetch rows from the cursor manager
-                let records = self.cursor_manager.fetch(&name.value, count).await?;
+                let records = self.cursor_manager.fetch(&name.value, direction).await?;
 
                 // Return the fetched records as the query output
                 Ok(QueryOutput::Records(records))
@@ -186,14 +225,78 @@ impl QueryExecutor for MySqlQueryExecutor {
                 );
                 Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                     "ERROR".to_owned(),
-                    "fdw_error".to_owned(),
+                    "0A000".to_owned(),
                     error,
                 ))))
             }
         }
     }
 
+    async fn bulk_load(
+        &self,
+        table: &ObjectName,
+        columns: &[Ident],
+        format: BulkLoadFormat,
+        data: ByteStream,
+    ) -> PgWireResult<QueryOutput> {
+        let BulkLoadFormat::Csv { header, delimiter } = format else {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                "binary COPY data can only be loaded into postgres peers".to_owned(),
+            ))));
+        };
+        let table = table
+            .0
+            .iter()
+            .map(quote_ident)
+            .collect::<Vec<_>>()
+            .join(".");
+        // the file name is only passed to the infile handler, which ignores it
+        let mut query = format!(
+            "LOAD DATA LOCAL INFILE 'peerdb_import' INTO TABLE {} CHARACTER SET utf8mb4 \
+            FIELDS TERMINATED BY '{}' OPTIONALLY ENCLOSED BY '\"' ESCAPED BY '' \
+            LINES TERMINATED BY '\\n'",
+            table,
+            (delimiter as char).to_string().replace('\'', "''")
+        );
+        if header {
+            query.push_str(" IGNORE 1 LINES");
+        }
+        // the fields are read into variables and empty ones stored as NULL,
+        // as with COPY's CSV format. mysql converts the rest to the types of
+        // their columns.
+        let columns = if columns.is_empty() {
+            self.query_schema(format!("SELECT * FROM {} LIMIT 0", table))
+                .await?
+                .iter()
+                .map(|field| Ident::new(field.name()))
+                .collect()
+        } else {
+            columns.to_vec()
+        };
+        let variables = (1..=columns.len())
+            .map(|i| format!("@f{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let assignments = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{} = NULLIF(@f{}, '')", quote_ident(column), i + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(query, " ({}) SET {}", variables, assignments).ok();
+        tracing::info!("mysql bulk load: {}", query);
+
+        let rows = self.load_data(query, data).await?;
+        Ok(QueryOutput::AffectedRows(rows as usize))
+    }
+
     // describe the output of the query
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        self.cursor_manager.move_cursor(name, direction).await
+    }
+
     async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
         // print the statement
         tracing::info!("[mysql] describe: {}", stmt);
@@ -225,9 +328,25 @@ impl QueryExecutor for MySqlQueryExecutor {
             }
             _ => PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "fdw_error".to_owned(),
+                "0A000".to_owned(),
                 "only SELECT statements are supported in mysql".to_owned(),
             )))),
         }
     }
+
+    async fn set_session_parameter(&self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == QUERY_LABELS {
+            *self.query_labels.lock().unwrap() =
+                QueryLabels::parse(value).map_err(|err| PgWireError::ApiError(err.into()))?;
+        }
+        Ok(())
+    }
+
+    async fn test_connection(&self) -> PgWireResult<()> {
+        select_one(self).await
+    }
+}
+
+fn quote_ident(ident: &Ident) -> String {
+    format!("`{}`", ident.value.replace('`', "``"))
 }
@@ -8,7 +8,7 @@ use crate::client::{self, MyClient};
 use futures::Stream;
 use mysql_async::consts::ColumnType;
 use mysql_async::{Column, Row};
-use peer_cursor::{Record, RecordStream, Schema};
+use peer_cursor::{util::InvalidUtf8, Record, RecordStream, Schema};
 use pgwire::{
     api::{
         results::{FieldFormat, FieldInfo},
@@ -23,6 +23,7 @@ use value::Value;
 pub struct MyRecordStream {
     schema: Schema,
     stream: ReceiverStream<client::Response>,
+    invalid_utf8: InvalidUtf8,
 }
 
 // convert ColumnType to pgwire FieldInfo's Type
@@ -79,12 +80,17 @@ pub fn schema_from_columns(columns: &[Column]) -> Schema {
 }
 
 impl MyRecordStream {
-    pub async fn query(conn: MyClient, query: String) -> PgWireResult<Self> {
+    pub async fn query(
+        conn: MyClient,
+        query: String,
+        invalid_utf8: InvalidUtf8,
+    ) -> PgWireResult<Self> {
         let (send, mut recv) = mpsc::channel::<client::Response>(1);
         conn.chan
             .send(client::Message {
                 query,
                 response: send,
+                infile: None,
             })
             .await
             .ok();
@@ -95,8 +101,12 @@ impl MyRecordStream {
                 client::Response::Schema(schema) => Ok(MyRecordStream {
                     schema: schema_from_columns(&schema),
                     stream: ReceiverStream::new(recv),
+                    invalid_utf8,
                 }),
                 client::Response::Err(err) => Err(PgWireError::ApiError(err.into())),
+                client::Response::AffectedRows(..) | client::Response::LoadWarning(..) => {
+                    Err(PgWireError::ApiError("query returned no rows".into()))
+                }
             }
         } else {
             Err(PgWireError::InvalidStartupMessage)
@@ -104,14 +114,14 @@ impl MyRecordStream {
     }
 }
 
-pub fn mysql_row_to_values(row: Row) -> Vec<Value> {
+pub fn mysql_row_to_values(row: Row, invalid_utf8: InvalidUtf8) -> PgWireResult<Vec<Value>> {
     use mysql_async::from_value;
     let columns = row.columns();
     row.unwrap()
         .into_iter()
         .zip(columns.iter())
         .map(|(val, col)| {
-            if val == mysql_async::Value::NULL {
+            Ok(if val == mysql_async::Value::NULL {
                 Value::Null
             } else {
                 match col.column_type() {
@@ -133,7 +143,9 @@ pub fn mysql_row_to_values(row: Row) -> Vec<Value> {
                     | ColumnType::MYSQL_TYPE_VAR_STRING
                     | ColumnType::MYSQL_TYPE_STRING
                     | ColumnType::MYSQL_TYPE_ENUM
-                    | ColumnType::MYSQL_TYPE_SET => Value::Text(from_value(val)),
+                    | ColumnType::MYSQL_TYPE_SET => {
+                        Value::Text(invalid_utf8.decode(from_value::<Vec<u8>>(val))?)
+                    }
                     ColumnType::MYSQL_TYPE_TINY_BLOB
                     | ColumnType::MYSQL_TYPE_MEDIUM_BLOB
                     | ColumnType::MYSQL_TYPE_LONG_BLOB
@@ -155,7 +167,7 @@ pub fn mysql_row_to_values(row: Row) -> Vec<Value> {
                     ColumnType::MYSQL_TYPE_JSON => Value::JsonB(from_value(val)),
                     ColumnType::MYSQL_TYPE_TYPED_ARRAY => Value::Null,
                 }
-            }
+            })
         })
         .collect()
 }
@@ -166,16 +178,23 @@ impl Stream for MyRecordStream {
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let row_stream = &mut self.stream;
         match Pin::new(row_stream).poll_next(cx) {
-            Poll::Ready(Some(client::Response::Row(row))) => Poll::Ready(Some(Ok(Record {
-                schema: self.schema.clone(),
-                values: mysql_row_to_values(row),
-            }))),
+            Poll::Ready(Some(client::Response::Row(row))) => Poll::Ready(Some(
+                mysql_row_to_values(row, self.invalid_utf8).map(|values| Record {
+                    schema: self.schema.clone(),
+                    values,
+                }),
+            )),
             Poll::Ready(Some(client::Response::Schema(..))) => Poll::Ready(Some(Err(
                 PgWireError::ApiError("second schema received".into()),
             ))),
             Poll::Ready(Some(client::Response::Err(e))) => {
                 Poll::Ready(Some(Err(PgWireError::ApiError(e.into()))))
             }
+            Poll::Ready(Some(
+                client::Response::AffectedRows(..) | client::Response::LoadWarning(..),
+            )) => Poll::Ready(Some(Err(PgWireError::ApiError(
+                "affected rows received in row stream".into(),
+            )))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
@@ -0,0 +1,134 @@
+//! Throughput of moving a table between postgres databases with COPY in the
+//! csv and in the binary format, out of one table and into another the way
+//! nexus streams it.
+//!
+//! Runs against the database of the PEERDB_CATALOG_* variables, creating
+//! and dropping the tables `copy_bench_source` and `copy_bench_target`.
+//!
+//! cargo bench -p peer-postgres --bench copy_formats
+
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+use peer_cursor::{BulkLoadFormat, ByteStream};
+use peer_postgres::{ast::PostgresAst, pg_bulk_load, pg_copy_out_binary};
+use pgwire::error::PgWireError;
+use pt::peerdb_peers::PostgresConfig;
+use sqlparser::{ast::ObjectName, dialect::PostgreSqlDialect, parser::Parser};
+use tokio_postgres::Client;
+
+const ROWS: usize = 1_000_000;
+
+fn config() -> PostgresConfig {
+    let env =
+        |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_owned());
+    PostgresConfig {
+        host: env("PEERDB_CATALOG_HOST", "localhost"),
+        port: env("PEERDB_CATALOG_PORT", "5432").parse().unwrap(),
+        user: env("PEERDB_CATALOG_USER", "postgres"),
+        password: env("PEERDB_CATALOG_PASSWORD", "postgres"),
+        database: env("PEERDB_CATALOG_DATABASE", "postgres"),
+        ..Default::default()
+    }
+}
+
+async fn setup(client: &Client) {
+    client
+        .batch_execute(&format!(
+            "DROP TABLE IF EXISTS copy_bench_source, copy_bench_target;
+            CREATE TABLE copy_bench_source AS
+                SELECT i AS id, md5(i::text) AS name, i / 7.0 AS ratio,
+                    now() - i * interval '1 second' AS created_at,
+                    ARRAY[i, i + 1, i + 2] AS neighbours
+                FROM generate_series(1, {}) i;
+            CREATE TABLE copy_bench_target (LIKE copy_bench_source);",
+            ROWS
+        ))
+        .await
+        .unwrap();
+}
+
+async fn csv_out(client: &Client) -> ByteStream {
+    let data = client
+        .copy_out("COPY copy_bench_source TO STDOUT WITH (FORMAT csv)")
+        .await
+        .unwrap();
+    Box::pin(data.map_err(|e| PgWireError::ApiError(Box::new(e))))
+}
+
+async fn binary_out(client: &Client) -> ByteStream {
+    let stmt = Parser::parse_sql(&PostgreSqlDialect {}, "SELECT * FROM copy_bench_source")
+        .unwrap()
+        .remove(0);
+    pg_copy_out_binary(client, PostgresAst { peername: None }, &stmt)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+}
+
+// the time to move all rows and the bytes moved
+async fn run(source: &Client, target: &Client, format: BulkLoadFormat) -> (Duration, usize) {
+    target
+        .batch_execute("TRUNCATE copy_bench_target")
+        .await
+        .unwrap();
+    let start = Instant::now();
+    let data = match format {
+        BulkLoadFormat::Csv { .. } => csv_out(source).await,
+        BulkLoadFormat::Binary => binary_out(source).await,
+    };
+    let (bytes_tx, bytes_rx) = std::sync::mpsc::channel();
+    let data = Box::pin(data.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            bytes_tx.send(chunk.len()).unwrap();
+        }
+    }));
+    let table = ObjectName(vec!["copy_bench_target".into()]);
+    pg_bulk_load(target, &table, &[], format, data)
+        .await
+        .unwrap();
+    (start.elapsed(), bytes_rx.try_iter().sum())
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let config = config();
+        let source = postgres_connection::connect_postgres(&config)
+            .await
+            .unwrap();
+        let target = postgres_connection::connect_postgres(&config)
+            .await
+            .unwrap();
+        setup(&source).await;
+
+        for (name, format) in [
+            (
+                "csv",
+                BulkLoadFormat::Csv {
+                    header: false,
+                    delimiter: b',',
+                },
+            ),
+            ("binary", BulkLoadFormat::Binary),
+        ] {
+            let (elapsed, bytes) = run(&source, &target, format).await;
+            println!(
+                "{:<6} {:>9.0} rows/s, {:>7.1} MB/s, {:>7.1} MB moved",
+                name,
+                ROWS as f64 / elapsed.as_secs_f64(),
+                bytes as f64 / elapsed.as_secs_f64() / 1e6,
+                bytes as f64 / 1e6
+            );
+        }
+
+        source
+            .batch_execute("DROP TABLE copy_bench_source, copy_bench_target")
+            .await
+            .unwrap();
+    });
+}
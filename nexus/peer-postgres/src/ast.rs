@@ -21,9 +21,17 @@ impl PostgresAst {
     }
 
     pub fn rewrite_statement(&self, stmt: &mut Statement) -> anyhow::Result<()> {
-        // DROP statement needs to be handled separately
+        // DROP and CALL statements need to be handled separately
         visit_statements_mut(stmt, |stmnt| {
-            if let Statement::Drop {
+            if let Statement::Call(function) = stmnt {
+                if let Some(ref peername) = self.peername {
+                    if function.name.0.len() > 1
+                        && peername.eq_ignore_ascii_case(&function.name.0[0].value)
+                    {
+                        function.name.0.remove(0);
+                    }
+                }
+            } else if let Statement::Drop {
                 ref object_type,
                 ref mut names,
                 ..
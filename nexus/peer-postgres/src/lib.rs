@@ -1,35 +1,117 @@
-use std::sync::Arc;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use peer_cursor::{QueryExecutor, QueryOutput, Schema};
+use futures::{SinkExt, StreamExt};
+use peer_cursor::{
+    error::{sqlstate, PeerError},
+    labels::{QueryLabels, QUERY_LABELS},
+    util::{describe_table_schema, export_schema_schema, has_returning, InvalidUtf8},
+    BinaryCopy, BulkLoadFormat, ByteStream, CursorManager, CursorModification, DryRun,
+    PeerCapabilities, QueryExecutor, QueryOutput, Record, Records, Schema,
+};
 use pgwire::{
-    api::results::{FieldFormat, FieldInfo},
-    error::{PgWireError, PgWireResult},
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
 };
 use pt::peerdb_peers::PostgresConfig;
-use sqlparser::ast::Statement;
+use sqlparser::ast::{CloseCursor, Declare, FetchDirection, Ident, ObjectName, Statement};
 use tokio_postgres::Client;
 
 pub mod ast;
+pub mod pool;
 pub mod stream;
 
+use pool::{PooledClient, PostgresPools};
+
+// the connection of an executor, its own or one out of the peer's pool
+enum PeerClient {
+    Dedicated(Box<Client>),
+    Pooled(PooledClient),
+}
+
+impl Deref for PeerClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match self {
+            PeerClient::Dedicated(client) => client,
+            PeerClient::Pooled(client) => client,
+        }
+    }
+}
+
 // PostgresQueryExecutor is a QueryExecutor that uses a Postgres database as its
 // backing store.
 pub struct PostgresQueryExecutor {
     peername: String,
-    client: Box<Client>,
+    client: PeerClient,
+    cursor_manager: CursorManager,
+    invalid_utf8: InvalidUtf8,
+    // whether BEGIN ran on the connection without a COMMIT or ROLLBACK yet
+    in_transaction: AtomicBool,
 }
 
 impl PostgresQueryExecutor {
-    pub async fn new(peername: String, config: &PostgresConfig) -> anyhow::Result<Self> {
+    pub const CAPABILITIES: PeerCapabilities = PeerCapabilities {
+        queryable: true,
+        writable: true,
+        supports_cursors: true,
+        supports_transactions: true,
+        supports_copy: true,
+    };
+
+    pub async fn new(
+        peername: String,
+        config: &PostgresConfig,
+        invalid_utf8: InvalidUtf8,
+    ) -> anyhow::Result<Self> {
         let client = postgres_connection::connect_postgres(config).await?;
-        Ok(Self {
+        Ok(Self::with_client(
             peername,
-            client: Box::new(client),
-        })
+            PeerClient::Dedicated(Box::new(client)),
+            invalid_utf8,
+        ))
+    }
+
+    /// An executor on a connection out of the peer's pool, which goes back to
+    /// the pool when the executor is dropped. Peers that don't pool their
+    /// connections get a connection of their own as with `new`.
+    pub async fn with_pool(
+        peername: String,
+        config: &PostgresConfig,
+        invalid_utf8: InvalidUtf8,
+        pools: &PostgresPools,
+    ) -> anyhow::Result<Self> {
+        match pools.get(&peername, config).await? {
+            Some(client) => Ok(Self::with_client(
+                peername,
+                PeerClient::Pooled(client),
+                invalid_utf8,
+            )),
+            None => Self::new(peername, config, invalid_utf8).await,
+        }
+    }
+
+    fn with_client(peername: String, client: PeerClient, invalid_utf8: InvalidUtf8) -> Self {
+        Self {
+            peername,
+            client,
+            cursor_manager: Default::default(),
+            invalid_utf8,
+            in_transaction: AtomicBool::new(false),
+        }
     }
 }
 
-async fn schema_from_query(client: &Client, query: &str) -> anyhow::Result<Schema> {
+async fn schema_from_query(client: &Client, query: &str) -> Result<Schema, tokio_postgres::Error> {
     let prepared = client.prepare_typed(query, &[]).await?;
 
     let fields: Vec<FieldInfo> = prepared
@@ -44,10 +126,63 @@ async fn schema_from_query(client: &Client, query: &str) -> anyhow::Result<Schem
     Ok(Arc::new(fields))
 }
 
+fn is_explain(stmt: &Statement) -> bool {
+    matches!(stmt, Statement::Explain { .. })
+}
+
+// the statement as postgres reads it: the options of EXPLAIN are written
+// bare by sqlparser, postgres only takes FORMAT within parentheses.
+fn postgres_sql(stmt: &Statement) -> String {
+    let Statement::Explain {
+        analyze,
+        verbose,
+        statement,
+        format,
+        ..
+    } = stmt
+    else {
+        return stmt.to_string();
+    };
+    let mut options = Vec::new();
+    if *analyze {
+        options.push("ANALYZE".to_owned());
+    }
+    if *verbose {
+        options.push("VERBOSE".to_owned());
+    }
+    if let Some(format) = format {
+        options.push(format!("FORMAT {}", format));
+    }
+    if options.is_empty() {
+        format!("EXPLAIN {}", statement)
+    } else {
+        format!("EXPLAIN ({}) {}", options.join(", "), statement)
+    }
+}
+
+/// The error postgres reported, with its SQLSTATE and message as the client
+/// would have gotten them from postgres. A lost connection to the peer is a
+/// connection failure, other errors of the client are internal.
+pub fn pg_error(context: &str, err: tokio_postgres::Error) -> PgWireError {
+    tracing::error!("{}: {}", context, err);
+    if let Some(db_error) = err.as_db_error() {
+        return PeerError::new(db_error.code().code(), db_error.message()).into();
+    }
+    let code = if err.is_closed()
+        || std::error::Error::source(&err).is_some_and(|source| source.is::<std::io::Error>())
+    {
+        sqlstate::CONNECTION_FAILURE
+    } else {
+        sqlstate::INTERNAL_ERROR
+    };
+    PeerError::new(code, format!("{}: {}", context, err)).into()
+}
+
 pub async fn pg_execute(
     client: &Client,
     ast: ast::PostgresAst,
     stmt: &Statement,
+    invalid_utf8: InvalidUtf8,
 ) -> PgWireResult<QueryOutput> {
     // if the query is a select statement, we need to fetch the rows
     // and return them as a QueryOutput::Stream, else we return the
@@ -63,10 +198,7 @@ pub async fn pg_execute(
             // could hold the pin on the connection for a long time.
             let schema = schema_from_query(client, &rewritten_query)
                 .await
-                .map_err(|e| {
-                    tracing::error!("error getting schema: {}", e);
-                    PgWireError::ApiError(format!("error getting schema: {}", e).into())
-                })?;
+                .map_err(|e| pg_error("error getting schema", e))?;
 
             tracing::info!("[peer-postgres] rewritten query: {}", rewritten_query);
             // given that there could be a lot of rows returned, we
@@ -75,54 +207,445 @@ pub async fn pg_execute(
             let stream = client
                 .query_raw(&rewritten_query, std::iter::empty::<&str>())
                 .await
-                .map_err(|e| {
-                    tracing::error!("error executing query: {}", e);
-                    PgWireError::ApiError(format!("error executing query: {}", e).into())
-                })?;
+                .map_err(|e| pg_error("error executing query", e))?;
 
             // log that raw query execution has completed
             tracing::info!("[peer-postgres] raw query execution completed");
 
-            let cursor = stream::PgRecordStream::new(stream, schema);
+            let cursor = stream::PgRecordStream::new(stream, schema, invalid_utf8);
             Ok(QueryOutput::Stream(Box::pin(cursor)))
         }
-        _ => {
+        // FETCH from a cursor declared directly on the connection, e.g. on the
+        // catalog within a transaction, returns rows like a query.
+        Statement::Fetch { .. } => {
+            let query = stmt.to_string();
+            let schema = schema_from_query(client, &query)
+                .await
+                .map_err(|e| pg_error("error getting schema", e))?;
+            let stream = client
+                .query_raw(&query, std::iter::empty::<&str>())
+                .await
+                .map_err(|e| pg_error("error executing fetch", e))?;
+            let cursor = stream::PgRecordStream::new(stream, schema, invalid_utf8);
+            Ok(QueryOutput::Stream(Box::pin(cursor)))
+        }
+        Statement::Call(_) => {
             let mut rewritten_stmt = stmt.clone();
             ast.rewrite_statement(&mut rewritten_stmt).map_err(|e| {
                 tracing::error!("error rewriting statement: {}", e);
                 PgWireError::ApiError(format!("error rewriting statement: {}", e).into())
             })?;
             let rewritten_query = rewritten_stmt.to_string();
+            tracing::info!("[peer-postgres] rewritten call: {}", rewritten_query);
+
+            // procedures with OUT parameters return a single row holding them
+            let schema = schema_from_query(client, &rewritten_query)
+                .await
+                .map_err(|e| pg_error("error getting schema", e))?;
+            if schema.is_empty() {
+                client
+                    .execute(&rewritten_query, &[])
+                    .await
+                    .map_err(|e| pg_error("error executing call", e))?;
+                return Ok(QueryOutput::AffectedRows(0));
+            }
+
+            let stream = client
+                .query_raw(&rewritten_query, std::iter::empty::<&str>())
+                .await
+                .map_err(|e| pg_error("error executing call", e))?;
+            let cursor = stream::PgRecordStream::new(stream, schema, invalid_utf8);
+            Ok(QueryOutput::Stream(Box::pin(cursor)))
+        }
+        // the rows of INSERT, UPDATE and DELETE ... RETURNING are sent back
+        // like those of a query, e.g. the keys generated for the new rows, and
+        // so are the lines of the plan of EXPLAIN.
+        _ if has_returning(stmt) || is_explain(stmt) => {
+            let mut rewritten_stmt = stmt.clone();
+            ast.rewrite_statement(&mut rewritten_stmt).map_err(|e| {
+                tracing::error!("error rewriting statement: {}", e);
+                PgWireError::ApiError(format!("error rewriting statement: {}", e).into())
+            })?;
+            let rewritten_query = postgres_sql(&rewritten_stmt);
             tracing::info!("[peer-postgres] rewritten statement: {}", rewritten_query);
-            let rows_affected = client.execute(&rewritten_query, &[]).await.map_err(|e| {
-                tracing::error!("error executing query: {}", e);
-                PgWireError::ApiError(format!("error executing query: {}", e).into())
+
+            let schema = schema_from_query(client, &rewritten_query)
+                .await
+                .map_err(|e| pg_error("error getting schema", e))?;
+            let stream = client
+                .query_raw(&rewritten_query, std::iter::empty::<&str>())
+                .await
+                .map_err(|e| pg_error("error executing query", e))?;
+            let cursor = stream::PgRecordStream::new(stream, schema, invalid_utf8);
+            Ok(QueryOutput::Stream(Box::pin(cursor)))
+        }
+        _ => {
+            let mut rewritten_stmt = stmt.clone();
+            ast.rewrite_statement(&mut rewritten_stmt).map_err(|e| {
+                tracing::error!("error rewriting statement: {}", e);
+                PgWireError::ApiError(format!("error rewriting statement: {}", e).into())
             })?;
+            let rewritten_query = rewritten_stmt.to_string();
+            tracing::info!("[peer-postgres] rewritten statement: {}", rewritten_query);
+            let rows_affected = client
+                .execute(&rewritten_query, &[])
+                .await
+                .map_err(|e| pg_error("error executing query", e))?;
             Ok(QueryOutput::AffectedRows(rows_affected as usize))
         }
     }
 }
 
+/// `MOVE` for a cursor declared directly on the connection, like FETCH in
+/// `pg_execute`.
+pub async fn pg_move(
+    client: &Client,
+    name: &str,
+    direction: &FetchDirection,
+) -> PgWireResult<usize> {
+    let query = format!("MOVE {} IN {}", direction, Ident::new(name));
+    let moved = client
+        .execute(&query, &[])
+        .await
+        .map_err(|e| pg_error("error executing move", e))?;
+    Ok(moved as usize)
+}
+
 pub async fn pg_describe(client: &Client, stmt: &Statement) -> PgWireResult<Option<Schema>> {
     match stmt {
         Statement::Query(_query) => {
             let schema = schema_from_query(client, &stmt.to_string())
                 .await
-                .map_err(|e| {
-                    tracing::error!("error getting schema: {}", e);
-                    PgWireError::ApiError(format!("error getting schema: {}", e).into())
-                })?;
+                .map_err(|e| pg_error("error getting schema", e))?;
+            Ok(Some(schema))
+        }
+        Statement::Call(_) => {
+            let schema = schema_from_query(client, &stmt.to_string())
+                .await
+                .map_err(|e| pg_error("error getting schema", e))?;
+            // procedures without OUT parameters return no rows
+            Ok(Some(schema).filter(|schema| !schema.is_empty()))
+        }
+        _ if has_returning(stmt) || is_explain(stmt) => {
+            let schema = schema_from_query(client, &postgres_sql(stmt))
+                .await
+                .map_err(|e| pg_error("error getting schema", e))?;
             Ok(Some(schema))
         }
         _ => Ok(None),
     }
 }
 
+// preparing the statement has postgres infer the types of its parameters.
+pub async fn pg_parameter_types(
+    client: &Client,
+    ast: ast::PostgresAst,
+    stmt: &Statement,
+) -> PgWireResult<Option<Vec<Type>>> {
+    let mut rewritten_stmt = stmt.clone();
+    ast.rewrite_statement(&mut rewritten_stmt).map_err(|e| {
+        tracing::error!("error rewriting statement: {}", e);
+        PgWireError::ApiError(format!("error rewriting statement: {}", e).into())
+    })?;
+    let prepared = client
+        .prepare(&postgres_sql(&rewritten_stmt))
+        .await
+        .map_err(|e| pg_error("error inferring parameter types", e))?;
+    Ok(Some(prepared.params().to_vec()))
+}
+
+// EXPLAIN plans the statement without running it.
+pub async fn pg_dry_run(
+    client: &Client,
+    ast: ast::PostgresAst,
+    stmt: &Statement,
+) -> PgWireResult<DryRun> {
+    let mut rewritten_stmt = stmt.clone();
+    ast.rewrite_statement(&mut rewritten_stmt).map_err(|e| {
+        tracing::error!("error rewriting statement: {}", e);
+        PgWireError::ApiError(format!("error rewriting statement: {}", e).into())
+    })?;
+    let rows = client
+        .query(&format!("EXPLAIN {}", rewritten_stmt), &[])
+        .await
+        .map_err(|e| pg_error("error explaining statement", e))?;
+
+    let plan = rows
+        .iter()
+        .map(|row| row.get::<_, String>(0))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(DryRun {
+        plan,
+        estimated_bytes_processed: None,
+    })
+}
+
+pub async fn pg_describe_table(
+    client: &Client,
+    schema: Option<&str>,
+    table: &str,
+) -> PgWireResult<QueryOutput> {
+    let rows = client
+        .query(
+            "SELECT column_name::text, data_type::text, is_nullable = 'YES', column_default::text
+            FROM information_schema.columns
+            WHERE table_schema::text = coalesce($1::text, current_schema())
+            AND table_name::text = $2::text
+            ORDER BY ordinal_position",
+            &[&schema, &table],
+        )
+        .await
+        .map_err(|e| pg_error(&format!("error describing table {}", table), e))?;
+
+    if rows.is_empty() {
+        return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "42P01".to_owned(),
+            format!("relation \"{}\" does not exist", table),
+        ))));
+    }
+
+    let schema = describe_table_schema();
+    let records = rows
+        .iter()
+        .map(|row| Record {
+            values: vec![
+                value::Value::Text(row.get(0)),
+                value::Value::Text(row.get(1)),
+                value::Value::Bool(row.get(2)),
+                row.get::<_, Option<String>>(3)
+                    .map_or(value::Value::Null, value::Value::Text),
+            ],
+            schema: schema.clone(),
+        })
+        .collect();
+    Ok(QueryOutput::Records(Records { records, schema }))
+}
+
+// one query for the whole peer, streamed as the catalog of a large database
+// can have many columns.
+pub async fn pg_export_schema(
+    client: &Client,
+    schema: Option<&str>,
+    invalid_utf8: InvalidUtf8,
+) -> PgWireResult<QueryOutput> {
+    let rows = client
+        .query_raw(
+            "SELECT table_schema::text, table_name::text, column_name::text, data_type::text,
+                is_nullable = 'YES', ordinal_position::int8
+            FROM information_schema.columns
+            WHERE table_schema NOT IN ('pg_catalog', 'information_schema')
+            AND ($1::text IS NULL OR table_schema::text = $1::text)
+            ORDER BY table_schema, table_name, ordinal_position",
+            [schema],
+        )
+        .await
+        .map_err(|e| pg_error("error exporting schema", e))?;
+    let stream = stream::PgRecordStream::new(rows, export_schema_schema(), invalid_utf8);
+    Ok(QueryOutput::Stream(Box::pin(stream)))
+}
+
+// COPY FROM STDIN lets postgres parse the data and coerce it to the column
+// types, the chunks are passed through as they arrive.
+pub async fn pg_bulk_load(
+    client: &Client,
+    table: &ObjectName,
+    columns: &[Ident],
+    format: BulkLoadFormat,
+    mut data: ByteStream,
+) -> PgWireResult<QueryOutput> {
+    let mut copy = format!("COPY {}", table);
+    if !columns.is_empty() {
+        let columns = columns
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        copy.push_str(&format!(" ({})", columns));
+    }
+    match format {
+        BulkLoadFormat::Csv { header, delimiter } => copy.push_str(&format!(
+            " FROM STDIN WITH (FORMAT csv, HEADER {}, DELIMITER '{}')",
+            header,
+            (delimiter as char).to_string().replace('\'', "''")
+        )),
+        BulkLoadFormat::Binary => copy.push_str(" FROM STDIN WITH (FORMAT binary)"),
+    }
+    tracing::info!("postgres bulk load: {}", copy);
+
+    let sink = client
+        .copy_in(&copy)
+        .await
+        .map_err(|e| pg_error(&format!("error starting copy into {}", table), e))?;
+    futures::pin_mut!(sink);
+    while let Some(chunk) = data.next().await {
+        sink.send(chunk?)
+            .await
+            .map_err(|e| pg_error(&format!("error copying into {}", table), e))?;
+    }
+    let rows = sink
+        .finish()
+        .await
+        .map_err(|e| pg_error(&format!("error copying into {}", table), e))?;
+    Ok(QueryOutput::AffectedRows(rows as usize))
+}
+
+// COPY TO STDOUT of the query in the binary format, the data is passed on as
+// postgres sends it. Only queries are copied, other statements are run as
+// usual.
+pub async fn pg_copy_out_binary(
+    client: &Client,
+    ast: ast::PostgresAst,
+    stmt: &Statement,
+) -> PgWireResult<Option<BinaryCopy>> {
+    let Statement::Query(query) = stmt else {
+        return Ok(None);
+    };
+    let mut query = query.clone();
+    ast.rewrite_query(&mut query);
+    let rewritten_query = query.to_string();
+
+    let schema = schema_from_query(client, &rewritten_query)
+        .await
+        .map_err(|e| pg_error("error getting schema", e))?;
+    let copy = format!("COPY ({}) TO STDOUT WITH (FORMAT binary)", rewritten_query);
+    tracing::info!("[peer-postgres] binary copy: {}", copy);
+    let data = client
+        .copy_out(&copy)
+        .await
+        .map_err(|e| pg_error("error starting binary copy", e))?;
+    Ok(Some(BinaryCopy {
+        columns: schema.len(),
+        data: Box::pin(data.map(|chunk| chunk.map_err(|e| pg_error("error copying out", e)))),
+    }))
+}
+
+// asks postgres to cancel the query running on the connection, it answers
+// nothing and an idle connection ignores it
+pub async fn pg_cancel(client: &Client) -> PgWireResult<()> {
+    client
+        .cancel_token()
+        .cancel_query(postgres_connection::tls_connector())
+        .await
+        .map_err(|e| pg_error("error canceling query", e))
+}
+
+// set_config takes the value as it would be written in postgresql.conf, so
+// lists like a search_path of several schemas keep their meaning.
+pub async fn pg_set_session_parameter(
+    client: &Client,
+    name: &str,
+    value: &str,
+) -> PgWireResult<()> {
+    let query = "SELECT set_config($1, $2, false)";
+    client
+        .execute(query, &[&name, &value])
+        .await
+        .map(|_| ())
+        .map_err(|e| pg_error(&format!("error setting {} on peer", name), e))
+}
+
 #[async_trait::async_trait]
 impl QueryExecutor for PostgresQueryExecutor {
+    fn capabilities(&self) -> PeerCapabilities {
+        Self::CAPABILITIES
+    }
+
     #[tracing::instrument(skip(self, stmt), fields(stmt = %stmt))]
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
-        pg_execute(
+        let ast = ast::PostgresAst {
+            peername: Some(self.peername.clone()),
+        };
+        // the peer connection isn't in a transaction, so cursors are kept in
+        // nexus the same way as for the other peers.
+        match stmt {
+            Statement::Declare { stmts } => {
+                if stmts.len() != 1 {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports singular declare statements".into(),
+                    ))
+                } else if let Declare {
+                    ref names,
+                    for_query: Some(ref query),
+                    ..
+                } = stmts[0]
+                {
+                    let name = &names[0];
+                    let mut query = query.clone();
+                    ast.rewrite_query(&mut query);
+                    let query_stmt = Statement::Query(query);
+                    self.cursor_manager
+                        .create_cursor(&name.value, &query_stmt, self)
+                        .await?;
+
+                    Ok(QueryOutput::Cursor(CursorModification::Created(
+                        name.value.clone(),
+                    )))
+                } else {
+                    Err(PgWireError::ApiError(
+                        "peerdb only supports declare for query statements".into(),
+                    ))
+                }
+            }
+            Statement::Fetch {
+                name, direction, ..
+            } => {
+                tracing::info!("fetching cursor for postgres: {}", name.value);
+                let records = self.cursor_manager.fetch(&name.value, direction).await?;
+                Ok(QueryOutput::Records(records))
+            }
+            Statement::Close { cursor } => {
+                let closed_cursors = match cursor {
+                    CloseCursor::All => self.cursor_manager.close_all_cursors().await?,
+                    CloseCursor::Specific { name } => {
+                        self.cursor_manager.close(&name.value).await?;
+                        vec![name.value.clone()]
+                    }
+                };
+                Ok(QueryOutput::Cursor(CursorModification::Closed(
+                    closed_cursors,
+                )))
+            }
+            _ => {
+                let opens_transaction = match stmt {
+                    Statement::StartTransaction { .. } => Some(true),
+                    Statement::Commit { chain: false }
+                    | Statement::Rollback {
+                        chain: false,
+                        savepoint: None,
+                    } => Some(false),
+                    _ => None,
+                };
+                let res = pg_execute(&self.client, ast, stmt, self.invalid_utf8).await;
+                // a failed COMMIT or ROLLBACK still ends the transaction
+                if let Some(open) = opens_transaction.filter(|open| res.is_ok() || !open) {
+                    self.in_transaction.store(open, Ordering::Relaxed);
+                }
+                res
+            }
+        }
+    }
+
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        self.cursor_manager.move_cursor(name, direction).await
+    }
+
+    async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
+        if matches!(stmt, Statement::Call(_)) || has_returning(stmt) || is_explain(stmt) {
+            let mut rewritten_stmt = stmt.clone();
+            ast::PostgresAst {
+                peername: Some(self.peername.clone()),
+            }
+            .rewrite_statement(&mut rewritten_stmt)
+            .map_err(|e| PgWireError::ApiError(e.into()))?;
+            return pg_describe(&self.client, &rewritten_stmt).await;
+        }
+        pg_describe(&self.client, stmt).await
+    }
+
+    async fn parameter_types(&self, stmt: &Statement) -> PgWireResult<Option<Vec<Type>>> {
+        pg_parameter_types(
             &self.client,
             ast::PostgresAst {
                 peername: Some(self.peername.clone()),
@@ -132,7 +655,79 @@ impl QueryExecutor for PostgresQueryExecutor {
         .await
     }
 
-    async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
-        pg_describe(&self.client, stmt).await
+    async fn describe_table(&self, schema: Option<&str>, table: &str) -> PgWireResult<QueryOutput> {
+        pg_describe_table(&self.client, schema, table).await
+    }
+
+    async fn export_schema(&self, schema: Option<&str>) -> PgWireResult<QueryOutput> {
+        pg_export_schema(&self.client, schema, self.invalid_utf8).await
+    }
+
+    async fn dry_run(&self, stmt: &Statement) -> PgWireResult<DryRun> {
+        pg_dry_run(
+            &self.client,
+            ast::PostgresAst {
+                peername: Some(self.peername.clone()),
+            },
+            stmt,
+        )
+        .await
+    }
+
+    async fn bulk_load(
+        &self,
+        table: &ObjectName,
+        columns: &[Ident],
+        format: BulkLoadFormat,
+        data: ByteStream,
+    ) -> PgWireResult<QueryOutput> {
+        pg_bulk_load(&self.client, table, columns, format, data).await
+    }
+
+    async fn copy_out_binary(&self, stmt: &Statement) -> PgWireResult<Option<BinaryCopy>> {
+        pg_copy_out_binary(
+            &self.client,
+            ast::PostgresAst {
+                peername: Some(self.peername.clone()),
+            },
+            stmt,
+        )
+        .await
+    }
+
+    async fn set_session_parameter(&self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == QUERY_LABELS {
+            // the labels show up in pg_stat_activity and the peer's logs
+            let labels =
+                QueryLabels::parse(value).map_err(|err| PgWireError::ApiError(err.into()))?;
+            let application_name = if labels.is_empty() {
+                postgres_connection::APPLICATION_NAME.to_owned()
+            } else {
+                format!("{} [{}]", postgres_connection::APPLICATION_NAME, labels)
+            };
+            return pg_set_session_parameter(&self.client, "application_name", &application_name)
+                .await;
+        }
+        pg_set_session_parameter(&self.client, name, value).await
+    }
+
+    async fn cancel(&self) -> PgWireResult<()> {
+        pg_cancel(&self.client).await
+    }
+
+    async fn test_connection(&self) -> PgWireResult<()> {
+        self.client
+            .simple_query("SELECT 1")
+            .await
+            .map(|_| ())
+            .map_err(|e| pg_error("error testing connection", e))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.in_transaction.load(Ordering::Relaxed)
     }
 }
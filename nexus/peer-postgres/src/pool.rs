@@ -0,0 +1,260 @@
+//! Pools of connections to postgres peers, shared by the client sessions of
+//! nexus so a burst of sessions doesn't open as many connections to the peer.
+//! A peer pools its connections when its config sets `pool_max_size`.
+//!
+//! A session's executor checks a connection out when it's created and hands
+//! it back when it's dropped: transactions, cursors and the parameters set by
+//! the session live on the connection, so it can't change between queries.
+//! The connection is reset when it's checked out again, connections that
+//! broke or fail the reset are closed and replaced.
+//!
+//! Connections above `pool_min_size` are closed once idle for longer than
+//! `pool_idle_timeout`, counting from their last checkout.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use deadpool::{
+    managed::{Manager, Metrics, Object, Pool, PoolError, RecycleError, RecycleResult},
+    Runtime,
+};
+use pt::peerdb_peers::PostgresConfig;
+use tokio::task::JoinHandle;
+use tokio_postgres::Client;
+
+// how often idle connections are closed and the pools topped up to their
+// minimum
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10);
+// how long a session waits for a connection of an exhausted pool
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// undoes what the previous session left on the connection, like DISCARD ALL
+// but keeping the statements tokio-postgres prepared for its type lookups
+const RESET_SESSION: &str = "SET SESSION AUTHORIZATION DEFAULT; RESET ALL; CLOSE ALL; \
+    UNLISTEN *; SELECT pg_advisory_unlock_all(); DISCARD TEMP;";
+
+/// A connection checked out of a pool, back to the pool when dropped.
+pub type PooledClient = Object<PostgresManager>;
+
+/// The state of the pool of a peer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolStats {
+    pub max_size: usize,
+    /// connections open, idle or checked out
+    pub size: usize,
+    pub idle: usize,
+    /// sessions waiting for a connection
+    pub waiting: usize,
+    /// connections opened since the pool was created
+    pub created: u64,
+    /// connections closed as broken or idle
+    pub closed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    created: AtomicU64,
+    closed: AtomicU64,
+}
+
+pub struct PostgresManager {
+    config: PostgresConfig,
+    counters: Arc<Counters>,
+}
+
+impl Manager for PostgresManager {
+    type Type = Client;
+    type Error = anyhow::Error;
+
+    async fn create(&self) -> anyhow::Result<Client> {
+        let client = postgres_connection::connect_postgres(&self.config).await?;
+        self.counters.created.fetch_add(1, Ordering::Relaxed);
+        Ok(client)
+    }
+
+    async fn recycle(&self, client: &mut Client, _: &Metrics) -> RecycleResult<anyhow::Error> {
+        let reset = if client.is_closed() {
+            Err(anyhow::anyhow!("the connection is closed"))
+        } else {
+            // a transaction left open by the previous session is rolled back
+            // first, outside of one ROLLBACK only warns
+            match client.batch_execute("ROLLBACK").await {
+                Ok(()) => client.batch_execute(RESET_SESSION).await,
+                Err(err) => Err(err),
+            }
+            .map_err(anyhow::Error::from)
+        };
+        reset.map_err(|err| {
+            tracing::warn!("closing pooled connection: {:#}", err);
+            self.counters.closed.fetch_add(1, Ordering::Relaxed);
+            RecycleError::Backend(err)
+        })
+    }
+}
+
+struct PeerPool {
+    config: PostgresConfig,
+    pool: Pool<PostgresManager>,
+    counters: Arc<Counters>,
+    maintenance: JoinHandle<()>,
+}
+
+impl PeerPool {
+    fn new(config: PostgresConfig, max_size: usize) -> anyhow::Result<Self> {
+        let counters = Arc::new(Counters::default());
+        let manager = PostgresManager {
+            config: config.clone(),
+            counters: counters.clone(),
+        };
+        let pool = Pool::builder(manager)
+            .max_size(max_size)
+            .wait_timeout(Some(WAIT_TIMEOUT))
+            .runtime(Runtime::Tokio1)
+            .build()?;
+        let min_size = config.pool_min_size.unwrap_or_default() as usize;
+        let idle_timeout = config
+            .pool_idle_timeout
+            .filter(|secs| *secs > 0)
+            .map(|secs| Duration::from_secs(secs as u64));
+        let maintenance = tokio::spawn(maintain(
+            pool.clone(),
+            min_size.min(max_size),
+            idle_timeout,
+            counters.clone(),
+        ));
+        Ok(Self {
+            config,
+            pool,
+            counters,
+            maintenance,
+        })
+    }
+
+    fn stats(&self) -> PoolStats {
+        let status = self.pool.status();
+        PoolStats {
+            max_size: status.max_size,
+            size: status.size,
+            idle: status.available,
+            waiting: status.waiting,
+            created: self.counters.created.load(Ordering::Relaxed),
+            closed: self.counters.closed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for PeerPool {
+    // the connections checked out are closed as they're handed back
+    fn drop(&mut self) {
+        self.maintenance.abort();
+        self.pool.close();
+    }
+}
+
+// closes the connections idle for too long and opens the ones missing to the
+// minimum size
+async fn maintain(
+    pool: Pool<PostgresManager>,
+    min_size: usize,
+    idle_timeout: Option<Duration>,
+    counters: Arc<Counters>,
+) {
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some(idle_timeout) = idle_timeout {
+            let open = Cell::new(pool.status().size);
+            let retained = pool.retain(|_, metrics| {
+                if metrics.last_used() < idle_timeout || open.get() <= min_size {
+                    return true;
+                }
+                open.set(open.get() - 1);
+                false
+            });
+            counters
+                .closed
+                .fetch_add(retained.removed.len() as u64, Ordering::Relaxed);
+        }
+        // the connections checked out here go back to the pool as idle ones
+        let mut opened = Vec::new();
+        while pool.status().size < min_size && opened.len() < min_size {
+            match pool.get().await {
+                Ok(client) => opened.push(client),
+                Err(err) => {
+                    tracing::warn!("unable to open pooled connection: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The connection pools of the postgres peers, by peer name.
+#[derive(Default)]
+pub struct PostgresPools {
+    pools: Mutex<HashMap<String, PeerPool>>,
+}
+
+impl PostgresPools {
+    /// A connection to the peer out of its pool, None when the peer doesn't
+    /// pool its connections.
+    pub async fn get(
+        &self,
+        peer: &str,
+        config: &PostgresConfig,
+    ) -> anyhow::Result<Option<PooledClient>> {
+        let Some(max_size) = config.pool_max_size.filter(|size| *size > 0) else {
+            return Ok(None);
+        };
+        let pool = {
+            let mut pools = self.pools.lock().unwrap();
+            match pools.get(peer) {
+                Some(existing) if existing.config == *config => existing.pool.clone(),
+                // a new peer or one whose config changed since
+                _ => {
+                    let created = PeerPool::new(config.clone(), max_size as usize)?;
+                    let pool = created.pool.clone();
+                    pools.insert(peer.to_owned(), created);
+                    pool
+                }
+            }
+        };
+        match pool.get().await {
+            Ok(client) => Ok(Some(client)),
+            Err(PoolError::Backend(err)) => Err(err),
+            Err(err) => Err(anyhow::anyhow!(
+                "unable to get a pooled connection: {}",
+                err
+            )),
+        }
+    }
+
+    /// Closes the pool of the peer, or the pools of every peer, the next
+    /// session connects anew.
+    pub fn remove(&self, peer: Option<&str>) {
+        let mut pools = self.pools.lock().unwrap();
+        match peer {
+            Some(peer) => {
+                pools.remove(peer);
+            }
+            None => pools.clear(),
+        }
+    }
+
+    /// The state of the pools, by peer name.
+    pub fn stats(&self) -> HashMap<String, PoolStats> {
+        self.pools
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, pool)| (peer.clone(), pool.stats()))
+            .collect()
+    }
+}
@@ -1,36 +1,58 @@
 use bytes::Bytes;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use futures::Stream;
-use peer_cursor::{Record, RecordStream, Schema};
-use pgwire::error::{PgWireError, PgWireResult};
+use peer_cursor::{util::InvalidUtf8, Record, RecordStream, Schema};
+use pgwire::error::PgWireResult;
 use postgres_inet::MaskedIpAddr;
 use rust_decimal::Decimal;
 use std::{
     pin::Pin,
     task::{Context, Poll},
 };
-use tokio_postgres::{types::Type, Row, RowStream};
+use tokio_postgres::{
+    types::{FromSql, Type},
+    Row, RowStream,
+};
 use uuid::Uuid;
 use value::{array::ArrayValue, Value};
 pub struct PgRecordStream {
     row_stream: Pin<Box<RowStream>>,
     schema: Schema,
+    invalid_utf8: InvalidUtf8,
 }
 
 impl PgRecordStream {
-    pub fn new(row_stream: RowStream, schema: Schema) -> Self {
+    pub fn new(row_stream: RowStream, schema: Schema, invalid_utf8: InvalidUtf8) -> Self {
         Self {
             row_stream: Box::pin(row_stream),
             schema,
+            invalid_utf8,
         }
     }
 }
 
-fn values_from_row(row: &Row) -> Vec<Value> {
+// The raw bytes of a text value, which aren't valid UTF-8 when the database
+// encoding is SQL_ASCII as postgres then doesn't convert them for the client.
+struct TextBytes(Vec<u8>);
+
+impl<'a> FromSql<'a> for TextBytes {
+    fn from_sql(
+        _ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(TextBytes(raw.to_vec()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::VARCHAR | Type::TEXT | Type::BPCHAR | Type::NAME)
+    }
+}
+
+fn values_from_row(row: &Row, invalid_utf8: InvalidUtf8) -> PgWireResult<Vec<Value>> {
     (0..row.len())
         .map(|i| {
             let col_type = row.columns()[i].type_();
-            match col_type {
+            Ok(match col_type {
                 &Type::BOOL => row
                     .get::<_, Option<bool>>(i)
                     .map(Value::Bool)
@@ -41,9 +63,11 @@ fn values_from_row(row: &Row) -> Vec<Value> {
                         .map(Value::Char)
                         .unwrap_or(Value::Null)
                 }
-                &Type::VARCHAR | &Type::TEXT | &Type::BPCHAR => {
-                    let s: Option<String> = row.get(i);
-                    s.map(Value::Text).unwrap_or(Value::Null)
+                &Type::VARCHAR | &Type::TEXT | &Type::BPCHAR | &Type::NAME => {
+                    match row.get::<_, Option<TextBytes>>(i) {
+                        Some(TextBytes(bytes)) => Value::Text(invalid_utf8.decode(bytes)?),
+                        None => Value::Null,
+                    }
                 }
                 &Type::VARCHAR_ARRAY | &Type::BPCHAR_ARRAY => {
                     let s: Option<Vec<String>> = row.get(i);
@@ -51,8 +75,7 @@ fn values_from_row(row: &Row) -> Vec<Value> {
                         .map(Value::Array)
                         .unwrap_or(Value::Null)
                 }
-                &Type::NAME
-                | &Type::REGNAMESPACE
+                &Type::REGNAMESPACE
                 | &Type::REGPROC
                 | &Type::REGPROCEDURE
                 | &Type::REGOPER
@@ -248,7 +271,7 @@ fn values_from_row(row: &Row) -> Vec<Value> {
                         }
                     }
                 }
-            }
+            })
         })
         .collect()
 }
@@ -262,12 +285,12 @@ impl Stream for PgRecordStream {
 
         match Pin::new(row_stream).poll_next(cx) {
             Poll::Ready(Some(Ok(row))) => {
-                let values = values_from_row(&row);
-                let record = Record { values, schema };
-                Poll::Ready(Some(Ok(record)))
+                let record = values_from_row(&row, self.invalid_utf8)
+                    .map(|values| Record { values, schema });
+                Poll::Ready(Some(record))
             }
             Poll::Ready(Some(Err(e))) => {
-                let err = PgWireError::ApiError(Box::new(e));
+                let err = crate::pg_error("error reading rows", e);
                 Poll::Ready(Some(Err(err)))
             }
             Poll::Ready(None) => Poll::Ready(None),
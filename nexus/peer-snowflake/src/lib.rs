@@ -1,8 +1,13 @@
 use anyhow::Context;
 use async_recursion::async_recursion;
-use peer_cursor::{CursorManager, CursorModification, QueryExecutor, QueryOutput, Schema};
+use peer_cursor::{
+    labels::{QueryLabels, QUERY_LABELS},
+    util::select_one,
+    CursorManager, CursorModification, PeerCapabilities, QueryExecutor, QueryOutput, Schema,
+};
 use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
 use std::cmp::min;
+use std::sync::Mutex;
 use std::time::Duration;
 use stream::SnowflakeDataType;
 
@@ -100,6 +105,7 @@ pub struct SnowflakeQueryExecutor {
     query_timeout: u64,
     reqwest_client: reqwest::Client,
     cursor_manager: CursorManager,
+    query_labels: Mutex<QueryLabels>,
 }
 
 enum QueryAttemptResult {
@@ -110,6 +116,14 @@ enum QueryAttemptResult {
 }
 
 impl SnowflakeQueryExecutor {
+    pub const CAPABILITIES: PeerCapabilities = PeerCapabilities {
+        queryable: true,
+        writable: false,
+        supports_cursors: true,
+        supports_transactions: false,
+        supports_copy: false,
+    };
+
     pub async fn new(config: &SnowflakeConfig) -> anyhow::Result<Self> {
         let mut default_headers = header::HeaderMap::new();
         default_headers.insert(
@@ -145,6 +159,7 @@ impl SnowflakeQueryExecutor {
             query_timeout: config.query_timeout,
             reqwest_client,
             cursor_manager: Default::default(),
+            query_labels: Mutex::default(),
         })
     }
 
@@ -200,7 +215,11 @@ impl SnowflakeQueryExecutor {
 
         let _ = ast::SnowflakeAst.rewrite(&mut query);
 
-        let query_str: String = query.to_string();
+        let query_str = self
+            .query_labels
+            .lock()
+            .unwrap()
+            .annotate(&query.to_string());
         info!("Processing SnowFlake query: {}", query_str);
 
         let result_set = self
@@ -292,6 +311,10 @@ impl SnowflakeQueryExecutor {
 
 #[async_trait::async_trait]
 impl QueryExecutor for SnowflakeQueryExecutor {
+    fn capabilities(&self) -> PeerCapabilities {
+        Self::CAPABILITIES
+    }
+
     #[tracing::instrument(skip(self, stmt), fields(stmt = %stmt))]
     async fn execute(&self, stmt: &Statement) -> PgWireResult<QueryOutput> {
         match stmt {
@@ -345,31 +368,8 @@ impl QueryExecutor for SnowflakeQueryExecutor {
             } => {
                 tracing::info!("fetching cursor for snowflake: {}", name.value);
 
-                // Attempt to extract the count from the direction
-                let count = match direction {
-                    FetchDirection::ForwardAll | FetchDirection::All => usize::MAX,
-                    FetchDirection::Next | FetchDirection::Forward { limit: None } => 1,
-                    FetchDirection::Count {
-                        limit: sqlparser::ast::Value::Number(n, _),
-                    }
-                    | FetchDirection::Forward {
-                        limit: Some(sqlparser::ast::Value::Number(n, _)),
-                    } => n
-                        .parse::<usize>()
-                        .map_err(|err| PgWireError::ApiError(err.into()))?,
-                    _ => {
-                        return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                            "ERROR".to_owned(),
-                            "fdw_error".to_owned(),
-                            "only FORWARD count and COUNT count are supported in FETCH".to_owned(),
-                        ))))
-                    }
-                };
-
-                tracing::info!("fetching {} rows", count);
-
                 // Fetch rows from the cursor manager
-                let records = self.cursor_manager.fetch(&name.value, count).await?;
+                let records = self.cursor_manager.fetch(&name.value, direction).await?;
 
                 // Return the fetched records as the query output
                 Ok(QueryOutput::Records(records))
@@ -393,13 +393,17 @@ impl QueryExecutor for SnowflakeQueryExecutor {
                 );
                 PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                     "ERROR".to_owned(),
-                    "fdw_error".to_owned(),
+                    "0A000".to_owned(),
                     error,
                 ))))
             }
         }
     }
 
+    async fn move_cursor(&self, name: &str, direction: &FetchDirection) -> PgWireResult<usize> {
+        self.cursor_manager.move_cursor(name, direction).await
+    }
+
     async fn describe(&self, stmt: &Statement) -> PgWireResult<Option<Schema>> {
         match stmt {
             Statement::Query(query) => {
@@ -434,9 +438,21 @@ impl QueryExecutor for SnowflakeQueryExecutor {
             }
             _ => PgWireResult::Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "fdw_error".to_owned(),
+                "0A000".to_owned(),
                 "only SELECT statements are supported in snowflake".to_owned(),
             )))),
         }
     }
+
+    async fn set_session_parameter(&self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == QUERY_LABELS {
+            *self.query_labels.lock().unwrap() =
+                QueryLabels::parse(value).map_err(|err| PgWireError::ApiError(err.into()))?;
+        }
+        Ok(())
+    }
+
+    async fn test_connection(&self) -> PgWireResult<()> {
+        select_one(self).await
+    }
 }
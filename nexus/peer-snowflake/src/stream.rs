@@ -135,7 +135,6 @@ impl SnowflakeRecordStream {
                         SnowflakeDataType::Binary => Binary(hex::decode(elem)?.into()),
                         SnowflakeDataType::Boolean => Bool(elem.parse()?),
                         SnowflakeDataType::Date => {
-                            println!("Entered Date. elem: {:#?}", elem);
                             Date(NaiveDate::parse_from_str(elem, DATE_PARSE_FORMAT)?)
                         }
                         SnowflakeDataType::Time => {
@@ -212,7 +211,6 @@ impl SnowflakeRecordStream {
         let secret = self.auth.get_jwt()?.expose_secret().clone();
         let statement_handle = self.result_set.statementHandle.clone();
         let url = self.endpoint_url.clone();
-        println!("Secret: {:#?}", secret);
         let response: PartitionResult = ureq::get(&format!("{}/{}", url, statement_handle))
             .query("partition", &partition_number.to_string())
             .set("Authorization", &format!("Bearer {}", secret))
@@ -221,7 +219,11 @@ impl SnowflakeRecordStream {
             .call()?
             .into_json()
             .map_err(|_| anyhow::anyhow!("get_partition failed"))?;
-        println!("Response: {:#?}", response.data);
+        tracing::debug!(
+            partition = partition_number,
+            rows = response.data.len(),
+            "fetched snowflake partition"
+        );
 
         self.result_set.data = response.data;
         Ok(true)
@@ -2,9 +2,13 @@ use pt::peerdb_peers::PostgresConfig;
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
 use std::fmt::Write;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio_postgres_rustls::MakeRustlsConnect;
 
+/// The application_name of nexus' connections to postgres.
+pub const APPLICATION_NAME: &str = "peerdb_nexus";
+
 #[derive(Copy, Clone, Debug)]
 struct NoCertificateVerification;
 
@@ -56,6 +60,10 @@ impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
 }
 
 pub fn get_pg_connection_string(config: &PostgresConfig) -> String {
+    connection_string_for_host(config, &config.host, config.port)
+}
+
+fn connection_string_for_host(config: &PostgresConfig, host: &str, port: u32) -> String {
     let mut connection_string = String::from("postgres://");
 
     connection_string.push_str(&urlencoding::encode(&config.user));
@@ -67,27 +75,105 @@ pub fn get_pg_connection_string(config: &PostgresConfig) -> String {
     // Add the timeout as a query parameter, sslmode changes here appear to be useless
     write!(
         connection_string,
-        "@{}:{}/{}?connect_timeout=15&application_name=peerdb_nexus",
-        config.host,
-        config.port,
-        urlencoding::encode(&config.database)
+        "@{}:{}/{}?connect_timeout=15&application_name={}",
+        host,
+        port,
+        urlencoding::encode(&config.database),
+        APPLICATION_NAME
     )
     .ok();
 
     connection_string
 }
 
-pub async fn connect_postgres(config: &PostgresConfig) -> anyhow::Result<tokio_postgres::Client> {
-    let connection_string = get_pg_connection_string(config);
+/// The kind of server a connection must land on, as in libpq's
+/// target_session_attrs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetSessionAttrs {
+    Any,
+    ReadWrite,
+    ReadOnly,
+    Primary,
+    Standby,
+}
+
+impl FromStr for TargetSessionAttrs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            "read-only" => Ok(TargetSessionAttrs::ReadOnly),
+            "primary" => Ok(TargetSessionAttrs::Primary),
+            "standby" => Ok(TargetSessionAttrs::Standby),
+            _ => Err(anyhow::anyhow!("invalid target_session_attrs: {}", s)),
+        }
+    }
+}
+
+impl TargetSessionAttrs {
+    async fn is_satisfied_by(&self, client: &tokio_postgres::Client) -> anyhow::Result<bool> {
+        let query = match self {
+            TargetSessionAttrs::Any => return Ok(true),
+            TargetSessionAttrs::ReadWrite | TargetSessionAttrs::ReadOnly => {
+                "SELECT current_setting('transaction_read_only') = 'on'"
+            }
+            TargetSessionAttrs::Primary | TargetSessionAttrs::Standby => {
+                "SELECT pg_is_in_recovery()"
+            }
+        };
+        let flag: bool = client.query_one(query, &[]).await?.get(0);
+        Ok(match self {
+            TargetSessionAttrs::ReadWrite | TargetSessionAttrs::Primary => !flag,
+            _ => flag,
+        })
+    }
+}
 
+// host:port followed by the entries of hosts. Entries are `host[:port]` and
+// default to the peer's port, IPv6 addresses need brackets to carry a port.
+fn candidate_hosts(config: &PostgresConfig) -> anyhow::Result<Vec<(String, u32)>> {
+    let mut candidates = vec![(config.host.clone(), config.port)];
+    for entry in &config.hosts {
+        let (host, port) = match entry.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => (host, Some(port)),
+            _ => (entry.as_str(), None),
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid port in hosts entry: {}", entry))?,
+            None => config.port,
+        };
+        candidates.push((host.to_string(), port));
+    }
+    Ok(candidates)
+}
+
+/// The TLS setup of nexus' connections to postgres, which doesn't verify the
+/// server's certificate.
+pub fn tls_connector() -> MakeRustlsConnect {
     let mut config = ClientConfig::builder()
         .with_root_certificates(RootCertStore::empty())
         .with_no_client_auth();
     config
         .dangerous()
         .set_certificate_verifier(Arc::new(NoCertificateVerification));
-    let tls_connector = MakeRustlsConnect::new(config);
-    let (client, connection) = tokio_postgres::connect(&connection_string, tls_connector)
+    MakeRustlsConnect::new(config)
+}
+
+async fn connect_host(
+    config: &PostgresConfig,
+    host: &str,
+    port: u32,
+) -> anyhow::Result<tokio_postgres::Client> {
+    // tokio-postgres always starts sessions with client_encoding=UTF8, so
+    // databases in other encodings convert text for us. Only SQL_ASCII
+    // databases send their bytes as is.
+    let connection_string = connection_string_for_host(config, host, port);
+
+    let (client, connection) = tokio_postgres::connect(&connection_string, tls_connector())
         .await
         .map_err(|e| anyhow::anyhow!("error encountered while connecting to postgres {:?}", e))?;
 
@@ -99,3 +185,58 @@ pub async fn connect_postgres(config: &PostgresConfig) -> anyhow::Result<tokio_p
 
     Ok(client)
 }
+
+/// Connects to the first of the configured hosts that accepts the connection
+/// and satisfies target_session_attrs, trying them in order.
+pub async fn connect_postgres(config: &PostgresConfig) -> anyhow::Result<tokio_postgres::Client> {
+    let target: TargetSessionAttrs = config
+        .target_session_attrs
+        .as_deref()
+        .unwrap_or_default()
+        .parse()?;
+
+    let mut last_err = None;
+    for (host, port) in candidate_hosts(config)? {
+        let client = match connect_host(config, &host, port).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::warn!(
+                    "unable to connect to postgres at {}:{}: {}",
+                    host,
+                    port,
+                    err
+                );
+                last_err = Some(err);
+                continue;
+            }
+        };
+        match target.is_satisfied_by(&client).await {
+            Ok(true) => return Ok(client),
+            Ok(false) => {
+                tracing::info!(
+                    "postgres at {}:{} does not satisfy target_session_attrs {:?}",
+                    host,
+                    port,
+                    target
+                );
+                last_err = Some(anyhow::anyhow!(
+                    "postgres at {}:{} does not satisfy target_session_attrs {:?}",
+                    host,
+                    port,
+                    target
+                ));
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "unable to check session attributes of {}:{}: {}",
+                    host,
+                    port,
+                    err
+                );
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no postgres hosts configured")))
+}
@@ -13,6 +13,7 @@ pub mod peerdb_peers;
 #[rustfmt::skip]
 #[path ="./gen/peerdb_route.rs"]
 pub mod peerdb_route;
+pub mod validate;
 
 pub use prost;
 pub use tonic;
@@ -0,0 +1,341 @@
+//! Checks of peer configs before they are stored, so a `CREATE PEER` with a
+//! missing option or options that can't work together fails right away with
+//! the option named, instead of when nexus or flow first connects.
+
+use std::fmt;
+
+use crate::peerdb_peers::{
+    peer::Config, BigqueryConfig, ClickhouseConfig, ElasticsearchAuthType, ElasticsearchConfig,
+    EventHubGroupConfig, KafkaConfig, MongoConfig, MySqlConfig, PostgresConfig, PubSubConfig,
+    S3Config, SnowflakeConfig, SqlServerConfig,
+};
+
+const POSTGRES_TARGET_SESSION_ATTRS: &[&str] = &[
+    "any",
+    "read-write",
+    "read-only",
+    "primary",
+    "standby",
+    "prefer-standby",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A required option is missing or empty.
+    Missing(&'static str),
+    /// An option has a value that can't work, alone or with the other options.
+    Invalid {
+        option: &'static str,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    /// The SQLSTATE to report the error with: a syntax error for missing
+    /// options, an invalid parameter value otherwise.
+    pub fn sqlstate(&self) -> &'static str {
+        match self {
+            ConfigError::Missing(_) => "42601",
+            ConfigError::Invalid { .. } => "22023",
+        }
+    }
+
+    fn invalid(option: &'static str, reason: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            option,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(option) => write!(f, "missing required option {}", option),
+            ConfigError::Invalid { option, reason } => {
+                write!(f, "invalid option {}: {}", option, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn required(option: &'static str, value: &str) -> Result<(), ConfigError> {
+    if value.trim().is_empty() {
+        return Err(ConfigError::Missing(option));
+    }
+    Ok(())
+}
+
+fn port(option: &'static str, port: u32) -> Result<(), ConfigError> {
+    if port == 0 || port > u16::MAX as u32 {
+        return Err(ConfigError::invalid(
+            option,
+            format!("{} is not a port between 1 and 65535", port),
+        ));
+    }
+    Ok(())
+}
+
+// credentials that only work together, like an access key and its secret
+fn paired(
+    (first, first_value): (&'static str, &str),
+    (second, second_value): (&'static str, &str),
+) -> Result<(), ConfigError> {
+    match (first_value.is_empty(), second_value.is_empty()) {
+        (false, true) => Err(ConfigError::invalid(
+            first,
+            format!("{} is required with it", second),
+        )),
+        (true, false) => Err(ConfigError::invalid(
+            second,
+            format!("{} is required with it", first),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self {
+            Config::SnowflakeConfig(config) => config.validate(),
+            Config::BigqueryConfig(config) => config.validate(),
+            Config::MongoConfig(config) => config.validate(),
+            Config::PostgresConfig(config) => config.validate(),
+            Config::S3Config(config) => config.validate(),
+            Config::SqlserverConfig(config) => config.validate(),
+            Config::EventhubGroupConfig(config) => config.validate(),
+            Config::ClickhouseConfig(config) => config.validate(),
+            Config::KafkaConfig(config) => config.validate(),
+            Config::PubsubConfig(config) => config.validate(),
+            Config::ElasticsearchConfig(config) => config.validate(),
+            Config::MysqlConfig(config) => config.validate(),
+        }
+    }
+}
+
+impl SnowflakeConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("account_id", &self.account_id)?;
+        required("username", &self.username)?;
+        required("private_key", &self.private_key)?;
+        required("database", &self.database)?;
+        required("warehouse", &self.warehouse)?;
+        required("role", &self.role)?;
+        if self.query_timeout == 0 {
+            return Err(ConfigError::invalid(
+                "query_timeout",
+                "must be at least 1 second",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl BigqueryConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("type", &self.auth_type)?;
+        required("project_id", &self.project_id)?;
+        required("private_key_id", &self.private_key_id)?;
+        required("private_key", &self.private_key)?;
+        required("client_email", &self.client_email)?;
+        required("dataset_id", &self.dataset_id)?;
+        if !self
+            .dataset_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(ConfigError::invalid(
+                "dataset_id",
+                "may only contain letters, digits and _",
+            ));
+        }
+        if let Some(bytes) = self.maximum_bytes_billed.filter(|bytes| *bytes <= 0) {
+            return Err(ConfigError::invalid(
+                "maximum_bytes_billed",
+                format!("{} is not a positive number of bytes", bytes),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl MongoConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("username", &self.username)?;
+        required("password", &self.password)?;
+        required("clusterurl", &self.clusterurl)?;
+        required("database", &self.database)?;
+        port(
+            "clusterport",
+            self.clusterport.try_into().unwrap_or_default(),
+        )
+    }
+}
+
+impl PostgresConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.hosts.is_empty() {
+            required("host", &self.host)?;
+        }
+        port("port", self.port)?;
+        required("user", &self.user)?;
+        // the password itself or a secret://... reference to it
+        required("password", &self.password)?;
+        required("database", &self.database)?;
+        if let Some(attrs) = &self.target_session_attrs {
+            if !POSTGRES_TARGET_SESSION_ATTRS.contains(&attrs.as_str()) {
+                return Err(ConfigError::invalid(
+                    "target_session_attrs",
+                    format!(
+                        "\"{}\" is not one of {}",
+                        attrs,
+                        POSTGRES_TARGET_SESSION_ATTRS.join(", ")
+                    ),
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.pool_min_size, self.pool_max_size) {
+            if min > max {
+                return Err(ConfigError::invalid(
+                    "pool_min_size",
+                    format!("{} is more than pool_max_size {}", min, max),
+                ));
+            }
+        }
+        if let Some(ssh) = &self.ssh_config {
+            required("ssh_config.host", &ssh.host)?;
+            port("ssh_config.port", ssh.port)?;
+            required("ssh_config.user", &ssh.user)?;
+            if ssh.password.is_empty() && ssh.private_key.is_empty() {
+                return Err(ConfigError::invalid(
+                    "ssh_config",
+                    "either password or private_key is required",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl S3Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("url", &self.url)?;
+        if !self.url.starts_with("s3://") {
+            return Err(ConfigError::invalid("url", "must start with s3://"));
+        }
+        paired(
+            (
+                "access_key_id",
+                self.access_key_id.as_deref().unwrap_or_default(),
+            ),
+            (
+                "secret_access_key",
+                self.secret_access_key.as_deref().unwrap_or_default(),
+            ),
+        )
+    }
+}
+
+impl SqlServerConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("server", &self.server)?;
+        port("port", self.port)?;
+        required("user", &self.user)?;
+        required("password", &self.password)?;
+        required("database", &self.database)
+    }
+}
+
+impl EventHubGroupConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.eventhubs.is_empty() {
+            return Err(ConfigError::Missing("eventhubs"));
+        }
+        for eventhub in self.eventhubs.values() {
+            required("eventhubs.namespace", &eventhub.namespace)?;
+            required("eventhubs.resource_group", &eventhub.resource_group)?;
+            required("eventhubs.subscription_id", &eventhub.subscription_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl ClickhouseConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("host", &self.host)?;
+        port("port", self.port)?;
+        if let Some(http_port) = self.http_port {
+            port("http_port", http_port)?;
+        }
+        required("user", &self.user)?;
+        required("database", &self.database)?;
+        paired(
+            ("access_key_id", &self.access_key_id),
+            ("secret_access_key", &self.secret_access_key),
+        )?;
+        paired(
+            (
+                "certificate",
+                self.certificate.as_deref().unwrap_or_default(),
+            ),
+            (
+                "private_key",
+                self.private_key.as_deref().unwrap_or_default(),
+            ),
+        )
+    }
+}
+
+impl KafkaConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.servers.iter().all(|server| server.trim().is_empty()) {
+            return Err(ConfigError::Missing("servers"));
+        }
+        paired(("user", &self.username), ("password", &self.password))
+    }
+}
+
+impl PubSubConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let account = self
+            .service_account
+            .as_ref()
+            .ok_or(ConfigError::Missing("private_key"))?;
+        required("type", &account.auth_type)?;
+        required("project_id", &account.project_id)?;
+        required("private_key_id", &account.private_key_id)?;
+        required("private_key", &account.private_key)?;
+        required("client_email", &account.client_email)
+    }
+}
+
+impl ElasticsearchConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self
+            .addresses
+            .iter()
+            .all(|address| address.trim().is_empty())
+        {
+            return Err(ConfigError::Missing("addresses"));
+        }
+        match self.auth_type() {
+            ElasticsearchAuthType::Basic => {
+                required("username", self.username.as_deref().unwrap_or_default())?;
+                required("password", self.password.as_deref().unwrap_or_default())
+            }
+            ElasticsearchAuthType::Apikey => {
+                required("api_key", self.api_key.as_deref().unwrap_or_default())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MySqlConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        required("host", &self.host)?;
+        port("port", self.port)
+    }
+}
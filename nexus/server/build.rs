@@ -0,0 +1,39 @@
+use std::{env, path::Path, process::Command};
+
+// embeds the commit and date of the build, reported by `peerdb.version()`.
+// Builds outside a git checkout, e.g. in docker, can pass the commit in
+// PEERDB_GIT_COMMIT.
+fn main() {
+    println!("cargo:rerun-if-env-changed=PEERDB_GIT_COMMIT");
+    let git_commit = env::var("PEERDB_GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| command_output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=PEERDB_GIT_COMMIT={}", git_commit);
+
+    let build_date = command_output("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"])
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=PEERDB_BUILD_DATE={}", build_date);
+
+    // a new commit moves the branch HEAD points to
+    if let Some(git_dir) = command_output("git", &["rev-parse", "--absolute-git-dir"]) {
+        let head = Path::new(&git_dir).join("HEAD");
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Some(branch) = command_output("git", &["symbolic-ref", "-q", "HEAD"]) {
+            println!(
+                "cargo:rerun-if-changed={}",
+                Path::new(&git_dir).join(branch).display()
+            );
+        }
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned())
+}
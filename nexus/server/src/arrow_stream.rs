@@ -0,0 +1,287 @@
+//! Rows as an Apache Arrow IPC stream, for `COPY ... TO STDOUT WITH (FORMAT
+//! arrow)`. The CopyData messages together make up one IPC stream: the
+//! schema, a record batch per `BATCH_ROWS` rows and the end-of-stream
+//! marker, which pyarrow, pandas and polars read without parsing any text.
+//!
+//! Numbers, booleans, dates, timestamps and bytea keep their type. Columns
+//! of other types are utf8, with the text postgres would send for them.
+
+use std::sync::Arc;
+
+use arrow_array::{
+    builder::{
+        BinaryBuilder, BooleanBuilder, Date32Builder, Float32Builder, Float64Builder, Int16Builder,
+        Int32Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder, UInt32Builder,
+    },
+    types::Date32Type,
+    ArrayRef, RecordBatch, RecordBatchOptions,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema as ArrowSchema, SchemaRef, TimeUnit};
+use bytes::{Bytes, BytesMut};
+use futures::{future, stream, Stream, StreamExt};
+use peer_cursor::{util::write_value, Record, Schema};
+use pgwire::{
+    api::{results::FieldFormat, Type},
+    error::{ErrorInfo, PgWireError, PgWireResult},
+};
+use value::Value;
+
+// rows gathered into one record batch
+const BATCH_ROWS: usize = 8192;
+
+/// The IPC stream of the rows, in chunks of one message or batch each.
+/// Nothing follows an error, the stream ends with it.
+pub fn ipc_stream<'a, S>(
+    schema: Schema,
+    rows: S,
+) -> impl Stream<Item = PgWireResult<Bytes>> + Send + 'a
+where
+    S: Stream<Item = PgWireResult<Record>> + Send + 'a,
+{
+    let (header, state) = match IpcWriter::new(schema) {
+        Ok(mut writer) => {
+            let header = writer.take();
+            (Ok(header), Some((writer, rows.boxed().chunks(BATCH_ROWS))))
+        }
+        Err(err) => (Err(arrow_error(&err)), None),
+    };
+    stream::once(future::ready(header)).chain(stream::unfold(state, |state| async move {
+        let (mut writer, mut chunks) = state?;
+        match chunks.next().await {
+            Some(records) => {
+                let batch = records
+                    .into_iter()
+                    .collect::<PgWireResult<Vec<_>>>()
+                    .and_then(|records| writer.write(&records));
+                let failed = batch.is_err();
+                Some((batch, (!failed).then_some((writer, chunks))))
+            }
+            None => Some((writer.finish(), None)),
+        }
+    }))
+}
+
+struct IpcWriter {
+    schema: Schema,
+    arrow_schema: SchemaRef,
+    writer: StreamWriter<Vec<u8>>,
+}
+
+impl IpcWriter {
+    fn new(schema: Schema) -> Result<Self, ArrowError> {
+        let arrow_schema = Arc::new(ArrowSchema::new(
+            schema
+                .iter()
+                .map(|field| Field::new(field.name(), arrow_type(field.datatype()), true))
+                .collect::<Vec<_>>(),
+        ));
+        let writer = StreamWriter::try_new(Vec::new(), &arrow_schema)?;
+        Ok(Self {
+            schema,
+            arrow_schema,
+            writer,
+        })
+    }
+
+    // the bytes written since the last call
+    fn take(&mut self) -> Bytes {
+        Bytes::from(std::mem::take(self.writer.get_mut()))
+    }
+
+    fn write(&mut self, records: &[Record]) -> PgWireResult<Bytes> {
+        let mut columns = self
+            .schema
+            .iter()
+            .map(|field| ColumnBuilder::new(field.datatype(), records.len()))
+            .collect::<Vec<_>>();
+        for record in records {
+            for (column, (builder, value)) in columns.iter_mut().zip(&record.values).enumerate() {
+                builder.append(value).map_err(|_| {
+                    convert_error(format!(
+                        "value of column \"{}\" does not match its type {}",
+                        self.schema[column].name(),
+                        self.schema[column].datatype()
+                    ))
+                })?;
+            }
+        }
+        let batch = RecordBatch::try_new_with_options(
+            self.arrow_schema.clone(),
+            columns.into_iter().map(ColumnBuilder::finish).collect(),
+            &RecordBatchOptions::new().with_row_count(Some(records.len())),
+        )
+        .map_err(|err| arrow_error(&err))?;
+        self.writer.write(&batch).map_err(|err| arrow_error(&err))?;
+        Ok(self.take())
+    }
+
+    fn finish(mut self) -> PgWireResult<Bytes> {
+        self.writer.finish().map_err(|err| arrow_error(&err))?;
+        Ok(self.take())
+    }
+}
+
+// the arrow type of a column, utf8 for the types kept as text
+fn arrow_type(datatype: &Type) -> DataType {
+    match *datatype {
+        Type::BOOL => DataType::Boolean,
+        Type::INT2 => DataType::Int16,
+        Type::INT4 => DataType::Int32,
+        Type::INT8 => DataType::Int64,
+        Type::OID => DataType::UInt32,
+        Type::FLOAT4 => DataType::Float32,
+        Type::FLOAT8 => DataType::Float64,
+        Type::DATE => DataType::Date32,
+        Type::TIMESTAMP => DataType::Timestamp(TimeUnit::Microsecond, None),
+        Type::TIMESTAMPTZ => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        Type::BYTEA => DataType::Binary,
+        _ => DataType::Utf8,
+    }
+}
+
+enum ColumnBuilder {
+    Bool(BooleanBuilder),
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    UInt32(UInt32Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Date32(Date32Builder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Binary(BinaryBuilder),
+    Text(StringBuilder, Type, BytesMut),
+}
+
+impl ColumnBuilder {
+    fn new(datatype: &Type, rows: usize) -> Self {
+        match arrow_type(datatype) {
+            DataType::Boolean => Self::Bool(BooleanBuilder::with_capacity(rows)),
+            DataType::Int16 => Self::Int16(Int16Builder::with_capacity(rows)),
+            DataType::Int32 => Self::Int32(Int32Builder::with_capacity(rows)),
+            DataType::Int64 => Self::Int64(Int64Builder::with_capacity(rows)),
+            DataType::UInt32 => Self::UInt32(UInt32Builder::with_capacity(rows)),
+            DataType::Float32 => Self::Float32(Float32Builder::with_capacity(rows)),
+            DataType::Float64 => Self::Float64(Float64Builder::with_capacity(rows)),
+            DataType::Date32 => Self::Date32(Date32Builder::with_capacity(rows)),
+            DataType::Timestamp(_, timezone) => Self::Timestamp(
+                TimestampMicrosecondBuilder::with_capacity(rows).with_timezone_opt(timezone),
+            ),
+            DataType::Binary => Self::Binary(BinaryBuilder::new()),
+            _ => Self::Text(StringBuilder::new(), datatype.clone(), BytesMut::new()),
+        }
+    }
+
+    // Err(()) when the value doesn't fit the column's type
+    fn append(&mut self, value: &Value) -> Result<(), ()> {
+        let integer = match value {
+            Value::TinyInt(v) => Some(*v as i64),
+            Value::SmallInt(v) => Some(*v as i64),
+            Value::Integer(v) => Some(*v as i64),
+            Value::BigInt(v) => Some(*v),
+            Value::Oid(v) => Some(*v as i64),
+            _ => None,
+        };
+        let float = match value {
+            Value::Float(v) => Some(*v as f64),
+            Value::Double(v) => Some(*v),
+            _ => integer.map(|v| v as f64),
+        };
+        let null = matches!(value, Value::Null);
+        match self {
+            Self::Bool(builder) => match value {
+                Value::Bool(v) => builder.append_value(*v),
+                _ if null => builder.append_null(),
+                _ => return Err(()),
+            },
+            Self::Int16(builder) => match integer {
+                Some(v) => builder.append_value(i16::try_from(v).map_err(|_| ())?),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::Int32(builder) => match integer {
+                Some(v) => builder.append_value(i32::try_from(v).map_err(|_| ())?),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::Int64(builder) => match integer {
+                Some(v) => builder.append_value(v),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::UInt32(builder) => match integer {
+                Some(v) => builder.append_value(u32::try_from(v).map_err(|_| ())?),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::Float32(builder) => match float {
+                Some(v) => builder.append_value(v as f32),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::Float64(builder) => match float {
+                Some(v) => builder.append_value(v),
+                None if null => builder.append_null(),
+                None => return Err(()),
+            },
+            Self::Date32(builder) => match value {
+                Value::Date(d) => builder.append_value(Date32Type::from_naive_date(*d)),
+                _ if null => builder.append_null(),
+                _ => return Err(()),
+            },
+            Self::Timestamp(builder) => match value {
+                Value::Timestamp(ts) | Value::TimestampWithTimeZone(ts) => {
+                    builder.append_value(ts.timestamp_micros())
+                }
+                Value::PostgresTimestamp(ts) => {
+                    builder.append_value(ts.and_utc().timestamp_micros())
+                }
+                _ if null => builder.append_null(),
+                _ => return Err(()),
+            },
+            Self::Binary(builder) => match value {
+                Value::Binary(b) | Value::VarBinary(b) => builder.append_value(b),
+                _ if null => builder.append_null(),
+                _ => return Err(()),
+            },
+            Self::Text(builder, datatype, text) => {
+                text.clear();
+                if write_value(value, datatype, FieldFormat::Text, text).map_err(|_| ())? {
+                    builder.append_value(String::from_utf8_lossy(text));
+                } else {
+                    builder.append_null();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> ArrayRef {
+        match self {
+            Self::Bool(mut builder) => Arc::new(builder.finish()),
+            Self::Int16(mut builder) => Arc::new(builder.finish()),
+            Self::Int32(mut builder) => Arc::new(builder.finish()),
+            Self::Int64(mut builder) => Arc::new(builder.finish()),
+            Self::UInt32(mut builder) => Arc::new(builder.finish()),
+            Self::Float32(mut builder) => Arc::new(builder.finish()),
+            Self::Float64(mut builder) => Arc::new(builder.finish()),
+            Self::Date32(mut builder) => Arc::new(builder.finish()),
+            Self::Timestamp(mut builder) => Arc::new(builder.finish()),
+            Self::Binary(mut builder) => Arc::new(builder.finish()),
+            Self::Text(mut builder, ..) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+fn convert_error(message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "42804".to_owned(),
+        message,
+    )))
+}
+
+fn arrow_error(err: &ArrowError) -> PgWireError {
+    PgWireError::ApiError(format!("unable to write arrow stream: {}", err).into())
+}
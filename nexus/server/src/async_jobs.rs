@@ -0,0 +1,319 @@
+//! Queries run in the background: `SELECT peerdb.submit_async('query')`
+//! returns the id of a job right away, `peerdb.async_status('id')` tells
+//! whether it is still running, and once it succeeded
+//! `peerdb.async_result('id')` returns its rows, a single time.
+//!
+//! A job runs on a connection of its own to the peer, or to the catalog, so
+//! it goes on after the connection that submitted it is closed and stays out
+//! of that connection's transactions. Its rows are held until they are
+//! fetched, counting towards the memory limit of the submitting connection.
+//!
+//! Jobs are recorded in the catalog's `nexus_async_jobs` table, where their
+//! status outlives nexus. Their rows don't: jobs still running when nexus
+//! stops are failed when it starts again, and rows not fetched are lost.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use catalog::Catalog;
+use futures::{future::BoxFuture, StreamExt};
+use peer_cursor::{QueryExecutor, QueryOutput, Records, Schema};
+use pgwire::{
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
+};
+use pt::peerdb_peers::PostgresConfig;
+
+use crate::{
+    memory::{record_size, MemoryAccount, Reservation},
+    peer_test::error_message,
+};
+
+enum JobState {
+    Running,
+    Succeeded(Records, Reservation),
+    Failed(String),
+}
+
+struct Job {
+    peer: Option<String>,
+    state: JobState,
+}
+
+pub struct AsyncJobs {
+    // jobs connect to the catalog anew, to run catalog queries and to
+    // record how they ended.
+    catalog_config: PostgresConfig,
+    // jobs of this nexus, until their rows are fetched. Failed jobs are
+    // only kept if recording their failure in the catalog failed.
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl AsyncJobs {
+    pub fn new(catalog_config: PostgresConfig) -> Arc<Self> {
+        Arc::new(Self {
+            catalog_config,
+            jobs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn status_schema() -> Schema {
+        let field = |name: &str, datatype: Type| {
+            FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+        };
+        Arc::new(vec![
+            field("job_id", Type::TEXT),
+            field("status", Type::TEXT),
+            field("peer", Type::TEXT),
+            field("rows", Type::INT8),
+            field("error", Type::TEXT),
+        ])
+    }
+
+    /// Records the job in the catalog and starts it, returning its id. `run`
+    /// executes the query on the peer's executor, or on the job's catalog
+    /// connection for queries routed to the catalog.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn submit<F>(
+        self: &Arc<Self>,
+        catalog: &Catalog,
+        user: &str,
+        query: &str,
+        peer: Option<String>,
+        executor: Option<Arc<dyn QueryExecutor>>,
+        memory: &Arc<MemoryAccount>,
+        run: F,
+    ) -> PgWireResult<String>
+    where
+        F: FnOnce(Arc<dyn QueryExecutor>) -> BoxFuture<'static, PgWireResult<QueryOutput>>
+            + Send
+            + 'static,
+    {
+        let id = uuid::Uuid::new_v4().to_string();
+        catalog
+            .insert_async_job(&id, query, peer.as_deref(), user)
+            .await
+            .map_err(|err| {
+                PgWireError::ApiError(format!("unable to record async job: {:?}", err).into())
+            })?;
+        self.jobs.lock().unwrap().insert(
+            id.clone(),
+            Job {
+                peer,
+                state: JobState::Running,
+            },
+        );
+
+        let jobs = Arc::clone(self);
+        let reservation = memory.reservation();
+        let job_id = id.clone();
+        tokio::spawn(async move {
+            let (job_catalog, outcome) = match Catalog::new(jobs.catalog_config.clone()).await {
+                Ok(job_catalog) => {
+                    let job_catalog = Arc::new(job_catalog);
+                    // the executor is kept until all rows are read
+                    let executor: Arc<dyn QueryExecutor> = match executor {
+                        Some(executor) => executor,
+                        None => job_catalog.clone(),
+                    };
+                    let outcome = match run(executor.clone()).await {
+                        Ok(output) => collect(output, reservation).await,
+                        Err(err) => Err(err),
+                    };
+                    drop(executor);
+                    (Some(job_catalog), outcome)
+                }
+                Err(err) => (
+                    None,
+                    Err(PgWireError::ApiError(
+                        format!("unable to connect to the catalog: {:?}", err).into(),
+                    )),
+                ),
+            };
+            jobs.finish(&job_id, job_catalog.as_deref(), outcome).await;
+        });
+        Ok(id)
+    }
+
+    async fn finish(
+        &self,
+        id: &str,
+        catalog: Option<&Catalog>,
+        outcome: PgWireResult<(Records, Reservation)>,
+    ) {
+        let (status, rows, state) = match outcome {
+            Ok((records, reservation)) => (
+                "succeeded",
+                records.records.len() as i64,
+                JobState::Succeeded(records, reservation),
+            ),
+            Err(err) => ("failed", 0, JobState::Failed(error_message(err))),
+        };
+        let error = match &state {
+            JobState::Failed(error) => Some(error.as_str()),
+            _ => None,
+        };
+        tracing::info!("async job {} {} with {} rows", id, status, rows);
+        let recorded = match catalog {
+            Some(catalog) => match catalog.finish_async_job(id, status, rows, error).await {
+                Ok(()) => true,
+                Err(err) => {
+                    tracing::error!("unable to record the end of async job {}: {:?}", id, err);
+                    false
+                }
+            },
+            None => false,
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        if recorded && matches!(state, JobState::Failed(_)) {
+            jobs.remove(id);
+        } else if let Some(job) = jobs.get_mut(id) {
+            job.state = state;
+        }
+    }
+
+    /// The row of `peerdb.async_status`, from the catalog once the job left
+    /// this nexus.
+    pub async fn status(&self, catalog: &Catalog, id: &str) -> PgWireResult<Vec<value::Value>> {
+        let text = |text: Option<String>| text.map_or(value::Value::Null, value::Value::Text);
+        let known = self.jobs.lock().unwrap().get(id).map(|job| {
+            let (status, rows, error) = match &job.state {
+                JobState::Running => ("running", 0, None),
+                JobState::Succeeded(records, _) => {
+                    ("succeeded", records.records.len() as i64, None)
+                }
+                JobState::Failed(error) => ("failed", 0, Some(error.clone())),
+            };
+            (status.to_owned(), job.peer.clone(), rows, error)
+        });
+        let (status, peer, rows, error) = match known {
+            Some(known) => known,
+            None => {
+                let job = get_job(catalog, id).await?;
+                (job.status, job.peer, job.rows, job.error)
+            }
+        };
+        Ok(vec![
+            value::Value::Text(id.to_owned()),
+            value::Value::Text(status),
+            text(peer),
+            value::Value::BigInt(rows),
+            text(error),
+        ])
+    }
+
+    /// The schema of the rows of a job that succeeded.
+    pub async fn result_schema(&self, catalog: &Catalog, id: &str) -> PgWireResult<Schema> {
+        let schema = self
+            .jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .and_then(|job| match &job.state {
+                JobState::Succeeded(records, _) => Some(records.schema.clone()),
+                _ => None,
+            });
+        match schema {
+            Some(schema) => Ok(schema),
+            None => Err(self.no_result(catalog, id).await),
+        }
+    }
+
+    /// The rows of a job that succeeded, which are then forgotten.
+    pub async fn take_result(&self, catalog: &Catalog, id: &str) -> PgWireResult<Records> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            if let Some(JobState::Succeeded(..)) = jobs.get(id).map(|job| &job.state) {
+                if let Some(JobState::Succeeded(records, _)) = jobs.remove(id).map(|job| job.state)
+                {
+                    return Ok(records);
+                }
+            }
+        }
+        Err(self.no_result(catalog, id).await)
+    }
+
+    // why a job has no rows to return
+    async fn no_result(&self, catalog: &Catalog, id: &str) -> PgWireError {
+        let state = self
+            .jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|job| match &job.state {
+                JobState::Failed(error) => Some(error.clone()),
+                _ => None,
+            });
+        let (code, message) = match state {
+            Some(None) => ("55000", format!("async job \"{}\" is still running", id)),
+            Some(Some(error)) => failed(id, &error),
+            None => match get_job(catalog, id).await {
+                Err(err) => return err,
+                Ok(job) if job.status == "failed" => failed(id, &job.error.unwrap_or_default()),
+                Ok(_) => (
+                    "55000",
+                    format!(
+                        "the rows of async job \"{}\" were already fetched or lost when nexus restarted",
+                        id
+                    ),
+                ),
+            },
+        };
+        job_error(code, message)
+    }
+}
+
+async fn get_job(catalog: &Catalog, id: &str) -> PgWireResult<catalog::AsyncJob> {
+    catalog
+        .get_async_job(id)
+        .await
+        .map_err(|err| {
+            PgWireError::ApiError(format!("unable to read async job: {:?}", err).into())
+        })?
+        .ok_or_else(|| job_error("42704", format!("async job \"{}\" does not exist", id)))
+}
+
+// buffers the rows of the query, charging them to the reservation
+async fn collect(
+    output: QueryOutput,
+    mut reservation: Reservation,
+) -> PgWireResult<(Records, Reservation)> {
+    let (schema, mut rows) = match output {
+        QueryOutput::Records(records) => (
+            records.schema,
+            futures::stream::iter(records.records.into_iter().map(Ok)).boxed(),
+        ),
+        QueryOutput::Stream(rows) => (rows.schema(), rows.boxed()),
+        _ => {
+            return Err(job_error(
+                "0A000",
+                "peerdb.submit_async only runs queries returning rows".to_owned(),
+            ))
+        }
+    };
+    let mut records = Vec::new();
+    while let Some(record) = rows.next().await {
+        let record = record?;
+        reservation.grow(record_size(&record))?;
+        records.push(record);
+    }
+    Ok((Records { records, schema }, reservation))
+}
+
+fn failed(id: &str, error: &str) -> (&'static str, String) {
+    ("XX000", format!("async job \"{}\" failed: {}", id, error))
+}
+
+fn job_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
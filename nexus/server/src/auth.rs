@@ -0,0 +1,455 @@
+use std::{
+    fmt::Debug,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use catalog::{Catalog, UserCredentials};
+use futures::{Sink, SinkExt};
+use ipnet::IpNet;
+use pgwire::{
+    api::{
+        auth::{
+            finish_authentication,
+            md5pass::Md5PasswordAuthStartupHandler,
+            save_startup_parameters_to_metadata,
+            scram::{gen_salted_password, SASLScramAuthStartupHandler},
+            AuthSource, LoginInfo, Password, StartupHandler,
+        },
+        ClientInfo, PgWireConnectionState,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
+    messages::{PgWireBackendMessage, PgWireFrontendMessage},
+};
+use rand::Rng;
+
+use crate::{runtime_config::RuntimeConfig, server_parameters::NexusServerParameterProvider};
+
+// PBKDF2 iterations of the SCRAM exchange, pgwire's default
+const SCRAM_ITERATIONS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    Trust,
+    Md5,
+    Scram,
+    /// The connection is refused.
+    Reject,
+}
+
+impl FromStr for AuthMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trust" => Ok(AuthMethod::Trust),
+            "md5" => Ok(AuthMethod::Md5),
+            "scram" | "scram-sha-256" => Ok(AuthMethod::Scram),
+            "reject" => Ok(AuthMethod::Reject),
+            _ => Err(anyhow::anyhow!("unknown authentication method: {}", s)),
+        }
+    }
+}
+
+/// The connections an hba rule applies to, by whether they use TLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionType {
+    /// `host`, any connection.
+    Host,
+    /// `hostssl`, TLS connections.
+    HostSsl,
+    /// `hostnossl`, plaintext connections.
+    HostNoSsl,
+}
+
+impl ConnectionType {
+    fn matches(self, secure: bool) -> bool {
+        match self {
+            ConnectionType::Host => true,
+            ConnectionType::HostSsl => secure,
+            ConnectionType::HostNoSsl => !secure,
+        }
+    }
+}
+
+/// An authentication rule of the form `METHOD [USER [ADDRESS]]`, e.g.
+/// `trust * 127.0.0.1/32` or `md5 svc_*`. USER is a pattern where `*`
+/// matches any sequence of characters and ADDRESS is a CIDR range, both
+/// default to matching everything. Rules read from an hba file can also
+/// restrict the database and whether the connection uses TLS.
+#[derive(Debug, Clone)]
+pub struct AuthRule {
+    method: AuthMethod,
+    connection: ConnectionType,
+    database: String,
+    user: String,
+    address: Option<IpNet>,
+}
+
+impl FromStr for AuthRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let method = fields
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("empty authentication rule"))?
+            .parse()?;
+        let user = fields.next().unwrap_or("*").to_owned();
+        let address = parse_address(fields.next().unwrap_or("*"))
+            .ok_or_else(|| anyhow::anyhow!("invalid address in rule: {}", s))?;
+        if fields.next().is_some() {
+            anyhow::bail!("too many fields in authentication rule: {}", s);
+        }
+
+        Ok(AuthRule {
+            method,
+            connection: ConnectionType::Host,
+            database: "*".to_owned(),
+            user,
+            address,
+        })
+    }
+}
+
+impl AuthRule {
+    /// Parses a line of an hba file, `host DATABASE USER ADDRESS METHOD` like
+    /// in postgres' pg_hba.conf, where `all` matches any database or user.
+    /// `hostssl` and `hostnossl` lines only match TLS and plaintext
+    /// connections. Returns None for blank and comment lines.
+    fn from_hba_line(line: &str) -> anyhow::Result<Option<Self>> {
+        let line = line.split_once('#').map_or(line, |(rule, _)| rule).trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let [kind, database, user, address, method] = fields[..] else {
+            anyhow::bail!("expected host DATABASE USER ADDRESS METHOD, got: {}", line);
+        };
+        let connection = match kind {
+            "host" => ConnectionType::Host,
+            "hostssl" => ConnectionType::HostSsl,
+            "hostnossl" => ConnectionType::HostNoSsl,
+            _ => anyhow::bail!("unsupported connection type {}: {}", kind, line),
+        };
+        let any = |pattern: &str| match pattern {
+            "all" => "*".to_owned(),
+            pattern => pattern.to_owned(),
+        };
+        Ok(Some(AuthRule {
+            method: method.parse()?,
+            connection,
+            database: any(database),
+            user: any(user),
+            address: parse_address(address)
+                .ok_or_else(|| anyhow::anyhow!("invalid address in rule: {}", line))?,
+        }))
+    }
+
+    fn matches(&self, database: &str, user: &str, addr: IpAddr, secure: bool) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            v4 => v4,
+        };
+        self.connection.matches(secure)
+            && matches_pattern(&self.database, database)
+            && matches_pattern(&self.user, user)
+            && self.address.map_or(true, |net| net.contains(&addr))
+    }
+}
+
+// a CIDR range or a single address, None inside for any address
+fn parse_address(address: &str) -> Option<Option<IpNet>> {
+    match address {
+        "*" | "all" => Some(None),
+        address => address
+            .parse::<IpNet>()
+            .or_else(|_| address.parse::<IpAddr>().map(IpNet::from))
+            .ok()
+            .map(Some),
+    }
+}
+
+// `*` matches any sequence of characters, everything else matches literally.
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=value.len())
+                .filter(|i| value.is_char_boundary(*i))
+                .any(|i| matches_pattern(rest, &value[i..]))
+        }
+    }
+}
+
+pub struct AuthConfig {
+    rules: Vec<AuthRule>,
+    // rules read from the hba file, after `rules`
+    hba_file: Option<PathBuf>,
+    hba_rules: RwLock<Vec<AuthRule>>,
+    // the password of users without one in the catalog, None refuses them
+    fallback_password: Option<String>,
+}
+
+impl AuthConfig {
+    pub fn new(
+        rules: Vec<AuthRule>,
+        hba_file: Option<PathBuf>,
+        fallback_password: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let config = Self {
+            rules,
+            hba_file,
+            hba_rules: RwLock::new(Vec::new()),
+            fallback_password,
+        };
+        config.reload()?;
+        Ok(config)
+    }
+
+    /// Reads the hba file again, the rules in effect are kept when it can't
+    /// be read or has an invalid line.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.hba_file else {
+            return Ok(());
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("unable to read {}: {}", path.display(), err))?;
+        let mut rules = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let rule = AuthRule::from_hba_line(line).map_err(|err| {
+                anyhow::anyhow!("{} line {}: {}", path.display(), number + 1, err)
+            })?;
+            rules.extend(rule);
+        }
+        tracing::info!("loaded {} rules from {}", rules.len(), path.display());
+        *self.hba_rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    /// The first rule matching the connection decides the method. Connections
+    /// matching no rule use SCRAM, or are rejected when there is an hba file.
+    fn method_for(&self, database: &str, user: &str, addr: IpAddr, secure: bool) -> AuthMethod {
+        let hba_rules = self.hba_rules.read().unwrap();
+        let unmatched = match self.hba_file {
+            Some(_) => AuthMethod::Reject,
+            None => AuthMethod::Scram,
+        };
+        self.rules
+            .iter()
+            .chain(hba_rules.iter())
+            .find(|rule| rule.matches(database, user, addr, secure))
+            .map_or(unmatched, |rule| rule.method)
+    }
+}
+
+/// The credentials of a user with the password, for the catalog.
+pub fn user_credentials(user: &str, password: &str) -> UserCredentials {
+    let scram_salt = rand::thread_rng().gen::<[u8; 16]>().to_vec();
+    UserCredentials {
+        password_md5: format!("md5{:x}", md5::compute(format!("{}{}", password, user))),
+        scram_salted_password: gen_salted_password(password, &scram_salt, SCRAM_ITERATIONS),
+        scram_salt,
+    }
+}
+
+// CatalogAuthSource checks logins against the passwords set with
+// `peerdb.set_password`, kept in the catalog. Users without one there log in
+// with `--peerdb-password`, unless `--require-catalog-users` is set.
+pub struct CatalogAuthSource {
+    catalog: Arc<Catalog>,
+    method: AuthMethod,
+    fallback_password: Option<String>,
+}
+
+#[async_trait]
+impl AuthSource for CatalogAuthSource {
+    async fn get_password(&self, login_info: &LoginInfo) -> PgWireResult<Password> {
+        let user = login_info.user().map(|u| &u[..]).unwrap_or("");
+        let credentials = self
+            .catalog
+            .get_user_credentials(user)
+            .await
+            .map_err(|err| {
+                PgWireError::ApiError(format!("unable to read user from catalog: {}", err).into())
+            })?;
+        let credentials = match (credentials, &self.fallback_password) {
+            (Some(credentials), _) => credentials,
+            (None, Some(password)) => user_credentials(user, password),
+            (None, None) => {
+                // like postgres, an unknown user only learns that the password
+                // is wrong, never whether the user exists
+                tracing::info!("user {} has no password in the catalog", user);
+                let mut rng = rand::thread_rng();
+                UserCredentials {
+                    password_md5: format!("md5{:x}", md5::compute(rng.gen::<[u8; 16]>())),
+                    scram_salt: rng.gen::<[u8; 16]>().to_vec(),
+                    scram_salted_password: rng.gen::<[u8; 32]>().to_vec(),
+                }
+            }
+        };
+
+        match self.method {
+            AuthMethod::Md5 => {
+                // the client answers with md5 of the stored hash and the salt
+                let salt = rand::thread_rng().gen::<[u8; 4]>();
+                let stored = credentials
+                    .password_md5
+                    .strip_prefix("md5")
+                    .unwrap_or(&credentials.password_md5);
+                let mut salted = stored.as_bytes().to_vec();
+                salted.extend_from_slice(&salt);
+                let hash_password = format!("md5{:x}", md5::compute(salted));
+                Ok(Password::new(
+                    Some(salt.to_vec()),
+                    hash_password.into_bytes(),
+                ))
+            }
+            _ => Ok(Password::new(
+                Some(credentials.scram_salt),
+                credentials.scram_salted_password,
+            )),
+        }
+    }
+}
+
+// NexusStartupHandler picks the authentication method for a connection from
+// the configured rules when the startup message arrives, then hands the rest
+// of the authentication exchange to the handler for that method. Once the
+// client is authenticated it gets the connect banner, if one is set.
+pub struct NexusStartupHandler {
+    config: Arc<AuthConfig>,
+    parameters: Arc<NexusServerParameterProvider>,
+    runtime_config: Arc<RuntimeConfig>,
+    md5: Md5PasswordAuthStartupHandler<CatalogAuthSource, NexusServerParameterProvider>,
+    scram: SASLScramAuthStartupHandler<CatalogAuthSource, NexusServerParameterProvider>,
+    method: std::sync::Mutex<Option<AuthMethod>>,
+    // the process id and secret key of the connection's CancelRequests
+    backend_key: (i32, i32),
+}
+
+impl NexusStartupHandler {
+    pub fn new(
+        config: Arc<AuthConfig>,
+        parameters: Arc<NexusServerParameterProvider>,
+        runtime_config: Arc<RuntimeConfig>,
+        catalog: Arc<Catalog>,
+        backend_key: (i32, i32),
+    ) -> Self {
+        let source = |method| {
+            Arc::new(CatalogAuthSource {
+                catalog: catalog.clone(),
+                method,
+                fallback_password: config.fallback_password.clone(),
+            })
+        };
+        let md5 = Md5PasswordAuthStartupHandler::new(source(AuthMethod::Md5), parameters.clone());
+        let scram = SASLScramAuthStartupHandler::new(source(AuthMethod::Scram), parameters.clone());
+        Self {
+            config,
+            parameters,
+            runtime_config,
+            md5,
+            scram,
+            method: std::sync::Mutex::new(None),
+            backend_key,
+        }
+    }
+}
+
+#[async_trait]
+impl StartupHandler for NexusStartupHandler {
+    async fn on_startup<C>(
+        &self,
+        client: &mut C,
+        message: PgWireFrontendMessage,
+    ) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let method = if let PgWireFrontendMessage::Startup(ref startup) = message {
+            // sent in BackendKeyData once the client is authenticated
+            client.set_pid_and_secret_key(self.backend_key.0, self.backend_key.1);
+            let user = startup
+                .parameters
+                .get("user")
+                .map(String::as_str)
+                .unwrap_or_default();
+            // postgres connects to the database named like the user by default
+            let database = startup
+                .parameters
+                .get("database")
+                .map_or(user, String::as_str);
+            let addr = client.socket_addr().ip();
+            let secure = client.is_secure();
+            let method = self.config.method_for(database, user, addr, secure);
+            tracing::info!(
+                "authenticating user {} from {} with {:?}",
+                user,
+                client.socket_addr(),
+                method
+            );
+            if method == AuthMethod::Reject {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "FATAL".to_owned(),
+                    "28000".to_owned(),
+                    format!(
+                        "no hba entry allows host \"{}\", user \"{}\", database \"{}\", {}",
+                        addr,
+                        user,
+                        database,
+                        if secure {
+                            "SSL encryption"
+                        } else {
+                            "no encryption"
+                        }
+                    ),
+                ))));
+            }
+            *self.method.lock().unwrap() = Some(method);
+            method
+        } else {
+            let method = *self.method.lock().unwrap();
+            method.ok_or_else(|| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "FATAL".to_owned(),
+                    "08P01".to_owned(),
+                    "authentication message received before startup".to_owned(),
+                )))
+            })?
+        };
+
+        match method {
+            AuthMethod::Trust => {
+                if let PgWireFrontendMessage::Startup(ref startup) = message {
+                    save_startup_parameters_to_metadata(client, startup);
+                    finish_authentication(client, self.parameters.as_ref()).await?;
+                }
+            }
+            AuthMethod::Md5 => self.md5.on_startup(client, message).await?,
+            AuthMethod::Scram => self.scram.on_startup(client, message).await?,
+            // refused at startup above
+            AuthMethod::Reject => (),
+        }
+
+        // the startup sequence ends with the first ReadyForQuery, clients
+        // take a notice after it like one sent during a query.
+        if matches!(client.state(), PgWireConnectionState::ReadyForQuery) {
+            if let Some(banner) = self.runtime_config.get().connect_banner {
+                let notice = ErrorInfo::new("NOTICE".to_owned(), "00000".to_owned(), banner);
+                client
+                    .send(PgWireBackendMessage::NoticeResponse(notice.into()))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
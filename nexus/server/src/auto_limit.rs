@@ -0,0 +1,81 @@
+//! `SET peerdb.auto_limit = 1000` adds a `LIMIT 1000` to the SELECTs a
+//! session sends to peers without a limit of their own, so exploring a peer
+//! doesn't accidentally read a whole table.
+
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    visit_expressions, Expr, GroupByExpr, Query, Select, SetExpr, Statement, Value,
+};
+
+// functions returning one row for all the rows of a query without GROUP BY
+const AGGREGATE_FUNCTIONS: &[&str] = &[
+    "count",
+    "sum",
+    "avg",
+    "min",
+    "max",
+    "array_agg",
+    "string_agg",
+    "json_agg",
+    "jsonb_agg",
+    "bool_and",
+    "bool_or",
+    "every",
+    "stddev",
+    "variance",
+    "approx_count_distinct",
+    "any_value",
+];
+
+/// Adds `LIMIT limit` to a query without LIMIT or FETCH, returning whether
+/// it did. Queries bound to return a single row, like aggregates without
+/// GROUP BY or SELECTs without FROM, are left as they are.
+pub fn add_limit(stmt: &mut Statement, limit: u64) -> bool {
+    let Statement::Query(query) = stmt else {
+        return false;
+    };
+    if query.limit.is_some() || query.fetch.is_some() || returns_one_row(query) {
+        return false;
+    }
+    query.limit = Some(Expr::Value(Value::Number(limit.to_string(), false)));
+    true
+}
+
+fn returns_one_row(query: &Query) -> bool {
+    match query.body.as_ref() {
+        SetExpr::Select(select) => select.from.is_empty() || is_plain_aggregate(select),
+        SetExpr::Query(query) => query.limit.is_some() || returns_one_row(query),
+        SetExpr::Values(values) => values.rows.len() <= 1,
+        _ => false,
+    }
+}
+
+// aggregates over all the rows of the query, which return one row
+fn is_plain_aggregate(select: &Select) -> bool {
+    let grouped = match &select.group_by {
+        GroupByExpr::All => true,
+        GroupByExpr::Expressions(exprs) => !exprs.is_empty(),
+    };
+    if grouped {
+        return false;
+    }
+
+    let mut aggregates = false;
+    visit_expressions(&select.projection, |expr| {
+        if let Expr::Function(function) = expr {
+            let name = function
+                .name
+                .0
+                .last()
+                .map(|ident| ident.value.to_lowercase())
+                .unwrap_or_default();
+            if function.over.is_none() && AGGREGATE_FUNCTIONS.contains(&name.as_str()) {
+                aggregates = true;
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    aggregates
+}
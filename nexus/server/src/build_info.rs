@@ -0,0 +1,10 @@
+/// Version of nexus, with the commit and date of the build embedded by
+/// build.rs, returned by `peerdb.version()` and logged at startup.
+pub fn version() -> String {
+    format!(
+        "PeerDB Nexus {} (commit {}, built {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("PEERDB_GIT_COMMIT"),
+        env!("PEERDB_BUILD_DATE")
+    )
+}
@@ -0,0 +1,120 @@
+//! CancelRequests, sent by clients on a connection of their own to stop the
+//! query running on another, e.g. when psql's user presses Ctrl-C. Each
+//! connection is handed a backend key at startup and registers it here, a
+//! CancelRequest carrying the key cancels the query running on the peers of
+//! the connection and fails it with 57014.
+//!
+//! Like postgres, a CancelRequest with an unknown key or arriving while no
+//! query runs does nothing, and the client isn't told either way.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use peer_cursor::QueryExecutor;
+use rand::Rng;
+use tokio_util::sync::CancellationToken;
+
+use crate::executor_registry::Executors;
+
+/// The cancellation of a connection's queries.
+pub struct QueryCancel {
+    pid: i32,
+    secret: i32,
+    // cancelled by a CancelRequest, replaced by a new one right after so the
+    // next query isn't canceled as well
+    token: Mutex<CancellationToken>,
+    executors: Arc<Executors>,
+    catalog: Arc<dyn QueryExecutor>,
+}
+
+impl QueryCancel {
+    /// The process id and secret key sent to the client in BackendKeyData.
+    pub fn backend_key(&self) -> (i32, i32) {
+        (self.pid, self.secret)
+    }
+
+    /// The token of the query about to run.
+    pub fn token(&self) -> CancellationToken {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// Asks the peers of the connection to cancel the query they run, when
+    /// nexus stops waiting for it.
+    pub async fn cancel_peers(&self) {
+        let mut executors = self
+            .executors
+            .iter()
+            .map(|executor| (executor.key().clone(), Arc::clone(executor.value())))
+            .collect::<Vec<_>>();
+        executors.push(("catalog".to_owned(), self.catalog.clone()));
+        for (peer, executor) in executors {
+            if let Err(err) = executor.cancel().await {
+                tracing::warn!("unable to cancel the query on peer {}: {:?}", peer, err);
+            }
+        }
+    }
+
+    // the peers are asked to cancel first, so the query fails with their
+    // error when they can cancel it
+    async fn cancel(&self) {
+        self.cancel_peers().await;
+        let token = std::mem::replace(&mut *self.token.lock().unwrap(), CancellationToken::new());
+        token.cancel();
+    }
+}
+
+#[derive(Default)]
+pub struct CancelRegistry {
+    // the connections by process id, with their secret key
+    connections: Mutex<HashMap<i32, (i32, Weak<QueryCancel>)>>,
+}
+
+impl CancelRegistry {
+    /// The cancellation of a new connection with a key of its own, dropped
+    /// with the connection.
+    pub fn register(
+        &self,
+        executors: Arc<Executors>,
+        catalog: Arc<dyn QueryExecutor>,
+    ) -> Arc<QueryCancel> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|_, (_, connection)| connection.strong_count() > 0);
+        let mut rng = rand::thread_rng();
+        let pid = loop {
+            let pid = rng.gen_range(1..i32::MAX);
+            if !connections.contains_key(&pid) {
+                break pid;
+            }
+        };
+        let cancel = Arc::new(QueryCancel {
+            pid,
+            secret: rng.gen(),
+            token: Mutex::new(CancellationToken::new()),
+            executors,
+            catalog,
+        });
+        connections.insert(pid, (cancel.secret, Arc::downgrade(&cancel)));
+        cancel
+    }
+
+    /// Cancels the query running on the connection of the key, returns
+    /// whether the key matched a connection.
+    pub async fn cancel(&self, pid: i32, secret: i32) -> bool {
+        let cancel = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(&pid)
+            .filter(|(key, _)| *key == secret)
+            .and_then(|(_, connection)| connection.upgrade());
+        match cancel {
+            Some(cancel) => {
+                cancel.cancel().await;
+                true
+            }
+            None => false,
+        }
+    }
+}
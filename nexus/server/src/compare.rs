@@ -0,0 +1,298 @@
+//! `COMPARE (query) WITH (query) [USING (column, ...)]`: runs both queries,
+//! possibly on different peers, and reports how many rows match, differ or
+//! are missing from either side, with an example row for each.
+//!
+//! Rows are compared by the text of their values, so an int4 column matches
+//! an int8 one with the same numbers. Without `USING` a row matches a row
+//! with the same values, each row matching at most one, and there are no
+//! differing rows. With `USING` rows are paired by the key columns and
+//! differ when the other columns don't match.
+//!
+//! Only hashes of the rows are kept in memory, counting towards the
+//! connection's memory limit. The rows themselves go to a temporary file,
+//! from which the example rows are read back at the end.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::SeekFrom,
+    sync::Arc,
+};
+
+use futures::{stream, stream::BoxStream, StreamExt};
+use peer_cursor::{QueryOutput, Record, Records, Schema};
+use pgwire::{
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
+};
+use sqlparser::ast::Ident;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
+};
+
+use crate::memory::{MemoryAccount, Reservation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    First,
+    Second,
+}
+
+impl Side {
+    fn name(self) -> &'static str {
+        match self {
+            Side::First => "first",
+            Side::Second => "second",
+        }
+    }
+}
+
+pub fn schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("status", Type::TEXT),
+        field("rows", Type::INT8),
+        field("first_row", Type::TEXT),
+        field("second_row", Type::TEXT),
+    ])
+}
+
+// rows of both sides under one key, as their hash and their offset in the
+// spill file. Rows of the second query matching one of the first are
+// dropped right away.
+#[derive(Default)]
+struct Slot {
+    first: Vec<(u128, u64)>,
+    second: Vec<(u128, u64)>,
+}
+
+pub struct Comparison {
+    key: Vec<Ident>,
+    columns: Option<usize>,
+    slots: HashMap<u128, Slot>,
+    matching: i64,
+    spill: BufWriter<File>,
+    spilled: u64,
+    reservation: Reservation,
+}
+
+impl Comparison {
+    pub fn new(key: Vec<Ident>, memory: &Arc<MemoryAccount>) -> PgWireResult<Self> {
+        let file = tempfile::tempfile().map_err(spill_error)?;
+        Ok(Self {
+            key,
+            columns: None,
+            slots: HashMap::new(),
+            matching: 0,
+            spill: BufWriter::new(File::from_std(file)),
+            spilled: 0,
+            reservation: memory.reservation(),
+        })
+    }
+
+    /// Reads all rows of one of the queries, the first one before the second.
+    pub async fn add(&mut self, side: Side, output: QueryOutput) -> PgWireResult<()> {
+        let (schema, mut rows) = output_rows(output)?;
+        match self.columns {
+            Some(columns) if columns != schema.len() => {
+                return Err(compare_error(
+                    "42601",
+                    "each COMPARE query must have the same number of columns".to_owned(),
+                ))
+            }
+            _ => self.columns = Some(schema.len()),
+        }
+        let key_columns = self
+            .key
+            .iter()
+            .map(|ident| key_column(&schema, ident, side))
+            .collect::<PgWireResult<Vec<_>>>()?;
+
+        while let Some(record) = rows.next().await {
+            let values = record?
+                .values
+                .iter()
+                .map(|value| value.to_serde_json_value())
+                .collect::<Vec<_>>();
+            let texts = values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>();
+            let row_hash = hash(texts.iter());
+            let key_hash = if key_columns.is_empty() {
+                row_hash
+            } else {
+                hash(key_columns.iter().map(|column| &texts[*column]))
+            };
+
+            if !self.slots.contains_key(&key_hash) {
+                self.reservation.grow(std::mem::size_of::<(u128, Slot)>())?;
+            }
+            let slot = self.slots.entry(key_hash).or_default();
+            if side == Side::Second {
+                if let Some(pos) = slot.first.iter().position(|(hash, _)| *hash == row_hash) {
+                    slot.first.swap_remove(pos);
+                    self.matching += 1;
+                    if slot.first.is_empty() && slot.second.is_empty() {
+                        self.slots.remove(&key_hash);
+                    }
+                    continue;
+                }
+            }
+
+            self.reservation.grow(std::mem::size_of::<(u128, u64)>())?;
+            let offset = self.spilled;
+            let row = serde_json::Value::Array(values).to_string();
+            self.spill
+                .write_u32(row.len() as u32)
+                .await
+                .map_err(spill_error)?;
+            self.spill
+                .write_all(row.as_bytes())
+                .await
+                .map_err(spill_error)?;
+            self.spilled += 4 + row.len() as u64;
+            match side {
+                Side::First => slot.first.push((row_hash, offset)),
+                Side::Second => slot.second.push((row_hash, offset)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The summary, one row per status with its count and an example.
+    pub async fn finish(mut self) -> PgWireResult<Records> {
+        // rows of a key left on both sides differ, the rest is missing from
+        // the other side.
+        let (mut differing, mut missing_from_second, mut missing_from_first) = (0, 0, 0);
+        let mut samples: [(Option<u64>, Option<u64>); 3] = Default::default();
+        for slot in self.slots.values() {
+            let paired = slot.first.len().min(slot.second.len());
+            differing += paired;
+            missing_from_second += slot.first.len() - paired;
+            missing_from_first += slot.second.len() - paired;
+            if paired > 0 && samples[0].0.is_none() {
+                samples[0] = (Some(slot.first[0].1), Some(slot.second[0].1));
+            }
+            if slot.first.len() > paired && samples[1].0.is_none() {
+                samples[1].0 = Some(slot.first[paired].1);
+            }
+            if slot.second.len() > paired && samples[2].1.is_none() {
+                samples[2].1 = Some(slot.second[paired].1);
+            }
+        }
+
+        self.spill.flush().await.map_err(spill_error)?;
+        let mut file = self.spill.into_inner();
+
+        let schema = schema();
+        let mut records = vec![Record {
+            values: vec![
+                value::Value::Text("matching".to_owned()),
+                value::Value::BigInt(self.matching),
+                value::Value::Null,
+                value::Value::Null,
+            ],
+            schema: schema.clone(),
+        }];
+        let statuses = [
+            ("differing", differing),
+            ("missing_from_second", missing_from_second),
+            ("missing_from_first", missing_from_first),
+        ];
+        for ((status, rows), (first, second)) in statuses.into_iter().zip(samples) {
+            records.push(Record {
+                values: vec![
+                    value::Value::Text(status.to_owned()),
+                    value::Value::BigInt(rows as i64),
+                    read_row(&mut file, first).await?,
+                    read_row(&mut file, second).await?,
+                ],
+                schema: schema.clone(),
+            });
+        }
+        Ok(Records { records, schema })
+    }
+}
+
+async fn read_row(file: &mut File, offset: Option<u64>) -> PgWireResult<value::Value> {
+    let Some(offset) = offset else {
+        return Ok(value::Value::Null);
+    };
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(spill_error)?;
+    let len = file.read_u32().await.map_err(spill_error)?;
+    let mut row = vec![0u8; len as usize];
+    file.read_exact(&mut row).await.map_err(spill_error)?;
+    Ok(value::Value::Text(
+        String::from_utf8_lossy(&row).into_owned(),
+    ))
+}
+
+fn output_rows(
+    output: QueryOutput,
+) -> PgWireResult<(Schema, BoxStream<'static, PgWireResult<Record>>)> {
+    match output {
+        QueryOutput::Stream(rows) => Ok((rows.schema(), rows.boxed())),
+        QueryOutput::Records(records) => Ok((
+            records.schema,
+            stream::iter(records.records.into_iter().map(Ok)).boxed(),
+        )),
+        _ => Err(compare_error(
+            "42601",
+            "COMPARE takes two queries returning rows".to_owned(),
+        )),
+    }
+}
+
+// unquoted names match the columns case insensitively, like in postgres
+fn key_column(schema: &Schema, ident: &Ident, side: Side) -> PgWireResult<usize> {
+    schema
+        .iter()
+        .position(|field| match ident.quote_style {
+            Some(_) => field.name() == &ident.value,
+            None => field.name().eq_ignore_ascii_case(&ident.value),
+        })
+        .ok_or_else(|| {
+            compare_error(
+                "42703",
+                format!(
+                    "column \"{}\" is not returned by the {} COMPARE query",
+                    ident.value,
+                    side.name()
+                ),
+            )
+        })
+}
+
+// 128 bits of two hashers seeded differently, so that distinct rows
+// colliding is not a concern even for billions of rows.
+fn hash<'a>(texts: impl Iterator<Item = &'a String>) -> u128 {
+    let mut high = DefaultHasher::new();
+    let mut low = DefaultHasher::new();
+    1u8.hash(&mut low);
+    for text in texts {
+        text.hash(&mut high);
+        text.hash(&mut low);
+    }
+    ((high.finish() as u128) << 64) | low.finish() as u128
+}
+
+fn compare_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
+
+fn spill_error(err: std::io::Error) -> PgWireError {
+    PgWireError::ApiError(format!("unable to spill COMPARE rows to disk: {}", err).into())
+}
@@ -0,0 +1,469 @@
+//! `COPY ... TO STDOUT`: the rows of the copied table or query are sent as
+//! CopyData in the text, csv or binary format of postgres, with its options
+//! for the header, delimiter, NULL string, quote and escape characters.
+//!
+//! Only STDIN and STDOUT are supported as the other end of a COPY. Files and
+//! programs would be on the machine of nexus, or of the peer the statement
+//! ends up on, neither of which clients should reach through nexus.
+//!
+//! Postgres peers and the catalog send the data of a binary COPY themselves,
+//! nexus passes it on without decoding the rows, which is much cheaper than
+//! encoding them again. The rows of other peers are encoded by nexus.
+//!
+//! `FORMAT arrow` is nexus' own: the data is an Apache Arrow IPC stream,
+//! see `arrow_stream`. With `SET peerdb.result_format = 'arrow'` the
+//! queries of simple query messages are sent that way too.
+//!
+//! With `SET peerdb.copy_compression = 'gzip'` the data of a COPY TO STDOUT
+//! is gzipped, for exports to clients far away. The wire protocol has no
+//! compression of its own, so query results are always sent uncompressed.
+
+use std::io::Write;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{write::GzEncoder, Compression};
+use futures::{future, stream, Stream, StreamExt};
+use peer_cursor::{util::write_value, BinaryCopy, Record, Schema};
+use pgwire::{
+    api::results::{CopyResponse, FieldFormat, Response},
+    error::{ErrorInfo, PgWireError, PgWireResult},
+    messages::copy::CopyData,
+};
+use sqlparser::{
+    ast::{CopyLegacyCsvOption, CopyLegacyOption, CopyOption, CopySource, CopyTarget, Statement},
+    dialect::PostgreSqlDialect,
+    parser::Parser,
+};
+
+use crate::arrow_stream::ipc_stream;
+
+const BINARY_SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+
+// compressed bytes gathered before they are sent as a CopyData
+const GZIP_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    Text,
+    Csv,
+    Binary,
+    /// An Apache Arrow IPC stream.
+    Arrow,
+}
+
+/// How the data of a COPY TO STDOUT is compressed, set by
+/// `peerdb.copy_compression`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyCompression {
+    #[default]
+    None,
+    /// The CopyData messages together make up one gzip stream.
+    Gzip,
+}
+
+impl CopyCompression {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "none" | "off" => Some(CopyCompression::None),
+            "gzip" => Some(CopyCompression::Gzip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    pub compression: CopyCompression,
+    pub header: bool,
+    pub delimiter: u8,
+    pub null: String,
+    pub quote: u8,
+    pub escape: u8,
+}
+
+impl CopyOptions {
+    /// The options of a COPY statement, in either the parenthesized or the
+    /// legacy syntax, with the defaults of their format for the rest.
+    pub fn parse(
+        options: &[CopyOption],
+        legacy_options: &[CopyLegacyOption],
+    ) -> PgWireResult<Self> {
+        let mut format = CopyFormat::Text;
+        let mut header = None;
+        let mut delimiter = None;
+        let mut null = None;
+        let mut quote = None;
+        let mut escape = None;
+
+        for option in options {
+            match option {
+                CopyOption::Format(name) => {
+                    format = match name.value.to_lowercase().as_str() {
+                        "text" => CopyFormat::Text,
+                        "csv" => CopyFormat::Csv,
+                        "binary" => CopyFormat::Binary,
+                        "arrow" => CopyFormat::Arrow,
+                        _ => {
+                            return Err(copy_error(
+                                "22023",
+                                format!("COPY format \"{}\" not recognized", name.value),
+                            ))
+                        }
+                    }
+                }
+                CopyOption::Header(on) => header = Some(*on),
+                CopyOption::Delimiter(c) => delimiter = Some(*c),
+                CopyOption::Null(s) => null = Some(s.clone()),
+                CopyOption::Quote(c) => quote = Some(*c),
+                CopyOption::Escape(c) => escape = Some(*c),
+                option => return Err(unsupported_option(option)),
+            }
+        }
+        for option in legacy_options {
+            match option {
+                CopyLegacyOption::Binary => format = CopyFormat::Binary,
+                CopyLegacyOption::Delimiter(c) => delimiter = Some(*c),
+                CopyLegacyOption::Null(s) => null = Some(s.clone()),
+                CopyLegacyOption::Csv(csv_options) => {
+                    format = CopyFormat::Csv;
+                    for option in csv_options {
+                        match option {
+                            CopyLegacyCsvOption::Header => header = Some(true),
+                            CopyLegacyCsvOption::Quote(c) => quote = Some(*c),
+                            CopyLegacyCsvOption::Escape(c) => escape = Some(*c),
+                            option => return Err(unsupported_option(option)),
+                        }
+                    }
+                }
+            }
+        }
+
+        if matches!(format, CopyFormat::Binary | CopyFormat::Arrow) {
+            let mode = if format == CopyFormat::Binary {
+                "BINARY"
+            } else {
+                "ARROW"
+            };
+            for (name, set) in [
+                ("DELIMITER", delimiter.is_some()),
+                ("NULL", null.is_some()),
+                ("HEADER", header.is_some()),
+            ] {
+                if set {
+                    return Err(copy_error(
+                        "42601",
+                        format!("cannot specify {} in {} mode", name, mode),
+                    ));
+                }
+            }
+        }
+        if format != CopyFormat::Csv {
+            if quote.is_some() {
+                return Err(copy_error("0A000", "COPY quote available only in CSV mode"));
+            }
+            if escape.is_some() {
+                return Err(copy_error(
+                    "0A000",
+                    "COPY escape available only in CSV mode",
+                ));
+            }
+        }
+
+        let csv = format == CopyFormat::Csv;
+        let delimiter = single_byte(
+            "delimiter",
+            delimiter.unwrap_or(if csv { ',' } else { '\t' }),
+        )?;
+        let quote = single_byte("quote", quote.unwrap_or('"'))?;
+        let escape = single_byte("escape", escape.unwrap_or(quote as char))?;
+        let null = null.unwrap_or_else(|| if csv { String::new() } else { "\\N".to_owned() });
+        if delimiter == b'\r' || delimiter == b'\n' {
+            return Err(copy_error(
+                "22023",
+                "COPY delimiter cannot be newline or carriage return",
+            ));
+        }
+        if null.contains(['\r', '\n']) {
+            return Err(copy_error(
+                "22023",
+                "COPY null representation cannot use newline or carriage return",
+            ));
+        }
+        if csv && delimiter == quote {
+            return Err(copy_error(
+                "22023",
+                "COPY delimiter and quote must be different",
+            ));
+        }
+
+        Ok(Self {
+            format,
+            compression: CopyCompression::None,
+            header: header.unwrap_or(false),
+            delimiter,
+            null,
+            quote,
+            escape,
+        })
+    }
+
+    /// The overall format code of the CopyOutResponse.
+    pub fn format_code(&self) -> i8 {
+        match self.format {
+            CopyFormat::Binary | CopyFormat::Arrow => 1,
+            CopyFormat::Text | CopyFormat::Csv => 0,
+        }
+    }
+
+    fn header_row(&self, schema: &Schema) -> Option<Bytes> {
+        if !self.header || self.format == CopyFormat::Binary {
+            return None;
+        }
+        let mut out = BytesMut::new();
+        for (i, field) in schema.iter().enumerate() {
+            if i > 0 {
+                out.put_u8(self.delimiter);
+            }
+            self.write_text(field.name().as_bytes(), &mut out);
+        }
+        out.put_u8(b'\n');
+        Some(out.freeze())
+    }
+
+    fn row(&self, schema: &Schema, record: &Record) -> PgWireResult<Bytes> {
+        let mut out = BytesMut::new();
+        if self.format == CopyFormat::Binary {
+            out.put_i16(record.values.len() as i16);
+            for (value, field) in record.values.iter().zip(schema.iter()) {
+                // the length is known once the value is written
+                let len_at = out.len();
+                out.put_i32(0);
+                if write_value(value, field.datatype(), FieldFormat::Binary, &mut out)? {
+                    let len = (out.len() - len_at - 4) as i32;
+                    out[len_at..len_at + 4].copy_from_slice(&len.to_be_bytes());
+                } else {
+                    out[len_at..len_at + 4].copy_from_slice(&(-1i32).to_be_bytes());
+                }
+            }
+            return Ok(out.freeze());
+        }
+
+        let mut text = BytesMut::new();
+        for (i, (value, field)) in record.values.iter().zip(schema.iter()).enumerate() {
+            if i > 0 {
+                out.put_u8(self.delimiter);
+            }
+            text.clear();
+            if write_value(value, field.datatype(), FieldFormat::Text, &mut text)? {
+                self.write_text(&text, &mut out);
+            } else {
+                out.put_slice(self.null.as_bytes());
+            }
+        }
+        out.put_u8(b'\n');
+        Ok(out.freeze())
+    }
+
+    // a non-NULL value, escaped so it can't be taken for a delimiter, the end
+    // of the row or the NULL string.
+    fn write_text(&self, value: &[u8], out: &mut BytesMut) {
+        if self.format == CopyFormat::Csv {
+            let needs_quotes = value == self.null.as_bytes()
+                || value
+                    .iter()
+                    .any(|&b| b == self.delimiter || b == self.quote || b == b'\r' || b == b'\n');
+            if !needs_quotes {
+                out.put_slice(value);
+                return;
+            }
+            out.put_u8(self.quote);
+            for &b in value {
+                if b == self.quote || b == self.escape {
+                    out.put_u8(self.escape);
+                }
+                out.put_u8(b);
+            }
+            out.put_u8(self.quote);
+            return;
+        }
+
+        for &b in value {
+            let escaped = match b {
+                b'\\' => b'\\',
+                b'\n' => b'n',
+                b'\r' => b'r',
+                b'\t' => b't',
+                0x08 => b'b',
+                0x0b => b'v',
+                0x0c => b'f',
+                b if b == self.delimiter => b,
+                b => {
+                    out.put_u8(b);
+                    continue;
+                }
+            };
+            out.put_u8(b'\\');
+            out.put_u8(escaped);
+        }
+    }
+}
+
+/// Rejects COPY to or from a program or a server-side file, before it gets
+/// near a peer.
+pub fn check_copy_target(target: &CopyTarget) -> PgWireResult<()> {
+    let message = match target {
+        CopyTarget::Stdin | CopyTarget::Stdout => return Ok(()),
+        CopyTarget::Program { .. } => {
+            "COPY to or from a program is not supported by nexus, use STDIN or STDOUT"
+        }
+        CopyTarget::File { .. } => {
+            "COPY to or from a file is not supported by nexus, use STDIN or STDOUT"
+        }
+    };
+    Err(copy_error("42501", message))
+}
+
+/// The query whose rows a COPY sends, the source query itself or a SELECT
+/// of the copied columns of the table.
+pub fn copy_query(source: &CopySource) -> PgWireResult<Statement> {
+    match source {
+        CopySource::Query(query) => Ok(Statement::Query(query.clone())),
+        CopySource::Table {
+            table_name,
+            columns,
+        } => {
+            let columns = if columns.is_empty() {
+                "*".to_owned()
+            } else {
+                columns
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let sql = format!("SELECT {} FROM {}", columns, table_name);
+            Parser::parse_sql(&PostgreSqlDialect {}, &sql)
+                .ok()
+                .and_then(|mut stmts| stmts.pop())
+                .ok_or_else(|| {
+                    PgWireError::ApiError(format!("unable to build query for COPY: {}", sql).into())
+                })
+        }
+    }
+}
+
+/// The CopyOut response sending the rows, one CopyData per row.
+pub fn copy_out<'a, S>(options: CopyOptions, schema: Schema, rows: S) -> Response<'a>
+where
+    S: Stream<Item = PgWireResult<Record>> + Send + 'a,
+{
+    let compression = options.compression;
+    // gzipped data isn't text, whatever the format of the rows inside
+    let format_code = match compression {
+        CopyCompression::None => options.format_code(),
+        CopyCompression::Gzip => 1,
+    };
+    let columns = schema.len();
+    if options.format == CopyFormat::Arrow {
+        return copy_response(compression, format_code, columns, ipc_stream(schema, rows));
+    }
+    let (header, trailer) = match options.format {
+        CopyFormat::Binary => {
+            let mut header = BytesMut::from(BINARY_SIGNATURE);
+            // flags, then the length of the header extension
+            header.put_i32(0);
+            header.put_i32(0);
+            (
+                Some(header.freeze()),
+                Some(Bytes::from_static(&[0xff, 0xff])),
+            )
+        }
+        _ => (options.header_row(&schema), None),
+    };
+
+    let rows = rows.map(move |record| options.row(&schema, &record?));
+    let data = stream::iter(header.map(Ok))
+        .chain(rows)
+        .chain(stream::iter(trailer.map(Ok)));
+    copy_response(compression, format_code, columns, data)
+}
+
+/// The CopyOut response sending binary COPY data as the peer produced it,
+/// for peers that speak postgres' binary format themselves.
+pub fn copy_out_binary<'a>(options: CopyOptions, copy: BinaryCopy) -> Response<'a> {
+    copy_response(options.compression, 1, copy.columns, copy.data)
+}
+
+fn copy_response<'a, S>(
+    compression: CopyCompression,
+    format_code: i8,
+    columns: usize,
+    data: S,
+) -> Response<'a>
+where
+    S: Stream<Item = PgWireResult<Bytes>> + Send + 'a,
+{
+    let data = match compression {
+        CopyCompression::None => data.boxed(),
+        CopyCompression::Gzip => gzip(data).boxed(),
+    };
+    let data = data.map(|data| data.map(CopyData::new));
+    Response::CopyOut(CopyResponse::new(format_code, columns, data))
+}
+
+// the data gzipped, in chunks of about GZIP_CHUNK_SIZE. Nothing follows an
+// error, the stream ends with it.
+fn gzip<'a, S>(data: S) -> impl Stream<Item = PgWireResult<Bytes>> + Send + 'a
+where
+    S: Stream<Item = PgWireResult<Bytes>> + Send + 'a,
+{
+    let mut encoder = Some(GzEncoder::new(Vec::new(), Compression::default()));
+    data.map(Some)
+        .chain(stream::once(future::ready(None)))
+        .filter_map(move |data| {
+            let chunk = match data {
+                Some(Ok(data)) => encoder.as_mut().and_then(|gz| {
+                    if let Err(err) = gz.write_all(&data) {
+                        return Some(Err(PgWireError::IoError(err)));
+                    }
+                    (gz.get_ref().len() >= GZIP_CHUNK_SIZE)
+                        .then(|| Ok(Bytes::from(std::mem::take(gz.get_mut()))))
+                }),
+                Some(Err(err)) => encoder.as_ref().map(|_| Err(err)),
+                None => encoder
+                    .take()
+                    .map(|gz| gz.finish().map(Bytes::from).map_err(PgWireError::IoError)),
+            };
+            if matches!(chunk, Some(Err(_))) {
+                encoder = None;
+            }
+            future::ready(chunk)
+        })
+}
+
+fn single_byte(name: &str, c: char) -> PgWireResult<u8> {
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        Err(copy_error(
+            "0A000",
+            format!("COPY {} must be a single one-byte character", name),
+        ))
+    }
+}
+
+fn unsupported_option(option: &impl std::fmt::Display) -> PgWireError {
+    copy_error(
+        "0A000",
+        format!("COPY option {} is not supported by nexus", option),
+    )
+}
+
+fn copy_error(code: &str, message: impl Into<String>) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message.into(),
+    )))
+}
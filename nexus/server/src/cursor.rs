@@ -1,11 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use pt::peerdb_peers::Peer;
 
 // PeerCursors is a map from name of cursor to the Peer that holds the cursor.
 // This is used to route cursor events to the correct peer.
+//
+// Like in postgres, a cursor is closed at the end of the transaction it was
+// declared in, the statement itself outside a transaction block, unless it
+// was declared WITH HOLD.
 pub struct PeerCursors {
-    cursors: HashMap<String, Box<Peer>>,
+    cursors: HashMap<String, PeerCursor>,
+    // cursors closed with their transaction, so that using them again tells
+    // why they are gone
+    closed: HashSet<String>,
+}
+
+struct PeerCursor {
+    peer: Box<Peer>,
+    hold: bool,
 }
 
 // have methods to deal with CursorModification events.
@@ -13,18 +25,66 @@ impl PeerCursors {
     pub fn new() -> Self {
         Self {
             cursors: HashMap::new(),
+            closed: HashSet::new(),
         }
     }
 
     pub fn add_cursor(&mut self, name: String, peer: Box<Peer>) {
-        self.cursors.insert(name, peer);
+        self.closed.remove(&name);
+        self.cursors.insert(name, PeerCursor { peer, hold: false });
+    }
+
+    // keeps the cursor open past the end of its transaction
+    pub fn hold_cursor(&mut self, name: &str) {
+        if let Some(cursor) = self.cursors.get_mut(name) {
+            cursor.hold = true;
+        }
     }
 
     pub fn remove_cursor(&mut self, name: &str) {
         self.cursors.remove(name);
+        self.closed.remove(name);
     }
 
     pub fn get_peer(&self, name: &str) -> Option<&Peer> {
-        self.cursors.get(name).map(|peer| peer.as_ref())
+        self.cursors.get(name).map(|cursor| cursor.peer.as_ref())
+    }
+
+    // whether the cursor was closed at the end of its transaction, forgetting
+    // it if `forget`
+    pub fn closed_with_transaction(&mut self, name: &str, forget: bool) -> bool {
+        if forget {
+            self.closed.remove(name)
+        } else {
+            self.closed.contains(name)
+        }
+    }
+
+    // every cursor, for CLOSE ALL to close them on their peers
+    pub fn close_all(&mut self) -> Vec<(String, Box<Peer>)> {
+        self.closed.clear();
+        self.cursors
+            .drain()
+            .map(|(name, cursor)| (name, cursor.peer))
+            .collect()
+    }
+
+    // at the end of a transaction, the cursors not declared WITH HOLD for
+    // them to be closed on their peers
+    pub fn end_transaction(&mut self) -> Vec<(String, Box<Peer>)> {
+        let names = self
+            .cursors
+            .iter()
+            .filter(|(_, cursor)| !cursor.hold)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let cursor = self.cursors.remove(&name)?;
+                self.closed.insert(name.clone());
+                Some((name, cursor.peer))
+            })
+            .collect()
     }
 }
@@ -0,0 +1,127 @@
+//! The peer executors of every connection, for `peerdb.reconnect_all()` and
+//! `peerdb.reconnect_peer('name')` to close them without a restart, e.g.
+//! after a network change or a credential rotation, and for the cursors left
+//! open at shutdown to be closed on their peers.
+//!
+//! Each connection keeps its own executors, one per peer it queried, and
+//! registers the map holding them here. Evicting an executor drops it from
+//! its connection's map, the connection to the peer is closed once no
+//! running query uses it anymore and the next query connects again.
+//! Executors with an open transaction are left alone, dropping them would
+//! roll the transaction back under the client.
+//!
+//! The pools of the postgres peers are shared by all connections and kept
+//! here too, evicting closes them so no pooled connection outlives the
+//! eviction.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use dashmap::DashMap;
+use peer_cursor::{CursorModification, QueryExecutor, QueryOutput};
+use peer_postgres::pool::PostgresPools;
+use sqlparser::{dialect::PostgreSqlDialect, parser::Parser};
+
+/// The executors of a connection, by peer name.
+pub type Executors = DashMap<String, Arc<dyn QueryExecutor>>;
+
+#[derive(Default)]
+pub struct ExecutorRegistry {
+    connections: Mutex<Vec<Weak<Executors>>>,
+    postgres_pools: PostgresPools,
+}
+
+/// What an eviction did, over all connections.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Eviction {
+    pub evicted: usize,
+    pub in_transaction: usize,
+}
+
+impl ExecutorRegistry {
+    /// The executor map of a new connection, dropped with the connection.
+    pub fn register(&self) -> Arc<Executors> {
+        let executors = Arc::new(DashMap::new());
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|connection| connection.strong_count() > 0);
+        connections.push(Arc::downgrade(&executors));
+        executors
+    }
+
+    pub fn postgres_pools(&self) -> &PostgresPools {
+        &self.postgres_pools
+    }
+
+    /// The executors kept by all connections.
+    pub fn cached(&self) -> usize {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|executors| executors.len())
+            .sum()
+    }
+
+    /// Evicts the executors of the peer, or of every peer, on all
+    /// connections.
+    pub fn evict(&self, peer: Option<&str>) -> Eviction {
+        self.postgres_pools.remove(peer);
+        let connections = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        let mut eviction = Eviction::default();
+        for executors in connections {
+            executors.retain(|name, executor| {
+                if peer.is_some_and(|peer| peer != name) {
+                    return true;
+                }
+                if executor.in_transaction() {
+                    eviction.in_transaction += 1;
+                    return true;
+                }
+                eviction.evicted += 1;
+                false
+            });
+        }
+        eviction
+    }
+
+    /// Closes the cursors of all connections on their peers, returning how
+    /// many were closed.
+    pub async fn close_cursors(&self) -> usize {
+        let executors = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|executors| {
+                executors
+                    .iter()
+                    .map(|executor| (executor.key().clone(), Arc::clone(executor.value())))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let Ok(mut stmts) = Parser::parse_sql(&PostgreSqlDialect {}, "CLOSE ALL") else {
+            return 0;
+        };
+        let close_all = stmts.remove(0);
+        let mut closed = 0;
+        for (peer, executor) in executors {
+            match executor.execute(&close_all).await {
+                Ok(QueryOutput::Cursor(CursorModification::Closed(cursors))) => {
+                    closed += cursors.len()
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!("unable to close the cursors on peer {}: {:?}", peer, err)
+                }
+            }
+        }
+        closed
+    }
+}
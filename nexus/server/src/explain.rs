@@ -0,0 +1,54 @@
+//! `EXPLAIN ANALYZE` on a peer: the statement runs through nexus once, and
+//! its plan, as the peer reports it for `EXPLAIN`, is followed by the time
+//! spent at each step on the nexus side. Postgres peers analyze the
+//! statement themselves, their `EXPLAIN ANALYZE` is forwarded.
+
+use std::time::Duration;
+
+use peer_cursor::{util::explain_records, Records};
+
+/// Where the time of a statement went, as measured by nexus.
+#[derive(Debug, Default)]
+pub struct NexusTiming {
+    /// Parsing the statement and finding the peer it runs on.
+    pub routing: Duration,
+    /// Getting an executor for the peer, connecting to it if needed.
+    pub executor_acquisition: Duration,
+    /// From sending the statement until its first row arrived, None when it
+    /// returned no rows.
+    pub first_row: Option<Duration>,
+    /// From sending the statement until its last row arrived.
+    pub execution: Duration,
+    pub rows: usize,
+}
+
+impl NexusTiming {
+    pub fn total(&self) -> Duration {
+        self.routing + self.executor_acquisition + self.execution
+    }
+}
+
+/// The plan lines of the peer followed by the nexus timing, a row per line
+/// like postgres' EXPLAIN.
+pub fn plan_records(peer_name: &str, peer_plan: &str, timing: &NexusTiming) -> Records {
+    let mut lines: Vec<String> = peer_plan.lines().map(str::to_owned).collect();
+    lines.push(format!("Nexus (peer {}):", peer_name));
+    lines.push(format!("  Routing Time: {}", millis(timing.routing)));
+    lines.push(format!(
+        "  Executor Acquisition Time: {}",
+        millis(timing.executor_acquisition)
+    ));
+    lines.push(format!(
+        "  Time to First Row: {}",
+        timing.first_row.map_or_else(|| "none".to_owned(), millis)
+    ));
+    lines.push(format!("  Rows: {}", timing.rows));
+    lines.push(format!("  Execution Time: {}", millis(timing.execution)));
+    lines.push(format!("  Total Time: {}", millis(timing.total())));
+
+    explain_records(lines)
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,159 @@
+//! `SET idle_in_transaction_session_timeout`: a transaction open on a peer
+//! that sits idle between the client's queries for longer than the timeout
+//! is rolled back, so a stalled client can't hold locks on the peer for
+//! good. The connection pinned by the transaction is dropped with it, and
+//! the next query of the client fails with 25P03 to tell it the transaction
+//! is gone. A query after that connects to the peer again.
+//!
+//! The idle time starts once the response of a query was sent, when its last
+//! row was written or the rows were dropped, so a client still reading a
+//! large result is not counted as idle.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use sqlparser::{dialect::PostgreSqlDialect, parser::Parser};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::executor_registry::Executors;
+
+#[derive(Default)]
+struct WatchState {
+    // rolls the transactions back once the timeout passed
+    timer: Option<JoinHandle<()>>,
+    // peers whose transaction was rolled back, reported to the next query
+    rolled_back: Vec<String>,
+    // the queries started so far, a response sent after the next query
+    // started doesn't start the timer
+    queries: u64,
+}
+
+#[derive(Default)]
+pub struct IdleTransactionWatch {
+    state: Arc<Mutex<WatchState>>,
+}
+
+impl IdleTransactionWatch {
+    /// Stops the timer as a query starts, failing the query when the
+    /// transaction was rolled back meanwhile.
+    pub async fn query_started(&self) -> PgWireResult<()> {
+        let mut state = self.state.lock().await;
+        state.queries += 1;
+        // a timer rolling back holds the lock, it can only be waiting here
+        if let Some(timer) = state.timer.take() {
+            timer.abort();
+        }
+        if state.rolled_back.is_empty() {
+            return Ok(());
+        }
+        let peers = std::mem::take(&mut state.rolled_back);
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "25P03".to_owned(),
+            format!(
+                "the transaction on peer {} was rolled back due to idle-in-transaction timeout",
+                peers.join(", ")
+            ),
+        ))))
+    }
+
+    /// Starts the timer once the response of the query started last was
+    /// sent, when the returned guard is dropped with the response's rows.
+    pub async fn query_finished(
+        &self,
+        timeout: Option<Duration>,
+        executors: &Arc<Executors>,
+    ) -> ResponseSent {
+        ResponseSent {
+            state: Arc::clone(&self.state),
+            query: self.state.lock().await.queries,
+            timeout,
+            executors: Arc::clone(executors),
+        }
+    }
+}
+
+/// Starts the idle timer when dropped, when a transaction is open on one of
+/// the peers.
+pub struct ResponseSent {
+    state: Arc<Mutex<WatchState>>,
+    query: u64,
+    timeout: Option<Duration>,
+    executors: Arc<Executors>,
+}
+
+impl ResponseSent {
+    /// The rows of the response, holding the guard until they were sent.
+    pub fn hold_until_sent<'a, S>(self, rows: S) -> BoxStream<'a, S::Item>
+    where
+        S: Stream + Send + 'a,
+    {
+        rows.map(move |row| {
+            let _sent = &self;
+            row
+        })
+        .boxed()
+    }
+}
+
+impl Drop for ResponseSent {
+    fn drop(&mut self) {
+        let Some(timeout) = self.timeout else {
+            return;
+        };
+        let watched = Arc::clone(&self.state);
+        let executors = Arc::clone(&self.executors);
+        let query = self.query;
+        tokio::spawn(async move {
+            let mut state = watched.lock().await;
+            if state.queries != query {
+                return;
+            }
+            if let Some(timer) = state.timer.take() {
+                timer.abort();
+            }
+            if !executors
+                .iter()
+                .any(|executor| executor.value().in_transaction())
+            {
+                return;
+            }
+            state.timer = Some(tokio::spawn(roll_back_after(
+                timeout,
+                Arc::clone(&watched),
+                executors,
+            )));
+        });
+    }
+}
+
+// rolls back the transactions still open on the peers once the timeout passed
+async fn roll_back_after(
+    timeout: Duration,
+    watched: Arc<Mutex<WatchState>>,
+    executors: Arc<Executors>,
+) {
+    tokio::time::sleep(timeout).await;
+    let mut state = watched.lock().await;
+    let idle = executors
+        .iter()
+        .filter(|executor| executor.value().in_transaction())
+        .map(|executor| (executor.key().clone(), Arc::clone(executor.value())))
+        .collect::<Vec<_>>();
+    for (peer, executor) in idle {
+        tracing::warn!(
+            "rolling back the transaction on peer {}, idle for more than {} ms",
+            peer,
+            timeout.as_millis()
+        );
+        if let Ok(mut stmts) = Parser::parse_sql(&PostgreSqlDialect {}, "ROLLBACK") {
+            if let Err(err) = executor.execute(&stmts.remove(0)).await {
+                // dropping the connection rolls the transaction back too
+                tracing::warn!("unable to roll back on peer {}: {:?}", peer, err);
+            }
+        }
+        executors.remove(&peer);
+        state.rolled_back.push(peer);
+    }
+}
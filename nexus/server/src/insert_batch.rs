@@ -0,0 +1,224 @@
+//! Batching of single-row INSERTs into multi-row INSERTs, enabled per
+//! session with `SET peerdb.insert_batching = on`.
+//!
+//! While batching is on, an `INSERT ... VALUES` into a postgres peer isn't run
+//! right away: its rows are buffered and the statement is acknowledged with
+//! its own row count. Buffered rows are sent as one multi-row INSERT when
+//! - an INSERT into another table, or with other columns, comes in,
+//! - any other statement comes in, which then runs after them,
+//! - `peerdb.insert_batch_size` rows are buffered, or
+//! - `peerdb.insert_batch_delay` passed since the first of them.
+//!
+//! Errors are attributed to the statement that was running when the batch was
+//! sent: an INSERT acknowledged earlier can fail as part of a later statement,
+//! and the statement reporting the error isn't run. The multi-row INSERT is a
+//! single statement on the peer, so a failure inserts none of its rows. A
+//! batch sent when the delay passes has no statement waiting on it, its error
+//! is reported by the next statement of the session instead.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use peer_cursor::{QueryExecutor, QueryOutput};
+use pgwire::error::{PgWireError, PgWireResult};
+use sqlparser::ast::{Expr, SetExpr, Statement, Values};
+use tokio::sync::Mutex;
+
+use crate::peer_stats::PeerStats;
+
+/// When buffered rows are sent to the peer.
+#[derive(Debug, Clone, Copy)]
+pub struct InsertBatchConfig {
+    pub max_rows: usize,
+    pub max_delay: Duration,
+}
+
+struct PendingBatch {
+    peer: String,
+    // the statement with its rows removed, only INSERTs with the same key
+    // are batched together.
+    key: String,
+    insert: Statement,
+    rows: Vec<Vec<Expr>>,
+    statements: usize,
+    executor: Arc<dyn QueryExecutor>,
+}
+
+#[derive(Default)]
+struct BatchState {
+    pending: Option<PendingBatch>,
+    // error of a batch sent by the timer, reported by the next statement
+    failed: Option<PgWireError>,
+    // bumped for every batch so a timer doesn't send a later one early
+    generation: u64,
+}
+
+impl BatchState {
+    async fn flush(&mut self, peer_stats: &PeerStats) -> PgWireResult<()> {
+        let Some(mut batch) = self.pending.take() else {
+            return Ok(());
+        };
+        let rows = batch.rows.len();
+        if let Some(values) = values_mut(&mut batch.insert) {
+            values.rows = std::mem::take(&mut batch.rows);
+        }
+
+        let started = Instant::now();
+        let res = batch.executor.execute(&batch.insert).await;
+        peer_stats.record(&batch.peer, started.elapsed(), res.is_err());
+        match res {
+            Ok(QueryOutput::AffectedRows(inserted)) if inserted != rows => {
+                tracing::warn!(
+                    "batched INSERT into peer {} inserted {} rows, expected {}",
+                    batch.peer,
+                    inserted,
+                    rows
+                );
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::error!(
+                    "batched INSERT of {} statements ({} rows) into peer {} failed: {:?}",
+                    batch.statements,
+                    rows,
+                    batch.peer,
+                    err
+                );
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The INSERTs a connection buffered, shared with the timer sending them.
+pub struct InsertBatcher {
+    state: Arc<Mutex<BatchState>>,
+    peer_stats: Arc<PeerStats>,
+}
+
+impl InsertBatcher {
+    pub fn new(peer_stats: Arc<PeerStats>) -> Self {
+        Self {
+            state: Default::default(),
+            peer_stats,
+        }
+    }
+
+    /// Buffers the rows of an INSERT for which `batch_key` returned `key`,
+    /// returning the number of rows to acknowledge it with.
+    pub async fn add(
+        &self,
+        peer: &str,
+        executor: Arc<dyn QueryExecutor>,
+        mut insert: Statement,
+        key: String,
+        config: InsertBatchConfig,
+    ) -> PgWireResult<usize> {
+        let mut state = self.state.lock().await;
+        if let Some(err) = state.failed.take() {
+            return Err(err);
+        }
+        if state
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.peer != peer || pending.key != key)
+        {
+            state.flush(&self.peer_stats).await?;
+        }
+
+        let rows = values_mut(&mut insert)
+            .map_or_else(Vec::new, |values| std::mem::take(&mut values.rows));
+        let count = rows.len();
+        match state.pending.as_mut() {
+            Some(pending) => {
+                pending.rows.extend(rows);
+                pending.statements += 1;
+            }
+            None => {
+                state.generation += 1;
+                state.pending = Some(PendingBatch {
+                    peer: peer.to_owned(),
+                    key,
+                    insert,
+                    rows,
+                    statements: 1,
+                    executor,
+                });
+                self.flush_after(config.max_delay, state.generation);
+            }
+        }
+
+        if state
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.rows.len() >= config.max_rows)
+        {
+            state.flush(&self.peer_stats).await?;
+        }
+        Ok(count)
+    }
+
+    /// Sends the buffered rows, or reports the error of a batch the timer sent.
+    pub async fn flush(&self) -> PgWireResult<()> {
+        let mut state = self.state.lock().await;
+        if let Some(err) = state.failed.take() {
+            return Err(err);
+        }
+        state.flush(&self.peer_stats).await
+    }
+
+    // the timer holds on to the state, so rows buffered by a connection that
+    // closes are still sent.
+    fn flush_after(&self, delay: Duration, generation: u64) {
+        let state = Arc::clone(&self.state);
+        let peer_stats = Arc::clone(&self.peer_stats);
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let mut state = state.lock().await;
+            if state.generation == generation {
+                if let Err(err) = state.flush(&peer_stats).await {
+                    state.failed = Some(err);
+                }
+            }
+        });
+    }
+}
+
+/// The key INSERTs are batched by, None for statements that can't be batched:
+/// only plain `INSERT ... VALUES` without `ON CONFLICT` or `RETURNING`.
+pub fn batch_key(stmt: &Statement) -> Option<String> {
+    match stmt {
+        Statement::Insert {
+            source: Some(query),
+            on: None,
+            returning: None,
+            ..
+        } if query.with.is_none()
+            && query.order_by.is_empty()
+            && query.limit.is_none()
+            && query.offset.is_none()
+            && query.fetch.is_none() =>
+        {
+            let mut key = stmt.clone();
+            values_mut(&mut key)?.rows.clear();
+            Some(key.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn values_mut(stmt: &mut Statement) -> Option<&mut Values> {
+    match stmt {
+        Statement::Insert {
+            source: Some(query),
+            ..
+        } => match query.body.as_mut() {
+            SetExpr::Values(values) => Some(values),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -1,107 +1,582 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Write,
-    sync::Arc,
-    time::Duration,
+    fs::File,
+    future::Future,
+    io::BufReader,
+    ops::ControlFlow,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
 };
 
-use analyzer::{PeerDDL, QueryAssociation};
+use analyzer::{Builtin, PeerDDL, QueryAssociation};
+use async_jobs::AsyncJobs;
 use async_trait::async_trait;
+use auth::{AuthConfig, AuthRule, NexusStartupHandler};
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
 use aws_sdk_kms::{primitives::Blob, Client as KmsClient};
 use base64::{engine::general_purpose, Engine as _};
-use bytes::{BufMut, BytesMut};
-use catalog::{Catalog, CatalogConfig};
+use bytes::{BufMut, Bytes, BytesMut};
+use cancel::{CancelRegistry, QueryCancel};
+use catalog::{Catalog, CatalogConfig, PeerCache};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use clap::Parser;
+use compare::{Comparison, Side};
+use copy::{check_copy_target, copy_out, copy_out_binary, copy_query, CopyFormat, CopyOptions};
 use cursor::PeerCursors;
 use dashmap::{mapref::entry::Entry as DashEntry, DashMap};
-use flow_rs::grpc::{FlowGrpcClient, PeerCreationResult};
+use executor_registry::{ExecutorRegistry, Executors};
+use explain::NexusTiming;
+use flow_rs::grpc::{FlowGrpcClient, PeerCreationResult, PeerValidationResult};
+use futures::{FutureExt, Sink, SinkExt, StreamExt};
+use idle_transaction::IdleTransactionWatch;
+use insert_batch::{batch_key, InsertBatcher};
+use memory::MemoryAccount;
+use param_log::ParameterLogConfig;
 use peer_connections::{PeerConnectionTracker, PeerConnections};
 use peer_cursor::{
-    util::{records_to_query_response, sendable_stream_to_query_response},
-    QueryExecutor, QueryOutput, Schema,
+    column_names::{postgres_column_names, with_postgres_column_names},
+    error::client_error,
+    util::{
+        describe_table_schema, dry_run_schema, explain_schema, export_schema_schema, has_returning,
+        records_to_query_response, sendable_stream_to_query_response, EncodePool, InvalidUtf8,
+    },
+    BulkLoadFormat, ByteStream, QueryExecutor, QueryOutput, Record, Records, Schema,
+};
+use peer_stats::PeerStats;
+use peer_test::PeerTestResult;
+use peer_types::PEER_TYPES;
+use peerdb_parser::{
+    ComparedQuery, CsvImport, NexusParsedStatement, NexusQueryParser, NexusStatement, RewriteRule,
+    RewriteRules,
 };
-use peerdb_parser::{NexusParsedStatement, NexusQueryParser, NexusStatement};
 use pgwire::{
     api::{
-        auth::{
-            scram::{gen_salted_password, SASLScramAuthStartupHandler},
-            AuthSource, LoginInfo, Password, ServerParameterProvider,
-        },
         copy::NoopCopyHandler,
         portal::Portal,
         query::{ExtendedQueryHandler, SimpleQueryHandler},
         results::{
-            DescribePortalResponse, DescribeResponse, DescribeStatementResponse, Response, Tag,
+            DescribePortalResponse, DescribeResponse, DescribeStatementResponse, FieldFormat,
+            FieldInfo, Response, Tag,
         },
         stmt::StoredStatement,
-        ClientInfo, PgWireHandlerFactory, Type,
+        store::PortalStore,
+        ClientInfo, ClientPortalStore, PgWireHandlerFactory, Type, DEFAULT_NAME, METADATA_USER,
     },
     error::{ErrorInfo, PgWireError, PgWireResult},
+    messages::{
+        extendedquery::{
+            Close, CloseComplete, TARGET_TYPE_BYTE_PORTAL, TARGET_TYPE_BYTE_STATEMENT,
+        },
+        PgWireBackendMessage,
+    },
     tokio::process_socket,
 };
+use portal::SuspendedPortal;
 use pt::{
     flow_model::QRepFlowJob,
     peerdb_peers::{peer::Config, Peer},
 };
-use rand::Rng;
+use result_limits::ResultLimits;
+use retry::ConnectRetryPolicy;
+use runtime_config::{RuntimeConfig, RuntimeSettings};
+use rust_decimal::Decimal;
+use secrets::SecretStore;
+use server_parameters::{AdvertisedParameter, NexusServerParameterProvider};
+use session::{Session, DEFAULT_PEER, STATEMENT_TIMEOUT};
+use spool::spool_stream;
+use sqlparser::ast::{
+    visit_expressions, CloseCursor, CopyLegacyOption, CopyOption, CopySource, CopyTarget, Declare,
+    Expr, Ident, Statement, Value,
+};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
-use tokio::{io::AsyncWriteExt, net::TcpListener};
+use tokio::{io::AsyncWriteExt, net::TcpListener, task::JoinSet};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::Instrument;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-
+use transaction::{Pinned, Transaction, TransactionControl};
+
+mod arrow_stream;
+mod async_jobs;
+mod auth;
+mod auto_limit;
+mod build_info;
+mod cancel;
+mod compare;
+mod copy;
 mod cursor;
-
-pub struct FixedPasswordAuthSource {
-    password: String,
-}
-
-impl FixedPasswordAuthSource {
-    pub fn new(password: String) -> Self {
-        Self { password }
-    }
-}
-
-#[async_trait]
-impl AuthSource for FixedPasswordAuthSource {
-    async fn get_password(&self, login_info: &LoginInfo) -> PgWireResult<Password> {
-        tracing::info!("login info: {:?}", login_info);
-
-        // randomly generate a 4 byte salt
-        let salt = rand::thread_rng().gen::<[u8; 4]>();
-        let hash_password = gen_salted_password(&self.password, &salt, 4096);
-        Ok(Password::new(Some(salt.to_vec()), hash_password))
-    }
-}
+mod executor_registry;
+mod explain;
+mod idle_transaction;
+mod insert_batch;
+mod memory;
+mod metrics;
+mod negotiate;
+mod param_log;
+mod peer_stats;
+mod peer_test;
+mod peer_types;
+mod placeholders;
+mod portal;
+mod result_limits;
+mod retry;
+mod runtime_config;
+mod secrets;
+mod server_parameters;
+mod session;
+mod show_peers;
+mod spool;
+mod transaction;
 
 pub struct NexusBackend {
     catalog: Arc<Catalog>,
+    peer_cache: Arc<PeerCache>,
     peer_connections: PeerConnectionTracker,
     query_parser: NexusQueryParser,
     peer_cursors: Mutex<PeerCursors>,
-    executors: DashMap<String, Arc<dyn QueryExecutor>>,
+    session: Mutex<Session>,
+    executors: Arc<Executors>,
+    // when each of the executors was last asked for
+    executors_used: DashMap<String, Instant>,
+    executor_registry: Arc<ExecutorRegistry>,
     flow_handler: Option<Arc<Mutex<FlowGrpcClient>>>,
     peerdb_fdw_mode: bool,
+    parameter_log: Option<Arc<ParameterLogConfig>>,
+    executor_config: PeerExecutorConfig,
+    peer_stats: Arc<PeerStats>,
+    secrets: Arc<SecretStore>,
+    runtime_config: Arc<RuntimeConfig>,
+    encode_pool: Option<Arc<EncodePool>>,
+    // the user the client logged in as, known from its first query
+    client_user: OnceLock<String>,
+    insert_batcher: InsertBatcher,
+    // rows left of the portals executed with a row limit, by portal name
+    suspended_portals: Mutex<HashMap<String, SuspendedPortal>>,
+    // the rows this connection buffers, capped by connection_memory_limit
+    memory: Arc<MemoryAccount>,
+    async_jobs: Arc<AsyncJobs>,
+    rewrite_rules: Arc<RewriteRules>,
+    idle_transactions: IdleTransactionWatch,
+    cancel: Arc<QueryCancel>,
+    // the transaction block the client is in
+    transaction: Mutex<Option<Transaction>>,
+}
+
+/// Settings for the executors a connection creates for the peers it queries.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerExecutorConfig {
+    pub invalid_utf8: InvalidUtf8,
+    pub connect_retry: ConnectRetryPolicy,
+    // executors kept per connection, the least recently used go first
+    pub max_cached: Option<usize>,
 }
 
 impl NexusBackend {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         catalog: Arc<Catalog>,
+        peer_cache: Arc<PeerCache>,
         peer_connections: PeerConnectionTracker,
         flow_handler: Option<Arc<Mutex<FlowGrpcClient>>>,
         peerdb_fdw_mode: bool,
+        parameter_log: Option<Arc<ParameterLogConfig>>,
+        default_peer: Option<String>,
+        server_parameters: Arc<HashMap<String, String>>,
+        executor_config: PeerExecutorConfig,
+        peer_stats: Arc<PeerStats>,
+        secrets: Arc<SecretStore>,
+        runtime_config: Arc<RuntimeConfig>,
+        encode_pool: Option<Arc<EncodePool>>,
+        async_jobs: Arc<AsyncJobs>,
+        rewrite_rules: Arc<RewriteRules>,
+        executor_registry: Arc<ExecutorRegistry>,
+        cancel_registry: Arc<CancelRegistry>,
     ) -> Self {
-        let query_parser = NexusQueryParser::new(catalog.clone());
+        let query_parser = NexusQueryParser::new(
+            catalog.clone(),
+            peer_cache.clone(),
+            default_peer.clone(),
+            rewrite_rules.clone(),
+        );
+        let memory = MemoryAccount::new(runtime_config.clone());
+        let executors = executor_registry.register();
+        let cancel = cancel_registry.register(executors.clone(), catalog.clone());
         Self {
             catalog,
+            peer_cache,
             peer_connections,
             query_parser,
             peer_cursors: Mutex::new(PeerCursors::new()),
-            executors: DashMap::new(),
+            session: Mutex::new(Session::new(default_peer, server_parameters)),
+            executors,
+            executors_used: DashMap::new(),
+            executor_registry,
             flow_handler,
             peerdb_fdw_mode,
+            parameter_log,
+            executor_config,
+            insert_batcher: InsertBatcher::new(peer_stats.clone()),
+            peer_stats,
+            secrets,
+            runtime_config,
+            encode_pool,
+            client_user: OnceLock::new(),
+            suspended_portals: Mutex::new(HashMap::new()),
+            memory,
+            async_jobs,
+            rewrite_rules,
+            idle_transactions: IdleTransactionWatch::default(),
+            cancel,
+            transaction: Mutex::new(None),
+        }
+    }
+
+    // the executor a query runs on, with the peer for peer queries
+    async fn query_executor(
+        &self,
+        assoc: &QueryAssociation,
+    ) -> PgWireResult<(Option<Box<Peer>>, Arc<dyn QueryExecutor>)> {
+        Ok(match assoc {
+            QueryAssociation::Peer(peer) => {
+                let executor = self.get_peer_executor(peer).await?;
+                (Some(peer.clone()), executor)
+            }
+            QueryAssociation::Catalog => (None, self.catalog.clone()),
+        })
+    }
+
+    // the executor a statement runs on, in a transaction block the one the
+    // block is pinned to. The first statement of a block pins it.
+    async fn statement_executor(
+        &self,
+        stmt: &Statement,
+        assoc: &QueryAssociation,
+    ) -> PgWireResult<(Option<Box<Peer>>, Arc<dyn QueryExecutor>)> {
+        let mut transaction = self.transaction.lock().await;
+        let Some(block) = transaction.as_mut() else {
+            return self.query_executor(assoc).await;
+        };
+        let peer = match assoc {
+            QueryAssociation::Peer(peer) => Some(peer.as_ref()),
+            QueryAssociation::Catalog => None,
+        };
+        if let Some(pinned) = &block.pinned {
+            if !pinned.runs_on(peer) && !(peer.is_none() && transaction::follows_block(stmt)) {
+                return Err(transaction::distributed_transaction(pinned, peer));
+            }
+            return Ok((pinned.peer.clone(), pinned.executor.clone()));
+        }
+        let pinned = self.pin_transaction(&block.begin, peer).await?;
+        let executor = (pinned.peer.clone(), pinned.executor.clone());
+        block.pinned = Some(pinned);
+        Ok(executor)
+    }
+
+    // pins a transaction block to the peer, None for the catalog, running its
+    // BEGIN there when the peer has transactions.
+    async fn pin_transaction(
+        &self,
+        begin: &Statement,
+        peer: Option<&Peer>,
+    ) -> PgWireResult<Pinned> {
+        let (executor, forwarded) = match peer {
+            Some(peer) => {
+                self.check_transaction_defaults(peer).await?;
+                let executor = self.get_peer_executor(peer).await?;
+                (
+                    executor,
+                    peer_types::capabilities(peer).supports_transactions,
+                )
+            }
+            None => (self.catalog.clone() as Arc<dyn QueryExecutor>, true),
+        };
+        if forwarded {
+            self.run_statement(executor.as_ref(), begin, peer).await?;
+        }
+        tracing::debug!(
+            peer = peer.map(|peer| peer.name.as_str()),
+            forwarded,
+            "transaction block pinned"
+        );
+        Ok(Pinned {
+            peer: peer.map(|peer| Box::new(peer.clone())),
+            executor,
+            forwarded,
+        })
+    }
+
+    // BEGIN opens a transaction block, postgres only warns about one already
+    // open.
+    async fn begin_transaction<'a>(
+        &self,
+        stmt: &Statement,
+        assoc: &QueryAssociation,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        // the INSERTs buffered before don't belong to the transaction
+        self.insert_batcher.flush().await?;
+        let mut transaction = self.transaction.lock().await;
+        if transaction.is_none() {
+            let mut block = Transaction::new(stmt.clone());
+            if let QueryAssociation::Peer(peer) = assoc {
+                block.pinned = Some(self.pin_transaction(stmt, Some(peer)).await?);
+            }
+            *transaction = Some(block);
+        }
+        Ok(vec![Response::TransactionStart(Tag::new("BEGIN"))])
+    }
+
+    // COMMIT or ROLLBACK of the open block, on the peer it is pinned to. A
+    // block a statement failed in is rolled back whatever the client asked
+    // for. The block ends even if the peer fails to end its transaction.
+    async fn end_transaction<'a>(
+        &self,
+        commit: bool,
+        chain: bool,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        self.insert_batcher.flush().await?;
+        // portals don't outlive the transaction they were bound in
+        self.suspended_portals.lock().await.clear();
+        let mut transaction = self.transaction.lock().await;
+        let Some(mut block) = transaction.take() else {
+            return Ok(vec![]);
+        };
+        let commit = commit && !block.failed;
+        if let Some(pinned) = block.pinned.as_ref().filter(|pinned| pinned.forwarded) {
+            let end = if commit {
+                Statement::Commit { chain }
+            } else {
+                Statement::Rollback {
+                    chain,
+                    savepoint: None,
+                }
+            };
+            self.run_statement(pinned.executor.as_ref(), &end, pinned.peer.as_deref())
+                .await?;
+        }
+        let tag = Tag::new(if commit { "COMMIT" } else { "ROLLBACK" });
+        if chain {
+            // the peer began the next transaction of the block already
+            block.failed = false;
+            *transaction = Some(block);
+            return Ok(vec![Response::Execution(tag)]);
         }
+        Ok(vec![Response::TransactionEnd(tag)])
+    }
+
+    // runs the statement within the session's statement timeout, recording
+    // it in the peer stats.
+    async fn run_statement(
+        &self,
+        executor: &dyn QueryExecutor,
+        stmt: &Statement,
+        peer: Option<&Peer>,
+    ) -> PgWireResult<QueryOutput> {
+        let settings = self.runtime_config.get();
+        let limits = ResultLimits {
+            max_columns: settings.max_result_columns,
+            max_value_size: settings.max_result_value_size,
+        };
+        let started = Instant::now();
+        let res = self
+            .with_statement_timeout(executor.execute(stmt))
+            .await
+            .map(|output| match peer {
+                Some(peer) if !Self::names_columns_like_postgres(peer) => {
+                    with_postgres_column_names(stmt, output)
+                }
+                _ => output,
+            })
+            .and_then(|output| limits.apply(output));
+        let elapsed = started.elapsed();
+        let peer_name = peer.map_or("catalog", |peer| &peer.name);
+        self.peer_stats.record(peer_name, elapsed, res.is_err());
+        metrics::record_query(peer_name, elapsed, &res);
+        if let Some(threshold) = settings.slow_query_threshold {
+            if elapsed >= threshold {
+                tracing::warn!(
+                    "slow query on {} took {} ms: {}",
+                    peer_name,
+                    elapsed.as_millis(),
+                    stmt
+                );
+            }
+        }
+        res
+    }
+
+    // postgres peers, like the catalog, name the columns of their results
+    // themselves, the names of other peers are made to match.
+    fn names_columns_like_postgres(peer: &Peer) -> bool {
+        matches!(peer.config, Some(Config::PostgresConfig(_)))
+    }
+
+    // postgres peers run EXPLAIN ANALYZE themselves and report the times they
+    // measured, nexus times the statement on the catalog and other peers.
+    fn analyzes_explain(peer: &Peer) -> bool {
+        matches!(peer.config, Some(Config::PostgresConfig(_)))
+    }
+
+    // the transaction defaults set in the session are applied by postgres
+    // peers, transactions on other peers would silently ignore them.
+    async fn check_transaction_defaults(&self, peer: &Peer) -> PgWireResult<()> {
+        if matches!(peer.config, Some(Config::PostgresConfig(_))) {
+            return Ok(());
+        }
+        let defaults = self.session.lock().await.transaction_defaults();
+        match defaults.first() {
+            None => Ok(()),
+            Some((name, value)) => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                format!(
+                    "peer {} does not support {} = {}, only postgres peers do",
+                    peer.name, name, value
+                ),
+            )))),
+        }
+    }
+
+    // RETURNING is postgres syntax, the other peers would fail on it with an
+    // error about the query text.
+    fn check_returning(stmt: &Statement, peer: &Peer) -> PgWireResult<()> {
+        if !has_returning(stmt) || matches!(peer.config, Some(Config::PostgresConfig(_))) {
+            return Ok(());
+        }
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            format!(
+                "RETURNING is not supported on peer {}, only postgres peers return the modified rows",
+                peer.name
+            ),
+        ))))
+    }
+
+    // runs the queries of a COMPARE one after the other, on their peers.
+    async fn compare(
+        &self,
+        first: &ComparedQuery,
+        second: &ComparedQuery,
+        key: Vec<Ident>,
+    ) -> PgWireResult<Records> {
+        let mut comparison = Comparison::new(key, &self.memory)?;
+        for (side, query) in [(Side::First, first), (Side::Second, second)] {
+            let (peer_holder, executor) = self.query_executor(&query.assoc).await?;
+            let output = self
+                .run_statement(executor.as_ref(), &query.stmt, peer_holder.as_deref())
+                .await?;
+            comparison.add(side, output).await?;
+        }
+        comparison.finish().await
+    }
+
+    // starts a job running the query in the background, on a connection to
+    // the peer of its own, and returns the job's id.
+    async fn submit_async(&self, query: &str) -> PgWireResult<String> {
+        let parsed = self.query_parser.parse_simple_sql(query).await?;
+        let NexusStatement::PeerQuery {
+            stmt: stmt @ Statement::Query(_),
+            assoc,
+        } = parsed.statement
+        else {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                "peerdb.submit_async only runs SELECT queries".to_owned(),
+            ))));
+        };
+        let (peer, executor) = match assoc {
+            QueryAssociation::Peer(peer) => {
+                let executor = self.connect_peer_with_retry(&peer).await?;
+                Self::check_row_locks(&stmt, &peer, executor.as_ref())?;
+                let forwarded = self.session.lock().await.forwarded_parameters();
+                for (name, value) in forwarded {
+                    executor.set_session_parameter(name, &value).await?;
+                }
+                (Some(peer), Some(executor))
+            }
+            QueryAssociation::Catalog => (None, None),
+        };
+        tracing::info!(
+            "submitting async query on {}: {}",
+            peer.as_ref().map_or("catalog", |peer| &peer.name),
+            stmt
+        );
+
+        let user = self.client_user.get().map_or("", String::as_str);
+        let peer_name = peer.as_ref().map(|peer| peer.name.clone());
+        let peer_stats = self.peer_stats.clone();
+        let run = move |executor: Arc<dyn QueryExecutor>| {
+            async move {
+                let started = Instant::now();
+                let res = executor.execute(&stmt).await.map(|output| match &peer {
+                    Some(peer) if !Self::names_columns_like_postgres(peer) => {
+                        with_postgres_column_names(&stmt, output)
+                    }
+                    _ => output,
+                });
+                let peer_name = peer.as_ref().map_or("catalog", |peer| &peer.name);
+                let elapsed = started.elapsed();
+                peer_stats.record(peer_name, elapsed, res.is_err());
+                metrics::record_query(peer_name, elapsed, &res);
+                res
+            }
+            .boxed()
+        };
+        self.async_jobs
+            .submit(
+                &self.catalog,
+                user,
+                query,
+                peer_name,
+                executor,
+                &self.memory,
+                run,
+            )
+            .await
+    }
+
+    // row locks are held by the peer's transaction, taken outside of one they
+    // would be released right away. The transaction is the one opened on this
+    // connection's executor for the peer, with `/*+ peer(name) */ BEGIN`.
+    fn check_row_locks(
+        stmt: &Statement,
+        peer: &Peer,
+        executor: &dyn QueryExecutor,
+    ) -> PgWireResult<()> {
+        let Statement::Query(query) = stmt else {
+            return Ok(());
+        };
+        let Some(lock) = query.locks.first() else {
+            return Ok(());
+        };
+        let (code, message) = if !matches!(peer.config, Some(Config::PostgresConfig(_))) {
+            (
+                "0A000",
+                format!(
+                    "SELECT ... FOR {} is not supported on peer {}, only postgres peers lock rows",
+                    lock.lock_type, peer.name
+                ),
+            )
+        } else if !executor.in_transaction() {
+            (
+                "25P01",
+                format!(
+                    "SELECT ... FOR {} can only be used in a transaction on peer {}, \
+                    start one with /*+ peer({}) */ BEGIN",
+                    lock.lock_type, peer.name, peer.name
+                ),
+            )
+        } else {
+            return Ok(());
+        };
+        Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            code.to_owned(),
+            message,
+        ))))
     }
 
     // execute a statement on a peer
@@ -111,14 +586,295 @@ impl NexusBackend {
         stmt: &sqlparser::ast::Statement,
         peer_holder: Option<Box<Peer>>,
     ) -> PgWireResult<Vec<Response<'a>>> {
-        let res = executor.execute(stmt).await?;
+        if self.session.lock().await.dry_run() && Self::is_dry_run_target(stmt) {
+            let peer_name = peer_holder.map_or_else(|| "catalog".to_owned(), |peer| peer.name);
+            return self.dry_run_statement(executor, stmt, peer_name).await;
+        }
+
+        let output = self
+            .run_statement(executor, stmt, peer_holder.as_deref())
+            .await?;
+        let mut responses = self.query_output_to_responses(output, peer_holder).await?;
+        if let Some(cursor) = Self::held_cursor(stmt) {
+            self.peer_cursors.lock().await.hold_cursor(cursor);
+        }
+        if let Some(command) = Self::command_tag(stmt) {
+            for response in responses.iter_mut() {
+                match response {
+                    Response::Execution(tag) => *tag = Tag::new(&command),
+                    Response::Query(query) => query.set_command_tag(&command),
+                    _ => (),
+                }
+            }
+        }
+        // rows returned by RETURNING or FETCH complete with the statement's
+        // tag, the row count is appended when they are sent, FETCH 0 past the
+        // end of a cursor.
+        let returning_tag = match stmt {
+            Statement::Insert { .. } => Some("INSERT 0"),
+            Statement::Update { .. } => Some("UPDATE"),
+            Statement::Delete { .. } => Some("DELETE"),
+            Statement::Fetch { .. } => Some("FETCH"),
+            _ => None,
+        };
+        if let Some(tag) = returning_tag {
+            for response in responses.iter_mut() {
+                if let Response::Query(query) = response {
+                    query.set_command_tag(tag);
+                }
+            }
+        }
+        Ok(responses)
+    }
+
+    // the tag postgres completes these statements with, peers report them as
+    // affected rows. COMMIT AND CHAIN and ROLLBACK AND CHAIN are tagged like
+    // COMMIT and ROLLBACK.
+    fn command_tag(stmt: &Statement) -> Option<String> {
+        let tag = match stmt {
+            Statement::Call(_) => "CALL",
+            Statement::StartTransaction { .. } => "BEGIN",
+            Statement::Commit { .. } => "COMMIT",
+            Statement::Rollback { .. } => "ROLLBACK",
+            Statement::CreateTable { .. } => "CREATE TABLE",
+            Statement::CreateView { .. } => "CREATE VIEW",
+            Statement::CreateIndex { .. } => "CREATE INDEX",
+            Statement::CreateSchema { .. } => "CREATE SCHEMA",
+            Statement::AlterTable { .. } => "ALTER TABLE",
+            Statement::Truncate { .. } => "TRUNCATE TABLE",
+            Statement::Drop { object_type, .. } => return Some(format!("DROP {}", object_type)),
+            _ => return None,
+        };
+        Some(tag.to_owned())
+    }
+
+    // the kind of statement logged with the queries, to filter them by
+    fn statement_kind(stmt: &Statement) -> &'static str {
+        match stmt {
+            Statement::Query(_) => "select",
+            Statement::Insert { .. } => "insert",
+            Statement::Update { .. } => "update",
+            Statement::Delete { .. } => "delete",
+            Statement::Declare { .. } => "declare",
+            Statement::Fetch { .. } => "fetch",
+            Statement::Close { .. } => "close",
+            Statement::StartTransaction { .. }
+            | Statement::Commit { .. }
+            | Statement::Rollback { .. } => "transaction",
+            Statement::SetVariable { .. } | Statement::ShowVariable { .. } => "setting",
+            Statement::CreateTable { .. }
+            | Statement::CreateView { .. }
+            | Statement::CreateIndex { .. }
+            | Statement::CreateSchema { .. }
+            | Statement::AlterTable { .. }
+            | Statement::Truncate { .. }
+            | Statement::Drop { .. } => "ddl",
+            _ => "other",
+        }
+    }
+
+    // statements that are planned instead of run while peerdb.dry_run is on,
+    // cursor and transaction control keep working as usual.
+    fn is_dry_run_target(stmt: &Statement) -> bool {
+        !matches!(
+            stmt,
+            Statement::Fetch { .. }
+                | Statement::Close { .. }
+                | Statement::Declare { .. }
+                | Statement::StartTransaction { .. }
+                | Statement::Commit { .. }
+                | Statement::Rollback { .. }
+                | Statement::Savepoint { .. }
+                | Statement::ReleaseSavepoint { .. }
+                | Statement::ShowVariable { .. }
+        )
+    }
+
+    // the cursor declared by a DECLARE ... WITH HOLD
+    fn held_cursor(stmt: &Statement) -> Option<&str> {
+        match stmt {
+            Statement::Declare { stmts } => match stmts.as_slice() {
+                [Declare {
+                    names,
+                    hold: Some(true),
+                    ..
+                }] => names.first().map(|name| name.value.as_str()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    async fn dry_run_statement<'a>(
+        &self,
+        executor: &dyn QueryExecutor,
+        stmt: &Statement,
+        peer_name: String,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let dry_run = self.with_statement_timeout(executor.dry_run(stmt)).await?;
+        let schema = dry_run_schema();
+        let records = Records {
+            records: vec![Record {
+                values: vec![
+                    value::Value::Text(peer_name),
+                    value::Value::Text(dry_run.plan),
+                    dry_run
+                        .estimated_bytes_processed
+                        .map_or(value::Value::Null, value::Value::BigInt),
+                ],
+                schema: schema.clone(),
+            }],
+            schema,
+        };
+        Ok(vec![records_to_query_response(records)?])
+    }
+
+    // `EXPLAIN ANALYZE`, the statement is run once through nexus to time it,
+    // the peer's plan is the one it reports without running it.
+    async fn explain_analyze<'a>(
+        &self,
+        executor: &dyn QueryExecutor,
+        stmt: &Statement,
+        peer: Option<&Peer>,
+        mut timing: NexusTiming,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let started = Instant::now();
+        let output = self.run_statement(executor, stmt, peer).await?;
+        match output {
+            QueryOutput::Stream(mut rows) => {
+                self.with_statement_timeout(async {
+                    while let Some(row) = rows.next().await {
+                        row?;
+                        timing.first_row.get_or_insert_with(|| started.elapsed());
+                        timing.rows += 1;
+                    }
+                    Ok::<_, PgWireError>(())
+                })
+                .await?;
+            }
+            QueryOutput::Records(records) => {
+                if !records.records.is_empty() {
+                    timing.first_row = Some(started.elapsed());
+                }
+                timing.rows = records.records.len();
+            }
+            QueryOutput::AffectedRows(rows) => timing.rows = rows,
+            QueryOutput::Cursor(_) => (),
+        }
+        timing.execution = started.elapsed();
+
+        let plan = match self.with_statement_timeout(executor.dry_run(stmt)).await {
+            Ok(dry_run) => dry_run.plan,
+            Err(err) => format!("(no plan from the peer: {})", err),
+        };
+        let peer_name = peer.map_or("catalog", |peer| &peer.name);
+        let records = explain::plan_records(peer_name, &plan, &timing);
+        Ok(vec![records_to_query_response(records)?])
+    }
+
+    // `COPY ... TO STDOUT`, run as the query it copies. While
+    // peerdb.dry_run is on, that query is planned instead.
+    async fn copy_to_stdout<'a>(
+        &self,
+        executor: &dyn QueryExecutor,
+        source: &CopySource,
+        options: &[CopyOption],
+        legacy_options: &[CopyLegacyOption],
+        peer_holder: Option<Box<Peer>>,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let mut copy_options = CopyOptions::parse(options, legacy_options)?;
+        let query = copy_query(source)?;
+        let dry_run = {
+            let session = self.session.lock().await;
+            copy_options.compression = session.copy_compression();
+            session.dry_run()
+        };
+        if dry_run {
+            return self.execute_statement(executor, &query, peer_holder).await;
+        }
+
+        if copy_options.format == CopyFormat::Binary {
+            if let Some(copy) = self
+                .with_statement_timeout(executor.copy_out_binary(&query))
+                .await?
+            {
+                return Ok(vec![copy_out_binary(copy_options, copy)]);
+            }
+        }
+        let output = self
+            .run_statement(executor, &query, peer_holder.as_deref())
+            .await?;
+        let response = match output {
+            QueryOutput::Stream(stream) => copy_out(copy_options, stream.schema(), stream),
+            QueryOutput::Records(records) => copy_out(
+                copy_options,
+                records.schema,
+                futures::stream::iter(records.records.into_iter().map(Ok)),
+            ),
+            _ => {
+                return Err(PgWireError::ApiError(
+                    "COPY source did not return rows".into(),
+                ))
+            }
+        };
+        Ok(vec![response])
+    }
+
+    // `DESCRIBE [peer.][schema.]table`, the peer part has already been used
+    // to pick the executor. Tables of the default peer come without one.
+    async fn describe_table<'a>(
+        &self,
+        executor: &dyn QueryExecutor,
+        table_name: &sqlparser::ast::ObjectName,
+        peer: Option<&Peer>,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let qualified = peer.is_some_and(|peer| {
+            table_name.0.len() > 1 && table_name.0[0].value.eq_ignore_ascii_case(&peer.name)
+        });
+        let parts = if qualified {
+            &table_name.0[1..]
+        } else {
+            &table_name.0[..]
+        };
+        let (schema, table) = match parts {
+            [table] => (None, table),
+            [schema, table] => (Some(schema.value.as_str()), table),
+            _ => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42601".to_owned(),
+                    format!("improper qualified name: {}", table_name),
+                ))))
+            }
+        };
+
+        let res = self
+            .with_statement_timeout(executor.describe_table(schema, &table.value))
+            .await?;
+        self.query_output_to_responses(res, None).await
+    }
+
+    async fn query_output_to_responses<'a>(
+        &self,
+        res: QueryOutput,
+        peer_holder: Option<Box<Peer>>,
+    ) -> PgWireResult<Vec<Response<'a>>> {
         match res {
             QueryOutput::AffectedRows(rows) => {
                 Ok(vec![Response::Execution(Tag::new("OK").with_rows(rows))])
             }
             QueryOutput::Stream(rows) => {
-                let schema = rows.schema();
-                let res = sendable_stream_to_query_response(schema, rows)?;
+                let spool_threshold = self.session.lock().await.spool_threshold();
+                let res = match spool_threshold {
+                    Some(threshold) => {
+                        self.with_statement_timeout(spool_stream(rows, threshold, &self.memory))
+                            .await?
+                    }
+                    None => {
+                        let schema = rows.schema();
+                        sendable_stream_to_query_response(schema, rows, self.encode_pool.clone())?
+                    }
+                };
                 Ok(vec![res])
             }
             QueryOutput::Records(records) => {
@@ -126,7 +882,11 @@ impl NexusBackend {
                 Ok(vec![res])
             }
             QueryOutput::Cursor(cm) => {
-                tracing::info!("cursor modification: {:?} {}", cm, peer_holder.is_some());
+                tracing::debug!(
+                    peer = peer_holder.as_ref().map(|peer| peer.name.as_str()),
+                    cursor = ?cm,
+                    "cursor modification"
+                );
                 let mut peer_cursors = self.peer_cursors.lock().await;
                 match cm {
                     peer_cursor::CursorModification::Created(cursor_name) => {
@@ -146,6 +906,521 @@ impl NexusBackend {
         }
     }
 
+    // run a future bounded by the statement's timeout hint, else the session's
+    // statement_timeout, else the server's. A CancelRequest of the client ends
+    // it too. The peers are asked to cancel a query that timed out, it would
+    // keep running on them otherwise.
+    async fn with_statement_timeout<T>(
+        &self,
+        fut: impl Future<Output = PgWireResult<T>>,
+    ) -> PgWireResult<T> {
+        let token = self.cancel.token();
+        let fut = async {
+            tokio::select! {
+                res = fut => res,
+                _ = token.cancelled() => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "57014".to_owned(),
+                    "canceling statement due to user request".to_owned(),
+                )))),
+            }
+        };
+        let timeout = {
+            let session = self.session.lock().await;
+            if let Some(hint) = session.timeout_hint() {
+                hint
+            } else if session.get(STATEMENT_TIMEOUT).is_some() {
+                session.statement_timeout()
+            } else {
+                self.runtime_config.get().statement_timeout
+            }
+        };
+        let Some(timeout) = timeout else {
+            return fut.await;
+        };
+        match tokio::time::timeout(timeout, fut).await {
+            Ok(res) => res,
+            Err(_) => {
+                self.cancel.cancel_peers().await;
+                Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "57014".to_owned(),
+                    "canceling statement due to statement timeout".to_owned(),
+                ))))
+            }
+        }
+    }
+
+    fn remember_client_user(&self, client: &impl ClientInfo) {
+        if self.client_user.get().is_none() {
+            if let Some(user) = client.metadata().get(METADATA_USER) {
+                let _ = self.client_user.set(user.clone());
+            }
+        }
+    }
+
+    // the user changing a rewrite rule, who must be an admin
+    fn check_rewrite_rule_admin(&self, rule: &str) -> PgWireResult<&str> {
+        self.check_admin(&format!("change rewrite rule \"{}\"", rule))
+    }
+
+    // the user doing what only admins may do
+    fn check_admin(&self, action: &str) -> PgWireResult<&str> {
+        let user = self.client_user.get().map_or("", String::as_str);
+        if !self.runtime_config.is_admin(user) {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42501".to_owned(),
+                format!(
+                    "permission denied to {}, \"{}\" is not an admin user",
+                    action, user
+                ),
+            ))));
+        }
+        Ok(user)
+    }
+
+    // adds `peerdb.auto_limit` to an unlimited SELECT on a peer, telling the
+    // client with a notice.
+    async fn apply_auto_limit<C>(
+        &self,
+        client: &mut C,
+        nexus_stmt: &mut NexusStatement,
+    ) -> PgWireResult<()>
+    where
+        C: Sink<PgWireBackendMessage> + Unpin + Send,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let NexusStatement::PeerQuery {
+            stmt,
+            assoc: QueryAssociation::Peer(peer),
+        } = nexus_stmt
+        else {
+            return Ok(());
+        };
+        let Some(limit) = self.session.lock().await.auto_limit() else {
+            return Ok(());
+        };
+        if auto_limit::add_limit(stmt, limit) {
+            let notice = ErrorInfo::new(
+                "NOTICE".to_owned(),
+                "00000".to_owned(),
+                format!(
+                    "peerdb.auto_limit applied, the query on peer {} returns at most {} rows",
+                    peer.name, limit
+                ),
+            );
+            client
+                .send(PgWireBackendMessage::NoticeResponse(notice.into()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // with peerdb.result_format = 'arrow' a query is run as a COPY of its
+    // rows in the arrow format. Only simple queries are, a portal's rows are
+    // described as rows before it is executed.
+    async fn apply_result_format(&self, nexus_stmt: &mut NexusStatement) {
+        let NexusStatement::PeerQuery {
+            stmt: stmt @ Statement::Query(_),
+            ..
+        } = nexus_stmt
+        else {
+            return;
+        };
+        if !self.session.lock().await.arrow_results() {
+            return;
+        }
+        if let Statement::Query(query) = stmt {
+            *stmt = Statement::Copy {
+                source: CopySource::Query(query.clone()),
+                to: true,
+                target: CopyTarget::Stdout,
+                options: vec![CopyOption::Format(Ident::new("arrow"))],
+                legacy_options: vec![],
+                values: vec![],
+            };
+        }
+    }
+
+    async fn builtin_schema(&self, builtin: &Builtin) -> PgWireResult<Schema> {
+        Ok(match builtin {
+            Builtin::Sleep(_) => Arc::new(vec![FieldInfo::new(
+                "pg_sleep".to_owned(),
+                None,
+                None,
+                Type::VOID,
+                FieldFormat::Text,
+            )]),
+            Builtin::PeerStats => Arc::new(PeerStats::schema()),
+            Builtin::PeerTypes => Arc::new(peer_types::schema()),
+            Builtin::Config => Arc::new(RuntimeConfig::schema()),
+            Builtin::SetConfig { .. } => Arc::new(vec![FieldInfo::new(
+                "set_config".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::Version => Arc::new(vec![FieldInfo::new(
+                "version".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::SubmitAsync { .. } => Arc::new(vec![FieldInfo::new(
+                "submit_async".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::AsyncStatus { .. } => AsyncJobs::status_schema(),
+            Builtin::RewriteRules => Arc::new(
+                [
+                    "name",
+                    "peer",
+                    "table_name",
+                    "action",
+                    "argument",
+                    "created_by",
+                ]
+                .into_iter()
+                .map(|name| {
+                    FieldInfo::new(name.to_owned(), None, None, Type::TEXT, FieldFormat::Text)
+                })
+                .collect(),
+            ),
+            Builtin::AddRewriteRule { .. } => Arc::new(vec![FieldInfo::new(
+                "add_rewrite_rule".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::DropRewriteRule { .. } => Arc::new(vec![FieldInfo::new(
+                "drop_rewrite_rule".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::ReconnectAll | Builtin::ReconnectPeer { .. } => Arc::new(
+                ["closed", "in_transaction"]
+                    .into_iter()
+                    .map(|name| {
+                        FieldInfo::new(name.to_owned(), None, None, Type::INT8, FieldFormat::Text)
+                    })
+                    .collect(),
+            ),
+            Builtin::SetPassword { .. } => Arc::new(vec![FieldInfo::new(
+                "set_password".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::DropUser { .. } => Arc::new(vec![FieldInfo::new(
+                "drop_user".to_owned(),
+                None,
+                None,
+                Type::TEXT,
+                FieldFormat::Text,
+            )]),
+            Builtin::AsyncResult { id } => self.async_jobs.result_schema(&self.catalog, id).await?,
+        })
+    }
+
+    // evaluate a builtin function within nexus, without involving any peer
+    async fn handle_builtin<'a>(&self, builtin: &Builtin) -> PgWireResult<Vec<Response<'a>>> {
+        Ok(vec![records_to_query_response(
+            self.builtin_records(builtin).await?,
+        )?])
+    }
+
+    async fn builtin_records(&self, builtin: &Builtin) -> PgWireResult<Records> {
+        let schema = self.builtin_schema(builtin).await?;
+        let rows = match builtin {
+            Builtin::Sleep(duration) => {
+                self.with_statement_timeout(async {
+                    tokio::time::sleep(*duration).await;
+                    Ok(())
+                })
+                .await?;
+                vec![vec![value::Value::Null]]
+            }
+            Builtin::Version => vec![vec![value::Value::Text(build_info::version())]],
+            Builtin::Config => self
+                .runtime_config
+                .rows()
+                .into_iter()
+                .map(|(name, setting, description)| {
+                    vec![
+                        value::Value::Text(name),
+                        value::Value::Text(setting),
+                        value::Value::Text(description),
+                    ]
+                })
+                .collect(),
+            Builtin::SetConfig {
+                name,
+                value: setting,
+            } => {
+                let user = self.client_user.get().map_or("", String::as_str);
+                let (settings, shown) = self
+                    .runtime_config
+                    .set(&self.catalog, user, name, setting)
+                    .await?;
+                self.secrets.set_ttl(settings.secret_cache_ttl);
+                tracing::info!("nexus setting {} set to {} by {}", name, shown, user);
+                vec![vec![value::Value::Text(shown)]]
+            }
+            Builtin::PeerStats => self
+                .peer_stats
+                .snapshot()
+                .into_iter()
+                .map(|row| {
+                    vec![
+                        value::Value::Text(row.peer),
+                        value::Value::BigInt(row.queries),
+                        value::Value::BigInt(row.errors),
+                        value::Value::Double(row.error_rate),
+                        value::Value::Double(row.p50_ms),
+                        value::Value::Double(row.p95_ms),
+                        value::Value::Double(row.p99_ms),
+                    ]
+                })
+                .collect(),
+            Builtin::RewriteRules => self
+                .rewrite_rules
+                .rules()
+                .into_iter()
+                .map(|rule| {
+                    vec![
+                        value::Value::Text(rule.name.clone()),
+                        value::Value::Text(rule.peer.clone()),
+                        value::Value::Text(rule.table.to_string()),
+                        value::Value::Text(rule.action_name().to_owned()),
+                        value::Value::Text(rule.argument()),
+                        value::Value::Text(rule.created_by),
+                    ]
+                })
+                .collect(),
+            Builtin::AddRewriteRule {
+                name,
+                peer,
+                table,
+                action,
+                argument,
+            } => {
+                let user = self.check_rewrite_rule_admin(name)?;
+                if !self
+                    .query_parser
+                    .get_peers_bridge()
+                    .await?
+                    .contains_key(peer)
+                {
+                    return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "42704".to_owned(),
+                        format!("peer \"{}\" does not exist", peer),
+                    ))));
+                }
+                let rule = RewriteRule::parse(name, peer, table, action, argument, user)?;
+                tracing::info!(
+                    "rewrite rule {} added by {} on peer {}: {} {} {}",
+                    name,
+                    user,
+                    peer,
+                    rule.action_name(),
+                    rule.table,
+                    rule.argument()
+                );
+                self.rewrite_rules.add(&self.catalog, rule).await?;
+                vec![vec![value::Value::Text(name.clone())]]
+            }
+            Builtin::DropRewriteRule { name } => {
+                let user = self.check_rewrite_rule_admin(name)?;
+                self.rewrite_rules.drop(&self.catalog, name).await?;
+                tracing::info!("rewrite rule {} dropped by {}", name, user);
+                vec![vec![value::Value::Text(name.clone())]]
+            }
+            Builtin::ReconnectAll => {
+                let user = self.check_admin("reconnect peers")?;
+                self.secrets.clear();
+                let eviction = self.executor_registry.evict(None);
+                tracing::info!(
+                    "{} closed {} peer connections, {} in a transaction were kept",
+                    user,
+                    eviction.evicted,
+                    eviction.in_transaction
+                );
+                vec![vec![
+                    value::Value::BigInt(eviction.evicted as i64),
+                    value::Value::BigInt(eviction.in_transaction as i64),
+                ]]
+            }
+            Builtin::ReconnectPeer { peer } => {
+                let user = self.check_admin(&format!("reconnect peer \"{}\"", peer))?;
+                let Some(found) = self.query_parser.get_peers_bridge().await?.remove(peer) else {
+                    return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "42704".to_owned(),
+                        format!("peer \"{}\" does not exist", peer),
+                    ))));
+                };
+                self.secrets.invalidate(&found);
+                let eviction = self.executor_registry.evict(Some(peer));
+                tracing::info!(
+                    "{} closed {} connections to peer {}, {} in a transaction were kept",
+                    user,
+                    eviction.evicted,
+                    peer,
+                    eviction.in_transaction
+                );
+                vec![vec![
+                    value::Value::BigInt(eviction.evicted as i64),
+                    value::Value::BigInt(eviction.in_transaction as i64),
+                ]]
+            }
+            Builtin::SetPassword { user, password } => {
+                let admin = self.check_admin(&format!("set the password of \"{}\"", user))?;
+                self.catalog
+                    .set_user_credentials(user, &auth::user_credentials(user, password))
+                    .await
+                    .map_err(|err| {
+                        PgWireError::ApiError(format!("unable to set password: {}", err).into())
+                    })?;
+                tracing::info!("password of user {} set by {}", user, admin);
+                vec![vec![value::Value::Text(user.clone())]]
+            }
+            Builtin::DropUser { user } => {
+                let admin = self.check_admin(&format!("drop user \"{}\"", user))?;
+                let dropped = self.catalog.delete_user(user).await.map_err(|err| {
+                    PgWireError::ApiError(format!("unable to drop user: {}", err).into())
+                })?;
+                if !dropped {
+                    return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "42704".to_owned(),
+                        format!("user \"{}\" does not exist", user),
+                    ))));
+                }
+                tracing::info!("user {} dropped by {}", user, admin);
+                vec![vec![value::Value::Text(user.clone())]]
+            }
+            Builtin::SubmitAsync { query } => {
+                vec![vec![value::Value::Text(self.submit_async(query).await?)]]
+            }
+            Builtin::AsyncStatus { id } => {
+                vec![self.async_jobs.status(&self.catalog, id).await?]
+            }
+            Builtin::AsyncResult { id } => self
+                .async_jobs
+                .take_result(&self.catalog, id)
+                .await?
+                .records
+                .into_iter()
+                .map(|record| record.values)
+                .collect(),
+            Builtin::PeerTypes => PEER_TYPES
+                .iter()
+                .map(|(db_type, capabilities)| {
+                    vec![
+                        value::Value::Text(db_type.as_str_name().to_owned()),
+                        value::Value::Bool(capabilities.queryable),
+                        value::Value::Bool(capabilities.writable),
+                        value::Value::Bool(capabilities.supports_cursors),
+                        value::Value::Bool(capabilities.supports_transactions),
+                        value::Value::Bool(capabilities.supports_copy),
+                    ]
+                })
+                .collect(),
+        };
+
+        Ok(Records {
+            records: rows
+                .into_iter()
+                .map(|values| Record {
+                    values,
+                    schema: schema.clone(),
+                })
+                .collect(),
+            schema,
+        })
+    }
+
+    fn show_variable_schema(name: &str) -> Schema {
+        Arc::new(vec![FieldInfo::new(
+            name.to_owned(),
+            None,
+            None,
+            Type::TEXT,
+            FieldFormat::Text,
+        )])
+    }
+
+    fn show_all_schema() -> Schema {
+        let field =
+            |name: &str| FieldInfo::new(name.to_owned(), None, None, Type::TEXT, FieldFormat::Text);
+        Arc::new(vec![field("name"), field("setting"), field("description")])
+    }
+
+    async fn handle_set_variable<'a>(
+        &self,
+        name: &str,
+        value: Option<&str>,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        self.set_variable(name, value).await?;
+        Ok(vec![Response::Execution(Tag::new("SET"))])
+    }
+
+    // RESET restores the server default, RESET ALL does so for every variable
+    // the session set.
+    async fn handle_reset_variable<'a>(
+        &self,
+        name: Option<&str>,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let names = match name {
+            Some(name) => vec![name.to_owned()],
+            None => self.session.lock().await.variable_names(),
+        };
+        for name in names {
+            self.set_variable(&name, None).await?;
+        }
+        Ok(vec![Response::Execution(Tag::new("RESET"))])
+    }
+
+    // sets the variable in the session, resetting it for a value of None.
+    async fn set_variable(&self, name: &str, value: Option<&str>) -> PgWireResult<()> {
+        let forwarded = {
+            let mut session = self.session.lock().await;
+            match value {
+                Some(value) => session.set(name, value)?,
+                None => session.reset(name),
+            }
+            if name == DEFAULT_PEER {
+                self.query_parser.set_default_peer(session.default_peer());
+            }
+            session.forwarded_value(name)
+        };
+
+        // apply the variable on the peers this connection already talks to,
+        // peers connected later pick it up in get_peer_executor.
+        if let Some(forwarded) = forwarded {
+            let executors = self
+                .executors
+                .iter()
+                .map(|entry| Arc::clone(entry.value()))
+                .collect::<Vec<_>>();
+            for executor in executors {
+                executor.set_session_parameter(name, &forwarded).await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn check_for_mirror(catalog: &Catalog, flow_name: &str) -> PgWireResult<bool> {
         let workflow_details = catalog.flow_name_exists(flow_name).await.map_err(|err| {
             PgWireError::ApiError(
@@ -171,9 +1446,49 @@ impl NexusBackend {
         }
     }
 
-    async fn create_peer<'a>(&self, peer: &Peer) -> anyhow::Result<()> {
+    // drops what sessions keep of a dropped peer, its executors in a
+    // transaction stay until the transaction ends
+    fn forget_peer(&self, peer_name: &str) {
+        self.peer_cache.invalidate();
+        let eviction = self.executor_registry.evict(Some(peer_name));
+        tracing::info!(
+            "DROP PEER: closed {} connections to peer {}, {} in a transaction",
+            eviction.evicted,
+            peer_name,
+            eviction.in_transaction
+        );
+    }
+
+    // `resolved` is the peer with its secret references resolved. The flow api
+    // stores the config it validates, so a peer with references is validated
+    // with the secrets there and stored with the references by nexus.
+    async fn create_peer<'a>(&self, peer: &Peer, resolved: &Peer) -> anyhow::Result<()> {
         let mut flow_handler = self.flow_handler.as_ref().unwrap().lock().await;
 
+        if secrets::has_secret_references(peer) {
+            let validate_request = pt::peerdb_route::ValidatePeerRequest {
+                peer: Some(resolved.clone()),
+            };
+            let validation = flow_handler
+                .validate_peer(validate_request)
+                .await
+                .map_err(|err| {
+                    PgWireError::ApiError(
+                        format!("unable to check peer validity: {:?}", err).into(),
+                    )
+                })?;
+            if let PeerValidationResult::Invalid(validate_err) = validation {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "08001".to_owned(),
+                    format!("failed to create peer: {}", validate_err),
+                )))
+                .into());
+            }
+            self.catalog.create_peer(peer).await?;
+            return Ok(());
+        }
+
         let create_request = pt::peerdb_route::CreatePeerRequest {
             peer: Some(Peer {
                 name: peer.name.clone(),
@@ -189,10 +1504,11 @@ impl NexusBackend {
             .map_err(|err| {
                 PgWireError::ApiError(format!("unable to check peer validity: {:?}", err).into())
             })?;
+        // the flow api fails the peers it can't connect to with the config
         if let PeerCreationResult::Failed(create_err) = create_response {
             Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                 "ERROR".to_owned(),
-                "internal_error".to_owned(),
+                "08001".to_owned(),
                 format!("failed to create peer: {}", create_err),
             )))
             .into())
@@ -305,22 +1621,240 @@ impl NexusBackend {
         }
     }
 
+    // buffers the statement if it's an INSERT the session batches, see
+    // insert_batch.
+    async fn batch_insert<'a>(
+        &self,
+        nexus_stmt: &NexusStatement,
+    ) -> PgWireResult<Option<Vec<Response<'a>>>> {
+        let config = {
+            let session = self.session.lock().await;
+            // dry runs plan INSERTs one at a time
+            session.insert_batching().filter(|_| !session.dry_run())
+        };
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        // the statements of a transaction block run in order on its peer
+        if self.transaction.lock().await.is_some() {
+            return Ok(None);
+        }
+        let NexusStatement::PeerQuery {
+            stmt,
+            assoc: QueryAssociation::Peer(peer),
+        } = nexus_stmt
+        else {
+            return Ok(None);
+        };
+        let key = match (&peer.config, batch_key(stmt)) {
+            (Some(Config::PostgresConfig(_)), Some(key)) => key,
+            _ => return Ok(None),
+        };
+
+        let executor = self.get_peer_executor(peer).await?;
+        let rows = self
+            .insert_batcher
+            .add(&peer.name, executor, stmt.clone(), key, config)
+            .await?;
+        Ok(Some(vec![Response::Execution(
+            Tag::new("INSERT").with_oid(0).with_rows(rows),
+        )]))
+    }
+
+    // `routed_in` is the time spent parsing the statement and finding the peer
+    // it runs on. BEGIN, COMMIT and ROLLBACK are handled for the transaction
+    // block, the peer cursors of a transaction that ended with the statement
+    // are closed after it.
     async fn handle_query<'a>(
         &self,
         nexus_stmt: NexusStatement,
+        routed_in: Duration,
+    ) -> PgWireResult<Vec<Response<'a>>> {
+        let control = nexus_stmt.ast().and_then(TransactionControl::of);
+        let rolls_back_to_savepoint = matches!(
+            nexus_stmt.ast(),
+            Some(Statement::Rollback {
+                savepoint: Some(_),
+                ..
+            })
+        );
+        let (in_block, aborted) = match self.transaction.lock().await.as_ref() {
+            Some(block) => (true, block.failed),
+            None => (false, false),
+        };
+        let res = match (control, &nexus_stmt) {
+            (Some(TransactionControl::End { commit, chain }), _) if in_block => {
+                self.end_transaction(commit, chain).await
+            }
+            // the peer warns there's no transaction, the client's transaction
+            // status is reset all the same
+            (Some(TransactionControl::End { .. }), _) => {
+                let responses = self.run_query(nexus_stmt, routed_in).await;
+                responses.map(|responses| {
+                    responses
+                        .into_iter()
+                        .map(|response| match response {
+                            Response::Execution(tag) => Response::TransactionEnd(tag),
+                            response => response,
+                        })
+                        .collect()
+                })
+            }
+            _ if aborted && !rolls_back_to_savepoint => Err(transaction::aborted()),
+            (Some(TransactionControl::Begin), NexusStatement::PeerQuery { stmt, assoc }) => {
+                self.begin_transaction(stmt, assoc).await
+            }
+            _ => self.run_query(nexus_stmt, routed_in).await,
+        };
+
+        let in_block = {
+            let mut transaction = self.transaction.lock().await;
+            if let Some(block) = transaction.as_mut() {
+                if res.is_err() {
+                    block.failed = true;
+                } else if rolls_back_to_savepoint {
+                    block.failed = false;
+                }
+            }
+            transaction.is_some()
+        };
+        // a statement outside a transaction block is a transaction of its own
+        if !in_block || matches!(control, Some(TransactionControl::End { .. })) {
+            let closing = self.peer_cursors.lock().await.end_transaction();
+            self.close_peer_cursors(closing).await;
+        }
+        res
+    }
+
+    // closes cursors left open on their peers, a peer whose executor was
+    // dropped took its cursors with it
+    async fn close_peer_cursors(&self, cursors: Vec<(String, Box<Peer>)>) {
+        for (name, peer) in cursors {
+            let Some(executor) = self.executors.get(&peer.name).map(|e| e.value().clone()) else {
+                continue;
+            };
+            let close = Statement::Close {
+                cursor: CloseCursor::Specific {
+                    name: Ident::new(&name),
+                },
+            };
+            if let Err(err) = executor.execute(&close).await {
+                tracing::warn!(
+                    "unable to close cursor {} on peer {}: {:?}",
+                    name,
+                    peer.name,
+                    err
+                );
+            }
+        }
+    }
+
+    async fn run_query<'a>(
+        &self,
+        nexus_stmt: NexusStatement,
+        routed_in: Duration,
     ) -> PgWireResult<Vec<Response<'a>>> {
+        if let Some(responses) = self.batch_insert(&nexus_stmt).await? {
+            return Ok(responses);
+        }
+        // anything else runs after the INSERTs buffered before it
+        self.insert_batcher.flush().await?;
+        if matches!(
+            nexus_stmt.ast(),
+            Some(Statement::Commit { .. } | Statement::Rollback { .. })
+        ) {
+            // portals don't outlive the transaction they were bound in
+            self.suspended_portals.lock().await.clear();
+        }
+
         match nexus_stmt {
             NexusStatement::PeerDDL { stmt: _, ref ddl } => match ddl.as_ref() {
-                PeerDDL::CreatePeer { peer, .. } => {
-                    self.create_peer(peer).await.map_err(|e| {
+                PeerDDL::CreatePeer { peer, validate, .. } => {
+                    if let Some(config) = &peer.config {
+                        config.validate().map_err(|err| {
+                            PgWireError::UserError(Box::new(ErrorInfo::new(
+                                "ERROR".to_owned(),
+                                err.sqlstate().to_owned(),
+                                format!("invalid config for peer \"{}\": {}", peer.name, err),
+                            )))
+                        })?;
+                    }
+                    if secrets::has_secret_references(peer) {
+                        self.check_admin(&format!(
+                            "use secret references in peer \"{}\"",
+                            peer.name
+                        ))?;
+                    }
+                    // references that don't resolve fail before anything connects
+                    let resolved = self.secrets.resolve_peer(peer).await.map_err(|err| {
                         PgWireError::UserError(Box::new(ErrorInfo::new(
                             "ERROR".to_owned(),
-                            "internal_error".to_owned(),
-                            e.to_string(),
+                            "08001".to_owned(),
+                            format!(
+                                "unable to resolve the secrets of peer \"{}\": {:#}",
+                                peer.name, err
+                            ),
                         )))
                     })?;
+                    if self.flow_handler.is_none() {
+                        return Err(PgWireError::ApiError(
+                            "flow service is not configured".into(),
+                        ));
+                    }
+                    if *validate {
+                        self.check_peer_connection(peer).await?;
+                    }
+                    self.create_peer(peer, &resolved).await.map_err(|err| {
+                        err.downcast::<PgWireError>().unwrap_or_else(|err| {
+                            PgWireError::UserError(Box::new(ErrorInfo::new(
+                                "ERROR".to_owned(),
+                                "XX000".to_owned(),
+                                err.to_string(),
+                            )))
+                        })
+                    })?;
+                    // the peer is usable by the next statement of any session
+                    self.peer_cache.invalidate();
 
-                    Ok(vec![Response::Execution(Tag::new("OK"))])
+                    Ok(vec![Response::Execution(Tag::new("CREATE PEER"))])
+                }
+                PeerDDL::AlterPeer { peer } => {
+                    if let Some(config) = &peer.config {
+                        config.validate().map_err(|err| {
+                            PgWireError::UserError(Box::new(ErrorInfo::new(
+                                "ERROR".to_owned(),
+                                err.sqlstate().to_owned(),
+                                format!("invalid config for peer \"{}\": {}", peer.name, err),
+                            )))
+                        })?;
+                    }
+                    if secrets::has_secret_references(peer) {
+                        self.check_admin(&format!(
+                            "use secret references in peer \"{}\"",
+                            peer.name
+                        ))?;
+                    }
+                    let updated = self.catalog.update_peer_config(peer).await.map_err(|err| {
+                        PgWireError::ApiError(format!("unable to alter peer: {:?}", err).into())
+                    })?;
+                    if !updated {
+                        return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                            "ERROR".to_owned(),
+                            "42704".to_owned(),
+                            format!("peer \"{}\" does not exist", peer.name),
+                        ))));
+                    }
+                    // the next query of every session connects with the new
+                    // config, connections in a transaction are kept until it ends
+                    self.peer_cache.invalidate();
+                    let eviction = self.executor_registry.evict(Some(&peer.name));
+                    tracing::info!(
+                        "ALTER PEER: closed {} connections to peer {}, {} in a transaction",
+                        eviction.evicted,
+                        peer.name,
+                        eviction.in_transaction
+                    );
+                    Ok(vec![Response::Execution(Tag::new("ALTER PEER"))])
                 }
                 PeerDDL::CreateMirrorForCDC {
                     if_not_exists,
@@ -417,17 +1951,13 @@ impl NexusBackend {
                 PeerDDL::DropPeer {
                     if_exists,
                     peer_name,
+                    cascade,
                 } => {
-                    if self.flow_handler.is_none() {
-                        return Err(PgWireError::ApiError(
-                            "flow service is not configured".into(),
-                        ));
-                    }
-
                     tracing::info!(
-                        "DROP PEER: peer_name: {}, if_exists: {}",
+                        "DROP PEER: peer_name: {}, if_exists: {}, cascade: {}",
                         peer_name,
-                        if_exists
+                        if_exists,
+                        cascade
                     );
                     let peer_exists =
                         self.catalog
@@ -441,10 +1971,74 @@ impl NexusBackend {
                             })?;
                     tracing::info!("peer exist count: {}", peer_exists);
                     if peer_exists != 0 {
-                        let mut flow_handler = self.flow_handler.as_ref().unwrap().lock().await;
+                        let mirrors =
+                            self.catalog
+                                .get_mirrors_for_peer(peer_name)
+                                .await
+                                .map_err(|err| {
+                                    PgWireError::ApiError(
+                                        format!("unable to query catalog for mirrors: {:?}", err)
+                                            .into(),
+                                    )
+                                })?;
+                        if !mirrors.is_empty() && !*cascade {
+                            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                                "ERROR".to_owned(),
+                                "2BP01".to_owned(),
+                                format!(
+                                    "cannot drop peer {} because mirrors depend on it: {}, use DROP PEER {} CASCADE to drop them too",
+                                    peer_name,
+                                    mirrors.join(", "),
+                                    peer_name
+                                ),
+                            ))));
+                        }
+
+                        let Some(flow_handler) = self.flow_handler.as_ref() else {
+                            // without a flow service only peers no mirror uses
+                            // can go, straight from the catalog
+                            if !mirrors.is_empty() {
+                                return Err(PgWireError::ApiError(
+                                    "flow service is not configured".into(),
+                                ));
+                            }
+                            self.catalog.drop_peer(peer_name).await.map_err(|err| {
+                                PgWireError::ApiError(
+                                    format!("unable to drop peer: {:?}", err).into(),
+                                )
+                            })?;
+                            self.forget_peer(peer_name);
+                            let drop_peer_success = format!("DROP PEER {}", peer_name);
+                            return Ok(vec![Response::Execution(Tag::new(&drop_peer_success))]);
+                        };
+                        let mut flow_handler = flow_handler.lock().await;
+                        for mirror in &mirrors {
+                            tracing::info!("DROP PEER CASCADE: dropping mirror {}", mirror);
+                            flow_handler
+                                .flow_state_change(
+                                    mirror,
+                                    pt::peerdb_flow::FlowStatus::StatusTerminated,
+                                    None,
+                                )
+                                .await
+                                .map_err(|err| {
+                                    PgWireError::ApiError(
+                                        format!("unable to shutdown flow job: {:?}", err).into(),
+                                    )
+                                })?;
+                            self.catalog
+                                .delete_flow_job_entry(mirror)
+                                .await
+                                .map_err(|err| {
+                                    PgWireError::ApiError(
+                                        format!("unable to delete job metadata: {:?}", err).into(),
+                                    )
+                                })?;
+                        }
                         flow_handler.drop_peer(peer_name).await.map_err(|err| {
                             PgWireError::ApiError(format!("unable to drop peer: {:?}", err).into())
                         })?;
+                        self.forget_peer(peer_name);
                         let drop_peer_success = format!("DROP PEER {}", peer_name);
                         Ok(vec![Response::Execution(Tag::new(&drop_peer_success))])
                     } else if *if_exists {
@@ -453,8 +2047,8 @@ impl NexusBackend {
                     } else {
                         Err(PgWireError::UserError(Box::new(ErrorInfo::new(
                             "ERROR".to_owned(),
-                            "error".to_owned(),
-                            format!("no such peer: {:?}", peer_name),
+                            "42704".to_owned(),
+                            format!("peer \"{}\" does not exist", peer_name),
                         ))))
                     }
                 }
@@ -559,59 +2153,305 @@ impl NexusBackend {
                 }
             },
             NexusStatement::PeerQuery { stmt, assoc } => {
-                // get the query executor
-                let (peer_holder, executor): (Option<_>, Arc<dyn QueryExecutor>) = match assoc {
-                    QueryAssociation::Peer(peer) => {
-                        tracing::info!("handling peer[{}] query: {}", peer.name, stmt);
-                        (
-                            Some(peer.clone()),
-                            self.get_peer_executor(&peer).await.map_err(|err| {
-                                PgWireError::ApiError(
-                                    format!("unable to get peer executor: {:?}", err).into(),
-                                )
-                            })?,
+                if let Statement::Copy { target, .. } = &stmt {
+                    check_copy_target(target)?;
+                }
+                if let QueryAssociation::Peer(peer) = &assoc {
+                    Self::check_returning(&stmt, peer)?;
+                }
+                let labels = self.session.lock().await.query_labels().to_string();
+                let labels = if labels.is_empty() {
+                    labels
+                } else {
+                    format!(" [{}]", labels)
+                };
+                match &assoc {
+                    QueryAssociation::Peer(peer) => tracing::info!(
+                        peer = %peer.name,
+                        kind = Self::statement_kind(&stmt),
+                        "handling peer[{}] query{}: {}",
+                        peer.name,
+                        labels,
+                        stmt
+                    ),
+                    QueryAssociation::Catalog => tracing::info!(
+                        kind = Self::statement_kind(&stmt),
+                        "handling catalog query{}: {}",
+                        labels,
+                        stmt
+                    ),
+                }
+                let acquisition_started = Instant::now();
+                let (peer_holder, executor) = self.statement_executor(&stmt, &assoc).await?;
+                if let Some(peer) = &peer_holder {
+                    Self::check_row_locks(&stmt, peer, executor.as_ref())?;
+                }
+                let timing = NexusTiming {
+                    routing: routed_in,
+                    executor_acquisition: acquisition_started.elapsed(),
+                    ..Default::default()
+                };
+                let dry_run = self.session.lock().await.dry_run();
+
+                let res = match &stmt {
+                    Statement::ExplainTable { table_name, .. } => {
+                        self.describe_table(executor.as_ref(), table_name, peer_holder.as_deref())
+                            .await
+                    }
+                    Statement::Copy {
+                        source,
+                        to: true,
+                        target: CopyTarget::Stdout,
+                        options,
+                        legacy_options,
+                        ..
+                    } => {
+                        self.copy_to_stdout(
+                            executor.as_ref(),
+                            source,
+                            options,
+                            legacy_options,
+                            peer_holder,
                         )
+                        .await
                     }
-                    QueryAssociation::Catalog => {
-                        tracing::info!("handling catalog query: {}", stmt);
-                        (None, self.catalog.clone())
+                    Statement::Explain {
+                        statement,
+                        analyze: true,
+                        ..
+                    } if !dry_run
+                        && !peer_holder.as_deref().is_some_and(Self::analyzes_explain) =>
+                    {
+                        self.explain_analyze(
+                            executor.as_ref(),
+                            statement,
+                            peer_holder.as_deref(),
+                            timing,
+                        )
+                        .await
+                    }
+                    _ => {
+                        self.execute_statement(executor.as_ref(), &stmt, peer_holder)
+                            .await
                     }
                 };
-
-                let res = self
-                    .execute_statement(executor.as_ref(), &stmt, peer_holder)
-                    .await;
                 // log the error if execution failed
                 if let Err(err) = &res {
                     tracing::error!("query execution failed: {:?}", err);
                 }
-                res
+                res
+            }
+
+            NexusStatement::PeerCursor { stmt, cursor } => {
+                if matches!(cursor, analyzer::CursorEvent::CloseAll) {
+                    let cursors = self.peer_cursors.lock().await.close_all();
+                    self.close_peer_cursors(cursors).await;
+                    // the cursors declared without a peer are the catalog's
+                    self.execute_statement(self.catalog.as_ref(), &stmt, None)
+                        .await?;
+                    return Ok(vec![Response::Execution(Tag::new("CLOSE CURSOR ALL"))]);
+                }
+                let executor = {
+                    let mut peer_cursors = self.peer_cursors.lock().await;
+                    let name = match &cursor {
+                        analyzer::CursorEvent::Fetch(c, _)
+                        | analyzer::CursorEvent::Move(c, _)
+                        | analyzer::CursorEvent::Close(c) => Some(c),
+                        analyzer::CursorEvent::CloseAll => None,
+                    };
+                    if let Some(name) = name {
+                        let forget = matches!(cursor, analyzer::CursorEvent::Close(_));
+                        if peer_cursors.closed_with_transaction(name, forget) {
+                            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                                "ERROR".to_owned(),
+                                "34000".to_owned(),
+                                format!(
+                                    "cursor \"{}\" was closed at the end of its transaction, \
+                                     declare it WITH HOLD to keep it open",
+                                    name
+                                ),
+                            ))));
+                        }
+                    }
+                    let peer = match &cursor {
+                        analyzer::CursorEvent::Fetch(c, _) | analyzer::CursorEvent::Move(c, _) => {
+                            peer_cursors.get_peer(c)
+                        }
+                        analyzer::CursorEvent::Close(c) => peer_cursors.get_peer(c),
+                        analyzer::CursorEvent::CloseAll => None,
+                    };
+                    match peer {
+                        None => self.catalog.clone(),
+                        Some(peer) => self.get_peer_executor(peer).await?,
+                    }
+                };
+
+                if let analyzer::CursorEvent::Move(name, direction) = &cursor {
+                    let moved = self
+                        .with_statement_timeout(executor.move_cursor(name, direction))
+                        .await?;
+                    return Ok(vec![Response::Execution(Tag::new("MOVE").with_rows(moved))]);
+                }
+                self.execute_statement(executor.as_ref(), &stmt, None).await
+            }
+
+            NexusStatement::Builtin { stmt: _, builtin } => self.handle_builtin(&builtin).await,
+
+            NexusStatement::SetVariable {
+                stmt: _,
+                name,
+                value,
+            } => self.handle_set_variable(&name, value.as_deref()).await,
+
+            NexusStatement::ResetVariable { name } => {
+                self.handle_reset_variable(name.as_deref()).await
+            }
+
+            NexusStatement::SetCharacteristics { stmt: _, settings } => {
+                for (name, value) in settings {
+                    self.set_variable(&name, Some(&value)).await?;
+                }
+                Ok(vec![Response::Execution(Tag::new("SET"))])
+            }
+
+            NexusStatement::ShowVariable { stmt: _, name } if name == "all" => {
+                let rows = self.session.lock().await.show_all();
+                let schema = Self::show_all_schema();
+                let records = rows
+                    .into_iter()
+                    .map(|(name, setting, description)| Record {
+                        values: vec![
+                            value::Value::Text(name),
+                            value::Value::Text(setting),
+                            value::Value::Text(description),
+                        ],
+                        schema: schema.clone(),
+                    })
+                    .collect();
+                Ok(vec![records_to_query_response(Records {
+                    records,
+                    schema,
+                })?])
+            }
+
+            NexusStatement::ShowVariable { stmt, name } => {
+                let value = self.session.lock().await.show(&name);
+                match value {
+                    Some(value) => {
+                        let schema = Self::show_variable_schema(&name);
+                        let records = Records {
+                            records: vec![Record {
+                                values: vec![value::Value::Text(value)],
+                                schema: schema.clone(),
+                            }],
+                            schema,
+                        };
+                        Ok(vec![records_to_query_response(records)?])
+                    }
+                    // not a setting nexus knows about, let the catalog report it
+                    None => {
+                        self.execute_statement(self.catalog.as_ref(), &stmt, None)
+                            .await
+                    }
+                }
+            }
+
+            NexusStatement::Rollback { stmt } => {
+                let (peer_holder, executor) = self
+                    .statement_executor(&stmt, &QueryAssociation::Catalog)
+                    .await?;
+                self.execute_statement(executor.as_ref(), &stmt, peer_holder)
+                    .await
+            }
+
+            NexusStatement::Import { import } => {
+                tracing::info!(
+                    "handling peer[{}] import into {}",
+                    import.peer.name,
+                    import.table
+                );
+                let executor = self.get_peer_executor(&import.peer).await?;
+
+                let CsvImport {
+                    table,
+                    columns,
+                    header,
+                    delimiter,
+                    data,
+                    ..
+                } = *import;
+                let format = BulkLoadFormat::Csv { header, delimiter };
+                let data: ByteStream =
+                    Box::pin(futures::stream::once(async move { Ok(Bytes::from(data)) }));
+                let res = self
+                    .with_statement_timeout(executor.bulk_load(&table, &columns, format, data))
+                    .await;
+                match res {
+                    Ok(QueryOutput::AffectedRows(rows)) => Ok(vec![Response::Execution(
+                        Tag::new("IMPORT").with_rows(rows),
+                    )]),
+                    Ok(res) => self.query_output_to_responses(res, None).await,
+                    Err(err) => {
+                        tracing::error!("import failed: {:?}", err);
+                        Err(err)
+                    }
+                }
             }
 
-            NexusStatement::PeerCursor { stmt, cursor } => {
-                let executor = {
-                    let peer_cursors = self.peer_cursors.lock().await;
-                    let peer = match cursor {
-                        analyzer::CursorEvent::Fetch(c, _) => peer_cursors.get_peer(&c),
-                        analyzer::CursorEvent::CloseAll => todo!("close all cursors"),
-                        analyzer::CursorEvent::Close(c) => peer_cursors.get_peer(&c),
-                    };
-                    match peer {
-                        None => self.catalog.clone(),
-                        Some(peer) => self.get_peer_executor(peer).await.map_err(|err| {
-                            PgWireError::ApiError(
-                                format!("unable to get peer executor: {:?}", err).into(),
-                            )
-                        })?,
-                    }
-                };
+            NexusStatement::Compare { left, right, key } => {
+                tracing::info!("comparing {} with {}", left.stmt, right.stmt);
+                let records = self
+                    .with_statement_timeout(self.compare(&left, &right, key))
+                    .await?;
+                Ok(vec![records_to_query_response(records)?])
+            }
 
-                self.execute_statement(executor.as_ref(), &stmt, None).await
+            NexusStatement::ExportSchema { peer, schema } => {
+                tracing::info!("exporting schema of peer[{}]", peer.name);
+                let executor = self.get_peer_executor(&peer).await?;
+                let res = self
+                    .with_statement_timeout(executor.export_schema(schema.as_deref()))
+                    .await?;
+                self.query_output_to_responses(res, None).await
             }
 
-            NexusStatement::Rollback { stmt } => {
-                self.execute_statement(self.catalog.as_ref(), &stmt, None)
+            NexusStatement::ShowPeers { pattern } => {
+                let peers = self
+                    .catalog
+                    .list_peers(pattern.as_deref())
                     .await
+                    .map_err(|err| {
+                        PgWireError::ApiError(format!("unable to list peers: {}", err).into())
+                    })?;
+                Ok(vec![records_to_query_response(show_peers::records(peers))?])
+            }
+
+            NexusStatement::TestPeer { peer } => {
+                tracing::info!("testing peer[{}]", peer.name);
+                let result = self.test_peer(&peer).await;
+                Ok(vec![records_to_query_response(peer_test::records(result))?])
+            }
+
+            NexusStatement::CreateTempView {
+                stmt: _,
+                name,
+                columns,
+                query,
+                or_replace,
+            } => {
+                tracing::info!("creating temporary view {}: {}", name, query);
+                self.query_parser
+                    .create_temp_view(&name, columns, *query, or_replace)?;
+                Ok(vec![Response::Execution(Tag::new("CREATE VIEW"))])
+            }
+
+            NexusStatement::DropTempViews {
+                stmt: _,
+                names,
+                if_exists,
+            } => {
+                self.query_parser.drop_temp_views(&names, if_exists)?;
+                Ok(vec![Response::Execution(Tag::new("DROP VIEW"))])
             }
 
             NexusStatement::Empty => Ok(vec![Response::EmptyQuery]),
@@ -644,150 +2484,665 @@ impl NexusBackend {
         Ok(workflow_id)
     }
 
-    async fn get_peer_executor(&self, peer: &Peer) -> anyhow::Result<Arc<dyn QueryExecutor>> {
-        Ok(match self.executors.entry(peer.name.clone()) {
+    // a single attempt at creating the executor for a peer, connecting to it.
+    // Secrets the peer refers to are fetched again after a failed attempt, in
+    // case they were rotated.
+    async fn connect_peer_executor(&self, peer: &Peer) -> anyhow::Result<Arc<dyn QueryExecutor>> {
+        let resolved = self.secrets.resolve_peer(peer).await?;
+        let executor = self.connect_resolved_peer_executor(&resolved).await;
+        if executor.is_err() {
+            self.secrets.invalidate(peer);
+        }
+        executor
+    }
+
+    async fn connect_resolved_peer_executor(
+        &self,
+        peer: &Peer,
+    ) -> anyhow::Result<Arc<dyn QueryExecutor>> {
+        let executor: Arc<dyn QueryExecutor> = match &peer.config {
+            Some(Config::BigqueryConfig(ref c)) => {
+                let executor = peer_bigquery::BigQueryQueryExecutor::new(
+                    peer.name.clone(),
+                    c,
+                    self.peer_connections.clone(),
+                )
+                .await?;
+                Arc::new(executor)
+            }
+            Some(Config::ClickhouseConfig(ref c)) => {
+                let executor =
+                    peer_clickhouse::ClickhouseQueryExecutor::new(peer.name.clone(), c).await?;
+                Arc::new(executor)
+            }
+            Some(Config::MysqlConfig(ref c)) => {
+                let executor = peer_mysql::MySqlQueryExecutor::new(
+                    peer.name.clone(),
+                    c,
+                    self.executor_config.invalid_utf8,
+                )
+                .await?;
+                Arc::new(executor)
+            }
+            Some(Config::PostgresConfig(ref c)) => {
+                let executor = peer_postgres::PostgresQueryExecutor::with_pool(
+                    peer.name.clone(),
+                    c,
+                    self.executor_config.invalid_utf8,
+                    self.executor_registry.postgres_pools(),
+                )
+                .await?;
+                Arc::new(executor)
+            }
+            Some(Config::SnowflakeConfig(ref c)) => {
+                let executor = peer_snowflake::SnowflakeQueryExecutor::new(c).await?;
+                Arc::new(executor)
+            }
+            _ => anyhow::bail!(
+                "peers of type {} can't be queried by nexus",
+                peer.r#type().as_str_name()
+            ),
+        };
+        Ok(executor)
+    }
+
+    // connects to the peer anew, or checks a connection out of its pool, and
+    // runs the probe of `TEST PEER`, failures are reported in the result
+    // rather than as an error.
+    async fn test_peer(&self, peer: &Peer) -> PeerTestResult {
+        let started = Instant::now();
+        let outcome = self
+            .with_statement_timeout(async {
+                if !peer_types::capabilities(peer).queryable {
+                    return Err(PgWireError::ApiError(
+                        format!(
+                            "peers of type {} can't be queried by nexus",
+                            peer.r#type().as_str_name()
+                        )
+                        .into(),
+                    ));
+                }
+                let executor = self
+                    .connect_peer_executor(peer)
+                    .await
+                    .map_err(|err| PgWireError::ApiError(format!("{:#}", err).into()))?;
+                executor.test_connection().await
+            })
+            .await;
+        PeerTestResult {
+            latency: started.elapsed(),
+            error: outcome.err().map(peer_test::error_message),
+        }
+    }
+
+    // connects to a peer about to be created and checks it answers queries,
+    // for CREATE PEER. The types nexus can't query are left to the flow
+    // service. The connection isn't pooled, a pool is made once the peer
+    // exists.
+    async fn check_peer_connection(&self, peer: &Peer) -> PgWireResult<()> {
+        if !peer_types::capabilities(peer).queryable {
+            return Ok(());
+        }
+        let mut probed = peer.clone();
+        if let Some(Config::PostgresConfig(config)) = &mut probed.config {
+            config.pool_max_size = None;
+        }
+        self.with_statement_timeout(async {
+            let executor = self
+                .connect_peer_executor(&probed)
+                .await
+                .map_err(|err| PgWireError::ApiError(format!("{:#}", err).into()))?;
+            executor.test_connection().await
+        })
+        .await
+        .map_err(|err| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "08001".to_owned(),
+                format!(
+                    "unable to connect to peer \"{}\": {}, create it WITH (validate = false) \
+                    to skip this check",
+                    peer.name,
+                    peer_test::error_message(err)
+                ),
+            )))
+        })
+    }
+
+    /// Drops the cached executor of the peer, its connection is closed, or
+    /// handed back to the peer's pool, once no running query uses it anymore.
+    pub fn invalidate_executor(&self, peer_name: &str) {
+        self.executors.remove(peer_name);
+        self.executors_used.remove(peer_name);
+    }
+
+    // closes the least recently used executors over max_cached, other than
+    // the one of the peer just asked for and those in a transaction
+    fn evict_executors(&self, keep: &str) {
+        let Some(max_cached) = self.executor_config.max_cached else {
+            return;
+        };
+        // executors dropped elsewhere, e.g. by peerdb.reconnect_all()
+        self.executors_used
+            .retain(|name, _| self.executors.contains_key(name));
+        let excess = self.executors.len().saturating_sub(max_cached);
+        if excess == 0 {
+            return;
+        }
+        let mut idle = self
+            .executors
+            .iter()
+            .filter(|executor| executor.key() != keep && !executor.value().in_transaction())
+            .map(|executor| executor.key().clone())
+            .collect::<Vec<_>>();
+        idle.sort_by_key(|name| self.executors_used.get(name).map(|used| *used));
+        for name in idle.into_iter().take(excess) {
+            tracing::info!(
+                "closing the connection to peer {}, more than {} peer connections are open",
+                name,
+                max_cached
+            );
+            self.invalidate_executor(&name);
+        }
+    }
+
+    // connects to the peer, retrying as connect_retry says. A peer of a type
+    // nexus can't query fails right away, one it can't reach with 08001.
+    async fn connect_peer_with_retry(&self, peer: &Peer) -> PgWireResult<Arc<dyn QueryExecutor>> {
+        if !peer_types::capabilities(peer).queryable {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "0A000".to_owned(),
+                format!(
+                    "peers of type {} can't be queried by nexus",
+                    peer.r#type().as_str_name()
+                ),
+            ))));
+        }
+        self.executor_config
+            .connect_retry
+            .run(&peer.name, || self.connect_peer_executor(peer))
+            .await
+            .map_err(|err| {
+                err.downcast::<PgWireError>().unwrap_or_else(|err| {
+                    PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "ERROR".to_owned(),
+                        "08001".to_owned(),
+                        format!("unable to connect to peer \"{}\": {:#}", peer.name, err),
+                    )))
+                })
+            })
+    }
+
+    // A connection the peer closed between statements is replaced by a new
+    // one, with the session's forwarded variables applied again, unless a
+    // transaction was open on it.
+    async fn get_peer_executor(&self, peer: &Peer) -> PgWireResult<Arc<dyn QueryExecutor>> {
+        let lost = self
+            .executors
+            .remove_if(&peer.name, |_, executor| executor.is_closed());
+        if let Some((_, executor)) = lost {
+            if executor.in_transaction() {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "08006".to_owned(),
+                    format!(
+                        "connection to peer \"{}\" was lost in a transaction block, \
+                        the transaction was rolled back",
+                        peer.name
+                    ),
+                ))));
+            }
+            tracing::warn!("connection to peer {} was lost, reconnecting", peer.name);
+        }
+        self.executors_used
+            .insert(peer.name.clone(), Instant::now());
+
+        let executor = match self.executors.entry(peer.name.clone()) {
             DashEntry::Occupied(entry) => Arc::clone(entry.get()),
             DashEntry::Vacant(entry) => {
-                let executor: Arc<dyn QueryExecutor> = match &peer.config {
-                    Some(Config::BigqueryConfig(ref c)) => {
-                        let executor = peer_bigquery::BigQueryQueryExecutor::new(
-                            peer.name.clone(),
-                            c,
-                            self.peer_connections.clone(),
-                        )
-                        .await?;
-                        Arc::new(executor)
-                    }
-                    Some(Config::MysqlConfig(ref c)) => {
-                        let executor =
-                            peer_mysql::MySqlQueryExecutor::new(peer.name.clone(), c).await?;
-                        Arc::new(executor)
-                    }
-                    Some(Config::PostgresConfig(ref c)) => {
-                        let executor =
-                            peer_postgres::PostgresQueryExecutor::new(peer.name.clone(), c).await?;
-                        Arc::new(executor)
-                    }
-                    Some(Config::SnowflakeConfig(ref c)) => {
-                        let executor = peer_snowflake::SnowflakeQueryExecutor::new(c).await?;
-                        Arc::new(executor)
-                    }
-                    _ => {
-                        panic!("peer type not supported: {:?}", peer)
-                    }
-                };
+                tracing::debug!(peer = %peer.name, "connecting to peer");
+                let executor = self.connect_peer_with_retry(peer).await?;
+
+                let forwarded = self.session.lock().await.forwarded_parameters();
+                for (name, value) in forwarded {
+                    executor.set_session_parameter(name, &value).await?;
+                }
 
                 entry.insert(Arc::clone(&executor));
                 executor
             }
-        })
+        };
+        self.evict_executors(&peer.name);
+        Ok(executor)
     }
 
     async fn do_describe(&self, stmt: &NexusParsedStatement) -> PgWireResult<Option<Schema>> {
-        tracing::info!("[eqp] do_describe: {}", stmt.query);
+        tracing::debug!(query = %stmt.query, "describing statement");
         let stmt = &stmt.statement;
+        if let NexusStatement::PeerQuery {
+            stmt: Statement::Copy { target, .. },
+            ..
+        } = stmt
+        {
+            check_copy_target(target)?;
+        }
         match stmt {
             NexusStatement::PeerDDL { .. } => Ok(None),
             NexusStatement::PeerCursor { .. } => Ok(None),
+            NexusStatement::Import { .. } => Ok(None),
+            NexusStatement::TestPeer { .. } => Ok(Some(peer_test::schema())),
+            NexusStatement::ShowPeers { .. } => Ok(Some(show_peers::schema())),
+            NexusStatement::ExportSchema { .. } => Ok(Some(export_schema_schema())),
+            NexusStatement::Compare { .. } => Ok(Some(compare::schema())),
+            NexusStatement::CreateTempView { .. } => Ok(None),
+            NexusStatement::DropTempViews { .. } => Ok(None),
             NexusStatement::Empty => Ok(None),
             NexusStatement::Rollback { .. } => Ok(None),
+            NexusStatement::Builtin { builtin, .. } => {
+                Ok(Some(self.builtin_schema(builtin).await?))
+            }
+            NexusStatement::SetVariable { .. } => Ok(None),
+            NexusStatement::ResetVariable { .. } => Ok(None),
+            NexusStatement::SetCharacteristics { .. } => Ok(None),
+            NexusStatement::ShowVariable { name, .. } if name == "all" => {
+                Ok(Some(Self::show_all_schema()))
+            }
+            NexusStatement::ShowVariable { name, .. } => Ok(Some(Self::show_variable_schema(name))),
+            NexusStatement::PeerQuery {
+                stmt: Statement::ExplainTable { .. },
+                ..
+            } => Ok(Some(describe_table_schema())),
+            NexusStatement::PeerQuery { stmt, .. }
+                if Self::is_dry_run_target(stmt) && self.session.lock().await.dry_run() =>
+            {
+                Ok(Some(dry_run_schema()))
+            }
+            NexusStatement::PeerQuery {
+                stmt: Statement::Explain { analyze: true, .. },
+                assoc,
+            } if !matches!(assoc, QueryAssociation::Peer(peer) if Self::analyzes_explain(peer)) => {
+                Ok(Some(explain_schema()))
+            }
+            // the rows are sent as CopyData, not as data rows
+            NexusStatement::PeerQuery {
+                stmt:
+                    Statement::Copy {
+                        to: true,
+                        target: CopyTarget::Stdout,
+                        ..
+                    },
+                ..
+            } => Ok(None),
             NexusStatement::PeerQuery { stmt, assoc } => {
                 let schema: Option<Schema> = match assoc {
-                    QueryAssociation::Peer(peer) => match &peer.config {
-                        Some(Config::BigqueryConfig(_)) => {
-                            let executor = self.get_peer_executor(peer).await.map_err(|err| {
-                                PgWireError::ApiError(
-                                    format!("unable to get peer executor: {:?}", err).into(),
-                                )
-                            })?;
-                            executor.describe(stmt).await?
-                        }
-                        Some(Config::MysqlConfig(_)) => {
-                            let executor = self.get_peer_executor(peer).await.map_err(|err| {
-                                PgWireError::ApiError(
-                                    format!("unable to get peer executor: {:?}", err).into(),
-                                )
-                            })?;
-                            executor.describe(stmt).await?
-                        }
-                        Some(Config::PostgresConfig(_)) => {
-                            let executor = self.get_peer_executor(peer).await.map_err(|err| {
-                                PgWireError::ApiError(
-                                    format!("unable to get peer executor: {:?}", err).into(),
-                                )
-                            })?;
-                            executor.describe(stmt).await?
-                        }
-                        Some(Config::SnowflakeConfig(_)) => {
-                            let executor = self.get_peer_executor(peer).await.map_err(|err| {
-                                PgWireError::ApiError(
-                                    format!("unable to get peer executor: {:?}", err).into(),
-                                )
-                            })?;
-                            executor.describe(stmt).await?
-                        }
-                        _ => {
-                            panic!("peer type not supported: {:?}", peer)
-                        }
-                    },
+                    QueryAssociation::Peer(peer) => {
+                        self.get_peer_executor(peer).await?.describe(stmt).await?
+                    }
                     QueryAssociation::Catalog => self.catalog.describe(stmt).await?,
                 };
+                let schema = match assoc {
+                    QueryAssociation::Peer(peer) if !Self::names_columns_like_postgres(peer) => {
+                        schema.map(|schema| postgres_column_names(stmt, &schema))
+                    }
+                    _ => schema,
+                };
 
                 Ok(if self.peerdb_fdw_mode { None } else { schema })
             }
         }
     }
-}
 
-#[async_trait]
-impl SimpleQueryHandler for NexusBackend {
-    async fn do_query<'a, C>(
+    /// Types of the parameters of a prepared statement. Types the client left
+    /// unspecified (oid 0, or missing) are inferred by the peer the statement
+    /// runs on, and sent as text when it can't infer them. They are inferred
+    /// once and kept with the statement for its later Describes and Executes.
+    async fn parameter_types(
         &self,
-        _client: &mut C,
+        stmt: &StoredStatement<NexusParsedStatement>,
+    ) -> PgWireResult<Vec<Type>> {
+        if let Some(types) = stmt.statement.parameter_types.get() {
+            return Ok(types.clone());
+        }
+        let types = self.infer_parameter_types(stmt).await?;
+        Ok(stmt.statement.parameter_types.get_or_init(|| types).clone())
+    }
+
+    async fn infer_parameter_types(
+        &self,
+        stmt: &StoredStatement<NexusParsedStatement>,
+    ) -> PgWireResult<Vec<Type>> {
+        let declared = &stmt.parameter_types;
+        let count = stmt
+            .statement
+            .statement
+            .ast()
+            .map_or(0, parameter_count)
+            .max(declared.len());
+        if declared.len() == count && !declared.contains(&Type::UNKNOWN) {
+            return Ok(declared.clone());
+        }
+
+        let inferred = match &stmt.statement.statement {
+            NexusStatement::PeerQuery { stmt, assoc } => {
+                tracing::debug!(query = %stmt, "inferring parameter types");
+                let (_, executor) = self.query_executor(assoc).await?;
+                executor.parameter_types(stmt).await?
+            }
+            _ => None,
+        }
+        .unwrap_or_default();
+
+        Ok((0..count)
+            .map(|idx| match declared.get(idx) {
+                Some(declared) if *declared != Type::UNKNOWN => declared.clone(),
+                _ => inferred.get(idx).cloned().unwrap_or(Type::TEXT),
+            })
+            .collect())
+    }
+
+    // runs a query for a portal executed with a row limit, the rows past the
+    // limit are kept for the portal's next Execute.
+    async fn execute_portal<'a>(
+        &self,
+        portal: &Portal<NexusParsedStatement>,
+        stmt: &Statement,
+        assoc: &QueryAssociation,
+        max_rows: usize,
+    ) -> PgWireResult<Response<'a>> {
+        self.insert_batcher.flush().await?;
+        let (peer_holder, executor) = self.statement_executor(stmt, assoc).await?;
+        if let Some(peer) = &peer_holder {
+            Self::check_row_locks(stmt, peer, executor.as_ref())?;
+        }
+        let output = self
+            .run_statement(executor.as_ref(), stmt, peer_holder.as_deref())
+            .await?;
+        let suspended = match output {
+            QueryOutput::Stream(stream) => {
+                SuspendedPortal::new(portal, stream.schema(), stream, &self.memory)
+            }
+            QueryOutput::Records(records) => SuspendedPortal::new(
+                portal,
+                records.schema,
+                futures::stream::iter(records.records.into_iter().map(Ok)),
+                &self.memory,
+            ),
+            output => {
+                let responses = self.query_output_to_responses(output, peer_holder).await?;
+                if let Some(cursor) = Self::held_cursor(stmt) {
+                    self.peer_cursors.lock().await.hold_cursor(cursor);
+                }
+                return single_response(responses);
+            }
+        };
+        self.fetch_portal(portal, suspended, max_rows).await
+    }
+
+    async fn fetch_portal<'a>(
+        &self,
+        portal: &Portal<NexusParsedStatement>,
+        mut suspended: SuspendedPortal,
+        max_rows: usize,
+    ) -> PgWireResult<Response<'a>> {
+        let records = self.with_statement_timeout(suspended.fetch(max_rows)).await;
+        self.suspended_portals
+            .lock()
+            .await
+            .insert(portal.name.clone(), suspended);
+        records_to_query_response(records?)
+    }
+
+    async fn simple_query<'a, C>(
+        &self,
+        client: &mut C,
         sql: &'a str,
     ) -> PgWireResult<Vec<Response<'a>>>
     where
-        C: ClientInfo + Unpin + Send + Sync,
+        C: Sink<PgWireBackendMessage> + Unpin + Send,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
+        let started = Instant::now();
         let parsed = self.query_parser.parse_simple_sql(sql).await?;
-        let nexus_stmt = parsed.statement;
-        self.handle_query(nexus_stmt).await
+        self.session
+            .lock()
+            .await
+            .set_timeout_hint(parsed.timeout_hint.as_deref())?;
+        let mut nexus_stmt = parsed.statement;
+        self.apply_auto_limit(client, &mut nexus_stmt).await?;
+        self.apply_result_format(&mut nexus_stmt).await;
+        self.handle_query(nexus_stmt, started.elapsed()).await
+    }
+
+    async fn extended_query<'a, C>(
+        &self,
+        client: &mut C,
+        portal: &'a Portal<NexusParsedStatement>,
+        max_rows: usize,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: Sink<PgWireBackendMessage> + Unpin + Send,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let stmt = &portal.statement.statement;
+        tracing::debug!(query = %stmt.query, max_rows, "executing portal");
+
+        // a portal that stopped at its row limit continues where it stopped,
+        // without a limit a drained one is taken to be bound again.
+        let suspended = self
+            .suspended_portals
+            .lock()
+            .await
+            .remove(&portal.name)
+            .filter(|suspended| {
+                suspended.is_bound_to(portal) && (max_rows > 0 || !suspended.is_drained())
+            });
+        if let Some(suspended) = suspended {
+            return self.fetch_portal(portal, suspended, max_rows).await;
+        }
+
+        if let Some(parameter_log) = &self.parameter_log {
+            tracing::info!(portal = %portal.name, "parameters: {}", parameter_log.describe(portal));
+        }
+
+        // manually replace variables in prepared statement
+        let started = Instant::now();
+        let parameter_types = self.parameter_types(&portal.statement).await?;
+        let values = (0..portal.parameter_len())
+            .map(|i| {
+                let param_type = parameter_types.get(i).unwrap_or(&Type::TEXT);
+                parameter_to_string(portal, i, param_type)
+            })
+            .collect::<PgWireResult<Vec<_>>>()?;
+        let sql = placeholders::bind(&stmt.query, &values);
+
+        let parsed = self.query_parser.parse_simple_sql(&sql).await?;
+        self.session
+            .lock()
+            .await
+            .set_timeout_hint(parsed.timeout_hint.as_deref())?;
+        let mut nexus_stmt = parsed.statement;
+        self.apply_auto_limit(client, &mut nexus_stmt).await?;
+        match &nexus_stmt {
+            NexusStatement::PeerQuery {
+                stmt: query @ Statement::Query(_),
+                assoc,
+            } if max_rows > 0 && !self.session.lock().await.dry_run() => {
+                return self.execute_portal(portal, query, assoc, max_rows).await;
+            }
+            // the rows of virtual tables and async_result() are handed out
+            // in turns just the same
+            NexusStatement::Builtin { builtin, .. } if max_rows > 0 => {
+                let records = self.builtin_records(builtin).await?;
+                let suspended = SuspendedPortal::new(
+                    portal,
+                    records.schema,
+                    futures::stream::iter(records.records.into_iter().map(Ok)),
+                    &self.memory,
+                );
+                return self.fetch_portal(portal, suspended, max_rows).await;
+            }
+            _ => (),
+        }
+        let result = self.handle_query(nexus_stmt, started.elapsed()).await?;
+        single_response(result)
+    }
+
+    // as a query starts, a transaction rolled back for idling ends the
+    // transaction block it was pinned by.
+    async fn query_started(&self) -> PgWireResult<()> {
+        let res = self.idle_transactions.query_started().await;
+        if res.is_err() {
+            self.transaction.lock().await.take();
+        }
+        res
+    }
+
+    // once the response of a query was sent, a transaction left open on a
+    // peer is watched for idling past idle_in_transaction_session_timeout.
+    // The rows of the last result hold off the watch until they were sent.
+    async fn watch_idle_transactions(&self, responses: &mut [Response<'_>]) {
+        let timeout = self.session.lock().await.idle_in_transaction_timeout();
+        let sent = self
+            .idle_transactions
+            .query_finished(timeout, &self.executors)
+            .await;
+        let last_rows = responses
+            .iter_mut()
+            .rev()
+            .find_map(|response| match response {
+                Response::Query(query) => Some(query.data_rows()),
+                _ => None,
+            });
+        if let Some(rows) = last_rows {
+            let unsent = std::mem::replace(rows, futures::stream::empty().boxed());
+            *rows = sent.hold_until_sent(unsent);
+        }
+    }
+}
+
+// the response of an Execute, which carries the results of one statement.
+// Statements answered with several results fail rather than lose all but
+// the first.
+fn single_response(mut responses: Vec<Response<'_>>) -> PgWireResult<Response<'_>> {
+    match responses.len() {
+        0 => Ok(Response::EmptyQuery),
+        1 => Ok(responses.remove(0)),
+        results => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "0A000".to_owned(),
+            format!(
+                "the statement returned {} results, the extended query protocol carries one",
+                results
+            ),
+        )))),
+    }
+}
+
+// the highest `$n` placeholder of the statement.
+fn parameter_count(stmt: &Statement) -> usize {
+    let mut count = 0;
+    visit_expressions(stmt, |expr| {
+        if let Expr::Value(Value::Placeholder(placeholder)) = expr {
+            if let Some(n) = placeholder
+                .strip_prefix('$')
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                count = count.max(n);
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    count
+}
+
+#[async_trait]
+impl SimpleQueryHandler for NexusBackend {
+    async fn do_query<'a, C>(&self, client: &mut C, sql: &'a str) -> PgWireResult<Vec<Response<'a>>>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: std::fmt::Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        self.remember_client_user(client);
+        self.query_started().await?;
+        let mut res = self
+            .simple_query(client, sql)
+            .instrument(tracing::info_span!("query", protocol = "simple"))
+            .await;
+        let responses = res.as_deref_mut().unwrap_or_default();
+        self.watch_idle_transactions(responses).await;
+        res.map_err(client_error)
     }
 }
 
-fn parameter_to_string(portal: &Portal<NexusParsedStatement>, idx: usize) -> PgWireResult<String> {
-    // the index is managed from portal's parameters count so it's safe to
-    // unwrap here.
-    let param_type = portal.statement.parameter_types.get(idx).unwrap();
+fn parameter_to_string(
+    portal: &Portal<NexusParsedStatement>,
+    idx: usize,
+    param_type: &Type,
+) -> PgWireResult<String> {
     match param_type {
-        &Type::VARCHAR | &Type::TEXT => Ok(format!(
-            "'{}'",
-            portal
-                .parameter::<String>(idx, param_type)?
-                .map(|s| s.replace('\'', "''"))
-                .as_deref()
-                .unwrap_or("")
-        )),
+        &Type::VARCHAR | &Type::TEXT => Ok(portal
+            .parameter::<String>(idx, param_type)?
+            .map_or_else(|| "NULL".to_owned(), |s| placeholders::quote_literal(&s))),
         &Type::BOOL => Ok(portal
             .parameter::<bool>(idx, param_type)?
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "".to_owned())),
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
         &Type::INT4 => Ok(portal
             .parameter::<i32>(idx, param_type)?
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "".to_owned())),
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
         &Type::INT8 => Ok(portal
             .parameter::<i64>(idx, param_type)?
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "".to_owned())),
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
         &Type::FLOAT4 => Ok(portal
             .parameter::<f32>(idx, param_type)?
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "".to_owned())),
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
         &Type::FLOAT8 => Ok(portal
             .parameter::<f64>(idx, param_type)?
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "".to_owned())),
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
+        // Decimal prints every digit of its scale and never an exponent
+        &Type::NUMERIC => Ok(portal
+            .parameter::<Decimal>(idx, param_type)?
+            .map_or_else(|| "NULL".to_owned(), |v| v.to_string())),
+        // a value sent as text can be passed on as a literal for postgres to
+        // cast, whatever its type
+        _ if !portal.parameter_format.is_binary(idx) => Ok(portal
+            .parameter::<String>(idx, &Type::TEXT)?
+            .map_or_else(|| "NULL".to_owned(), |s| placeholders::quote_literal(&s))),
+        // binary dates and times are rendered as ISO 8601 literals, read the
+        // same by postgres whatever its DateStyle and by bigquery. Timestamps
+        // with a time zone are given in UTC so the session's TimeZone doesn't
+        // shift them.
+        &Type::TIMESTAMP => Ok(portal
+            .parameter::<NaiveDateTime>(idx, param_type)?
+            .map_or_else(
+                || "NULL".to_owned(),
+                |v| placeholders::quote_literal(&v.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+            )),
+        &Type::TIMESTAMPTZ => Ok(portal
+            .parameter::<DateTime<Utc>>(idx, param_type)?
+            .map_or_else(
+                || "NULL".to_owned(),
+                |v| placeholders::quote_literal(&v.format("%Y-%m-%d %H:%M:%S%.f%:z").to_string()),
+            )),
+        &Type::DATE => Ok(portal.parameter::<NaiveDate>(idx, param_type)?.map_or_else(
+            || "NULL".to_owned(),
+            |v| placeholders::quote_literal(&v.format("%Y-%m-%d").to_string()),
+        )),
+        &Type::TIME => Ok(portal.parameter::<NaiveTime>(idx, param_type)?.map_or_else(
+            || "NULL".to_owned(),
+            |v| placeholders::quote_literal(&v.format("%H:%M:%S%.f").to_string()),
+        )),
+        &Type::UUID => Ok(portal
+            .parameter::<uuid::Uuid>(idx, param_type)?
+            .map_or_else(
+                || "NULL".to_owned(),
+                |v| placeholders::quote_literal(&v.to_string()),
+            )),
+        // a hex bytea literal, peers without bytea rewrite the cast
+        &Type::BYTEA => Ok(portal
+            .parameter::<Vec<u8>>(idx, param_type)?
+            .map_or_else(|| "NULL".to_owned(), |v| placeholders::bytea_literal(&v))),
         _ => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
             "ERROR".to_owned(),
             "22023".to_owned(),
@@ -805,32 +3160,55 @@ impl ExtendedQueryHandler for NexusBackend {
         Arc::new(self.query_parser.clone())
     }
 
+    // a closed portal's suspended rows are freed with it, they would
+    // otherwise stay buffered until the transaction ends. Closing a portal
+    // or statement that doesn't exist is not an error.
+    async fn on_close<C>(&self, client: &mut C, message: Close) -> PgWireResult<()>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: std::fmt::Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let name = message.name.as_deref().unwrap_or(DEFAULT_NAME);
+        match message.target_type {
+            TARGET_TYPE_BYTE_STATEMENT => client.portal_store().rm_statement(name),
+            TARGET_TYPE_BYTE_PORTAL => {
+                client.portal_store().rm_portal(name);
+                self.suspended_portals.lock().await.remove(name);
+            }
+            _ => (),
+        }
+        client
+            .send(PgWireBackendMessage::CloseComplete(CloseComplete::new()))
+            .await?;
+        Ok(())
+    }
+
     async fn do_query<'a, C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         portal: &'a Portal<Self::Statement>,
-        _max_rows: usize,
+        max_rows: usize,
     ) -> PgWireResult<Response<'a>>
     where
-        C: ClientInfo + Unpin + Send + Sync,
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: std::fmt::Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
-        let stmt = &portal.statement.statement;
-        tracing::info!("[eqp] do_query: {}", stmt.query);
-
-        // manually replace variables in prepared statement
-        let mut sql = stmt.query.clone();
-        for i in 0..portal.parameter_len() {
-            sql = sql.replace(&format!("${}", i + 1), &parameter_to_string(portal, i)?);
-        }
-
-        let parsed = self.query_parser.parse_simple_sql(&sql).await?;
-        let nexus_stmt = parsed.statement;
-        let result = self.handle_query(nexus_stmt).await?;
-        if result.is_empty() {
-            Ok(Response::EmptyQuery)
-        } else {
-            Ok(result.into_iter().next().unwrap())
-        }
+        self.remember_client_user(client);
+        self.query_started().await?;
+        let mut res = self
+            .extended_query(client, portal, max_rows)
+            .instrument(tracing::info_span!(
+                "query",
+                protocol = "extended",
+                portal = %portal.name
+            ))
+            .await;
+        let responses = res.as_mut().map_or(&mut [][..], std::slice::from_mut);
+        self.watch_idle_transactions(responses).await;
+        res.map_err(client_error)
     }
 
     async fn do_describe_portal<C>(
@@ -842,7 +3220,11 @@ impl ExtendedQueryHandler for NexusBackend {
         C: ClientInfo + Unpin + Send + Sync,
     {
         Ok(
-            if let Some(schema) = self.do_describe(&target.statement.statement).await? {
+            if let Some(schema) = self
+                .do_describe(&target.statement.statement)
+                .await
+                .map_err(client_error)?
+            {
                 DescribePortalResponse::new((*schema).clone())
             } else {
                 DescribePortalResponse::no_data()
@@ -858,13 +3240,14 @@ impl ExtendedQueryHandler for NexusBackend {
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        Ok(
-            if let Some(schema) = self.do_describe(&target.statement).await? {
-                DescribeStatementResponse::new(target.parameter_types.clone(), (*schema).clone())
-            } else {
-                DescribeStatementResponse::no_data()
-            },
-        )
+        let parameter_types = self.parameter_types(target).await.map_err(client_error)?;
+        // no fields are described as NoData
+        let fields = self
+            .do_describe(&target.statement)
+            .await
+            .map_err(client_error)?
+            .map_or_else(Vec::new, |schema| (*schema).clone());
+        Ok(DescribeStatementResponse::new(parameter_types, fields))
     }
 }
 
@@ -917,12 +3300,18 @@ struct Args {
     #[clap(short, long, env = "PEERDB_LOG_DIR")]
     log_dir: Option<String>,
 
-    /// Password for the  postgres interface.
+    /// Password for the  postgres interface, of the users without one set with
+    /// `peerdb.set_password`.
     ///
     /// Defaults to `peerdb`.
     #[clap(long, env = "PEERDB_PASSWORD", default_value = "peerdb")]
     peerdb_password: String,
 
+    /// Refuse logins of users without a password set with `peerdb.set_password`,
+    /// instead of checking their password against `--peerdb-password`.
+    #[clap(long, default_value = "false", env = "PEERDB_REQUIRE_CATALOG_USERS")]
+    require_catalog_users: bool,
+
     /// Points to the URL for the Flow API server.
     ///
     /// This is an optional parameter. If not provided, the MIRROR commands will not be supported.
@@ -936,9 +3325,196 @@ struct Args {
     #[clap(long, default_value = "false", env = "PEERDB_MIGRATIONS_ONLY")]
     migrations_only: bool,
 
+    /// Migrate the catalog up or down to this version instead of the latest.
+    /// Migrating down requires rollback scripts for the versions being undone.
+    #[clap(long, env = "PEERDB_MIGRATION_TARGET_VERSION")]
+    migration_target_version: Option<u32>,
+
+    /// If set to true, nexus prints the catalog's migration version and exits
+    #[clap(long, default_value = "false")]
+    print_migration_version: bool,
+
     /// KMS Key ID for decrypting the catalog password
     #[clap(long, env = "PEERDB_KMS_KEY_ID")]
     kms_key_id: Option<String>,
+
+    /// If set to true, bound parameter values of prepared statements are logged
+    /// alongside the query.
+    #[clap(long, default_value = "false", env = "PEERDB_LOG_PARAMETERS")]
+    log_parameters: bool,
+
+    /// Parameters that are never logged, either a placeholder like `$2` or a
+    /// column name fragment matched against the column the parameter is compared with.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "password,secret,token,key",
+        env = "PEERDB_LOG_PARAMETERS_REDACT"
+    )]
+    log_parameters_redact: Vec<String>,
+
+    /// Authentication rules of the form `METHOD [USER [ADDRESS]]`, separated by commas,
+    /// e.g. `trust * 127.0.0.1/32,md5 svc_*,scram`. METHOD is one of `trust`, `md5` or
+    /// `scram`, USER may contain `*` wildcards and ADDRESS is a CIDR range.
+    ///
+    /// The first rule matching the connecting user and address wins, connections
+    /// matching no rule authenticate with SCRAM.
+    #[clap(long, value_delimiter = ',', env = "PEERDB_AUTH_RULES")]
+    auth_rules: Vec<AuthRule>,
+
+    /// File of pg_hba.conf style rules `host DATABASE USER ADDRESS METHOD`, one per
+    /// line, e.g. `host all all 10.0.0.0/8 md5`. METHOD may also be `reject`.
+    ///
+    /// The rules are consulted after `--auth-rules`, connections matching neither
+    /// are rejected. The file is read again on SIGHUP.
+    #[clap(long, env = "PEERDB_HBA_FILE")]
+    hba_file: Option<std::path::PathBuf>,
+
+    /// Peer that queries of tables not qualified with a peer name are routed to,
+    /// sessions can override it with `SET peerdb.default_peer`. Queries that only
+    /// read the system catalogs always run on the catalog. A routing hint such as
+    /// `/*+ peer(name) */` ahead of a query takes precedence over both.
+    #[clap(long, env = "PEERDB_DEFAULT_PEER")]
+    default_peer: Option<String>,
+
+    /// What to do with text from peers that isn't valid UTF-8: `replace` invalid
+    /// sequences with U+FFFD or fail the query with an `error`.
+    #[clap(long, default_value = "replace", env = "PEERDB_INVALID_UTF8")]
+    invalid_utf8: InvalidUtf8,
+
+    /// Attempts at connecting to a peer before a query on it fails, retries wait
+    /// `peer_connect_backoff_ms`, doubling after every failed attempt.
+    #[clap(long, default_value = "3", env = "PEERDB_PEER_CONNECT_ATTEMPTS")]
+    peer_connect_attempts: u32,
+
+    /// Milliseconds to wait before retrying to connect to a peer.
+    #[clap(long, default_value = "200", env = "PEERDB_PEER_CONNECT_BACKOFF_MS")]
+    peer_connect_backoff_ms: u64,
+
+    /// Milliseconds spent connecting to a peer at most, across all attempts.
+    #[clap(long, default_value = "10000", env = "PEERDB_PEER_CONNECT_MAX_WAIT_MS")]
+    peer_connect_max_wait_ms: u64,
+
+    /// Peer connections a client connection keeps open at most, the least
+    /// recently queried peer's is closed to make room for another, 0 for no
+    /// limit. Connections in a transaction are never closed.
+    #[clap(long, default_value = "16", env = "PEERDB_MAX_CACHED_EXECUTORS")]
+    max_cached_executors: usize,
+
+    /// Seconds secrets referenced by peer configs are cached for, e.g.
+    /// `password = 'secret://vault/secret/data/pg#password'`.
+    #[clap(long, default_value = "300", env = "PEERDB_SECRET_CACHE_TTL_SECS")]
+    secret_cache_ttl_secs: u64,
+
+    /// Seconds the peers read from the catalog are cached for routing statements,
+    /// 0 reads them for every statement. Peers created or dropped through this
+    /// nexus are picked up right away, others once the cache expires.
+    #[clap(long, default_value = "30", env = "PEERDB_PEER_CACHE_TTL_SECS")]
+    peer_cache_ttl_secs: u64,
+
+    /// Default statement timeout in milliseconds of sessions that don't set
+    /// `statement_timeout`, 0 for none.
+    #[clap(long, default_value = "0", env = "PEERDB_STATEMENT_TIMEOUT_MS")]
+    statement_timeout_ms: u64,
+
+    /// Queries on peers running at least this many milliseconds are logged,
+    /// 0 turns logging slow queries off.
+    #[clap(long, default_value = "0", env = "PEERDB_SLOW_QUERY_THRESHOLD_MS")]
+    slow_query_threshold_ms: u64,
+
+    /// Megabytes of buffered rows a connection may hold before its query
+    /// fails, 0 for no limit.
+    #[clap(long, default_value = "0", env = "PEERDB_CONNECTION_MEMORY_LIMIT_MB")]
+    connection_memory_limit_mb: usize,
+
+    /// Columns a result may have before its query fails, 0 for no limit.
+    #[clap(long, default_value = "0", env = "PEERDB_MAX_RESULT_COLUMNS")]
+    max_result_columns: usize,
+
+    /// Kilobytes a single value of a result may take before its query fails,
+    /// 0 for no limit.
+    #[clap(long, default_value = "0", env = "PEERDB_MAX_RESULT_VALUE_SIZE_KB")]
+    max_result_value_size_kb: usize,
+
+    /// Notice sent to clients once they are connected, e.g. a compliance
+    /// banner. Can be changed later with `peerdb.set_config`.
+    #[clap(long, env = "PEERDB_CONNECT_BANNER")]
+    connect_banner: Option<String>,
+
+    /// Users allowed to change server settings with `peerdb.set_config`.
+    #[clap(
+        long,
+        default_value = "peerdb",
+        value_delimiter = ',',
+        env = "PEERDB_ADMIN_USERS"
+    )]
+    admin_users: Vec<String>,
+
+    /// Threads encoding the rows peers return for the clients, 0 encodes
+    /// them on the async runtime with the connections' I/O.
+    #[clap(long, default_value = "0", env = "PEERDB_ENCODE_THREADS")]
+    encode_threads: usize,
+
+    /// Seconds connections are given to finish on SIGTERM, new ones are refused
+    /// meanwhile. Connections still open afterwards have their cursors closed
+    /// on the peers and are dropped.
+    #[clap(long, default_value = "30", env = "PEERDB_SHUTDOWN_GRACE_SECS")]
+    shutdown_grace_secs: u64,
+
+    /// Port serving the Prometheus metrics at `/metrics`, not served when
+    /// unset.
+    #[clap(long, env = "PEERDB_METRICS_PORT")]
+    metrics_port: Option<u16>,
+
+    /// Postgres version reported to clients as `server_version`, e.g. `15.4`
+    /// to match the postgres servers clients otherwise connect to. Some
+    /// clients enable features by it.
+    #[clap(
+        long,
+        default_value = "14",
+        value_parser = server_parameters::parse_server_version,
+        env = "PEERDB_ADVERTISED_SERVER_VERSION"
+    )]
+    advertised_server_version: String,
+
+    /// Parameter reported to clients when they connect, as `name=value`, e.g.
+    /// `--advertised-parameter standard_conforming_strings=on`. Can be repeated,
+    /// or given as a comma separated list in the environment. The encodings
+    /// and DateStyle can't be changed.
+    #[clap(
+        long = "advertised-parameter",
+        value_delimiter = ',',
+        env = "PEERDB_ADVERTISED_PARAMETERS"
+    )]
+    advertised_parameters: Vec<AdvertisedParameter>,
+}
+
+// waits for the connections to end, for the grace period at most. Cursors of
+// the connections left are closed on their peers before they're dropped.
+async fn drain_connections<T: 'static>(
+    mut connections: JoinSet<T>,
+    grace: Duration,
+    executor_registry: &ExecutorRegistry,
+) {
+    tracing::info!(
+        "shutting down, waiting up to {} seconds for {} connections to end",
+        grace.as_secs(),
+        connections.len()
+    );
+    let drained = tokio::time::timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_ok() {
+        return;
+    }
+    let cursors = executor_registry.close_cursors().await;
+    tracing::warn!(
+        "closing {} connections still open after the grace period, {} cursors were closed",
+        connections.len(),
+        cursors
+    );
+    connections.shutdown().await;
 }
 
 async fn decrypt_password(encrypted_password: &str, kms_key_id: &str) -> anyhow::Result<String> {
@@ -986,22 +3562,21 @@ async fn get_catalog_config(args: &Args) -> anyhow::Result<CatalogConfig<'_>> {
     })
 }
 
-pub struct NexusServerParameterProvider;
-
-impl ServerParameterProvider for NexusServerParameterProvider {
-    fn server_parameters<C>(&self, _client: &C) -> Option<HashMap<String, String>>
-    where
-        C: ClientInfo,
-    {
-        let mut params = HashMap::with_capacity(5);
-        params.insert("server_version".to_owned(), "14".to_owned());
-        params.insert("server_encoding".to_owned(), "UTF8".to_owned());
-        params.insert("client_encoding".to_owned(), "UTF8".to_owned());
-        params.insert("DateStyle".to_owned(), "ISO, MDY".to_owned());
-        params.insert("integer_datetimes".to_owned(), "on".to_owned());
-
-        Some(params)
-    }
+// Build the acceptor used to upgrade connections that send an SSLRequest.
+fn setup_tls(cert_path: &str, key_path: &str) -> anyhow::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| anyhow::anyhow!("no private key found in {}", key_path))?;
+
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"postgresql".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
 }
 
 type TracerGuards = Option<WorkerGuard>;
@@ -1035,13 +3610,19 @@ fn setup_tracing(log_dir: Option<&str>) -> TracerGuards {
     }
 }
 
-async fn run_migrations<'a>(config: &CatalogConfig<'a>) -> anyhow::Result<()> {
+async fn run_migrations<'a>(
+    config: &CatalogConfig<'a>,
+    target_version: Option<u32>,
+) -> anyhow::Result<()> {
     // retry connecting to the catalog 3 times with 30 seconds delay
     // if it fails, return an error
     for _ in 0..3 {
         match Catalog::new(config.to_postgres_config()).await {
             Ok(mut catalog) => {
-                catalog.run_migrations().await?;
+                match target_version {
+                    Some(target) => catalog.migrate_to(target).await?,
+                    None => catalog.run_migrations().await?,
+                }
                 return Ok(());
             }
             Err(err) => {
@@ -1058,16 +3639,12 @@ async fn run_migrations<'a>(config: &CatalogConfig<'a>) -> anyhow::Result<()> {
 }
 
 pub struct Handlers {
-    authenticator: (
-        Arc<FixedPasswordAuthSource>,
-        Arc<NexusServerParameterProvider>,
-    ),
+    authenticator: (Arc<AuthConfig>, Arc<NexusServerParameterProvider>),
     nexus: Arc<NexusBackend>,
 }
 
 impl PgWireHandlerFactory for Handlers {
-    type StartupHandler =
-        SASLScramAuthStartupHandler<FixedPasswordAuthSource, NexusServerParameterProvider>;
+    type StartupHandler = NexusStartupHandler;
     type SimpleQueryHandler = NexusBackend;
     type ExtendedQueryHandler = NexusBackend;
     type CopyHandler = NoopCopyHandler;
@@ -1081,9 +3658,12 @@ impl PgWireHandlerFactory for Handlers {
     }
 
     fn startup_handler(&self) -> Arc<Self::StartupHandler> {
-        Arc::new(SASLScramAuthStartupHandler::new(
+        Arc::new(NexusStartupHandler::new(
             self.authenticator.0.clone(),
             self.authenticator.1.clone(),
+            self.nexus.runtime_config.clone(),
+            self.nexus.catalog.clone(),
+            self.nexus.cancel.backend_key(),
         ))
     }
 
@@ -1098,16 +3678,33 @@ pub async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
     let _guard = setup_tracing(args.log_dir.as_ref().map(|s| &s[..]));
+    tracing::info!("starting {}", build_info::version());
     let catalog_config = get_catalog_config(&args).await?;
 
-    run_migrations(&catalog_config).await?;
+    if args.print_migration_version {
+        let mut catalog = Catalog::new(catalog_config.to_postgres_config()).await?;
+        match catalog.migration_version().await? {
+            Some(version) => println!("{}", version),
+            None => println!("no migrations applied"),
+        }
+        return Ok(());
+    }
+
+    run_migrations(&catalog_config, args.migration_target_version).await?;
     if args.migrations_only {
         return Ok(());
     }
 
     let authenticator = (
-        Arc::new(FixedPasswordAuthSource::new(args.peerdb_password.clone())),
-        Arc::new(NexusServerParameterProvider),
+        Arc::new(AuthConfig::new(
+            args.auth_rules.clone(),
+            args.hba_file.clone(),
+            (!args.require_catalog_users).then(|| args.peerdb_password.clone()),
+        )?),
+        Arc::new(NexusServerParameterProvider::new(
+            &args.advertised_server_version,
+            &args.advertised_parameters,
+        )),
     );
 
     let peer_conns = {
@@ -1116,6 +3713,83 @@ pub async fn main() -> anyhow::Result<()> {
         Arc::new(pconns)
     };
 
+    let parameter_log = args
+        .log_parameters
+        .then(|| Arc::new(ParameterLogConfig::new(args.log_parameters_redact.clone())));
+
+    // without an acceptor SSLRequests are answered with `N` and clients that
+    // require TLS disconnect.
+    let tls_acceptor = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            tracing::info!("TLS enabled");
+            Some(Arc::new(setup_tls(cert, key)?))
+        }
+        _ => {
+            tracing::info!("TLS disabled");
+            None
+        }
+    };
+
+    let executor_config = PeerExecutorConfig {
+        invalid_utf8: args.invalid_utf8,
+        connect_retry: ConnectRetryPolicy {
+            attempts: args.peer_connect_attempts,
+            initial_backoff: Duration::from_millis(args.peer_connect_backoff_ms),
+            max_wait: Duration::from_millis(args.peer_connect_max_wait_ms),
+        },
+        max_cached: (args.max_cached_executors > 0).then_some(args.max_cached_executors),
+    };
+
+    let peer_stats = PeerStats::new();
+    let millis = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+    let runtime_config = RuntimeConfig::new(
+        RuntimeSettings {
+            statement_timeout: millis(args.statement_timeout_ms),
+            slow_query_threshold: millis(args.slow_query_threshold_ms),
+            secret_cache_ttl: Duration::from_secs(args.secret_cache_ttl_secs),
+            connection_memory_limit: (args.connection_memory_limit_mb > 0)
+                .then(|| args.connection_memory_limit_mb * 1024 * 1024),
+            connect_banner: args
+                .connect_banner
+                .clone()
+                .filter(|banner| !banner.is_empty()),
+            max_result_columns: (args.max_result_columns > 0).then_some(args.max_result_columns),
+            max_result_value_size: (args.max_result_value_size_kb > 0)
+                .then(|| args.max_result_value_size_kb * 1024),
+        },
+        args.admin_users.clone(),
+    );
+    let rewrite_rules = {
+        let catalog = Catalog::new(catalog_config.to_postgres_config()).await?;
+        runtime_config.load(&catalog).await?;
+        let failed = catalog.fail_unfinished_async_jobs().await?;
+        if failed > 0 {
+            tracing::warn!("{} async jobs were running when nexus stopped", failed);
+        }
+        Arc::new(RewriteRules::load(&catalog).await?)
+    };
+    let async_jobs = AsyncJobs::new(catalog_config.to_postgres_config());
+    let executor_registry = Arc::new(ExecutorRegistry::default());
+    let cancel_registry = Arc::new(CancelRegistry::default());
+    if let Some(port) = args.metrics_port {
+        metrics::install(port, executor_registry.clone())?;
+    }
+    let secrets = Arc::new(SecretStore::with_default_resolvers(
+        runtime_config.get().secret_cache_ttl,
+    ));
+
+    let peer_cache = Arc::new(PeerCache::new(Duration::from_secs(
+        args.peer_cache_ttl_secs,
+    )));
+
+    let encode_pool = match args.encode_threads {
+        0 => None,
+        threads => {
+            tracing::info!("encoding rows on {} threads", threads);
+            Some(Arc::new(EncodePool::new(threads)?))
+        }
+    };
+
     let server_addr = format!("{}:{}", args.host, args.port);
     let listener = TcpListener::bind(&server_addr).await.unwrap();
     tracing::info!("Listening on {}", server_addr);
@@ -1130,17 +3804,57 @@ pub async fn main() -> anyhow::Result<()> {
     };
 
     let mut sigintstream = signal(SignalKind::interrupt()).expect("Failed to setup signal handler");
+    let mut sighupstream = signal(SignalKind::hangup()).expect("Failed to setup signal handler");
+    let mut sigtermstream =
+        signal(SignalKind::terminate()).expect("Failed to setup signal handler");
+    let mut connections = JoinSet::new();
     loop {
         let (mut socket, _) = tokio::select! {
             _ = sigintstream.recv() => return Ok(()),
+            _ = sigtermstream.recv() => {
+                drop(listener);
+                drain_connections(
+                    connections,
+                    Duration::from_secs(args.shutdown_grace_secs),
+                    &executor_registry,
+                )
+                .await;
+                return Ok(());
+            }
+            // reaps the connections that ended
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = sighupstream.recv() => {
+                if let Err(err) = authenticator.0.reload() {
+                    tracing::error!("keeping the authentication rules in effect: {}", err);
+                }
+                continue;
+            }
             v = listener.accept() => v,
         }?;
         let conn_flow_handler = flow_handler.clone();
         let conn_peer_conns = peer_conns.clone();
         let authenticator = authenticator.clone();
+        let parameter_log = parameter_log.clone();
+        let tls_acceptor = tls_acceptor.clone();
+        let default_peer = args.default_peer.clone();
+        let peer_stats = peer_stats.clone();
+        let secrets = secrets.clone();
+        let runtime_config = runtime_config.clone();
+        let encode_pool = encode_pool.clone();
+        let async_jobs = async_jobs.clone();
+        let rewrite_rules = rewrite_rules.clone();
+        let executor_registry = executor_registry.clone();
+        let cancel_registry = cancel_registry.clone();
+        let peer_cache = peer_cache.clone();
         let pg_config = catalog_config.to_postgres_config();
 
-        tokio::task::spawn(async move {
+        connections.spawn(async move {
+            if let Some((pid, secret)) = negotiate::read_cancel_request(&mut socket).await? {
+                if !cancel_registry.cancel(pid, secret).await {
+                    tracing::info!("ignoring CancelRequest for unknown process {}", pid);
+                }
+                return socket.shutdown().await;
+            }
             match Catalog::new(pg_config).await {
                 Ok(catalog) => {
                     let conn_uuid = uuid::Uuid::new_v4();
@@ -1148,18 +3862,37 @@ pub async fn main() -> anyhow::Result<()> {
 
                     let nexus = Arc::new(NexusBackend::new(
                         Arc::new(catalog),
+                        peer_cache,
                         tracker,
                         conn_flow_handler,
                         args.peerdb_fdw_mode,
+                        parameter_log,
+                        default_peer,
+                        authenticator.1.parameters(),
+                        executor_config,
+                        peer_stats,
+                        secrets,
+                        runtime_config,
+                        encode_pool,
+                        async_jobs,
+                        rewrite_rules,
+                        executor_registry,
+                        cancel_registry,
                     ));
+                    negotiate::decline_gssenc_request(&mut socket).await?;
+                    if negotiate::refuse_unsupported_protocol(&mut socket).await? {
+                        return Ok(());
+                    }
+                    let _active = metrics::ActiveConnection::open();
                     process_socket(
                         socket,
-                        None,
+                        tls_acceptor,
                         Arc::new(Handlers {
                             nexus,
                             authenticator,
                         }),
                     )
+                    .instrument(tracing::info_span!("connection", id = %conn_uuid))
                     .await
                 }
                 Err(e) => {
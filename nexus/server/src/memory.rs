@@ -0,0 +1,119 @@
+//! Accounting of the memory a connection holds in rows it buffers, the rows
+//! read ahead for portals and the in-memory part of spooled results.
+//!
+//! Buffered rows take a reservation on the connection's account, released
+//! when the rows are dropped. A reservation that would take the connection
+//! past the `connection_memory_limit` setting fails the query with 53200
+//! instead of letting one large result run the whole server out of memory.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use peer_cursor::Record;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+
+use crate::runtime_config::RuntimeConfig;
+
+/// The bytes of buffered rows a connection holds.
+pub struct MemoryAccount {
+    used: AtomicUsize,
+    runtime_config: Arc<RuntimeConfig>,
+}
+
+impl MemoryAccount {
+    pub fn new(runtime_config: Arc<RuntimeConfig>) -> Arc<Self> {
+        Arc::new(Self {
+            used: AtomicUsize::new(0),
+            runtime_config,
+        })
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// An empty reservation, grown as rows are buffered.
+    pub fn reservation(self: &Arc<Self>) -> Reservation {
+        Reservation {
+            account: Arc::clone(self),
+            bytes: 0,
+        }
+    }
+
+    // takes `bytes` more, unless that exceeds the limit, which is read on
+    // every call so a changed setting applies to running queries too.
+    fn take(&self, bytes: usize) -> bool {
+        let limit = self.runtime_config.connection_memory_limit();
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                let used = used.saturating_add(bytes);
+                match limit {
+                    Some(limit) if used > limit => None,
+                    _ => Some(used),
+                }
+            })
+            .is_ok()
+    }
+
+    fn limit_error(&self, bytes: usize) -> PgWireError {
+        let limit = self
+            .runtime_config
+            .connection_memory_limit()
+            .unwrap_or_default();
+        PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "53200".to_owned(),
+            format!(
+                "out of memory: buffering {} more bytes exceeds the connection memory limit of {}kB, \
+                {} bytes in use; read the result without buffering it or add a LIMIT",
+                bytes,
+                limit / 1024,
+                self.used()
+            ),
+        )))
+    }
+}
+
+/// Memory taken from a connection's account, given back when dropped.
+pub struct Reservation {
+    account: Arc<MemoryAccount>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Takes `bytes` more, failing with 53200 past the connection's limit.
+    pub fn grow(&mut self, bytes: usize) -> PgWireResult<()> {
+        if self.try_grow(bytes) {
+            Ok(())
+        } else {
+            Err(self.account.limit_error(bytes))
+        }
+    }
+
+    /// Takes `bytes` more, false past the connection's limit.
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        let taken = self.account.take(bytes);
+        if taken {
+            self.bytes += bytes;
+        }
+        taken
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.account.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Approximate bytes a buffered row takes.
+pub fn record_size(record: &Record) -> usize {
+    std::mem::size_of::<Record>()
+        + record
+            .values
+            .iter()
+            .map(|value| value.approximate_size())
+            .sum::<usize>()
+}
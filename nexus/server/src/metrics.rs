@@ -0,0 +1,88 @@
+//! Prometheus metrics of nexus, served over HTTP on `--metrics-port` apart
+//! from the pgwire listener, so Prometheus scrapes them without speaking the
+//! wire protocol. Without the port no recorder is installed and recording
+//! them does nothing.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use peer_cursor::QueryOutput;
+use pgwire::error::PgWireResult;
+
+use crate::executor_registry::ExecutorRegistry;
+
+const QUERIES: &str = "nexus_queries_total";
+const QUERY_DURATION: &str = "nexus_query_duration_seconds";
+const ACTIVE_CONNECTIONS: &str = "nexus_active_connections";
+const CACHED_EXECUTORS: &str = "nexus_cached_executors";
+
+const QUERY_DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0, 120.0,
+];
+
+// how often the executor cache is counted, it changes on every connection
+// and isn't worth tracking exactly
+const CACHE_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serves the metrics on the port of all interfaces.
+pub fn install(port: u16, executor_registry: Arc<ExecutorRegistry>) -> anyhow::Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(SocketAddr::from(([0, 0, 0, 0], port)))
+        .set_buckets_for_metric(
+            Matcher::Full(QUERY_DURATION.to_owned()),
+            &QUERY_DURATION_BUCKETS,
+        )?
+        .install()?;
+
+    describe_counter!(QUERIES, "statements run on peers and the catalog");
+    describe_histogram!(
+        QUERY_DURATION,
+        metrics::Unit::Seconds,
+        "time until a statement's output was ready, rows are streamed after"
+    );
+    describe_gauge!(ACTIVE_CONNECTIONS, "client connections open");
+    describe_gauge!(
+        CACHED_EXECUTORS,
+        "peer connections kept by the client connections"
+    );
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CACHE_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            gauge!(CACHED_EXECUTORS).set(executor_registry.cached() as f64);
+        }
+    });
+    tracing::info!("serving metrics on port {}", port);
+    Ok(())
+}
+
+/// Counts a statement run on the peer, by the kind of its output.
+pub fn record_query(peer: &str, elapsed: Duration, res: &PgWireResult<QueryOutput>) {
+    let output = match res {
+        Ok(QueryOutput::Stream(_) | QueryOutput::Records(_)) => "rows",
+        Ok(QueryOutput::AffectedRows(_)) => "affected_rows",
+        Ok(QueryOutput::Cursor(_)) => "cursor",
+        Err(_) => "error",
+    };
+    let labels = [("peer", peer.to_owned()), ("output", output.to_owned())];
+    counter!(QUERIES, &labels).increment(1);
+    histogram!(QUERY_DURATION, &labels).record(elapsed.as_secs_f64());
+}
+
+/// Counts a client connection for as long as it's kept.
+pub struct ActiveConnection(());
+
+impl ActiveConnection {
+    pub fn open() -> Self {
+        gauge!(ACTIVE_CONNECTIONS).increment(1.0);
+        Self(())
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        gauge!(ACTIVE_CONNECTIONS).decrement(1.0);
+    }
+}
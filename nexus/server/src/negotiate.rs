@@ -0,0 +1,140 @@
+use std::{io::ErrorKind, os::fd::AsFd, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::TcpStream,
+};
+
+// request codes sent in place of a protocol version, by a GSSENCRequest, an
+// SSLRequest and a CancelRequest.
+pub const GSSENC_REQUEST_CODE: i32 = 80877104;
+const SSL_REQUEST_CODE: i32 = 80877103;
+const CANCEL_REQUEST_CODE: i32 = 80877102;
+
+// the major protocol version nexus speaks, 3.0 since postgres 7.4.
+const PROTOCOL_MAJOR: i32 = 3;
+
+// the longest startup message postgres accepts.
+const MAX_STARTUP_LENGTH: i32 = 10000;
+
+// how long to wait for the first 8 bytes of a connection.
+const PEEK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Peeks at the first message of a connection without consuming it and
+/// returns its length and the protocol version or request code that follows.
+/// Returns None if the client disconnected before sending 8 bytes.
+pub async fn peek_request_code(socket: &TcpStream) -> std::io::Result<Option<(i32, i32)>> {
+    // a std handle on the same socket, for a peek that doesn't wait
+    let peeker = std::net::TcpStream::from(socket.as_fd().try_clone_to_owned()?);
+    let peek = async {
+        let mut buf = [0u8; 8];
+        loop {
+            socket.readable().await?;
+            // a header that only arrived in part counts as no data, which
+            // waits for the socket to be readable again when more arrives
+            let peeked = socket.try_io(Interest::READABLE, || match peeker.peek(&mut buf)? {
+                n if n == 0 || n == buf.len() => Ok(n),
+                _ => Err(ErrorKind::WouldBlock.into()),
+            });
+            match peeked {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    let len = i32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                    let code = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+                    return Ok(Some((len, code)));
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    };
+
+    tokio::time::timeout(PEEK_TIMEOUT, peek)
+        .await
+        .unwrap_or(Ok(None))
+}
+
+/// Declines GSSAPI encryption requests, which pgwire doesn't handle, with `N`
+/// as postgres does when it has no GSSAPI support. Clients then continue on
+/// the same connection with an SSLRequest or a startup message.
+pub async fn decline_gssenc_request(socket: &mut TcpStream) -> std::io::Result<()> {
+    if let Some((8, GSSENC_REQUEST_CODE)) = peek_request_code(socket).await? {
+        let mut request = [0u8; 8];
+        socket.read_exact(&mut request).await?;
+        socket.write_all(b"N").await?;
+    }
+    Ok(())
+}
+
+/// Reads a CancelRequest, which is all a client sends on its connection, and
+/// returns the process id and secret key it carries. None when the
+/// connection starts with another message, which is left unread.
+pub async fn read_cancel_request(socket: &mut TcpStream) -> std::io::Result<Option<(i32, i32)>> {
+    let Some((16, CANCEL_REQUEST_CODE)) = peek_request_code(socket).await? else {
+        return Ok(None);
+    };
+    let mut request = [0u8; 16];
+    socket.read_exact(&mut request).await?;
+    let pid = i32::from_be_bytes([request[8], request[9], request[10], request[11]]);
+    let secret = i32::from_be_bytes([request[12], request[13], request[14], request[15]]);
+    Ok(Some((pid, secret)))
+}
+
+/// Refuses a startup message of a protocol version other than 3.x with a
+/// FATAL error, as postgres does, instead of letting pgwire misread it.
+/// Returns true if the connection was refused and closed.
+pub async fn refuse_unsupported_protocol(socket: &mut TcpStream) -> std::io::Result<bool> {
+    let Some((len, version)) = peek_request_code(socket).await? else {
+        return Ok(false);
+    };
+    if matches!(
+        version,
+        GSSENC_REQUEST_CODE | SSL_REQUEST_CODE | CANCEL_REQUEST_CODE
+    ) || version >> 16 == PROTOCOL_MAJOR
+    {
+        return Ok(false);
+    }
+
+    // the message is read before answering, so closing the connection
+    // doesn't reset it before the client reads the error.
+    if (8..=MAX_STARTUP_LENGTH).contains(&len) {
+        let mut message = vec![0u8; len as usize];
+        socket.read_exact(&mut message).await?;
+    }
+    let message = format!(
+        "unsupported frontend protocol {}.{}: server supports {}.0 to {}.0",
+        version >> 16,
+        version & 0xffff,
+        PROTOCOL_MAJOR,
+        PROTOCOL_MAJOR
+    );
+    tracing::warn!("refusing connection: {}", message);
+    socket.write_all(&error_response(version, &message)).await?;
+    socket.shutdown().await?;
+    Ok(true)
+}
+
+// a FATAL 0A000 error in the format of the client's protocol: clients of
+// protocol 2 and older only understand a bare message.
+fn error_response(version: i32, message: &str) -> Vec<u8> {
+    let mut buf = vec![b'E'];
+    if version >> 16 < PROTOCOL_MAJOR {
+        buf.extend_from_slice(format!("FATAL:  {}\n\0", message).as_bytes());
+        return buf;
+    }
+    let mut fields = Vec::new();
+    for (field, value) in [
+        (b'S', "FATAL"),
+        (b'V', "FATAL"),
+        (b'C', "0A000"),
+        (b'M', message),
+    ] {
+        fields.push(field);
+        fields.extend_from_slice(value.as_bytes());
+        fields.push(0);
+    }
+    fields.push(0);
+    buf.extend_from_slice(&(fields.len() as i32 + 4).to_be_bytes());
+    buf.extend_from_slice(&fields);
+    buf
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashSet, fmt::Write, ops::ControlFlow};
+
+use peerdb_parser::NexusParsedStatement;
+use pgwire::api::portal::Portal;
+use sqlparser::ast::{visit_expressions, Expr, SetExpr, Statement, Value};
+
+// ParameterLogConfig controls how bound parameter values of prepared
+// statements are written to the log. Parameters compared with, inserted into
+// or assigned to a column whose name contains one of the `redact` entries, or
+// whose placeholder (`$2`) is listed explicitly, are never logged.
+pub struct ParameterLogConfig {
+    redact: Vec<String>,
+}
+
+impl ParameterLogConfig {
+    pub fn new(redact: Vec<String>) -> Self {
+        Self {
+            redact: redact
+                .into_iter()
+                .map(|r| r.trim().to_lowercase())
+                .filter(|r| !r.is_empty())
+                .collect(),
+        }
+    }
+
+    fn is_redacted_column(&self, column: &str) -> bool {
+        let column = column.to_lowercase();
+        self.redact.iter().any(|r| column.contains(r.as_str()))
+    }
+
+    // returns the 0-based indices of the parameters that must not be logged.
+    fn redacted_parameters(&self, stmt: Option<&Statement>) -> HashSet<usize> {
+        let mut redacted: HashSet<usize> = self
+            .redact
+            .iter()
+            .filter_map(|r| placeholder_index(r))
+            .collect();
+
+        if let Some(stmt) = stmt {
+            visit_expressions(stmt, |expr| {
+                if let Expr::BinaryOp { left, right, .. } = expr {
+                    for (column, value) in [(left, right), (right, left)] {
+                        if let (Some(column), Some(p)) = (column_name(column), placeholder(value)) {
+                            if self.is_redacted_column(column) {
+                                redacted.extend(placeholder_index(p));
+                            }
+                        }
+                    }
+                }
+                ControlFlow::<()>::Continue(())
+            });
+
+            for (column, value) in stored_values(stmt) {
+                if let Some(p) = placeholder(value) {
+                    if self.is_redacted_column(column) {
+                        redacted.extend(placeholder_index(p));
+                    }
+                }
+            }
+        }
+
+        redacted
+    }
+
+    /// Renders the bound parameters of the portal for logging, e.g.
+    /// `$1 = 'abc', $2 = <redacted>, $3 = NULL`.
+    pub fn describe(&self, portal: &Portal<NexusParsedStatement>) -> String {
+        let redacted = self.redacted_parameters(portal.statement.statement.statement.ast());
+
+        let mut out = String::new();
+        for (idx, param) in portal.parameters.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(", ");
+            }
+            let _ = write!(out, "${} = ", idx + 1);
+            match param {
+                _ if redacted.contains(&idx) => out.push_str("<redacted>"),
+                None => out.push_str("NULL"),
+                Some(bytes) => {
+                    let text = if portal.parameter_format.is_binary(idx) {
+                        None
+                    } else {
+                        std::str::from_utf8(bytes).ok()
+                    };
+                    match text {
+                        Some(text) => {
+                            let _ = write!(out, "'{}'", text.replace('\'', "''"));
+                        }
+                        // binary or non utf8 values are rendered as a bytea hex literal
+                        None => {
+                            out.push_str("'\\x");
+                            for b in bytes.iter() {
+                                let _ = write!(out, "{:02x}", b);
+                            }
+                            out.push('\'');
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn column_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Identifier(ident) => Some(&ident.value),
+        Expr::CompoundIdentifier(idents) => idents.last().map(|ident| ident.value.as_str()),
+        _ => None,
+    }
+}
+
+// the placeholder of a parameter, also when it is cast like `$1::text`
+fn placeholder(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Value(Value::Placeholder(p)) => Some(p),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => placeholder(expr),
+        _ => None,
+    }
+}
+
+// the values a statement stores in columns: the rows of an
+// `INSERT INTO t (a, b) VALUES (...)` by position and the assignments of an
+// `UPDATE t SET a = ...`.
+fn stored_values(stmt: &Statement) -> Vec<(&str, &Expr)> {
+    match stmt {
+        Statement::Insert {
+            columns,
+            source: Some(query),
+            ..
+        } => match query.body.as_ref() {
+            SetExpr::Values(values) => values
+                .rows
+                .iter()
+                .flat_map(|row| columns.iter().map(|c| c.value.as_str()).zip(row))
+                .collect(),
+            _ => Vec::new(),
+        },
+        Statement::Update { assignments, .. } => assignments
+            .iter()
+            .filter_map(|a| a.id.last().map(|id| (id.value.as_str(), &a.value)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// `$3` -> Some(2)
+fn placeholder_index(placeholder: &str) -> Option<usize> {
+    placeholder
+        .strip_prefix('$')
+        .and_then(|n| n.parse::<usize>().ok())
+        .and_then(|n| n.checked_sub(1))
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use pgwire::api::{
+    results::{FieldFormat, FieldInfo},
+    Type,
+};
+
+// statistics cover the queries of the last WINDOW, keeping at most
+// MAX_SAMPLES per peer so a busy peer can't grow them without bound.
+const WINDOW: Duration = Duration::from_secs(300);
+const MAX_SAMPLES: usize = 10_000;
+
+struct Sample {
+    at: Instant,
+    latency: Duration,
+    failed: bool,
+}
+
+/// Rolling latency and error statistics of the queries run on each peer,
+/// shared by all connections and read through `peerdb.peer_stats`. They are
+/// kept in memory only and start over when nexus restarts.
+#[derive(Default)]
+pub struct PeerStats {
+    samples: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+pub struct PeerStatsRow {
+    pub peer: String,
+    pub queries: i64,
+    pub errors: i64,
+    pub error_rate: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl PeerStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record(&self, peer: &str, latency: Duration, failed: bool) {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        let peer_samples = samples.entry(peer.to_owned()).or_default();
+        peer_samples.push_back(Sample {
+            at: now,
+            latency,
+            failed,
+        });
+        while peer_samples.len() > MAX_SAMPLES
+            || peer_samples
+                .front()
+                .is_some_and(|sample| now.duration_since(sample.at) > WINDOW)
+        {
+            peer_samples.pop_front();
+        }
+    }
+
+    /// Statistics of every peer with queries in the window, ordered by peer.
+    pub fn snapshot(&self) -> Vec<PeerStatsRow> {
+        let now = Instant::now();
+        let samples = self.samples.lock().unwrap();
+        let mut rows = samples
+            .iter()
+            .filter_map(|(peer, samples)| {
+                let mut latencies = samples
+                    .iter()
+                    .filter(|sample| now.duration_since(sample.at) <= WINDOW)
+                    .map(|sample| (sample.latency, sample.failed))
+                    .collect::<Vec<_>>();
+                if latencies.is_empty() {
+                    return None;
+                }
+                latencies.sort_unstable_by_key(|(latency, _)| *latency);
+
+                let queries = latencies.len();
+                let errors = latencies.iter().filter(|(_, failed)| *failed).count();
+                // nearest-rank percentile
+                let percentile = |p: f64| {
+                    let rank = ((p * queries as f64).ceil() as usize).clamp(1, queries);
+                    latencies[rank - 1].0.as_secs_f64() * 1000.0
+                };
+                Some(PeerStatsRow {
+                    peer: peer.clone(),
+                    queries: queries as i64,
+                    errors: errors as i64,
+                    error_rate: errors as f64 / queries as f64,
+                    p50_ms: percentile(0.50),
+                    p95_ms: percentile(0.95),
+                    p99_ms: percentile(0.99),
+                })
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| a.peer.cmp(&b.peer));
+        rows
+    }
+
+    pub fn schema() -> Vec<FieldInfo> {
+        let field = |name: &str, datatype: Type| {
+            FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+        };
+        vec![
+            field("peer", Type::TEXT),
+            field("queries", Type::INT8),
+            field("errors", Type::INT8),
+            field("error_rate", Type::FLOAT8),
+            field("p50_ms", Type::FLOAT8),
+            field("p95_ms", Type::FLOAT8),
+            field("p99_ms", Type::FLOAT8),
+        ]
+    }
+}
@@ -0,0 +1,54 @@
+//! `TEST PEER name`: connects to an existing peer and checks it answers
+//! queries, with `SELECT 1` for most peers, reporting whether that worked,
+//! how long it took and the error if not. A fresh connection is made, the
+//! ones of the session are left alone.
+
+use std::{sync::Arc, time::Duration};
+
+use peer_cursor::{error::client_error, Record, Records, Schema};
+use pgwire::{
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::PgWireError,
+};
+
+pub struct PeerTestResult {
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+pub fn schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("reachable", Type::BOOL),
+        field("latency_ms", Type::FLOAT8),
+        field("error", Type::TEXT),
+    ])
+}
+
+pub fn records(result: PeerTestResult) -> Records {
+    let schema = schema();
+    Records {
+        records: vec![Record {
+            values: vec![
+                value::Value::Bool(result.error.is_none()),
+                value::Value::Double(result.latency.as_secs_f64() * 1000.0),
+                result.error.map_or(value::Value::Null, value::Value::Text),
+            ],
+            schema: schema.clone(),
+        }],
+        schema,
+    }
+}
+
+/// The message of an error, without the wrapping of its pgwire variant.
+pub fn error_message(err: PgWireError) -> String {
+    match client_error(err) {
+        PgWireError::UserError(info) => info.message().clone(),
+        err => err.to_string(),
+    }
+}
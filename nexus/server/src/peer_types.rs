@@ -0,0 +1,51 @@
+use peer_bigquery::BigQueryQueryExecutor;
+use peer_clickhouse::ClickhouseQueryExecutor;
+use peer_cursor::PeerCapabilities;
+use peer_mysql::MySqlQueryExecutor;
+use peer_postgres::PostgresQueryExecutor;
+use peer_snowflake::SnowflakeQueryExecutor;
+use pgwire::api::{
+    results::{FieldFormat, FieldInfo},
+    Type,
+};
+use pt::peerdb_peers::{DbType, Peer};
+
+/// The peer types `CREATE PEER` accepts with what nexus can do with them,
+/// read through `peerdb.peer_types`. Types without a query executor can only
+/// be used for mirrors.
+pub const PEER_TYPES: &[(DbType, PeerCapabilities)] = &[
+    (DbType::Bigquery, BigQueryQueryExecutor::CAPABILITIES),
+    (DbType::Clickhouse, ClickhouseQueryExecutor::CAPABILITIES),
+    (DbType::Elasticsearch, PeerCapabilities::NONE),
+    (DbType::Eventhubs, PeerCapabilities::NONE),
+    (DbType::Kafka, PeerCapabilities::NONE),
+    (DbType::Mongo, PeerCapabilities::NONE),
+    (DbType::Mysql, MySqlQueryExecutor::CAPABILITIES),
+    (DbType::Postgres, PostgresQueryExecutor::CAPABILITIES),
+    (DbType::Pubsub, PeerCapabilities::NONE),
+    (DbType::S3, PeerCapabilities::NONE),
+    (DbType::Snowflake, SnowflakeQueryExecutor::CAPABILITIES),
+    (DbType::Sqlserver, PeerCapabilities::NONE),
+];
+
+pub fn schema() -> Vec<FieldInfo> {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    vec![
+        field("peer_type", Type::TEXT),
+        field("queryable", Type::BOOL),
+        field("writable", Type::BOOL),
+        field("supports_cursors", Type::BOOL),
+        field("supports_transactions", Type::BOOL),
+        field("supports_copy", Type::BOOL),
+    ]
+}
+
+/// What nexus can do with the peer, nothing for types without an executor.
+pub fn capabilities(peer: &Peer) -> PeerCapabilities {
+    PEER_TYPES
+        .iter()
+        .find(|(db_type, _)| *db_type as i32 == peer.r#type)
+        .map_or(PeerCapabilities::NONE, |(_, capabilities)| *capabilities)
+}
@@ -0,0 +1,143 @@
+//! Parameters of the extended protocol are bound into the query text before
+//! it is routed, as literals. The values are quoted the way postgres'
+//! `quote_literal` does, and the placeholders are replaced in one pass that
+//! leaves string literals, quoted identifiers and comments alone, so no value
+//! can end its literal or be taken for another placeholder.
+
+use std::fmt::Write;
+
+/// The value as a string literal, an `E''` literal with doubled backslashes
+/// when it holds one so that it reads the same whatever
+/// `standard_conforming_strings` is.
+pub fn quote_literal(value: &str) -> String {
+    let escaped = value.replace('\'', "''");
+    if escaped.contains('\\') {
+        format!("E'{}'", escaped.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", escaped)
+    }
+}
+
+/// A bytea literal of the bytes in postgres' hex format.
+pub fn bytea_literal(value: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + value.len() * 2);
+    hex.push_str("\\x");
+    for byte in value {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    format!("{}::bytea", quote_literal(&hex))
+}
+
+/// The query with `$1`, `$2`, ... replaced by the values, placeholders past
+/// the values are kept.
+pub fn bind(sql: &str, values: &[String]) -> String {
+    let bytes = sql.as_bytes();
+    let mut bound = String::with_capacity(sql.len());
+    // the start of the text not copied to `bound` yet
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"') => i = skip_quoted(bytes, i, quote),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = sql[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_block_comment(bytes, i),
+            // part of an identifier such as `a$1`
+            b'$' if i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_') => {
+                i += 1
+            }
+            b'$' => {
+                let digits = bytes[i + 1..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count();
+                if digits > 0 {
+                    let end = i + 1 + digits;
+                    let value = sql[i + 1..end]
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| values.get(n.checked_sub(1)?));
+                    if let Some(value) = value {
+                        bound.push_str(&sql[copied..i]);
+                        bound.push_str(value);
+                        copied = end;
+                    }
+                    i = end;
+                } else {
+                    i = skip_dollar_quoted(sql, i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bound.push_str(&sql[copied..]);
+    bound
+}
+
+// past the closing quote, a doubled quote doesn't close
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        // backslashes escape in E'' literals
+        if bytes[i] == b'\\' && quote == b'\'' && is_escape_string(bytes, start) {
+            i += 2;
+            continue;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+fn is_escape_string(bytes: &[u8], quote: usize) -> bool {
+    quote > 0
+        && matches!(bytes[quote - 1], b'e' | b'E')
+        && (quote < 2 || !(bytes[quote - 2].is_ascii_alphanumeric() || bytes[quote - 2] == b'_'))
+}
+
+// past the end of the comment, block comments nest
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+// past the end of a `$tag$ ... $tag$` string, or just past the `$` when it
+// doesn't start one
+fn skip_dollar_quoted(sql: &str, start: usize) -> usize {
+    let tag_len = sql[start + 1..]
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        .count();
+    let tag_end = start + 1 + tag_len;
+    if sql.as_bytes().get(tag_end) != Some(&b'$') {
+        return start + 1;
+    }
+    let tag = &sql[start..=tag_end];
+    sql[tag_end + 1..]
+        .find(tag)
+        .map_or(sql.len(), |end| tag_end + 1 + end + tag.len())
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use peer_cursor::{Record, Records, Schema};
+use peerdb_parser::NexusParsedStatement;
+use pgwire::{
+    api::{portal::Portal, stmt::StoredStatement},
+    error::PgWireResult,
+};
+use tokio::sync::mpsc;
+
+use crate::memory::{record_size, MemoryAccount, Reservation};
+
+/// The rows left of a portal executed with a row limit, returned by the
+/// portal's next Execute messages so several portals of a connection can be
+/// read in turns. A drained portal is kept too, executing it again returns no
+/// rows instead of running the query again.
+///
+/// The rows are pulled from the peer in the background: a peer connection
+/// answers its queries in order, so a portal left unread would otherwise
+/// hold up the queries of the other portals on the same peer. The rows read
+/// ahead count towards the connection's memory limit, a portal exceeding it
+/// stops reading and fails its next Execute with 53200.
+pub struct SuspendedPortal {
+    statement: Arc<StoredStatement<NexusParsedStatement>>,
+    parameters: Vec<Option<Bytes>>,
+    schema: Schema,
+    rows: mpsc::UnboundedReceiver<PgWireResult<(Record, Reservation)>>,
+    drained: bool,
+}
+
+impl SuspendedPortal {
+    pub fn new<S>(
+        portal: &Portal<NexusParsedStatement>,
+        schema: Schema,
+        mut stream: S,
+        memory: &Arc<MemoryAccount>,
+    ) -> Self
+    where
+        S: Stream<Item = PgWireResult<Record>> + Send + Unpin + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let memory = Arc::clone(memory);
+        tokio::spawn(async move {
+            while let Some(row) = stream.next().await {
+                let row = row.and_then(|record| {
+                    let mut reservation = memory.reservation();
+                    reservation.grow(record_size(&record))?;
+                    Ok((record, reservation))
+                });
+                let failed = row.is_err();
+                // the portal is gone when the receiver is
+                if tx.send(row).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            statement: Arc::clone(&portal.statement),
+            parameters: portal.parameters.clone(),
+            schema,
+            rows: rx,
+            drained: false,
+        }
+    }
+
+    /// Whether the portal is still the one this was created for, and not one
+    /// bound under the same name since.
+    pub fn is_bound_to(&self, portal: &Portal<NexusParsedStatement>) -> bool {
+        Arc::ptr_eq(&self.statement, &portal.statement) && self.parameters == portal.parameters
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.drained
+    }
+
+    /// The next `max_rows` rows, all of them for 0.
+    pub async fn fetch(&mut self, max_rows: usize) -> PgWireResult<Records> {
+        let max_rows = if max_rows == 0 { usize::MAX } else { max_rows };
+        let mut records = Vec::new();
+        while records.len() < max_rows {
+            match self.rows.recv().await {
+                // rows handed out for sending no longer count as buffered
+                Some(row) => records.push(row?.0),
+                None => {
+                    self.drained = true;
+                    break;
+                }
+            }
+        }
+        Ok(Records {
+            records,
+            schema: self.schema.clone(),
+        })
+    }
+}
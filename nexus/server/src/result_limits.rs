@@ -0,0 +1,114 @@
+//! Guards against results too wide for nexus and its clients: a query
+//! returning more columns than `max_result_columns` fails before any row is
+//! read, and one returning a value larger than `max_result_value_size` fails
+//! on the row holding it, before the row is buffered, spooled or encoded.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{Stream, StreamExt};
+use peer_cursor::{QueryOutput, Record, RecordStream, Schema, SendableStream};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResultLimits {
+    pub max_columns: Option<usize>,
+    pub max_value_size: Option<usize>,
+}
+
+impl ResultLimits {
+    /// The output, failing when it has too many columns and, for its rows,
+    /// on the first value that is too large.
+    pub fn apply(self, output: QueryOutput) -> PgWireResult<QueryOutput> {
+        match output {
+            QueryOutput::Stream(stream) => {
+                self.check_columns(&stream.schema())?;
+                match self.max_value_size {
+                    Some(max_value_size) => Ok(QueryOutput::Stream(Box::pin(LimitedStream {
+                        stream,
+                        max_value_size,
+                    }))),
+                    None => Ok(QueryOutput::Stream(stream)),
+                }
+            }
+            QueryOutput::Records(records) => {
+                self.check_columns(&records.schema)?;
+                if let Some(max_value_size) = self.max_value_size {
+                    for record in &records.records {
+                        check_values(record, max_value_size)?;
+                    }
+                }
+                Ok(QueryOutput::Records(records))
+            }
+            output => Ok(output),
+        }
+    }
+
+    fn check_columns(&self, schema: &Schema) -> PgWireResult<()> {
+        match self.max_columns {
+            Some(max_columns) if schema.len() > max_columns => Err(limit_error(
+                "54011",
+                format!(
+                    "result has {} columns, more than max_result_columns of {}",
+                    schema.len(),
+                    max_columns
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_values(record: &Record, max_value_size: usize) -> PgWireResult<()> {
+    for (column, value) in record.values.iter().enumerate() {
+        let size = value.approximate_size();
+        if size > max_value_size {
+            let name = record
+                .schema
+                .get(column)
+                .map_or("?column?", |field| field.name().as_str());
+            return Err(limit_error(
+                "54000",
+                format!(
+                    "value of column \"{}\" takes {} bytes, more than max_result_value_size of {} bytes",
+                    name, size, max_value_size
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn limit_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        code.to_owned(),
+        message,
+    )))
+}
+
+struct LimitedStream {
+    stream: SendableStream,
+    max_value_size: usize,
+}
+
+impl Stream for LimitedStream {
+    type Item = PgWireResult<Record>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let max_value_size = self.max_value_size;
+        self.stream.poll_next_unpin(cx).map(|record| {
+            record.map(|record| {
+                record.and_then(|record| check_values(&record, max_value_size).map(|()| record))
+            })
+        })
+    }
+}
+
+impl RecordStream for LimitedStream {
+    fn schema(&self) -> Schema {
+        self.stream.schema()
+    }
+}
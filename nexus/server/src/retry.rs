@@ -0,0 +1,61 @@
+use std::{future::Future, time::Duration};
+
+use tokio::time::Instant;
+
+/// How connecting to a peer is retried when its executor is created, so a
+/// peer that is briefly unreachable, e.g. during a failover, doesn't fail the
+/// query right away.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectRetryPolicy {
+    /// Attempts in total, 1 disables retries.
+    pub attempts: u32,
+    /// Wait before the second attempt, doubled after every failed attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound on the time spent connecting across all attempts.
+    pub max_wait: Duration,
+}
+
+impl ConnectRetryPolicy {
+    /// Runs `connect` until it succeeds, the attempts are used up or the next
+    /// attempt would end past `max_wait`, returning the last error then.
+    pub async fn run<T, F, Fut>(&self, peer_name: &str, mut connect: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let deadline = Instant::now() + self.max_wait;
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let err = match tokio::time::timeout(remaining, connect()).await {
+                Ok(Ok(connected)) => return Ok(connected),
+                Ok(Err(err)) => err,
+                Err(_) => anyhow::anyhow!(
+                    "timed out connecting to peer {} after {:?}",
+                    peer_name,
+                    self.max_wait
+                ),
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if attempt >= self.attempts || backoff >= remaining {
+                return Err(err.context(format!(
+                    "unable to connect to peer {} after {} attempts",
+                    peer_name, attempt
+                )));
+            }
+            tracing::warn!(
+                "connecting to peer {} failed (attempt {}/{}), retrying in {:?}: {:?}",
+                peer_name,
+                attempt,
+                self.attempts,
+                backoff,
+                err
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
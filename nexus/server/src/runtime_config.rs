@@ -0,0 +1,261 @@
+//! Server settings an admin can change without a restart, with
+//! `SELECT peerdb.set_config('name', 'value')`, and read through
+//! `peerdb.config`.
+//!
+//! Changes are kept in the catalog's `nexus_settings` table and take effect
+//! at once for every connection of this nexus. They are loaded again at
+//! startup, where they win over the command line flags. Other nexus
+//! instances sharing the catalog pick them up when they restart.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use catalog::Catalog;
+use pgwire::{
+    api::{
+        results::{FieldFormat, FieldInfo},
+        Type,
+    },
+    error::{ErrorInfo, PgWireError, PgWireResult},
+};
+
+use crate::session::{parse_size, parse_timeout};
+
+pub const STATEMENT_TIMEOUT: &str = "statement_timeout";
+pub const SLOW_QUERY_THRESHOLD: &str = "slow_query_threshold";
+pub const SECRET_CACHE_TTL: &str = "secret_cache_ttl";
+pub const CONNECTION_MEMORY_LIMIT: &str = "connection_memory_limit";
+pub const CONNECT_BANNER: &str = "connect_banner";
+pub const MAX_RESULT_COLUMNS: &str = "max_result_columns";
+pub const MAX_RESULT_VALUE_SIZE: &str = "max_result_value_size";
+
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    (
+        CONNECT_BANNER,
+        "Sets a notice sent to clients once they are connected, empty for none.",
+    ),
+    (
+        CONNECTION_MEMORY_LIMIT,
+        "Sets the memory a connection may hold in buffered rows, 0 for no limit.",
+    ),
+    (
+        MAX_RESULT_COLUMNS,
+        "Sets the number of columns a result may have, 0 for no limit.",
+    ),
+    (
+        MAX_RESULT_VALUE_SIZE,
+        "Sets the size a single value of a result may take, 0 for no limit.",
+    ),
+    (
+        SECRET_CACHE_TTL,
+        "Sets how long secrets referenced by peer configs are cached.",
+    ),
+    (
+        SLOW_QUERY_THRESHOLD,
+        "Logs queries running at least this long, 0 turns logging off.",
+    ),
+    (
+        STATEMENT_TIMEOUT,
+        "Sets the statement timeout of sessions that don't set one, 0 for none.",
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct RuntimeSettings {
+    /// Timeout of the statements of sessions without a `statement_timeout`.
+    pub statement_timeout: Option<Duration>,
+    /// Queries on peers running at least this long are logged.
+    pub slow_query_threshold: Option<Duration>,
+    pub secret_cache_ttl: Duration,
+    /// Bytes of buffered rows a connection may hold before its query fails.
+    pub connection_memory_limit: Option<usize>,
+    /// Sent to clients as a notice once they are connected.
+    pub connect_banner: Option<String>,
+    /// Results with more columns fail before any row is read.
+    pub max_result_columns: Option<usize>,
+    /// Results fail on the first value taking more bytes.
+    pub max_result_value_size: Option<usize>,
+}
+
+impl RuntimeSettings {
+    fn set(&mut self, name: &str, value: &str) -> PgWireResult<()> {
+        let invalid = |hint: &str| {
+            PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "22023".to_owned(),
+                format!(
+                    "invalid value for setting \"{}\": \"{}\", {}",
+                    name, value, hint
+                ),
+            )))
+        };
+        let duration = || {
+            parse_timeout(value)
+                .ok_or_else(|| invalid("expected milliseconds or a duration like '1s'"))
+        };
+        match name {
+            STATEMENT_TIMEOUT => self.statement_timeout = duration()?,
+            SLOW_QUERY_THRESHOLD => self.slow_query_threshold = duration()?,
+            SECRET_CACHE_TTL => self.secret_cache_ttl = duration()?.unwrap_or_default(),
+            CONNECTION_MEMORY_LIMIT => {
+                let limit = parse_size(value).ok_or_else(|| {
+                    invalid("expected a number of kilobytes or a size like '1GB'")
+                })?;
+                self.connection_memory_limit = (limit > 0).then_some(limit);
+            }
+            MAX_RESULT_COLUMNS => {
+                let limit = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| invalid("expected a number of columns"))?;
+                self.max_result_columns = (limit > 0).then_some(limit);
+            }
+            MAX_RESULT_VALUE_SIZE => {
+                let limit = parse_size(value).ok_or_else(|| {
+                    invalid("expected a number of kilobytes or a size like '1GB'")
+                })?;
+                self.max_result_value_size = (limit > 0).then_some(limit);
+            }
+            CONNECT_BANNER => {
+                self.connect_banner = (!value.is_empty()).then(|| value.to_owned());
+            }
+            _ => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "42704".to_owned(),
+                    format!("unrecognized nexus setting \"{}\"", name),
+                ))))
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or_else(|| "0".to_owned(), |d| format!("{}ms", d.as_millis()))
+        };
+        match name {
+            STATEMENT_TIMEOUT => Some(millis(self.statement_timeout)),
+            SLOW_QUERY_THRESHOLD => Some(millis(self.slow_query_threshold)),
+            SECRET_CACHE_TTL => Some(millis(Some(self.secret_cache_ttl))),
+            CONNECTION_MEMORY_LIMIT => Some(
+                self.connection_memory_limit
+                    .map_or_else(|| "0".to_owned(), |limit| format!("{}kB", limit / 1024)),
+            ),
+            MAX_RESULT_COLUMNS => Some(self.max_result_columns.unwrap_or(0).to_string()),
+            // values may be limited to less than a kilobyte
+            MAX_RESULT_VALUE_SIZE => Some(match self.max_result_value_size {
+                None => "0".to_owned(),
+                Some(limit) if limit % 1024 == 0 => format!("{}kB", limit / 1024),
+                Some(limit) => format!("{}B", limit),
+            }),
+            CONNECT_BANNER => Some(self.connect_banner.clone().unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+/// The current runtime settings, shared by all connections.
+pub struct RuntimeConfig {
+    settings: RwLock<RuntimeSettings>,
+    // users allowed to change the settings, and the query rewrite rules
+    admin_users: Vec<String>,
+}
+
+impl RuntimeConfig {
+    pub fn new(defaults: RuntimeSettings, admin_users: Vec<String>) -> Arc<Self> {
+        Arc::new(Self {
+            settings: RwLock::new(defaults),
+            admin_users,
+        })
+    }
+
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.admin_users.iter().any(|admin| admin == user)
+    }
+
+    pub fn get(&self) -> RuntimeSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// The connection memory limit, read for every buffered row without
+    /// copying the other settings.
+    pub fn connection_memory_limit(&self) -> Option<usize> {
+        self.settings.read().unwrap().connection_memory_limit
+    }
+
+    /// Applies the settings stored in the catalog. A stored value that is no
+    /// longer valid is skipped, so it can't keep nexus from starting.
+    pub async fn load(&self, catalog: &Catalog) -> anyhow::Result<()> {
+        let stored = catalog.get_nexus_settings().await?;
+        let mut settings = self.settings.write().unwrap();
+        for (name, value) in stored {
+            match settings.set(&name, &value) {
+                Ok(()) => tracing::info!("nexus setting {} = {} from catalog", name, value),
+                Err(err) => {
+                    tracing::warn!("ignoring nexus setting {} from catalog: {}", name, err)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes a setting for the user, returning the new settings and the
+    /// value as `peerdb.config` shows it.
+    pub async fn set(
+        &self,
+        catalog: &Catalog,
+        user: &str,
+        name: &str,
+        value: &str,
+    ) -> PgWireResult<(RuntimeSettings, String)> {
+        if !self.is_admin(user) {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "42501".to_owned(),
+                format!(
+                    "permission denied to change nexus setting \"{}\", \"{}\" is not an admin user",
+                    name, user
+                ),
+            ))));
+        }
+
+        let name = name.to_lowercase();
+        let mut settings = self.get();
+        settings.set(&name, value)?;
+        let shown = settings.get(&name).unwrap_or_default();
+        catalog
+            .set_nexus_setting(&name, &shown)
+            .await
+            .map_err(|err| {
+                PgWireError::ApiError(format!("unable to store nexus setting: {:?}", err).into())
+            })?;
+        // settings changed concurrently are applied one after the other
+        let mut current = self.settings.write().unwrap();
+        current.set(&name, value)?;
+        Ok((current.clone(), shown))
+    }
+
+    /// Rows of `peerdb.config` as (name, setting, description).
+    pub fn rows(&self) -> Vec<(String, String, String)> {
+        let settings = self.get();
+        DESCRIPTIONS
+            .iter()
+            .map(|(name, description)| {
+                (
+                    name.to_string(),
+                    settings.get(name).unwrap_or_default(),
+                    description.to_string(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn schema() -> Vec<FieldInfo> {
+        let field =
+            |name: &str| FieldInfo::new(name.to_owned(), None, None, Type::TEXT, FieldFormat::Text);
+        vec![field("name"), field("setting"), field("description")]
+    }
+}
@@ -0,0 +1,422 @@
+//! Peer credentials kept in a secret manager instead of the catalog.
+//!
+//! A credential option of a peer can be a reference of the form
+//! `secret://<backend>/<path>[#<key>]`, e.g.
+//! `password = 'secret://vault/secret/data/pg_prod#password'`. The reference
+//! is what the catalog stores, the secret is fetched when an executor for the
+//! peer is created. With a key, the secret is a JSON object and the key's
+//! field is used. Backends:
+//! - `aws`: AWS Secrets Manager, the path is the secret id or ARN.
+//! - `gcp`: GCP Secret Manager, the path is the secret's resource name,
+//!   `projects/<project>/secrets/<secret>`, its latest version unless the
+//!   name ends in `/versions/<version>`. Authenticates through the metadata
+//!   server, so only on GCP.
+//! - `vault`: the KV engine of HashiCorp Vault at `VAULT_ADDR`, the path is
+//!   the API path after `/v1/`, read with `VAULT_TOKEN`.
+//!
+//! The secret is sent to the peer's host, so only admin users can create or
+//! alter peers with secret references.
+//!
+//! Resolved secrets are cached for a while, and dropped when connecting to
+//! the peer fails so a rotated secret is fetched again.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
+use base64::{engine::general_purpose, Engine as _};
+use pt::peerdb_peers::{peer::Config, Peer};
+use serde_json::Value as JsonValue;
+
+const SECRET_SCHEME: &str = "secret://";
+
+/// Fetches secrets from one secret manager.
+#[async_trait]
+pub trait SecretResolver: Send + Sync {
+    /// The secret at `path`, the `key` field of it when given.
+    async fn resolve(&self, path: &str, key: Option<&str>) -> anyhow::Result<String>;
+}
+
+struct SecretRef<'a> {
+    backend: &'a str,
+    path: &'a str,
+    key: Option<&'a str>,
+}
+
+impl<'a> SecretRef<'a> {
+    fn parse(value: &'a str) -> Option<Self> {
+        let reference = value.strip_prefix(SECRET_SCHEME)?;
+        let (reference, key) = match reference.rsplit_once('#') {
+            Some((reference, key)) => (reference, Some(key)),
+            None => (reference, None),
+        };
+        let (backend, path) = reference.split_once('/')?;
+        Some(Self { backend, path, key })
+    }
+}
+
+/// The resolvers of the secret references in peer configs, with the secrets
+/// they resolved recently.
+pub struct SecretStore {
+    resolvers: HashMap<String, Arc<dyn SecretResolver>>,
+    ttl: Mutex<Duration>,
+    cache: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl SecretStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            resolvers: HashMap::new(),
+            ttl: Mutex::new(ttl),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The store with every backend nexus knows, vault only when `VAULT_ADDR`
+    /// is set.
+    pub fn with_default_resolvers(ttl: Duration) -> Self {
+        let mut store = Self::new(ttl)
+            .with_resolver("aws", Arc::new(AwsSecretsManagerResolver))
+            .with_resolver("gcp", Arc::new(GcpSecretManagerResolver::new()));
+        if let Ok(addr) = std::env::var("VAULT_ADDR") {
+            let token = std::env::var("VAULT_TOKEN").unwrap_or_default();
+            store = store.with_resolver("vault", Arc::new(VaultResolver::new(addr, token)));
+        }
+        store
+    }
+
+    pub fn with_resolver(mut self, backend: &str, resolver: Arc<dyn SecretResolver>) -> Self {
+        self.resolvers.insert(backend.to_owned(), resolver);
+        self
+    }
+
+    /// Changes how long secrets are cached, for the ones cached already too.
+    pub fn set_ttl(&self, ttl: Duration) {
+        *self.ttl.lock().unwrap() = ttl;
+    }
+
+    /// The value itself, or the secret it refers to.
+    pub async fn resolve(&self, value: &str) -> anyhow::Result<String> {
+        let Some(reference) = SecretRef::parse(value) else {
+            if value.starts_with(SECRET_SCHEME) {
+                anyhow::bail!("invalid secret reference {}", value);
+            }
+            return Ok(value.to_owned());
+        };
+        let ttl = *self.ttl.lock().unwrap();
+        if let Some((secret, resolved_at)) = self.cache.lock().unwrap().get(value) {
+            if resolved_at.elapsed() < ttl {
+                return Ok(secret.clone());
+            }
+        }
+
+        let resolver = self.resolvers.get(reference.backend).with_context(|| {
+            format!(
+                "unknown secret backend \"{}\" in {}",
+                reference.backend, value
+            )
+        })?;
+        let secret = resolver
+            .resolve(reference.path, reference.key)
+            .await
+            .with_context(|| format!("unable to resolve secret {}", value))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(value.to_owned(), (secret.clone(), Instant::now()));
+        Ok(secret)
+    }
+
+    /// A copy of the peer with the secret references in its credentials
+    /// replaced by the secrets.
+    pub async fn resolve_peer(&self, peer: &Peer) -> anyhow::Result<Peer> {
+        let mut peer = peer.clone();
+        if let Some(config) = peer.config.as_mut() {
+            for credential in credentials_mut(config) {
+                *credential = self.resolve(credential.as_str()).await?;
+            }
+        }
+        Ok(peer)
+    }
+
+    /// Forgets all secrets, e.g. after they were rotated.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Forgets the secrets of the peer, e.g. after they failed to connect.
+    pub fn invalidate(&self, peer: &Peer) {
+        let mut config = peer.config.clone();
+        let mut cache = self.cache.lock().unwrap();
+        for credential in config.iter_mut().flat_map(credentials_mut) {
+            cache.remove(credential.as_str());
+        }
+    }
+}
+
+/// Whether a credential of the peer is a secret reference.
+pub fn has_secret_references(peer: &Peer) -> bool {
+    let mut config = peer.config.clone();
+    config
+        .iter_mut()
+        .flat_map(credentials_mut)
+        .any(|credential| credential.starts_with(SECRET_SCHEME))
+}
+
+// the options of a peer that may hold secret references
+fn credentials_mut(config: &mut Config) -> Vec<&mut String> {
+    match config {
+        Config::PostgresConfig(c) => vec![&mut c.password],
+        Config::MysqlConfig(c) => vec![&mut c.password],
+        Config::ClickhouseConfig(c) => vec![&mut c.password],
+        Config::BigqueryConfig(c) => vec![&mut c.private_key],
+        Config::SnowflakeConfig(c) => {
+            let mut credentials = vec![&mut c.private_key];
+            credentials.extend(c.password.as_mut());
+            credentials
+        }
+        _ => Vec::new(),
+    }
+}
+
+// a secret with a key is a JSON object holding the value under the key
+fn secret_field(secret: String, key: Option<&str>) -> anyhow::Result<String> {
+    let Some(key) = key else {
+        return Ok(secret);
+    };
+    let object: JsonValue = serde_json::from_str(&secret).context("secret is not JSON")?;
+    json_field(&object, key)
+}
+
+fn json_field(object: &JsonValue, key: &str) -> anyhow::Result<String> {
+    match object.get(key) {
+        Some(JsonValue::String(value)) => Ok(value.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => anyhow::bail!("secret has no key \"{}\"", key),
+    }
+}
+
+struct AwsSecretsManagerResolver;
+
+#[async_trait]
+impl SecretResolver for AwsSecretsManagerResolver {
+    async fn resolve(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+        let config = aws_config::defaults(BehaviorVersion::v2024_03_28())
+            .region(region_provider)
+            .load()
+            .await;
+        let client = aws_sdk_secretsmanager::Client::new(&config);
+        let output = client.get_secret_value().secret_id(path).send().await?;
+        let secret = output
+            .secret_string()
+            .context("only string secrets are supported")?;
+        secret_field(secret.to_owned(), key)
+    }
+}
+
+struct GcpSecretManagerResolver {
+    client: reqwest::Client,
+}
+
+impl GcpSecretManagerResolver {
+    fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // an access token of the service account of the instance
+    async fn access_token(&self) -> anyhow::Result<String> {
+        let token: JsonValue = self
+            .client
+            .get("http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token")
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        json_field(&token, "access_token")
+    }
+}
+
+#[async_trait]
+impl SecretResolver for GcpSecretManagerResolver {
+    async fn resolve(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let version = if path.contains("/versions/") {
+            path.to_owned()
+        } else {
+            format!("{}/versions/latest", path)
+        };
+        let response: JsonValue = self
+            .client
+            .get(format!(
+                "https://secretmanager.googleapis.com/v1/{}:access",
+                version
+            ))
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let data = response
+            .pointer("/payload/data")
+            .and_then(JsonValue::as_str)
+            .context("secret has no payload")?;
+        let secret = String::from_utf8(general_purpose::STANDARD.decode(data)?)?;
+        secret_field(secret, key)
+    }
+}
+
+struct VaultResolver {
+    addr: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl VaultResolver {
+    fn new(addr: String, token: String) -> Self {
+        Self {
+            addr: addr.trim_end_matches('/').to_owned(),
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretResolver for VaultResolver {
+    async fn resolve(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let response: JsonValue = self
+            .client
+            .get(format!("{}/v1/{}", self.addr, path))
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // version 2 of the KV engine nests the secret's fields once more
+        let data = response
+            .pointer("/data/data")
+            .or_else(|| response.get("data"))
+            .context("vault response has no data")?;
+        match (key, data.as_object()) {
+            (Some(key), _) => json_field(data, key),
+            (None, Some(fields)) if fields.len() == 1 => {
+                json_field(data, fields.keys().next().unwrap())
+            }
+            (None, _) => anyhow::bail!("vault secrets with several fields need a #key"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use pt::peerdb_peers::PostgresConfig;
+
+    use super::*;
+
+    // a secret manager holding one JSON secret, counting its lookups
+    #[derive(Default)]
+    struct MockResolver {
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl SecretResolver for MockResolver {
+        async fn resolve(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            anyhow::ensure!(path == "pg_prod", "no secret at {}", path);
+            secret_field(r#"{"user": "app", "password": "hunter2"}"#.to_owned(), key)
+        }
+    }
+
+    fn store(ttl: Duration) -> (SecretStore, Arc<MockResolver>) {
+        let resolver = Arc::new(MockResolver::default());
+        let store = SecretStore::new(ttl).with_resolver("mock", resolver.clone());
+        (store, resolver)
+    }
+
+    fn peer(password: &str) -> Peer {
+        Peer {
+            name: "pg".to_owned(),
+            config: Some(Config::PostgresConfig(PostgresConfig {
+                password: password.to_owned(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn password(peer: &Peer) -> &str {
+        match &peer.config {
+            Some(Config::PostgresConfig(config)) => &config.password,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn key_is_extracted_from_secret() {
+        let (store, _) = store(Duration::from_secs(60));
+
+        let secret = store.resolve("secret://mock/pg_prod#password").await;
+        assert_eq!(secret.unwrap(), "hunter2");
+        let secret = store.resolve("secret://mock/pg_prod").await;
+        assert_eq!(secret.unwrap(), r#"{"user": "app", "password": "hunter2"}"#);
+        assert!(store.resolve("secret://mock/pg_prod#token").await.is_err());
+        assert!(store.resolve("secret://env/HOME").await.is_err());
+        assert!(store.resolve("secret://mock").await.is_err());
+        assert_eq!(store.resolve("plain").await.unwrap(), "plain");
+    }
+
+    #[tokio::test]
+    async fn secrets_are_cached_for_their_ttl() {
+        let (store, resolver) = store(Duration::from_millis(100));
+        let reference = "secret://mock/pg_prod#password";
+
+        store.resolve(reference).await.unwrap();
+        store.resolve(reference).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        store.resolve(reference).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+
+        store.set_ttl(Duration::ZERO);
+        store.resolve(reference).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn invalidated_secrets_are_fetched_again() {
+        let (store, resolver) = store(Duration::from_secs(60));
+        let peer = peer("secret://mock/pg_prod#password");
+
+        let resolved = store.resolve_peer(&peer).await.unwrap();
+        assert_eq!(password(&resolved), "hunter2");
+        store.resolve_peer(&peer).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+
+        // as after connecting with the resolved peer failed
+        store.invalidate(&peer);
+        store.resolve_peer(&peer).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn secret_references_are_found_in_credentials() {
+        assert!(has_secret_references(&peer("secret://vault/pg#password")));
+        assert!(!has_secret_references(&peer("hunter2")));
+        assert!(!has_secret_references(&Peer::default()));
+    }
+}
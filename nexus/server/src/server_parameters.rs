@@ -0,0 +1,101 @@
+//! The parameters reported to clients when they connect, as ParameterStatus
+//! messages. Some clients and ORMs enable features by `server_version`, so
+//! it can be set to the version of the postgres servers nexus stands in
+//! for, and other parameters can be added.
+//!
+//! The encodings and DateStyle are always the ones nexus encodes values
+//! with, clients rely on them to read the rows.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use pgwire::api::{auth::ServerParameterProvider, ClientInfo};
+
+/// The parameters whose value can't be changed.
+const FIXED_PARAMETERS: &[(&str, &str)] = &[
+    ("server_encoding", "UTF8"),
+    ("client_encoding", "UTF8"),
+    ("DateStyle", "ISO, MDY"),
+];
+
+/// A parameter added with `--advertised-parameter name=value`.
+#[derive(Debug, Clone)]
+pub struct AdvertisedParameter {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for AdvertisedParameter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected name=value, got: {}", s))?;
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("missing parameter name in: {}", s);
+        }
+        if name.eq_ignore_ascii_case("server_version") {
+            anyhow::bail!("server_version is set with --advertised-server-version");
+        }
+        if let Some((fixed, _)) = FIXED_PARAMETERS
+            .iter()
+            .find(|(fixed, _)| fixed.eq_ignore_ascii_case(name))
+        {
+            anyhow::bail!("{} can't be changed, nexus sends values in it", fixed);
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            value: value.trim().to_owned(),
+        })
+    }
+}
+
+/// Parses `--advertised-server-version`: a postgres version like `14` or
+/// `15.4`, which clients read as a major version and an optional minor one.
+pub fn parse_server_version(s: &str) -> anyhow::Result<String> {
+    let version = s.trim();
+    let valid = version
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    if !valid || version.split('.').count() > 2 {
+        anyhow::bail!("expected a postgres version like 14 or 15.4, got: {}", s);
+    }
+    Ok(version.to_owned())
+}
+
+pub struct NexusServerParameterProvider {
+    parameters: Arc<HashMap<String, String>>,
+}
+
+impl NexusServerParameterProvider {
+    pub fn new(server_version: &str, extra: &[AdvertisedParameter]) -> Self {
+        let mut parameters = HashMap::with_capacity(FIXED_PARAMETERS.len() + 2 + extra.len());
+        parameters.insert("server_version".to_owned(), server_version.to_owned());
+        parameters.insert("integer_datetimes".to_owned(), "on".to_owned());
+        for parameter in extra {
+            parameters.insert(parameter.name.clone(), parameter.value.clone());
+        }
+        for (name, value) in FIXED_PARAMETERS {
+            parameters.insert((*name).to_owned(), (*value).to_owned());
+        }
+        Self {
+            parameters: Arc::new(parameters),
+        }
+    }
+
+    /// The parameters as sent, which `SHOW` reports for those the session
+    /// didn't set.
+    pub fn parameters(&self) -> Arc<HashMap<String, String>> {
+        self.parameters.clone()
+    }
+}
+
+impl ServerParameterProvider for NexusServerParameterProvider {
+    fn server_parameters<C>(&self, _client: &C) -> Option<HashMap<String, String>>
+    where
+        C: ClientInfo,
+    {
+        Some((*self.parameters).clone())
+    }
+}
@@ -0,0 +1,644 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use peer_cursor::labels::{QueryLabels, QUERY_LABELS};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+
+use crate::{copy::CopyCompression, insert_batch::InsertBatchConfig};
+
+pub const STATEMENT_TIMEOUT: &str = "statement_timeout";
+pub const IDLE_IN_TRANSACTION_SESSION_TIMEOUT: &str = "idle_in_transaction_session_timeout";
+pub const DRY_RUN: &str = "peerdb.dry_run";
+pub const AUTO_LIMIT: &str = "peerdb.auto_limit";
+pub const COPY_COMPRESSION: &str = "peerdb.copy_compression";
+pub const DEFAULT_PEER: &str = "peerdb.default_peer";
+pub const INSERT_BATCHING: &str = "peerdb.insert_batching";
+pub const INSERT_BATCH_SIZE: &str = "peerdb.insert_batch_size";
+pub const INSERT_BATCH_DELAY: &str = "peerdb.insert_batch_delay";
+pub const RESULT_FORMAT: &str = "peerdb.result_format";
+pub const SPOOL_LARGE_RESULTS: &str = "peerdb.spool_large_results";
+pub const SPOOL_THRESHOLD: &str = "peerdb.spool_threshold";
+pub const DEFAULT_TRANSACTION_ISOLATION: &str = "default_transaction_isolation";
+pub const DEFAULT_TRANSACTION_READ_ONLY: &str = "default_transaction_read_only";
+pub const APPLICATION_NAME: &str = "application_name";
+pub const SEARCH_PATH: &str = "search_path";
+pub const TIMEZONE: &str = "timezone";
+
+const DEFAULT_ISOLATION_LEVEL: &str = "read committed";
+const ISOLATION_LEVELS: &[&str] = &[
+    "serializable",
+    "repeatable read",
+    "read committed",
+    "read uncommitted",
+];
+
+const DEFAULT_INSERT_BATCH: InsertBatchConfig = InsertBatchConfig {
+    max_rows: 1000,
+    max_delay: Duration::from_millis(100),
+};
+
+const DEFAULT_SPOOL_THRESHOLD: usize = 64 * 1024 * 1024;
+
+// variables that are also applied on the peers of the connection.
+const FORWARDED_PARAMETERS: &[&str] = &[
+    STATEMENT_TIMEOUT,
+    APPLICATION_NAME,
+    // after application_name, which postgres peers replace with the labels
+    QUERY_LABELS,
+    DEFAULT_TRANSACTION_ISOLATION,
+    DEFAULT_TRANSACTION_READ_ONLY,
+    SEARCH_PATH,
+    TIMEZONE,
+];
+
+pub struct Guc {
+    pub name: &'static str,
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+/// Settings known to nexus, reported by `SHOW` and `SHOW ALL` with their
+/// default when the session didn't set them.
+pub const GUCS: &[Guc] = &[
+    Guc {
+        name: APPLICATION_NAME,
+        default: "",
+        description: "Sets the application name to be reported in statistics and logs.",
+    },
+    Guc {
+        name: "client_encoding",
+        default: "UTF8",
+        description: "Sets the client's character set encoding.",
+    },
+    Guc {
+        name: "DateStyle",
+        default: "ISO, MDY",
+        description: "Sets the display format for date and time values.",
+    },
+    Guc {
+        name: DEFAULT_TRANSACTION_ISOLATION,
+        default: DEFAULT_ISOLATION_LEVEL,
+        description: "Sets the transaction isolation level of each new transaction.",
+    },
+    Guc {
+        name: DEFAULT_TRANSACTION_READ_ONLY,
+        default: "off",
+        description: "Sets the default read-only status of new transactions.",
+    },
+    Guc {
+        name: "extra_float_digits",
+        default: "1",
+        description: "Sets the number of digits displayed for floating-point values.",
+    },
+    Guc {
+        name: IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+        default: "0",
+        description: "Sets the maximum allowed idle time between queries, when in a transaction.",
+    },
+    Guc {
+        name: "integer_datetimes",
+        default: "on",
+        description: "Shows whether datetimes are integer based.",
+    },
+    Guc {
+        name: "IntervalStyle",
+        default: "postgres",
+        description: "Sets the display format for interval values.",
+    },
+    Guc {
+        name: "max_identifier_length",
+        default: "63",
+        description: "Shows the maximum identifier length.",
+    },
+    Guc {
+        name: AUTO_LIMIT,
+        default: "0",
+        description: "Adds this LIMIT to SELECTs on peers without one, 0 turns it off.",
+    },
+    Guc {
+        name: COPY_COMPRESSION,
+        default: "none",
+        description: "Sets the compression of COPY TO STDOUT data, none or gzip.",
+    },
+    Guc {
+        name: DEFAULT_PEER,
+        default: "",
+        description: "Sets the peer that queries of tables not qualified with a peer run on.",
+    },
+    Guc {
+        name: DRY_RUN,
+        default: "off",
+        description: "Plans queries and estimates their cost instead of running them.",
+    },
+    Guc {
+        name: INSERT_BATCHING,
+        default: "off",
+        description: "Buffers INSERTs into postgres peers and sends them as multi-row INSERTs.",
+    },
+    Guc {
+        name: INSERT_BATCH_SIZE,
+        default: "1000",
+        description: "Sets the number of buffered rows at which batched INSERTs are sent.",
+    },
+    Guc {
+        name: INSERT_BATCH_DELAY,
+        default: "100ms",
+        description: "Sets the maximum time batched INSERTs are buffered before being sent.",
+    },
+    Guc {
+        name: QUERY_LABELS,
+        default: "",
+        description: "Sets key=value labels sent to peers with queries, for cost attribution.",
+    },
+    Guc {
+        name: RESULT_FORMAT,
+        default: "postgres",
+        description:
+            "Sets the format of query results, postgres rows or an arrow stream sent as COPY data.",
+    },
+    Guc {
+        name: SEARCH_PATH,
+        default: "\"$user\", public",
+        description: "Sets the schema search order for names that are not schema-qualified.",
+    },
+    Guc {
+        name: "server_encoding",
+        default: "UTF8",
+        description: "Shows the server (database) character set encoding.",
+    },
+    Guc {
+        name: "server_version",
+        default: "14",
+        description: "Shows the server version.",
+    },
+    Guc {
+        name: SPOOL_LARGE_RESULTS,
+        default: "off",
+        description: "Reads results in full before sending them, spooling large ones to disk.",
+    },
+    Guc {
+        name: SPOOL_THRESHOLD,
+        default: "64MB",
+        description: "Sets the size of a result above which spooled rows are written to disk.",
+    },
+    Guc {
+        name: "standard_conforming_strings",
+        default: "on",
+        description: "Causes '...' strings to treat backslashes literally.",
+    },
+    Guc {
+        name: STATEMENT_TIMEOUT,
+        default: "0",
+        description: "Sets the maximum allowed duration of any statement.",
+    },
+    Guc {
+        name: "TimeZone",
+        default: "UTC",
+        description: "Sets the time zone for displaying and interpreting time stamps.",
+    },
+    Guc {
+        name: "transaction_isolation",
+        default: "read committed",
+        description: "Sets the current transaction's isolation level.",
+    },
+];
+
+pub fn find_guc(name: &str) -> Option<&'static Guc> {
+    GUCS.iter().find(|guc| guc.name.eq_ignore_ascii_case(name))
+}
+
+// Session holds the variables set by a client over the lifetime of its
+// connection, e.g. `SET statement_timeout = '30s'`.
+pub struct Session {
+    variables: HashMap<String, String>,
+    statement_timeout: Option<Duration>,
+    idle_in_transaction_timeout: Option<Duration>,
+    // the `/*+ timeout(..) */` hint of the statement being run
+    timeout_hint: Option<Option<Duration>>,
+    dry_run: bool,
+    auto_limit: Option<u64>,
+    copy_compression: CopyCompression,
+    arrow_results: bool,
+    insert_batching: bool,
+    insert_batch: InsertBatchConfig,
+    spool_large_results: bool,
+    spool_threshold: usize,
+    query_labels: QueryLabels,
+    default_isolation: &'static str,
+    default_read_only: bool,
+    // --default-peer, used until the session sets peerdb.default_peer
+    server_default_peer: Option<String>,
+    // the parameters reported to the client when it connected
+    server_parameters: Arc<HashMap<String, String>>,
+}
+
+impl Session {
+    pub fn new(
+        server_default_peer: Option<String>,
+        server_parameters: Arc<HashMap<String, String>>,
+    ) -> Self {
+        Self {
+            variables: HashMap::new(),
+            statement_timeout: None,
+            idle_in_transaction_timeout: None,
+            timeout_hint: None,
+            dry_run: false,
+            auto_limit: None,
+            copy_compression: CopyCompression::None,
+            arrow_results: false,
+            insert_batching: false,
+            insert_batch: DEFAULT_INSERT_BATCH,
+            spool_large_results: false,
+            spool_threshold: DEFAULT_SPOOL_THRESHOLD,
+            query_labels: QueryLabels::default(),
+            default_isolation: DEFAULT_ISOLATION_LEVEL,
+            default_read_only: false,
+            server_default_peer,
+            server_parameters,
+        }
+    }
+
+    /// Sets a variable, validating the value for variables nexus interprets itself.
+    pub fn set(&mut self, name: &str, value: &str) -> PgWireResult<()> {
+        if name == STATEMENT_TIMEOUT {
+            self.statement_timeout = parse_timeout(value).ok_or_else(|| {
+                invalid_parameter_value(
+                    name,
+                    value,
+                    "expected a number of milliseconds or a duration like '30s'",
+                )
+            })?;
+        } else if name == IDLE_IN_TRANSACTION_SESSION_TIMEOUT {
+            self.idle_in_transaction_timeout = parse_timeout(value).ok_or_else(|| {
+                invalid_parameter_value(
+                    name,
+                    value,
+                    "expected a number of milliseconds or a duration like '30s'",
+                )
+            })?;
+        } else if name == DRY_RUN {
+            self.dry_run = parse_bool(value)
+                .ok_or_else(|| invalid_parameter_value(name, value, "expected on or off"))?;
+        } else if name == AUTO_LIMIT {
+            let limit = value.trim().parse::<u64>().map_err(|_| {
+                invalid_parameter_value(name, value, "expected a number of rows, 0 for none")
+            })?;
+            self.auto_limit = (limit > 0).then_some(limit);
+        } else if name == COPY_COMPRESSION {
+            self.copy_compression = CopyCompression::parse(value)
+                .ok_or_else(|| invalid_parameter_value(name, value, "expected none or gzip"))?;
+        } else if name == RESULT_FORMAT {
+            self.arrow_results = match value.trim().to_lowercase().as_str() {
+                "postgres" => false,
+                "arrow" => true,
+                _ => {
+                    return Err(invalid_parameter_value(
+                        name,
+                        value,
+                        "expected postgres or arrow",
+                    ))
+                }
+            };
+        } else if name == INSERT_BATCHING {
+            self.insert_batching = parse_bool(value)
+                .ok_or_else(|| invalid_parameter_value(name, value, "expected on or off"))?;
+        } else if name == INSERT_BATCH_SIZE {
+            self.insert_batch.max_rows = value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|rows| *rows > 0)
+                .ok_or_else(|| {
+                    invalid_parameter_value(name, value, "expected a positive number of rows")
+                })?;
+        } else if name == INSERT_BATCH_DELAY {
+            self.insert_batch.max_delay = parse_timeout(value).flatten().ok_or_else(|| {
+                invalid_parameter_value(
+                    name,
+                    value,
+                    "expected a positive number of milliseconds or a duration like '1s'",
+                )
+            })?;
+        } else if name == SPOOL_LARGE_RESULTS {
+            self.spool_large_results = parse_bool(value)
+                .ok_or_else(|| invalid_parameter_value(name, value, "expected on or off"))?;
+        } else if name == SPOOL_THRESHOLD {
+            self.spool_threshold = parse_size(value).ok_or_else(|| {
+                invalid_parameter_value(
+                    name,
+                    value,
+                    "expected a number of kilobytes or a size like '64MB'",
+                )
+            })?;
+        } else if name == QUERY_LABELS {
+            self.query_labels = QueryLabels::parse(value)
+                .map_err(|hint| invalid_parameter_value(name, value, &hint))?;
+        } else if name == DEFAULT_TRANSACTION_ISOLATION {
+            let level = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            self.default_isolation = ISOLATION_LEVELS
+                .iter()
+                .find(|known| known.eq_ignore_ascii_case(&level))
+                .copied()
+                .ok_or_else(|| {
+                    invalid_parameter_value(
+                        name,
+                        value,
+                        "expected serializable, repeatable read, read committed or read uncommitted",
+                    )
+                })?;
+        } else if name == DEFAULT_TRANSACTION_READ_ONLY {
+            self.default_read_only = parse_bool(value)
+                .ok_or_else(|| invalid_parameter_value(name, value, "expected on or off"))?;
+        }
+        if find_guc(name).is_none() {
+            tracing::warn!("setting unrecognized configuration parameter {}", name);
+        }
+        self.variables.insert(name.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    pub fn reset(&mut self, name: &str) {
+        if name == STATEMENT_TIMEOUT {
+            self.statement_timeout = None;
+        } else if name == IDLE_IN_TRANSACTION_SESSION_TIMEOUT {
+            self.idle_in_transaction_timeout = None;
+        } else if name == DRY_RUN {
+            self.dry_run = false;
+        } else if name == AUTO_LIMIT {
+            self.auto_limit = None;
+        } else if name == COPY_COMPRESSION {
+            self.copy_compression = CopyCompression::None;
+        } else if name == RESULT_FORMAT {
+            self.arrow_results = false;
+        } else if name == INSERT_BATCHING {
+            self.insert_batching = false;
+        } else if name == INSERT_BATCH_SIZE {
+            self.insert_batch.max_rows = DEFAULT_INSERT_BATCH.max_rows;
+        } else if name == INSERT_BATCH_DELAY {
+            self.insert_batch.max_delay = DEFAULT_INSERT_BATCH.max_delay;
+        } else if name == SPOOL_LARGE_RESULTS {
+            self.spool_large_results = false;
+        } else if name == SPOOL_THRESHOLD {
+            self.spool_threshold = DEFAULT_SPOOL_THRESHOLD;
+        } else if name == QUERY_LABELS {
+            self.query_labels = QueryLabels::default();
+        } else if name == DEFAULT_TRANSACTION_ISOLATION {
+            self.default_isolation = DEFAULT_ISOLATION_LEVEL;
+        } else if name == DEFAULT_TRANSACTION_READ_ONLY {
+            self.default_read_only = false;
+        }
+        self.variables.remove(name);
+    }
+
+    /// The variables set in this session, which `RESET ALL` resets.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.variables.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(|v| v.as_str())
+    }
+
+    // the session's value, else the server level default for settings that
+    // have one.
+    fn setting(&self, name: &str) -> Option<&str> {
+        self.get(name).or_else(|| match name {
+            DEFAULT_PEER => self.server_default_peer.as_deref(),
+            _ => self
+                .server_parameters
+                .iter()
+                .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str()),
+        })
+    }
+
+    /// The value `SHOW name` reports: the session's value, else the value
+    /// reported when the client connected or the default of a known setting.
+    pub fn show(&self, name: &str) -> Option<String> {
+        self.setting(name)
+            .or_else(|| find_guc(name).map(|guc| guc.default))
+            .map(str::to_owned)
+    }
+
+    /// Rows of `SHOW ALL` as (name, setting, description): every known setting
+    /// followed by the unrecognized variables set in this session.
+    pub fn show_all(&self) -> Vec<(String, String, String)> {
+        let mut rows = GUCS
+            .iter()
+            .map(|guc| {
+                let setting = self
+                    .setting(&guc.name.to_lowercase())
+                    .unwrap_or(guc.default)
+                    .to_owned();
+                (guc.name.to_owned(), setting, guc.description.to_owned())
+            })
+            .collect::<Vec<_>>();
+
+        let mut custom = self
+            .variables
+            .iter()
+            .filter(|(name, _)| find_guc(name).is_none())
+            .map(|(name, value)| {
+                (
+                    name.clone(),
+                    value.clone(),
+                    "Unrecognized by nexus, only stored for this session.".to_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        custom.sort();
+        rows.extend(custom);
+        rows
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
+
+    /// How long a transaction on a peer may stay idle before it is rolled
+    /// back, None without a timeout.
+    pub fn idle_in_transaction_timeout(&self) -> Option<Duration> {
+        self.idle_in_transaction_timeout
+    }
+
+    /// Sets the timeout hint of the statement about to run, replacing the
+    /// previous statement's.
+    pub fn set_timeout_hint(&mut self, hint: Option<&str>) -> PgWireResult<()> {
+        self.timeout_hint = None;
+        if let Some(hint) = hint {
+            let timeout = parse_timeout(hint).ok_or_else(|| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "22023".to_owned(),
+                    format!(
+                        "invalid timeout hint \"{}\", expected a number of milliseconds or a duration like '5s'",
+                        hint
+                    ),
+                )))
+            })?;
+            self.timeout_hint = Some(timeout);
+        }
+        Ok(())
+    }
+
+    /// The timeout of the running statement from its hint, `Some(None)` when
+    /// the hint turns the timeout off and None without a hint.
+    pub fn timeout_hint(&self) -> Option<Option<Duration>> {
+        self.timeout_hint
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The LIMIT added to SELECTs on peers without one, None while
+    /// `peerdb.auto_limit` is 0.
+    pub fn auto_limit(&self) -> Option<u64> {
+        self.auto_limit
+    }
+
+    pub fn copy_compression(&self) -> CopyCompression {
+        self.copy_compression
+    }
+
+    /// Whether queries are answered with an arrow stream, set by
+    /// `peerdb.result_format`.
+    pub fn arrow_results(&self) -> bool {
+        self.arrow_results
+    }
+
+    /// When INSERTs are batched, None while `peerdb.insert_batching` is off.
+    pub fn insert_batching(&self) -> Option<InsertBatchConfig> {
+        self.insert_batching.then_some(self.insert_batch)
+    }
+
+    /// The size in bytes above which results are spooled to disk, None while
+    /// `peerdb.spool_large_results` is off.
+    pub fn spool_threshold(&self) -> Option<usize> {
+        self.spool_large_results.then_some(self.spool_threshold)
+    }
+
+    pub fn query_labels(&self) -> &QueryLabels {
+        &self.query_labels
+    }
+
+    /// The transaction defaults the session changed, as (name, value), which
+    /// only postgres peers apply.
+    pub fn transaction_defaults(&self) -> Vec<(&'static str, String)> {
+        let mut defaults = Vec::new();
+        if self.default_isolation != DEFAULT_ISOLATION_LEVEL {
+            defaults.push((
+                DEFAULT_TRANSACTION_ISOLATION,
+                self.default_isolation.to_owned(),
+            ));
+        }
+        if self.default_read_only {
+            defaults.push((DEFAULT_TRANSACTION_READ_ONLY, "on".to_owned()));
+        }
+        defaults
+    }
+
+    /// The peer unqualified queries are routed to, an empty setting turns the
+    /// server level default off for the session. Routing hints win over it.
+    pub fn default_peer(&self) -> Option<String> {
+        self.setting(DEFAULT_PEER)
+            .filter(|peer| !peer.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// The value to apply on the peers for a variable, in the form the peers
+    /// expect, or None if the variable isn't forwarded to peers.
+    pub fn forwarded_value(&self, name: &str) -> Option<String> {
+        match name {
+            STATEMENT_TIMEOUT => Some(
+                self.statement_timeout
+                    .map_or(0, |t| t.as_millis())
+                    .to_string(),
+            ),
+            QUERY_LABELS => Some(self.query_labels.to_string()),
+            DEFAULT_TRANSACTION_ISOLATION => Some(self.default_isolation.to_owned()),
+            DEFAULT_TRANSACTION_READ_ONLY => {
+                Some(if self.default_read_only { "on" } else { "off" }.to_owned())
+            }
+            APPLICATION_NAME | SEARCH_PATH | TIMEZONE => self.show(name),
+            _ => None,
+        }
+    }
+
+    /// Forwarded variables explicitly set in this session, to be applied on
+    /// newly connected peers.
+    pub fn forwarded_parameters(&self) -> Vec<(&'static str, String)> {
+        FORWARDED_PARAMETERS
+            .iter()
+            .filter(|name| self.variables.contains_key(**name))
+            .filter_map(|name| self.forwarded_value(name).map(|value| (*name, value)))
+            .collect()
+    }
+}
+
+fn invalid_parameter_value(name: &str, value: &str, hint: &str) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "22023".to_owned(),
+        format!(
+            "invalid value for parameter \"{}\": \"{}\", {}",
+            name, value, hint
+        ),
+    )))
+}
+
+// the spellings postgres accepts for boolean settings
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" | "t" | "y" => Some(true),
+        "off" | "false" | "no" | "0" | "f" | "n" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a timeout in the forms postgres accepts for `statement_timeout`:
+/// a plain number of milliseconds or a number with a unit (`us`, `ms`, `s`,
+/// `min`, `h`, `d`). Zero disables the timeout.
+///
+/// Returns `Some(None)` when there is no timeout and `None` for invalid values.
+pub fn parse_timeout(value: &str) -> Option<Option<Duration>> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let millis = match unit.trim().to_lowercase().as_str() {
+        "us" => number / 1000.0,
+        "" | "ms" => number,
+        "s" => number * 1000.0,
+        "min" => number * 60_000.0,
+        "h" => number * 3_600_000.0,
+        "d" => number * 86_400_000.0,
+        _ => return None,
+    };
+    if millis == 0.0 {
+        return Some(None);
+    }
+    // values past what a Duration holds are out of range
+    Duration::try_from_secs_f64(millis / 1000.0).ok().map(Some)
+}
+
+/// Parses a size in the forms postgres accepts for memory settings: a plain
+/// number of kilobytes or a number with a unit (`B`, `kB`, `MB`, `GB`, `TB`).
+pub fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<usize>().ok()?;
+    let unit: usize = match unit.trim() {
+        "B" => 1,
+        "" | "kB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        "TB" => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.checked_mul(unit)
+}
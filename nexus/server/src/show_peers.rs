@@ -0,0 +1,45 @@
+//! `SHOW PEERS [LIKE 'pattern']`: the peers registered in the catalog with
+//! their type and when they were created, answered by nexus without
+//! connecting to any of them.
+
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use peer_cursor::{Record, Records, Schema};
+use pgwire::api::{
+    results::{FieldFormat, FieldInfo},
+    Type,
+};
+use pt::peerdb_peers::DbType;
+
+pub fn schema() -> Schema {
+    let field = |name: &str, datatype: Type| {
+        FieldInfo::new(name.to_owned(), None, None, datatype, FieldFormat::Text)
+    };
+    Arc::new(vec![
+        field("name", Type::TEXT),
+        field("peer_type", Type::TEXT),
+        field("created_at", Type::TIMESTAMP),
+    ])
+}
+
+/// The peers as listed by the catalog, as (name, type, created at).
+pub fn records(peers: Vec<(String, i32, NaiveDateTime)>) -> Records {
+    let schema = schema();
+    Records {
+        records: peers
+            .into_iter()
+            .map(|(name, peer_type, created_at)| Record {
+                values: vec![
+                    value::Value::Text(name),
+                    DbType::try_from(peer_type).map_or(value::Value::Null, |db_type| {
+                        value::Value::Text(db_type.as_str_name().to_owned())
+                    }),
+                    value::Value::PostgresTimestamp(created_at),
+                ],
+                schema: schema.clone(),
+            })
+            .collect(),
+        schema,
+    }
+}
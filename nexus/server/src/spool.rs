@@ -0,0 +1,117 @@
+//! Spooling of results to disk, enabled per session with
+//! `SET peerdb.spool_large_results = on`.
+//!
+//! A spooled result is read from the peer in full before its first row is
+//! sent, so the peer connection is free again while a slow client reads it.
+//! Rows are kept in memory up to `peerdb.spool_threshold` bytes, or until
+//! the connection reaches its memory limit, past that they go to a temporary
+//! file that is removed once the result is sent, the client went away or
+//! reading from the peer failed.
+
+use std::{io::SeekFrom, sync::Arc};
+
+use bytes::BytesMut;
+use futures::{stream, StreamExt};
+use peer_cursor::{util::encode_record, SendableStream};
+use pgwire::{
+    api::results::{QueryResponse, Response},
+    error::{PgWireError, PgWireResult},
+    messages::data::DataRow,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
+};
+
+use crate::memory::MemoryAccount;
+
+/// Reads all rows of the stream, then answers with a response sending them
+/// from memory and from the spool file.
+pub async fn spool_stream<'a>(
+    mut rows: SendableStream,
+    threshold: usize,
+    memory: &Arc<MemoryAccount>,
+) -> PgWireResult<Response<'a>> {
+    let schema = rows.schema();
+    let mut in_memory = Vec::new();
+    let mut in_memory_bytes = 0;
+    let mut reservation = memory.reservation();
+    let mut file: Option<BufWriter<File>> = None;
+
+    while let Some(record) = rows.next().await {
+        let row = encode_record(&schema, &record?)?;
+        if let Some(file) = file.as_mut() {
+            write_row(file, &row).await.map_err(spool_error)?;
+        } else if in_memory_bytes + row.data.len() > threshold
+            || !reservation.try_grow(row.data.len())
+        {
+            tracing::info!(
+                "result exceeds the spool threshold of {} bytes or the connection memory limit, \
+                spooling to disk",
+                threshold
+            );
+            let mut spool =
+                BufWriter::new(File::from_std(tempfile::tempfile().map_err(spool_error)?));
+            write_row(&mut spool, &row).await.map_err(spool_error)?;
+            file = Some(spool);
+        } else {
+            in_memory_bytes += row.data.len();
+            in_memory.push(row);
+        }
+    }
+    // the peer is done with the query here, the rest is between nexus and
+    // the client.
+    drop(rows);
+
+    let spooled = match file {
+        Some(mut file) => {
+            file.flush().await.map_err(spool_error)?;
+            let mut file = file.into_inner();
+            file.seek(SeekFrom::Start(0)).await.map_err(spool_error)?;
+            Some(BufReader::new(file))
+        }
+        None => None,
+    };
+    let spooled_rows = stream::unfold(spooled, |reader| async move {
+        let mut reader = reader?;
+        match read_row(&mut reader).await {
+            Ok(Some(row)) => Some((Ok(row), Some(reader))),
+            Ok(None) => None,
+            // the file is closed, and so removed, with the reader
+            Err(err) => Some((Err(spool_error(err)), None)),
+        }
+    });
+    let data_rows = stream::iter(in_memory)
+        .map(move |row| {
+            // the rows in memory count towards the limit until sent
+            let _held = &reservation;
+            Ok(row)
+        })
+        .chain(spooled_rows)
+        .boxed();
+    Ok(Response::Query(QueryResponse::new(schema, data_rows)))
+}
+
+// rows are written as their field count and length followed by the encoded
+// fields, the way they are sent to the client.
+async fn write_row(file: &mut BufWriter<File>, row: &DataRow) -> std::io::Result<()> {
+    file.write_i16(row.field_count).await?;
+    file.write_u32(row.data.len() as u32).await?;
+    file.write_all(&row.data).await
+}
+
+async fn read_row(reader: &mut BufReader<File>) -> std::io::Result<Option<DataRow>> {
+    let field_count = match reader.read_i16().await {
+        Ok(field_count) => field_count,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let len = reader.read_u32().await? as usize;
+    let mut data = BytesMut::zeroed(len);
+    reader.read_exact(&mut data).await?;
+    Ok(Some(DataRow::new(data, field_count)))
+}
+
+fn spool_error(err: std::io::Error) -> PgWireError {
+    PgWireError::ApiError(format!("unable to spool result to disk: {}", err).into())
+}
@@ -0,0 +1,127 @@
+//! The transaction block of a connection. BEGIN opens the block in nexus,
+//! which pins it to the peer the first statement in it runs on, or to the
+//! catalog, and runs the BEGIN there then. A BEGIN routed to a peer, by a
+//! hint or the default peer, pins the block to it right away. The executor
+//! the block is pinned to is kept until COMMIT or ROLLBACK end it there.
+//!
+//! A statement for any other peer fails, nexus can't commit on two peers at
+//! once. Peers without transactions only answer queries: a block can be
+//! pinned to them but BEGIN, COMMIT and ROLLBACK don't run on them.
+//!
+//! Like in postgres, once a statement of the block failed the others are
+//! rejected until ROLLBACK or ROLLBACK TO SAVEPOINT, and COMMIT rolls the
+//! transaction back.
+
+use std::sync::Arc;
+
+use peer_cursor::QueryExecutor;
+use pgwire::error::{ErrorInfo, PgWireError};
+use pt::peerdb_peers::Peer;
+use sqlparser::ast::Statement;
+
+/// The statements nexus handles for the block.
+#[derive(Debug, Clone, Copy)]
+pub enum TransactionControl {
+    Begin,
+    /// COMMIT or ROLLBACK, AND CHAIN starting the next transaction right
+    /// away.
+    End {
+        commit: bool,
+        chain: bool,
+    },
+}
+
+impl TransactionControl {
+    pub fn of(stmt: &Statement) -> Option<Self> {
+        match stmt {
+            Statement::StartTransaction { .. } => Some(Self::Begin),
+            Statement::Commit { chain } => Some(Self::End {
+                commit: true,
+                chain: *chain,
+            }),
+            Statement::Rollback {
+                chain,
+                savepoint: None,
+            } => Some(Self::End {
+                commit: false,
+                chain: *chain,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Where the statements of a block run.
+pub struct Pinned {
+    /// None for the catalog.
+    pub peer: Option<Box<Peer>>,
+    pub executor: Arc<dyn QueryExecutor>,
+    /// Whether the BEGIN ran on the peer.
+    pub forwarded: bool,
+}
+
+impl Pinned {
+    pub fn name(&self) -> &str {
+        self.peer
+            .as_deref()
+            .map_or("the catalog", |peer| peer.name.as_str())
+    }
+
+    pub fn runs_on(&self, peer: Option<&Peer>) -> bool {
+        self.peer.as_deref().map(|pinned| &pinned.name) == peer.map(|peer| &peer.name)
+    }
+}
+
+pub struct Transaction {
+    /// The client's BEGIN, with its isolation level and access mode.
+    pub begin: Statement,
+    /// None until a statement of the block ran.
+    pub pinned: Option<Pinned>,
+    pub failed: bool,
+}
+
+impl Transaction {
+    pub fn new(begin: Statement) -> Self {
+        Self {
+            begin,
+            pinned: None,
+            failed: false,
+        }
+    }
+}
+
+/// Statements that belong to the block rather than to a peer, they run
+/// wherever the block is pinned.
+pub fn follows_block(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::Savepoint { .. }
+            | Statement::ReleaseSavepoint { .. }
+            | Statement::Rollback {
+                savepoint: Some(_),
+                ..
+            }
+    )
+}
+
+pub fn distributed_transaction(pinned: &Pinned, peer: Option<&Peer>) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "0A000".to_owned(),
+        format!(
+            "distributed transactions are unsupported: the transaction block runs on {}, \
+             the statement on {}",
+            pinned.name(),
+            peer.map_or("the catalog", |peer| peer.name.as_str())
+        ),
+    )))
+}
+
+pub fn aborted() -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "25P02".to_owned(),
+        "current transaction is aborted, commands ignored until end of transaction block"
+            .to_owned(),
+    )))
+}
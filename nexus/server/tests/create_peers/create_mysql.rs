@@ -0,0 +1,29 @@
+use postgres::Client;
+use std::env;
+
+/// `setup` runs on every connection the peer opens, so each server starts
+/// from an empty `import_test` table.
+pub fn create(nexus: &mut Client) {
+    dotenvy::dotenv().ok();
+    let peer_host = env::var("PEERDB_MYSQL_HOST").expect("PEERDB_MYSQL_HOST not set");
+    let peer_port = env::var("PEERDB_MYSQL_PORT").expect("PEERDB_MYSQL_PORT not set");
+    let peer_database = env::var("PEERDB_MYSQL_DATABASE").expect("PEERDB_MYSQL_DATABASE not set");
+    let peer_user = env::var("PEERDB_MYSQL_USER").expect("PEERDB_MYSQL_USER not set");
+    let peer_password = env::var("PEERDB_MYSQL_PASSWORD").expect("PEERDB_MYSQL_PASSWORD not set");
+
+    let create_stmt = format!(
+        "
+    CREATE PEER IF NOT EXISTS mysql_test FROM MYSQL WITH
+    (
+        host = '{}',
+        port = '{}',
+        user = '{}',
+        password = '{}',
+        database = '{}',
+        setup = 'DROP TABLE IF EXISTS import_test;CREATE TABLE import_test(id int, name text, score double)'
+    );",
+        &peer_host, &peer_port, &peer_user, &peer_password, &peer_database
+    );
+
+    let _ = nexus.simple_query(&create_stmt);
+}
@@ -1,3 +1,4 @@
 pub mod create_bq;
+pub mod create_mysql;
 pub mod create_pg;
 pub mod create_sf;
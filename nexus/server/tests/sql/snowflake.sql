@@ -116,6 +116,6 @@ SELECT TIMESTAMP_TZ AT TIME ZONE 'UTC' FROM sf_test.PUBLIC.DATETIME LIMIT 1;
 SELECT TIMESTAMP_TZ AT TIME ZONE 'Asia/Kolkata' FROM sf_test.PUBLIC.DATETIME WHERE TIMESTAMP_TZ > '2023-01-01' LIMIT 1;
 
 
-DECLARE liahona CURSOR FOR SELECT NAME FROM sf_test.PUBLIC.USERS LIMIT 1000;
+DECLARE liahona CURSOR WITH HOLD FOR SELECT NAME FROM sf_test.PUBLIC.USERS LIMIT 1000;
 FETCH 1 IN liahona;
 FETCH FORWARD 1 IN liahona;
\ No newline at end of file
@@ -126,6 +126,37 @@ impl ArrayValue {
             ),
         }
     }
+
+    /// Approximate bytes the elements take on the heap.
+    pub fn approximate_size(&self) -> usize {
+        fn elements<T>(arr: &[T]) -> usize {
+            std::mem::size_of_val(arr)
+        }
+        fn strings<S: AsRef<[u8]>>(arr: &[S]) -> usize {
+            elements(arr) + arr.iter().map(|s| s.as_ref().len()).sum::<usize>()
+        }
+        match self {
+            ArrayValue::Empty => 0,
+            ArrayValue::Bool(arr) => elements(arr),
+            ArrayValue::TinyInt(arr) => elements(arr),
+            ArrayValue::SmallInt(arr) => elements(arr),
+            ArrayValue::Integer(arr) => elements(arr),
+            ArrayValue::BigInt(arr) => elements(arr),
+            ArrayValue::Float(arr) => elements(arr),
+            ArrayValue::Double(arr) => elements(arr),
+            ArrayValue::Numeric(arr) => strings(arr),
+            ArrayValue::Char(arr) => elements(arr),
+            ArrayValue::VarChar(arr) => strings(arr),
+            ArrayValue::Text(arr) => strings(arr),
+            ArrayValue::Binary(arr) => strings(arr),
+            ArrayValue::VarBinary(arr) => strings(arr),
+            ArrayValue::Date(arr) => elements(arr),
+            ArrayValue::Time(arr) => elements(arr),
+            ArrayValue::TimeWithTimeZone(arr) => elements(arr),
+            ArrayValue::Timestamp(arr) => elements(arr),
+            ArrayValue::TimestampWithTimeZone(arr) => elements(arr),
+        }
+    }
 }
 
 impl ToSql for ArrayValue {
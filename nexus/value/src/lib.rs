@@ -261,6 +261,31 @@ impl Value {
             }
         }
     }
+
+    /// Approximate bytes the value takes in memory, used to account for the
+    /// rows nexus buffers.
+    pub fn approximate_size(&self) -> usize {
+        fn json_size(value: &serde_json::Value) -> usize {
+            std::mem::size_of::<serde_json::Value>()
+                + match value {
+                    serde_json::Value::String(s) => s.len(),
+                    serde_json::Value::Array(arr) => arr.iter().map(json_size).sum(),
+                    serde_json::Value::Object(map) => {
+                        map.iter().map(|(k, v)| k.len() + json_size(v)).sum()
+                    }
+                    _ => 0,
+                }
+        }
+        let heap = match self {
+            Value::VarChar(s) | Value::Text(s) | Value::Enum(s) => s.len(),
+            Value::Binary(b) | Value::VarBinary(b) => b.len(),
+            Value::Array(arr) => arr.approximate_size(),
+            Value::Json(json) | Value::JsonB(json) => json_size(json),
+            Value::Hstore(map) => map.iter().map(|(k, v)| k.len() + v.len()).sum(),
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + heap
+    }
 }
 
 impl fmt::Display for Value {